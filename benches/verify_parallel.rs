@@ -0,0 +1,79 @@
+//! Compares verifying a macaroon with many discharges serially and with sibling discharges
+//! verified in parallel.
+//!
+//! Run with `cargo bench --features parallel --bench verify_parallel`.
+
+use macaroon::{Macaroon, MacaroonKey, Verifier, VerifierOptions};
+use std::time::{Duration, Instant};
+
+/// Third-party caveats on the macaroon, and on each of its discharges
+const WIDTH: usize = 16;
+/// Levels of discharges below the macaroon
+const DEPTH: usize = 2;
+/// First-party caveats on each discharge
+const CAVEATS: usize = 64;
+const ROUNDS: usize = 10;
+
+/// Add `WIDTH` third-party caveats to `m`, and mint and bind their (nested) discharges
+fn add_discharges(m: &mut Macaroon, depth: usize, prefix: &str, discharges: &mut Vec<Macaroon>) {
+    if depth == DEPTH {
+        return;
+    }
+    for i in 0..WIDTH {
+        let id = format!("{}/{}", prefix, i);
+        let caveat_key = MacaroonKey::generate(id.as_bytes());
+        m.add_third_party_caveat("https://auth.example/", &caveat_key, id.as_str().into());
+        let mut discharge = Macaroon::create(None, &caveat_key, id.as_str().into()).unwrap();
+        for j in 0..CAVEATS {
+            discharge.add_first_party_caveat(format!("check {} {}", id, j).into());
+        }
+        add_discharges(&mut discharge, depth + 1, &id, discharges);
+        discharges.push(discharge);
+    }
+}
+
+fn main() {
+    let key = MacaroonKey::generate(b"bench key");
+    let mut macaroon = Macaroon::create(None, &key, "root".into()).unwrap();
+    let mut discharges = Vec::new();
+    add_discharges(&mut macaroon, 0, "d", &mut discharges);
+    for discharge in discharges.iter_mut() {
+        macaroon.bind(discharge);
+    }
+
+    let mut verifier = Verifier::default();
+    verifier.satisfy_general(|predicate| predicate.as_ref().starts_with(b"check "));
+    let serial = VerifierOptions::builder()
+        .unbounded()
+        .parallel(false)
+        .build();
+    let parallel = VerifierOptions::builder()
+        .unbounded()
+        .parallel(true)
+        .build();
+
+    // alternate the two, keeping the best of a few rounds, to even out warm-up and noise
+    let mut serial_elapsed = Duration::MAX;
+    let mut parallel_elapsed = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        verifier
+            .verify_with_options(&macaroon, &key, discharges.clone(), &serial)
+            .unwrap();
+        serial_elapsed = serial_elapsed.min(start.elapsed());
+
+        let start = Instant::now();
+        verifier
+            .verify_with_options(&macaroon, &key, discharges.clone(), &parallel)
+            .unwrap();
+        parallel_elapsed = parallel_elapsed.min(start.elapsed());
+    }
+
+    println!(
+        "{} discharges, {} threads",
+        discharges.len(),
+        rayon::current_num_threads()
+    );
+    println!("serial:   {:>8.1?}", serial_elapsed);
+    println!("parallel: {:>8.1?}", parallel_elapsed);
+}
@@ -0,0 +1,153 @@
+/* C API of the macaroon-rs libmacaroons replacement (see capi/src/lib.rs).
+ *
+ * Declarations match libmacaroons' macaroons.h, so existing code and bindings
+ * can be compiled and linked against this library unchanged.
+ */
+#ifndef macaroons_h_
+#define macaroons_h_
+
+#include <stdlib.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* All byte strings are passed as a pointer and a size, and are not NUL-terminated. */
+
+struct macaroon;
+struct macaroon_verifier;
+
+enum macaroon_returncode
+{
+    MACAROON_SUCCESS          = 2048,
+    MACAROON_OUT_OF_MEMORY    = 2049,
+    MACAROON_HASH_FAILED      = 2050,
+    MACAROON_INVALID          = 2051,
+    MACAROON_TOO_MANY_CAVEATS = 2052,
+    MACAROON_CYCLE            = 2053,
+    MACAROON_BUF_TOO_SMALL    = 2054,
+    MACAROON_NOT_AUTHORIZED   = 2055,
+    MACAROON_NO_JSON_SUPPORT  = 2056,
+    MACAROON_UNSUPPORTED_FORMAT = 2057
+};
+
+enum macaroon_format
+{
+    MACAROON_V1,  /* base64-encoded */
+    MACAROON_V2,  /* binary */
+    MACAROON_V2J  /* JSON */
+};
+
+struct macaroon*
+macaroon_create(const unsigned char* location, size_t location_sz,
+                const unsigned char* key, size_t key_sz,
+                const unsigned char* id, size_t id_sz,
+                enum macaroon_returncode* err);
+
+void
+macaroon_destroy(struct macaroon* M);
+
+/* 0 if M is well-formed */
+int
+macaroon_validate(const struct macaroon* M);
+
+struct macaroon*
+macaroon_add_first_party_caveat(const struct macaroon* M,
+                                const unsigned char* predicate, size_t predicate_sz,
+                                enum macaroon_returncode* err);
+
+struct macaroon*
+macaroon_add_third_party_caveat(const struct macaroon* M,
+                                const unsigned char* location, size_t location_sz,
+                                const unsigned char* key, size_t key_sz,
+                                const unsigned char* id, size_t id_sz,
+                                enum macaroon_returncode* err);
+
+unsigned
+macaroon_num_third_party_caveats(const struct macaroon* M);
+
+/* 0 on success, -1 if there is no such caveat */
+int
+macaroon_third_party_caveat(const struct macaroon* M, unsigned which,
+                            const unsigned char** location, size_t* location_sz,
+                            const unsigned char** identifier, size_t* identifier_sz);
+
+struct macaroon*
+macaroon_prepare_for_request(const struct macaroon* M,
+                             const struct macaroon* D,
+                             enum macaroon_returncode* err);
+
+struct macaroon_verifier*
+macaroon_verifier_create(void);
+
+void
+macaroon_verifier_destroy(struct macaroon_verifier* V);
+
+int
+macaroon_verifier_satisfy_exact(struct macaroon_verifier* V,
+                                const unsigned char* predicate, size_t predicate_sz,
+                                enum macaroon_returncode* err);
+
+/* general_check returns 0 if it satisfies the predicate */
+int
+macaroon_verifier_satisfy_general(struct macaroon_verifier* V,
+                                  int (*general_check)(void* f, const unsigned char* pred, size_t pred_sz),
+                                  void* f, enum macaroon_returncode* err);
+
+/* 0 if M verifies; otherwise -1, with err set to MACAROON_NOT_AUTHORIZED on a
+ * verification failure */
+int
+macaroon_verify(const struct macaroon_verifier* V,
+                const struct macaroon* M,
+                const unsigned char* key, size_t key_sz,
+                struct macaroon** MS, size_t MS_sz,
+                enum macaroon_returncode* err);
+
+void
+macaroon_location(const struct macaroon* M,
+                  const unsigned char** location, size_t* location_sz);
+
+void
+macaroon_identifier(const struct macaroon* M,
+                    const unsigned char** identifier, size_t* identifier_sz);
+
+void
+macaroon_signature(const struct macaroon* M,
+                   const unsigned char** signature, size_t* signature_sz);
+
+size_t
+macaroon_serialize_size_hint(const struct macaroon* M, enum macaroon_format f);
+
+/* Returns the number of bytes written (followed by a NUL byte), or 0 on error */
+size_t
+macaroon_serialize(const struct macaroon* M, enum macaroon_format f,
+                   unsigned char* buf, size_t buf_sz,
+                   enum macaroon_returncode* err);
+
+/* Accepts any format */
+struct macaroon*
+macaroon_deserialize(const unsigned char* data, size_t data_sz,
+                     enum macaroon_returncode* err);
+
+size_t
+macaroon_inspect_size_hint(const struct macaroon* M);
+
+int
+macaroon_inspect(const struct macaroon* M, char* data, size_t data_sz,
+                 enum macaroon_returncode* err);
+
+struct macaroon*
+macaroon_copy(const struct macaroon* M, enum macaroon_returncode* err);
+
+/* 0 if M and N are equal */
+int
+macaroon_cmp(const struct macaroon* M, const struct macaroon* N);
+
+const char*
+macaroon_error(enum macaroon_returncode err);
+
+#ifdef __cplusplus
+} /* extern "C" */
+#endif
+
+#endif /* macaroons_h_ */
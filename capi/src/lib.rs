@@ -0,0 +1,766 @@
+//! C bindings for the `macaroon` crate, compatible with the libmacaroons API.
+//!
+//! The functions, types and return codes match libmacaroons' `macaroons.h` (a copy is in
+//! `include/macaroons.h`), so C programs, and the Python or Ruby bindings built on libmacaroons,
+//! can link against this library instead. As in libmacaroons, macaroons are immutable: adding a
+//! caveat returns a new macaroon, and every macaroon and verifier must be freed with
+//! `macaroon_destroy` or `macaroon_verifier_destroy`.
+//!
+//! Keys of any length are derived with the libmacaroons key generator ([MacaroonKey::generate]).
+//! Pointers returned by accessors such as `macaroon_identifier` stay valid until the macaroon is
+//! destroyed.
+//!
+//! # Safety
+//!
+//! Every function is `unsafe`, with the usual C rules: buffers must be valid for the sizes
+//! passed with them, and handles must come from this library and not have been destroyed.
+//! Null handles are reported as `MACAROON_INVALID`.
+
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use ::macaroon::{
+    ByteString, Caveat, Format, Macaroon, MacaroonError, MacaroonKey, Result, Verifier,
+};
+use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_void};
+use std::{ptr, slice, str};
+
+/// Error codes, with the same values as in libmacaroons
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum macaroon_returncode {
+    MACAROON_SUCCESS = 2048,
+    MACAROON_OUT_OF_MEMORY = 2049,
+    MACAROON_HASH_FAILED = 2050,
+    MACAROON_INVALID = 2051,
+    MACAROON_TOO_MANY_CAVEATS = 2052,
+    MACAROON_CYCLE = 2053,
+    MACAROON_BUF_TOO_SMALL = 2054,
+    MACAROON_NOT_AUTHORIZED = 2055,
+    MACAROON_NO_JSON_SUPPORT = 2056,
+    MACAROON_UNSUPPORTED_FORMAT = 2057,
+}
+
+use macaroon_returncode::*;
+
+/// Serialization format, an `enum macaroon_format` in C. This is a plain integer here so that
+/// out-of-range values from C are reported rather than undefined behaviour.
+pub type macaroon_format = c_int;
+/// Base64-encoded V1
+pub const MACAROON_V1: macaroon_format = 0;
+/// Binary V2 (not base64-encoded, as in libmacaroons)
+pub const MACAROON_V2: macaroon_format = 1;
+/// V2 JSON
+pub const MACAROON_V2J: macaroon_format = 2;
+
+/// An immutable macaroon, with the fields returned by pointer cached alongside it
+pub struct macaroon {
+    inner: Macaroon,
+    location: String,
+    identifier: ByteString,
+    signature: MacaroonKey,
+    third_party: Vec<(String, ByteString)>,
+}
+
+impl macaroon {
+    fn into_raw(inner: Macaroon) -> *mut macaroon {
+        let third_party = inner
+            .third_party_caveats()
+            .into_iter()
+            .filter_map(|c| match c {
+                Caveat::ThirdParty(tp) => Some((tp.location(), tp.id())),
+                Caveat::FirstParty(_) => None,
+            })
+            .collect();
+        Box::into_raw(Box::new(macaroon {
+            location: inner.location().unwrap_or_default(),
+            identifier: inner.identifier(),
+            signature: inner.signature(),
+            third_party,
+            inner,
+        }))
+    }
+}
+
+pub struct macaroon_verifier {
+    inner: Verifier,
+}
+
+/// A `general_check` callback and its argument
+struct GeneralCheck {
+    func: unsafe extern "C" fn(*mut c_void, *const c_uchar, usize) -> c_int,
+    f: *mut c_void,
+}
+
+// As with libmacaroons, the caller is responsible for `f` being usable from the thread that
+// verifies
+unsafe impl Send for GeneralCheck {}
+unsafe impl Sync for GeneralCheck {}
+
+impl GeneralCheck {
+    fn call(&self, predicate: &ByteString) -> bool {
+        let predicate = predicate.as_ref();
+        unsafe { (self.func)(self.f, predicate.as_ptr(), predicate.len()) == 0 }
+    }
+}
+
+fn returncode(error: &MacaroonError) -> macaroon_returncode {
+    match error {
+        MacaroonError::InitializationError | MacaroonError::CryptoError(_) => MACAROON_HASH_FAILED,
+        MacaroonError::InvalidSignature
+        | MacaroonError::CaveatNotSatisfied(_)
+        | MacaroonError::DischargeNotUsed
+        | MacaroonError::DischargeNotBound(_)
+        | MacaroonError::DischargeBoundTwice(_)
+        | MacaroonError::Revoked(_)
+        | MacaroonError::LocationMismatch(_) => MACAROON_NOT_AUTHORIZED,
+        MacaroonError::LimitExceeded(_) => MACAROON_TOO_MANY_CAVEATS,
+        _ => MACAROON_INVALID,
+    }
+}
+
+unsafe fn set_err(err: *mut macaroon_returncode, code: macaroon_returncode) {
+    if let Some(err) = err.as_mut() {
+        *err = code;
+    }
+}
+
+unsafe fn bytes<'a>(data: *const c_uchar, size: usize) -> &'a [u8] {
+    if data.is_null() || size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, size)
+    }
+}
+
+unsafe fn deref<'a, T>(handle: *const T) -> Result<&'a T> {
+    handle
+        .as_ref()
+        .ok_or(MacaroonError::IncompleteMacaroon("null handle"))
+}
+
+/// Hand a new macaroon to the caller, or report the error and return null
+unsafe fn new_macaroon(result: Result<Macaroon>, err: *mut macaroon_returncode) -> *mut macaroon {
+    match result {
+        Ok(m) => macaroon::into_raw(m),
+        Err(e) => {
+            set_err(err, returncode(&e));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// `0` on success, or `-1` after reporting the error
+unsafe fn status(result: Result<()>, err: *mut macaroon_returncode) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_err(err, returncode(&e));
+            -1
+        }
+    }
+}
+
+fn serialize(
+    m: &Macaroon,
+    format: macaroon_format,
+) -> std::result::Result<Vec<u8>, macaroon_returncode> {
+    let result = match format {
+        MACAROON_V1 => m.serialize(Format::V1).map(String::into_bytes),
+        MACAROON_V2 => m.serialize_binary(),
+        MACAROON_V2J => m.serialize(Format::V2JSON).map(String::into_bytes),
+        _ => return Err(MACAROON_UNSUPPORTED_FORMAT),
+    };
+    result.map_err(|e| returncode(&e))
+}
+
+/// Point `data` and `data_sz` at `field`, or at NULL and `0` if there is none (a null handle)
+unsafe fn field_out(field: Option<&[u8]>, data: *mut *const c_uchar, data_sz: *mut usize) {
+    let (field_ptr, field_sz) = field.map_or((ptr::null(), 0), |f| (f.as_ptr(), f.len()));
+    if let Some(data) = data.as_mut() {
+        *data = field_ptr;
+    }
+    if let Some(data_sz) = data_sz.as_mut() {
+        *data_sz = field_sz;
+    }
+}
+
+/// Copy `data` and a terminating NUL into `buf`, if it fits
+unsafe fn copy_out(data: &[u8], buf: *mut c_uchar, buf_sz: usize) -> bool {
+    if buf.is_null() || buf_sz <= data.len() {
+        return false;
+    }
+    ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+    *buf.add(data.len()) = 0;
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_create(
+    location: *const c_uchar,
+    location_sz: usize,
+    key: *const c_uchar,
+    key_sz: usize,
+    id: *const c_uchar,
+    id_sz: usize,
+    err: *mut macaroon_returncode,
+) -> *mut macaroon {
+    let result = ::macaroon::initialize().and_then(|()| {
+        let location = match bytes(location, location_sz) {
+            [] => None,
+            location => Some(str::from_utf8(location)?.to_string()),
+        };
+        Macaroon::create(
+            location,
+            &MacaroonKey::generate(bytes(key, key_sz)),
+            bytes(id, id_sz).into(),
+        )
+    });
+    new_macaroon(result, err)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_destroy(m: *mut macaroon) {
+    if !m.is_null() {
+        drop(Box::from_raw(m));
+    }
+}
+
+/// `0` if `m` is a well-formed macaroon, `-1` otherwise
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_validate(m: *const macaroon) -> c_int {
+    match m.as_ref() {
+        Some(m) if !m.identifier.as_ref().is_empty() => 0,
+        _ => -1,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_add_first_party_caveat(
+    m: *const macaroon,
+    predicate: *const c_uchar,
+    predicate_sz: usize,
+    err: *mut macaroon_returncode,
+) -> *mut macaroon {
+    let result = deref(m).map(|m| {
+        let mut m = m.inner.clone();
+        m.add_first_party_caveat(bytes(predicate, predicate_sz).into());
+        m
+    });
+    new_macaroon(result, err)
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn macaroon_add_third_party_caveat(
+    m: *const macaroon,
+    location: *const c_uchar,
+    location_sz: usize,
+    key: *const c_uchar,
+    key_sz: usize,
+    id: *const c_uchar,
+    id_sz: usize,
+    err: *mut macaroon_returncode,
+) -> *mut macaroon {
+    let result = deref(m).and_then(|m| {
+        let location = str::from_utf8(bytes(location, location_sz))?;
+        let mut m = m.inner.clone();
+        m.add_third_party_caveat(
+            location,
+            &MacaroonKey::generate(bytes(key, key_sz)),
+            bytes(id, id_sz).into(),
+        );
+        Ok(m)
+    });
+    new_macaroon(result, err)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_num_third_party_caveats(m: *const macaroon) -> c_uint {
+    m.as_ref().map_or(0, |m| m.third_party.len() as c_uint)
+}
+
+/// The location and identifier of the `which`th third-party caveat; `-1` if there is none
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_third_party_caveat(
+    m: *const macaroon,
+    which: c_uint,
+    location: *mut *const c_uchar,
+    location_sz: *mut usize,
+    identifier: *mut *const c_uchar,
+    identifier_sz: *mut usize,
+) -> c_int {
+    match m.as_ref().and_then(|m| m.third_party.get(which as usize)) {
+        Some((cl, cid)) => {
+            *location = cl.as_ptr();
+            *location_sz = cl.len();
+            *identifier = cid.as_ref().as_ptr();
+            *identifier_sz = cid.as_ref().len();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// A copy of the discharge `d`, bound to `m` so it can be sent with a request
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_prepare_for_request(
+    m: *const macaroon,
+    d: *const macaroon,
+    err: *mut macaroon_returncode,
+) -> *mut macaroon {
+    let result = deref(m).and_then(|m| {
+        let mut discharge = deref(d)?.inner.clone();
+        m.inner.bind(&mut discharge);
+        Ok(discharge)
+    });
+    new_macaroon(result, err)
+}
+
+#[no_mangle]
+pub extern "C" fn macaroon_verifier_create() -> *mut macaroon_verifier {
+    if ::macaroon::initialize().is_err() {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(macaroon_verifier {
+        inner: Default::default(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_verifier_destroy(v: *mut macaroon_verifier) {
+    if !v.is_null() {
+        drop(Box::from_raw(v));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_verifier_satisfy_exact(
+    v: *mut macaroon_verifier,
+    predicate: *const c_uchar,
+    predicate_sz: usize,
+    err: *mut macaroon_returncode,
+) -> c_int {
+    let result = deref(v).map(|_| {
+        (*v).inner
+            .satisfy_exact(bytes(predicate, predicate_sz).into())
+    });
+    status(result, err)
+}
+
+/// Satisfy the first-party caveats for which `general_check(f, predicate, predicate_sz)`
+/// returns `0`
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_verifier_satisfy_general(
+    v: *mut macaroon_verifier,
+    general_check: Option<unsafe extern "C" fn(*mut c_void, *const c_uchar, usize) -> c_int>,
+    f: *mut c_void,
+    err: *mut macaroon_returncode,
+) -> c_int {
+    let result = deref(v).and_then(|_| {
+        let check = GeneralCheck {
+            func: general_check.ok_or(MacaroonError::IncompleteMacaroon("null general_check"))?,
+            f,
+        };
+        (*v).inner
+            .satisfy_general(move |predicate| check.call(predicate));
+        Ok(())
+    });
+    status(result, err)
+}
+
+/// `0` if `m` verifies with `key` and the discharges `ms`, `-1` otherwise, with `err` set to
+/// `MACAROON_NOT_AUTHORIZED` if verification failed
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_verify(
+    v: *const macaroon_verifier,
+    m: *const macaroon,
+    key: *const c_uchar,
+    key_sz: usize,
+    ms: *mut *mut macaroon,
+    ms_sz: usize,
+    err: *mut macaroon_returncode,
+) -> c_int {
+    let result = deref(v).and_then(|v| {
+        let m = deref(m)?;
+        let discharges = (0..ms_sz)
+            .map(|i| deref(*ms.add(i)).map(|d| d.inner.clone()))
+            .collect::<Result<Vec<Macaroon>>>()?;
+        v.inner.verify(
+            &m.inner,
+            &MacaroonKey::generate(bytes(key, key_sz)),
+            discharges,
+        )
+    });
+    status(result, err)
+}
+
+/// The location of `m`, or NULL and `0` if `m` is null
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_location(
+    m: *const macaroon,
+    location: *mut *const c_uchar,
+    location_sz: *mut usize,
+) {
+    let field = m.as_ref().map(|m| m.location.as_bytes());
+    field_out(field, location, location_sz);
+}
+
+/// The identifier of `m`, or NULL and `0` if `m` is null
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_identifier(
+    m: *const macaroon,
+    identifier: *mut *const c_uchar,
+    identifier_sz: *mut usize,
+) {
+    let field = m.as_ref().map(|m| m.identifier.as_ref());
+    field_out(field, identifier, identifier_sz);
+}
+
+/// The signature of `m`, or NULL and `0` if `m` is null
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_signature(
+    m: *const macaroon,
+    signature: *mut *const c_uchar,
+    signature_sz: *mut usize,
+) {
+    let field = m.as_ref().map(|m| &m.signature[..]);
+    field_out(field, signature, signature_sz);
+}
+
+/// Size of the buffer needed to serialize `m` in format `f`, or `0` if it can't be
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_serialize_size_hint(
+    m: *const macaroon,
+    f: macaroon_format,
+) -> usize {
+    match m.as_ref().map(|m| serialize(&m.inner, f)) {
+        Some(Ok(data)) => data.len() + 1,
+        _ => 0,
+    }
+}
+
+/// Serialize `m` into `buf`, followed by a NUL byte, returning the length written (without the
+/// NUL), or `0` on error
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_serialize(
+    m: *const macaroon,
+    f: macaroon_format,
+    buf: *mut c_uchar,
+    buf_sz: usize,
+    err: *mut macaroon_returncode,
+) -> usize {
+    let data = match deref(m) {
+        Ok(m) => serialize(&m.inner, f),
+        Err(e) => Err(returncode(&e)),
+    };
+    match data {
+        Ok(data) if copy_out(&data, buf, buf_sz) => data.len(),
+        Ok(_) => {
+            set_err(err, MACAROON_BUF_TOO_SMALL);
+            0
+        }
+        Err(code) => {
+            set_err(err, code);
+            0
+        }
+    }
+}
+
+/// Deserialize a macaroon in any format: base64 V1 or V2, binary V2, or V2 JSON
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_deserialize(
+    data: *const c_uchar,
+    data_sz: usize,
+    err: *mut macaroon_returncode,
+) -> *mut macaroon {
+    let data = bytes(data, data_sz);
+    let result = ::macaroon::initialize().and_then(|()| match data.first() {
+        Some(2) => Macaroon::deserialize_binary(data),
+        _ => Macaroon::deserialize(data),
+    });
+    new_macaroon(result, err)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_inspect_size_hint(m: *const macaroon) -> usize {
+    m.as_ref().map_or(0, |m| m.inner.inspect().len() + 1)
+}
+
+/// Write the human-readable form of `m` (see [Macaroon::inspect]) into `data`, NUL-terminated
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_inspect(
+    m: *const macaroon,
+    data: *mut c_char,
+    data_sz: usize,
+    err: *mut macaroon_returncode,
+) -> c_int {
+    match deref(m) {
+        Ok(m) if copy_out(m.inner.inspect().as_bytes(), data as *mut c_uchar, data_sz) => 0,
+        Ok(_) => {
+            set_err(err, MACAROON_BUF_TOO_SMALL);
+            -1
+        }
+        Err(e) => status(Err(e), err),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_copy(
+    m: *const macaroon,
+    err: *mut macaroon_returncode,
+) -> *mut macaroon {
+    new_macaroon(deref(m).map(|m| m.inner.clone()), err)
+}
+
+/// `0` if `m` and `n` are the same macaroon
+#[no_mangle]
+pub unsafe extern "C" fn macaroon_cmp(m: *const macaroon, n: *const macaroon) -> c_int {
+    match (m.as_ref(), n.as_ref()) {
+        (Some(m), Some(n)) if m.inner == n.inner => 0,
+        _ => 1,
+    }
+}
+
+/// The name of an error code, eg `"MACAROON_INVALID"`
+#[no_mangle]
+pub extern "C" fn macaroon_error(err: c_int) -> *const c_char {
+    let name: &'static [u8] = match err {
+        2048 => b"MACAROON_SUCCESS\0",
+        2049 => b"MACAROON_OUT_OF_MEMORY\0",
+        2050 => b"MACAROON_HASH_FAILED\0",
+        2051 => b"MACAROON_INVALID\0",
+        2052 => b"MACAROON_TOO_MANY_CAVEATS\0",
+        2053 => b"MACAROON_CYCLE\0",
+        2054 => b"MACAROON_BUF_TOO_SMALL\0",
+        2055 => b"MACAROON_NOT_AUTHORIZED\0",
+        2056 => b"MACAROON_NO_JSON_SUPPORT\0",
+        2057 => b"MACAROON_UNSUPPORTED_FORMAT\0",
+        _ => b"unrecognized error code\0",
+    };
+    name.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    const KEY: &[u8] = b"this is our super secret key; only we should know it";
+
+    unsafe fn create(location: &[u8], key: &[u8], id: &[u8]) -> *mut macaroon {
+        let mut err = MACAROON_SUCCESS;
+        let m = macaroon_create(
+            location.as_ptr(),
+            location.len(),
+            key.as_ptr(),
+            key.len(),
+            id.as_ptr(),
+            id.len(),
+            &mut err,
+        );
+        assert_eq!(MACAROON_SUCCESS, err);
+        m
+    }
+
+    unsafe fn signature_hex(m: *const macaroon) -> String {
+        let (mut sig, mut sig_sz) = (ptr::null(), 0);
+        macaroon_signature(m, &mut sig, &mut sig_sz);
+        slice::from_raw_parts(sig, sig_sz)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    unsafe fn serialize_to_vec(m: *const macaroon, f: macaroon_format) -> Vec<u8> {
+        let mut err = MACAROON_SUCCESS;
+        let mut buf = vec![0; macaroon_serialize_size_hint(m, f)];
+        let len = macaroon_serialize(m, f, buf.as_mut_ptr(), buf.len(), &mut err);
+        assert_eq!(MACAROON_SUCCESS, err);
+        buf.truncate(len);
+        buf
+    }
+
+    unsafe extern "C" fn check_account(
+        f: *mut c_void,
+        pred: *const c_uchar,
+        pred_sz: usize,
+    ) -> c_int {
+        let expected = &*(f as *const &[u8]);
+        if slice::from_raw_parts(pred, pred_sz) == *expected {
+            0
+        } else {
+            -1
+        }
+    }
+
+    #[test]
+    fn test_libmacaroons_example() {
+        unsafe {
+            let m = create(b"http://mybank/", KEY, b"we used our secret key");
+            assert_eq!(0, macaroon_validate(m));
+            assert_eq!(
+                "e3d9e02908526c4c0039ae15114115d97fdd68bf2ba379b342aaf0f617d0552f",
+                signature_hex(m)
+            );
+            let predicate = b"account = 3735928559";
+            let mut err = MACAROON_SUCCESS;
+            let n =
+                macaroon_add_first_party_caveat(m, predicate.as_ptr(), predicate.len(), &mut err);
+            assert_eq!(
+                "1efe4763f290dbce0c1d08477367e11f4eee456a64933cf662d79772dbb82128",
+                signature_hex(n)
+            );
+            assert_ne!(0, macaroon_cmp(m, n));
+
+            let mut inspected = vec![0 as c_char; macaroon_inspect_size_hint(n)];
+            assert_eq!(
+                0,
+                macaroon_inspect(n, inspected.as_mut_ptr(), inspected.len(), &mut err)
+            );
+            let inspected = CStr::from_ptr(inspected.as_ptr()).to_str().unwrap();
+            assert!(inspected.starts_with("location http://mybank/\n"));
+            assert!(inspected.contains("\ncid account = 3735928559\n"));
+
+            for f in [MACAROON_V1, MACAROON_V2, MACAROON_V2J] {
+                let data = serialize_to_vec(n, f);
+                let d = macaroon_deserialize(data.as_ptr(), data.len(), &mut err);
+                assert_eq!(MACAROON_SUCCESS, err);
+                assert_eq!(0, macaroon_cmp(n, d));
+                macaroon_destroy(d);
+            }
+
+            let v = macaroon_verifier_create();
+            assert_eq!(
+                -1,
+                macaroon_verify(v, n, KEY.as_ptr(), KEY.len(), ptr::null_mut(), 0, &mut err)
+            );
+            assert_eq!(MACAROON_NOT_AUTHORIZED, err);
+            let mut expected: &[u8] = predicate;
+            macaroon_verifier_satisfy_general(
+                v,
+                Some(check_account),
+                &mut expected as *mut &[u8] as *mut c_void,
+                &mut err,
+            );
+            err = MACAROON_SUCCESS;
+            assert_eq!(
+                0,
+                macaroon_verify(v, n, KEY.as_ptr(), KEY.len(), ptr::null_mut(), 0, &mut err)
+            );
+            assert_eq!(MACAROON_SUCCESS, err);
+
+            macaroon_verifier_destroy(v);
+            macaroon_destroy(n);
+            macaroon_destroy(m);
+        }
+    }
+
+    #[test]
+    fn test_third_party() {
+        unsafe {
+            let mut err = MACAROON_SUCCESS;
+            let m = create(b"http://mybank/", KEY, b"we used our other secret key");
+            let (cl, caveat_key, cid) = (
+                b"http://auth.mybank/",
+                b"4; guaranteed random by a fair toss of the dice",
+                b"this was how we remind auth of key/pred",
+            );
+            let n = macaroon_add_third_party_caveat(
+                m,
+                cl.as_ptr(),
+                cl.len(),
+                caveat_key.as_ptr(),
+                caveat_key.len(),
+                cid.as_ptr(),
+                cid.len(),
+                &mut err,
+            );
+            assert_eq!(1, macaroon_num_third_party_caveats(n));
+            let (mut loc, mut loc_sz, mut id, mut id_sz) = (ptr::null(), 0, ptr::null(), 0);
+            assert_eq!(
+                0,
+                macaroon_third_party_caveat(n, 0, &mut loc, &mut loc_sz, &mut id, &mut id_sz)
+            );
+            assert_eq!(&cl[..], slice::from_raw_parts(loc, loc_sz));
+            assert_eq!(&cid[..], slice::from_raw_parts(id, id_sz));
+            assert_eq!(
+                -1,
+                macaroon_third_party_caveat(n, 1, &mut loc, &mut loc_sz, &mut id, &mut id_sz)
+            );
+
+            let d = create(b"http://auth.mybank/", caveat_key, cid);
+            let mut bound = macaroon_prepare_for_request(n, d, &mut err);
+            let v = macaroon_verifier_create();
+            assert_eq!(
+                0,
+                macaroon_verify(v, n, KEY.as_ptr(), KEY.len(), &mut bound, 1, &mut err)
+            );
+            assert_eq!(MACAROON_SUCCESS, err);
+            assert_eq!(
+                -1,
+                macaroon_verify(v, n, KEY.as_ptr(), KEY.len(), ptr::null_mut(), 0, &mut err)
+            );
+            assert_eq!(MACAROON_NOT_AUTHORIZED, err);
+
+            for handle in [m, n, d, bound] {
+                macaroon_destroy(handle);
+            }
+            macaroon_verifier_destroy(v);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let mut err = MACAROON_SUCCESS;
+            assert!(macaroon_deserialize(b"garbage".as_ptr(), 7, &mut err).is_null());
+            assert_eq!(MACAROON_INVALID, err);
+            assert!(
+                macaroon_add_first_party_caveat(ptr::null(), ptr::null(), 0, &mut err).is_null()
+            );
+            assert_eq!(MACAROON_INVALID, err);
+            type Getter = unsafe extern "C" fn(*const macaroon, *mut *const c_uchar, *mut usize);
+            let getters: [Getter; 3] = [macaroon_location, macaroon_identifier, macaroon_signature];
+            for getter in getters {
+                let (mut data, mut data_sz) = (b"x".as_ptr(), 1);
+                getter(ptr::null(), &mut data, &mut data_sz);
+                assert!(data.is_null());
+                assert_eq!(0, data_sz);
+            }
+
+            let m = create(b"", KEY, b"keyid");
+            let (mut loc, mut loc_sz) = (ptr::null(), 1);
+            macaroon_location(m, &mut loc, &mut loc_sz);
+            assert_eq!(0, loc_sz);
+            let mut buf = [0; 8];
+            assert_eq!(
+                0,
+                macaroon_serialize(m, MACAROON_V1, buf.as_mut_ptr(), buf.len(), &mut err)
+            );
+            assert_eq!(MACAROON_BUF_TOO_SMALL, err);
+            assert_eq!(
+                0,
+                macaroon_serialize(m, 7, buf.as_mut_ptr(), buf.len(), &mut err)
+            );
+            assert_eq!(MACAROON_UNSUPPORTED_FORMAT, err);
+
+            // binary V2 tokens are checked against the deserialization limits too
+            let predicate = vec![b'x'; 70_000];
+            let n =
+                macaroon_add_first_party_caveat(m, predicate.as_ptr(), predicate.len(), &mut err);
+            assert!(!n.is_null());
+            assert_eq!(0, macaroon_serialize_size_hint(n, MACAROON_V2));
+            let mut buf = vec![0; 80_000];
+            assert_eq!(
+                0,
+                macaroon_serialize(n, MACAROON_V2, buf.as_mut_ptr(), buf.len(), &mut err)
+            );
+            assert_eq!(MACAROON_INVALID, err);
+            macaroon_destroy(n);
+            macaroon_destroy(m);
+
+            let name = CStr::from_ptr(macaroon_error(MACAROON_BUF_TOO_SMALL as c_int));
+            assert_eq!("MACAROON_BUF_TOO_SMALL", name.to_str().unwrap());
+        }
+    }
+}
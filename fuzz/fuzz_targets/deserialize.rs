@@ -0,0 +1,13 @@
+//! Any input, as given to `Macaroon::deserialize` by a server reading tokens from requests
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use macaroon::{Format, Macaroon};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(macaroon) = Macaroon::deserialize(data) {
+        // whatever was accepted can be written out again
+        let _ = macaroon.serialize(Format::V2);
+        let _ = macaroon.serialize(Format::V2JSON);
+    }
+});
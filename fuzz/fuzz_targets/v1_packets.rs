@@ -0,0 +1,18 @@
+//! The V1 packet framing (and V2 field framing), through `RawToken`, which reads as many fields
+//! as it can rather than stopping at the first unexpected one
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use macaroon::RawToken;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(raw) = RawToken::parse(data) {
+        let offsets = raw.fields().iter().map(|f| f.offset());
+        let mut previous = None;
+        for offset in offsets {
+            assert!(offset < data.len());
+            assert!(previous.map_or(true, |p| p < offset));
+            previous = Some(offset);
+        }
+    }
+});
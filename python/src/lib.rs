@@ -0,0 +1,379 @@
+//! Python bindings for the `macaroon` crate.
+//!
+//! The module mirrors the parts of the [pymacaroons](https://github.com/ecordell/pymacaroons) API
+//! that applications actually call, so code migrating off pymacaroons can mostly swap the import:
+//!
+//! ```python
+//! from macaroon_rs import Macaroon, Verifier
+//!
+//! m = Macaroon(location="http://example.org/", identifier="keyid", key="secret")
+//! m.add_first_party_caveat("account = 3735928559")
+//! token = m.serialize()
+//!
+//! v = Verifier()
+//! v.satisfy_exact("account = 3735928559")
+//! v.verify(Macaroon.deserialize(token), "secret")
+//! ```
+//!
+//! Keys are passed as `str` or `bytes` and derived with the same key generator as pymacaroons and
+//! libmacaroons. Serialization defaults to the V1 format, as in pymacaroons; `deserialize`
+//! accepts any format.
+
+use macaroon::{ByteString, Caveat, Format, MacaroonError, MacaroonKey};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+create_exception!(macaroon_rs, MacaroonException, PyException);
+create_exception!(macaroon_rs, MacaroonInitException, MacaroonException);
+create_exception!(
+    macaroon_rs,
+    MacaroonDeserializationException,
+    MacaroonException
+);
+create_exception!(
+    macaroon_rs,
+    MacaroonVerificationFailedException,
+    MacaroonException
+);
+create_exception!(
+    macaroon_rs,
+    MacaroonInvalidSignatureException,
+    MacaroonVerificationFailedException
+);
+create_exception!(
+    macaroon_rs,
+    MacaroonUnmetCaveatException,
+    MacaroonVerificationFailedException
+);
+
+fn to_py_err(error: MacaroonError) -> PyErr {
+    let message = error.to_string();
+    match error {
+        MacaroonError::IncompleteMacaroon(_) | MacaroonError::IncompleteCaveat(_) => {
+            MacaroonInitException::new_err(message)
+        }
+        MacaroonError::DeserializationError(_)
+        | MacaroonError::InvalidBase64(_)
+        | MacaroonError::InvalidPacketHeader { .. }
+        | MacaroonError::UnsupportedVersion(_)
+        | MacaroonError::SignatureLengthMismatch { .. } => {
+            MacaroonDeserializationException::new_err(message)
+        }
+        MacaroonError::InvalidSignature => MacaroonInvalidSignatureException::new_err(message),
+        MacaroonError::CaveatNotSatisfied(_) => MacaroonUnmetCaveatException::new_err(message),
+        MacaroonError::DischargeNotUsed
+        | MacaroonError::DischargeNotBound(_)
+        | MacaroonError::DischargeBoundTwice(_)
+        | MacaroonError::LimitExceeded(_)
+        | MacaroonError::Revoked(_)
+        | MacaroonError::LocationMismatch(_) => {
+            MacaroonVerificationFailedException::new_err(message)
+        }
+        _ => MacaroonException::new_err(message),
+    }
+}
+
+/// Accept `str` (encoded as UTF-8) or `bytes`, like pymacaroons does
+fn to_bytes(value: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    if let Ok(s) = value.downcast::<PyString>() {
+        return Ok(s.to_str()?.as_bytes().to_vec());
+    }
+    Ok(value.downcast::<PyBytes>()?.as_bytes().to_vec())
+}
+
+fn to_key(value: &Bound<'_, PyAny>) -> PyResult<MacaroonKey> {
+    Ok(MacaroonKey::generate(&to_bytes(value)?))
+}
+
+fn parse_format(name: &str) -> PyResult<Format> {
+    match name {
+        "v1" | "binary" => Ok(Format::V1),
+        "v2" => Ok(Format::V2),
+        "v2json" | "json" => Ok(Format::V2JSON),
+        _ => Err(MacaroonException::new_err(format!(
+            "unknown serialization format {:?}",
+            name
+        ))),
+    }
+}
+
+fn lossy(bytes: &ByteString) -> String {
+    String::from_utf8_lossy(bytes.as_ref()).into_owned()
+}
+
+/// A single caveat, as returned by `Macaroon.caveats`
+#[pyclass(module = "macaroon_rs", name = "Caveat")]
+#[derive(Clone)]
+struct PyCaveat(Caveat);
+
+#[pymethods]
+impl PyCaveat {
+    /// The predicate of a first-party caveat, or the identifier of a third-party caveat
+    #[getter]
+    fn caveat_id(&self) -> String {
+        match &self.0 {
+            Caveat::FirstParty(fp) => lossy(fp.predicate_ref()),
+            Caveat::ThirdParty(tp) => lossy(tp.id_ref()),
+        }
+    }
+
+    #[getter]
+    fn caveat_id_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        match &self.0 {
+            Caveat::FirstParty(fp) => PyBytes::new(py, fp.predicate_ref().as_ref()),
+            Caveat::ThirdParty(tp) => PyBytes::new(py, tp.id_ref().as_ref()),
+        }
+    }
+
+    #[getter]
+    fn location(&self) -> Option<String> {
+        match &self.0 {
+            Caveat::FirstParty(_) => None,
+            Caveat::ThirdParty(tp) => Some(tp.location()),
+        }
+    }
+
+    #[getter]
+    fn verification_key_id<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        match &self.0 {
+            Caveat::FirstParty(_) => None,
+            Caveat::ThirdParty(tp) => Some(PyBytes::new(py, tp.verifier_id_ref().as_ref())),
+        }
+    }
+
+    fn first_party(&self) -> bool {
+        matches!(self.0, Caveat::FirstParty(_))
+    }
+
+    fn third_party(&self) -> bool {
+        matches!(self.0, Caveat::ThirdParty(_))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Caveat({:?})", self.caveat_id())
+    }
+}
+
+#[pyclass(module = "macaroon_rs", name = "Macaroon")]
+#[derive(Clone)]
+struct PyMacaroon(macaroon::Macaroon);
+
+#[pymethods]
+impl PyMacaroon {
+    #[new]
+    #[pyo3(signature = (location=None, identifier=None, key=None))]
+    fn new(
+        location: Option<&str>,
+        identifier: Option<&Bound<'_, PyAny>>,
+        key: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<Self> {
+        let identifier = match identifier {
+            Some(identifier) => to_bytes(identifier)?,
+            None => return Err(MacaroonInitException::new_err("Must supply identifier")),
+        };
+        let key = match key {
+            Some(key) => to_key(key)?,
+            None => return Err(MacaroonInitException::new_err("Must supply key")),
+        };
+        macaroon::Macaroon::create(location.map(String::from), &key, identifier.into())
+            .map(PyMacaroon)
+            .map_err(to_py_err)
+    }
+
+    #[staticmethod]
+    fn deserialize(serialized: &Bound<'_, PyAny>) -> PyResult<Self> {
+        macaroon::Macaroon::deserialize(to_bytes(serialized)?)
+            .map(PyMacaroon)
+            .map_err(to_py_err)
+    }
+
+    #[pyo3(signature = (format="v1"))]
+    fn serialize(&self, format: &str) -> PyResult<String> {
+        self.0.serialize(parse_format(format)?).map_err(to_py_err)
+    }
+
+    #[getter]
+    fn location(&self) -> Option<String> {
+        self.0.location()
+    }
+
+    #[getter]
+    fn identifier(&self) -> String {
+        lossy(&self.0.identifier())
+    }
+
+    #[getter]
+    fn identifier_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.0.identifier().as_ref())
+    }
+
+    /// Hex-encoded signature, as in pymacaroons
+    #[getter]
+    fn signature(&self) -> String {
+        self.0
+            .signature()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    #[getter]
+    fn signature_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.signature())
+    }
+
+    #[getter]
+    fn caveats(&self) -> Vec<PyCaveat> {
+        self.0.caveats().into_iter().map(PyCaveat).collect()
+    }
+
+    fn first_party_caveats(&self) -> Vec<PyCaveat> {
+        self.0
+            .first_party_caveats()
+            .into_iter()
+            .map(PyCaveat)
+            .collect()
+    }
+
+    fn third_party_caveats(&self) -> Vec<PyCaveat> {
+        self.0
+            .third_party_caveats()
+            .into_iter()
+            .map(PyCaveat)
+            .collect()
+    }
+
+    fn add_first_party_caveat(&mut self, predicate: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.0.add_first_party_caveat(to_bytes(predicate)?.into());
+        Ok(())
+    }
+
+    fn add_third_party_caveat(
+        &mut self,
+        location: &str,
+        key: &Bound<'_, PyAny>,
+        key_id: &Bound<'_, PyAny>,
+    ) -> PyResult<()> {
+        self.0
+            .add_third_party_caveat(location, &to_key(key)?, to_bytes(key_id)?.into());
+        Ok(())
+    }
+
+    /// Return a copy of `discharge_macaroon` bound to this macaroon, ready to send with a request
+    fn prepare_for_request(&self, discharge_macaroon: &PyMacaroon) -> PyMacaroon {
+        let mut discharge = discharge_macaroon.0.clone();
+        self.0.bind(&mut discharge);
+        PyMacaroon(discharge)
+    }
+
+    fn copy(&self) -> PyMacaroon {
+        self.clone()
+    }
+
+    /// Human-readable dump of the macaroon, in the same layout as pymacaroons
+    fn inspect(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(location) = self.0.location() {
+            lines.push(format!("location {}", location));
+        }
+        lines.push(format!("identifier {}", self.identifier()));
+        for caveat in self.0.caveats() {
+            match &caveat {
+                Caveat::FirstParty(fp) => lines.push(format!("cid {}", lossy(fp.predicate_ref()))),
+                Caveat::ThirdParty(tp) => {
+                    lines.push(format!("cid {}", lossy(tp.id_ref())));
+                    lines.push(format!("vid {}", tp.verifier_id_ref()));
+                    lines.push(format!("cl {}", tp.location()));
+                }
+            }
+        }
+        lines.push(format!("signature {}", self.signature()));
+        lines.join("\n")
+    }
+
+    fn __eq__(&self, other: &PyMacaroon) -> bool {
+        self.0 == other.0
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Macaroon(identifier={:?})", self.identifier())
+    }
+}
+
+#[pyclass(module = "macaroon_rs", name = "Verifier")]
+#[derive(Default)]
+struct PyVerifier(macaroon::Verifier);
+
+#[pymethods]
+impl PyVerifier {
+    #[new]
+    fn new() -> Self {
+        PyVerifier::default()
+    }
+
+    fn satisfy_exact(&mut self, predicate: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.0.satisfy_exact(to_bytes(predicate)?.into());
+        Ok(())
+    }
+
+    /// Register `func(predicate: str) -> bool`; exceptions raised by `func` count as unsatisfied
+    fn satisfy_general(&mut self, func: PyObject) {
+        self.0.satisfy_general(move |predicate: &ByteString| {
+            Python::with_gil(|py| {
+                func.call1(py, (lossy(predicate),))
+                    .and_then(|result| result.is_truthy(py))
+                    .unwrap_or(false)
+            })
+        });
+    }
+
+    #[pyo3(signature = (macaroon, key, discharge_macaroons=None))]
+    fn verify(
+        &self,
+        macaroon: &PyMacaroon,
+        key: &Bound<'_, PyAny>,
+        discharge_macaroons: Option<Vec<PyMacaroon>>,
+    ) -> PyResult<bool> {
+        let discharges = discharge_macaroons
+            .unwrap_or_default()
+            .into_iter()
+            .map(|m| m.0)
+            .collect();
+        self.0
+            .verify(&macaroon.0, &to_key(key)?, discharges)
+            .map_err(to_py_err)?;
+        Ok(true)
+    }
+}
+
+#[pymodule]
+fn macaroon_rs(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    macaroon::initialize().map_err(to_py_err)?;
+    m.add_class::<PyMacaroon>()?;
+    m.add_class::<PyCaveat>()?;
+    m.add_class::<PyVerifier>()?;
+    m.add("MacaroonException", py.get_type::<MacaroonException>())?;
+    m.add(
+        "MacaroonInitException",
+        py.get_type::<MacaroonInitException>(),
+    )?;
+    m.add(
+        "MacaroonDeserializationException",
+        py.get_type::<MacaroonDeserializationException>(),
+    )?;
+    m.add(
+        "MacaroonVerificationFailedException",
+        py.get_type::<MacaroonVerificationFailedException>(),
+    )?;
+    m.add(
+        "MacaroonInvalidSignatureException",
+        py.get_type::<MacaroonInvalidSignatureException>(),
+    )?;
+    m.add(
+        "MacaroonUnmetCaveatException",
+        py.get_type::<MacaroonUnmetCaveatException>(),
+    )?;
+    Ok(())
+}
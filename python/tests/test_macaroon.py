@@ -0,0 +1,41 @@
+import pytest
+
+from macaroon_rs import (
+    Macaroon,
+    MacaroonInvalidSignatureException,
+    MacaroonUnmetCaveatException,
+    Verifier,
+)
+
+KEY = "this is our super secret key; only we should know it"
+
+
+def test_signature_matches_pymacaroons():
+    m = Macaroon(location="http://mybank/", identifier="we used our secret key", key=KEY)
+    assert m.signature == "e3d9e02908526c4c0039ae15114115d97fdd68bf2ba379b342aaf0f617d0552f"
+
+
+def test_roundtrip_and_verify():
+    m = Macaroon(location="http://mybank/", identifier="we used our secret key", key=KEY)
+    m.add_first_party_caveat("account = 3735928559")
+    m.add_third_party_caveat("http://auth.mybank/", "caveat key", "caveat id")
+    discharge = Macaroon(location="http://auth.mybank/", identifier="caveat id", key="caveat key")
+    bound = m.prepare_for_request(discharge)
+
+    for fmt in ("v1", "v2", "v2json"):
+        parsed = Macaroon.deserialize(m.serialize(format=fmt))
+        assert parsed == m
+        v = Verifier()
+        v.satisfy_general(lambda predicate: predicate.startswith("account = "))
+        assert v.verify(parsed, KEY, discharge_macaroons=[bound])
+
+
+def test_failures():
+    m = Macaroon(identifier="id", key=KEY)
+    m.add_first_party_caveat("account = 3735928559")
+    with pytest.raises(MacaroonUnmetCaveatException):
+        Verifier().verify(m, KEY)
+    v = Verifier()
+    v.satisfy_exact("account = 3735928559")
+    with pytest.raises(MacaroonInvalidSignatureException):
+        v.verify(m, "wrong key")
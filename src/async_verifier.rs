@@ -0,0 +1,341 @@
+use crate::bakery::FirstPartyCaveatChecker;
+use crate::report::SatisfiedBy;
+use crate::verifier::{self, VerifierOptions, VerifyContext};
+use crate::{ByteString, HmacSha256, Macaroon, MacaroonKey, Result, SignatureScheme, Verifier};
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The future returned by an async satisfier (see [AsyncVerifier::satisfy_general_async])
+pub type SatisfierFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+type AsyncSatisfier = Box<dyn Fn(ByteString) -> SatisfierFuture + Send + Sync>;
+
+/// A [Verifier] which can also satisfy caveats asynchronously, eg by looking up revocations or
+/// entitlements in a database
+///
+/// The signatures of the macaroon and its discharges are checked first, and each discharge
+/// matched to the third-party caveat it discharges. Only then are the first-party caveats the
+/// wrapped [Verifier] doesn't satisfy given to the async satisfiers, so they never see caveats
+/// of forged, unbound or unused discharges. Finally the macaroon is verified as by
+/// [Verifier::verify], treating the caveats they accepted as satisfied.
+///
+/// ```rust
+/// use macaroon::{AsyncVerifier, Macaroon, MacaroonKey, Verifier};
+///
+/// async fn is_entitled(predicate: &[u8]) -> bool {
+///     // eg a database query
+///     predicate == b"plan = pro"
+/// }
+///
+/// # async fn run() {
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_first_party_caveat("plan = pro".into());
+///
+/// let mut verifier = AsyncVerifier::new(Verifier::default());
+/// verifier.satisfy_general_async(|predicate| async move { is_entitled(predicate.as_ref()).await });
+/// verifier.verify(&macaroon, &key, vec![]).await.unwrap();
+/// # }
+/// ```
+pub struct AsyncVerifier {
+    verifier: Verifier,
+    general: Vec<AsyncSatisfier>,
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AsyncVerifier>();
+};
+
+impl Default for AsyncVerifier {
+    fn default() -> Self {
+        AsyncVerifier::new(Verifier::default())
+    }
+}
+
+impl From<Verifier> for AsyncVerifier {
+    fn from(verifier: Verifier) -> Self {
+        AsyncVerifier::new(verifier)
+    }
+}
+
+impl AsyncVerifier {
+    /// Wrap `verifier`, whose satisfiers are tried before the async ones
+    pub fn new(verifier: Verifier) -> AsyncVerifier {
+        AsyncVerifier {
+            verifier,
+            general: Vec::new(),
+        }
+    }
+
+    pub fn verifier(&self) -> &Verifier {
+        &self.verifier
+    }
+
+    pub fn verifier_mut(&mut self) -> &mut Verifier {
+        &mut self.verifier
+    }
+
+    /// Satisfy caveats for which `f` resolves to `true`
+    pub fn satisfy_general_async<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(ByteString) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.general
+            .push(Box::new(move |predicate| Box::pin(f(predicate))))
+    }
+
+    /// Verify `m` with `key` and `discharges`, like [Verifier::verify]
+    ///
+    /// # Errors
+    ///
+    /// The errors of [Verifier::verify].
+    pub async fn verify(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+    ) -> Result<()> {
+        self.verify_with_options(m, key, discharges, &VerifierOptions::default())
+            .await
+    }
+
+    /// Like [AsyncVerifier::verify], with [VerifierOptions] controlling limits and strictness
+    pub async fn verify_with_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        options: &VerifierOptions,
+    ) -> Result<()> {
+        self.verify_with_scheme_and_options(m, key, discharges, &HmacSha256, options)
+            .await
+    }
+
+    /// Like [AsyncVerifier::verify_with_options], for macaroons signed with a
+    /// [SignatureScheme] other than the default (see [Verifier::verify_with_scheme])
+    pub async fn verify_with_scheme_and_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        scheme: &dyn SignatureScheme,
+        options: &VerifierOptions,
+    ) -> Result<()> {
+        // Authenticate everything first, collecting the caveats left to the async satisfiers
+        let pending = PendingChecker {
+            verifier: &self.verifier,
+            clock_skew: options.clock_skew(),
+            pending: Mutex::new(Vec::new()),
+        };
+        verifier::verify_with_checker(
+            &self.context(&pending, scheme, options),
+            m,
+            key,
+            discharges.clone(),
+        )?;
+        let pending = pending
+            .pending
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let mut satisfied: HashSet<ByteString> = HashSet::new();
+        for predicate in pending {
+            for f in self.general.iter() {
+                if f(predicate.clone()).await {
+                    satisfied.insert(predicate);
+                    break;
+                }
+            }
+        }
+
+        let checker = AsyncChecker {
+            verifier: &self.verifier,
+            clock_skew: options.clock_skew(),
+            satisfied: &satisfied,
+        };
+        verifier::verify_with_checker(&self.context(&checker, scheme, options), m, key, discharges)
+    }
+
+    fn context<'a>(
+        &'a self,
+        checker: &'a dyn FirstPartyCaveatChecker,
+        scheme: &'a dyn SignatureScheme,
+        options: &'a VerifierOptions,
+    ) -> VerifyContext<'a> {
+        VerifyContext {
+            checker,
+            options,
+            scheme,
+            decryptor: self.verifier.decryptor(),
+            binding: self.verifier.binding(),
+            revocation: self.verifier.revocation_checker(),
+            location: self.verifier.expected_location(),
+        }
+    }
+}
+
+/// Accepts every caveat, recording those the [Verifier] doesn't satisfy (once each, in the
+/// order they are reached) for the async satisfiers
+struct PendingChecker<'a> {
+    verifier: &'a Verifier,
+    clock_skew: Duration,
+    pending: Mutex<Vec<ByteString>>,
+}
+
+impl FirstPartyCaveatChecker for PendingChecker<'_> {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        if self
+            .verifier
+            .find_satisfier_with_skew(&(), predicate, self.clock_skew)
+            .is_err()
+        {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            if !pending.contains(predicate) {
+                pending.push(predicate.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        self.check_first_party_caveat(predicate)
+            .map(|()| SatisfiedBy::Checker)
+    }
+}
+
+/// Checks caveats with a [Verifier], and accepts those already satisfied asynchronously
+struct AsyncChecker<'a> {
+    verifier: &'a Verifier,
+    clock_skew: Duration,
+    satisfied: &'a HashSet<ByteString>,
+}
+
+impl FirstPartyCaveatChecker for AsyncChecker<'_> {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        self.find_satisfier(predicate).map(|_| ())
+    }
+
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        if self.satisfied.contains(predicate) {
+            return Ok(SatisfiedBy::Checker);
+        }
+        self.verifier
+            .find_satisfier_with_skew(&(), predicate, self.clock_skew)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncVerifier;
+    use crate::test_util::block_on;
+    use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, Verifier, VerifierOptions};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_async_satisfiers() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_first_party_caveat("entitled = pro".into());
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        discharge.add_first_party_caveat("fresh = yes".into());
+        macaroon.bind(&mut discharge);
+
+        let mut sync = Verifier::default();
+        sync.satisfy_exact("account = 3735928559".into());
+        let mut verifier = AsyncVerifier::new(sync);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        verifier.satisfy_general_async(move |predicate: ByteString| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move { predicate.as_ref().starts_with(b"entitled ") }
+        });
+
+        // the discharge's caveat isn't satisfied yet
+        assert!(matches!(
+            block_on(verifier.verify(&macaroon, &root_key, vec![discharge.clone()])),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+        // only caveats the sync verifier can't satisfy are checked asynchronously
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        verifier.satisfy_general_async(|predicate: ByteString| async move {
+            predicate == ByteString::from("fresh = yes")
+        });
+        block_on(verifier.verify(&macaroon, &root_key, vec![discharge.clone()])).unwrap();
+
+        // signatures are still checked, and the future can move between threads
+        let future = verifier.verify(&macaroon, &caveat_key, vec![discharge.clone()]);
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&future);
+        assert!(block_on(future).is_err());
+
+        // options apply
+        let options = VerifierOptions::builder().max_depth(0).build();
+        assert!(matches!(
+            block_on(verifier.verify_with_options(
+                &macaroon,
+                &root_key,
+                vec![discharge.clone()],
+                &options
+            )),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_async_satisfiers_authenticated_only() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_first_party_caveat("entitled = pro".into());
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        discharge.add_first_party_caveat("lookup = discharge".into());
+        let forged = Macaroon::create(None, &root_key, "forged".into()).unwrap();
+        let mut unbound = discharge.clone();
+        unbound.add_first_party_caveat("lookup = unbound".into());
+        macaroon.bind(&mut discharge);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let mut verifier = AsyncVerifier::default();
+        verifier.satisfy_general_async(move |predicate: ByteString| {
+            recorder.lock().unwrap().push(predicate);
+            async { true }
+        });
+        let seen = move || std::mem::take(&mut *seen.lock().unwrap());
+
+        // nothing reaches the async satisfiers before the macaroons are authenticated
+        assert!(
+            block_on(verifier.verify(&macaroon, &caveat_key, vec![discharge.clone()])).is_err()
+        );
+        assert!(block_on(verifier.verify(&macaroon, &root_key, vec![unbound])).is_err());
+        let mut with_forged = vec![discharge.clone(), forged];
+        assert!(block_on(verifier.verify(&macaroon, &root_key, with_forged.clone())).is_err());
+        assert!(seen().is_empty());
+
+        // unused discharges aren't looked at when they are allowed
+        let options = VerifierOptions::builder()
+            .allow_unused_discharges(true)
+            .build();
+        with_forged[1].add_first_party_caveat("lookup = forged".into());
+        block_on(verifier.verify_with_options(&macaroon, &root_key, with_forged, &options))
+            .unwrap();
+        assert_eq!(
+            vec![
+                ByteString::from("entitled = pro"),
+                ByteString::from("lookup = discharge")
+            ],
+            seen()
+        );
+    }
+}
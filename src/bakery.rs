@@ -0,0 +1,474 @@
+use crate::caveat::{self, Caveat};
+use crate::crypto;
+use crate::identifier::{IdentifierGenerator, UuidV4Generator};
+use crate::mint_store::{MintRecord, MintStore};
+use crate::report::SatisfiedBy;
+use crate::timestamp;
+use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, Result, Verifier};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Decides whether first-party caveats are satisfied, during verification
+///
+/// This is the common interface between a configured [Verifier] (which implements it using its
+/// exact and general satisfiers) and higher-level checkers, so policy can be defined once and
+/// used from either side; see [Verifier::satisfy_checker] for the other direction.
+pub trait FirstPartyCaveatChecker: Send + Sync {
+    /// Returns [MacaroonError::CaveatNotSatisfied] if `predicate` isn't satisfied
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()>;
+
+    /// Like [FirstPartyCaveatChecker::check_first_party_caveat], saying how `predicate` was
+    /// satisfied, for [VerificationReport](crate::VerificationReport)s. By default, this is
+    /// [SatisfiedBy::Checker].
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        self.check_first_party_caveat(predicate)
+            .map(|()| SatisfiedBy::Checker)
+    }
+}
+
+impl FirstPartyCaveatChecker for Verifier {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        self.check_satisfied(&(), predicate)
+    }
+
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        Verifier::find_satisfier(self, &(), predicate)
+    }
+}
+
+impl<C: FirstPartyCaveatChecker + ?Sized> FirstPartyCaveatChecker for Arc<C> {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        (**self).check_first_party_caveat(predicate)
+    }
+
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        (**self).find_satisfier(predicate)
+    }
+}
+
+impl<T> Verifier<T> {
+    /// Satisfy any first-party caveat accepted by `checker`, eg to reuse a higher-level checker's
+    /// policy, or another [Verifier], from this one
+    ///
+    /// ```rust
+    /// use macaroon::{FirstPartyCaveatChecker, Verifier};
+    ///
+    /// let mut accounts = Verifier::default();
+    /// accounts.satisfy_exact("account = 3735928559".into());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_exact("user = alice".into());
+    /// verifier.satisfy_checker(accounts);
+    /// assert!(verifier
+    ///     .check_first_party_caveat(&"account = 3735928559".into())
+    ///     .is_ok());
+    /// ```
+    pub fn satisfy_checker<C>(&mut self, checker: C)
+    where
+        C: FirstPartyCaveatChecker + 'static,
+    {
+        self.satisfy_general_with_err(move |predicate| {
+            match checker.check_first_party_caveat(predicate) {
+                Ok(()) => Ok(true),
+                Err(MacaroonError::CaveatNotSatisfied(_)) => Ok(false),
+                Err(e) => Err(e),
+            }
+        });
+    }
+}
+
+/// A list of caveats to add to every macaroon minted by [Bakery::bake] or [Bakery::bake_batch]
+///
+/// First-party caveats are built once, when added to the template, and shared by every token.
+/// Third-party caveats are encrypted per token, since their verifier ID depends on the running
+/// signature.
+#[derive(Clone, Debug, Default)]
+pub struct CaveatTemplate {
+    caveats: Vec<TemplateCaveat>,
+    expiry: Expiry,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Expiry {
+    /// Use the bakery's default TTL, unless there is already an expiry caveat
+    Default,
+    At(SystemTime),
+    Never,
+}
+
+impl Default for Expiry {
+    fn default() -> Self {
+        Expiry::Default
+    }
+}
+
+#[derive(Clone, Debug)]
+enum TemplateCaveat {
+    FirstParty(Caveat),
+    ThirdParty {
+        location: String,
+        key: MacaroonKey,
+        id: ByteString,
+    },
+}
+
+impl CaveatTemplate {
+    pub fn new() -> CaveatTemplate {
+        Default::default()
+    }
+
+    pub fn first_party(mut self, predicate: ByteString) -> Self {
+        self.caveats
+            .push(TemplateCaveat::FirstParty(caveat::new_first_party(
+                predicate,
+            )));
+        self
+    }
+
+    pub fn third_party(mut self, location: &str, key: &MacaroonKey, id: ByteString) -> Self {
+        self.caveats.push(TemplateCaveat::ThirdParty {
+            location: location.to_string(),
+            key: key.clone(),
+            id,
+        });
+        self
+    }
+
+    /// Expire at `at`, instead of after the bakery's default TTL
+    pub fn expires_at(mut self, at: SystemTime) -> Self {
+        self.expiry = Expiry::At(at);
+        self
+    }
+
+    /// Don't add an expiry caveat, even if the bakery has a default TTL
+    pub fn never_expires(mut self) -> Self {
+        self.expiry = Expiry::Never;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.caveats.len()
+    }
+
+    /// Whether the template has an expiry caveat, `time < ...` or bakery's
+    /// `[std:]time-before ...`
+    fn has_expiry_caveat(&self) -> bool {
+        self.caveats.iter().any(|c| match c {
+            TemplateCaveat::FirstParty(Caveat::FirstParty(fp)) => {
+                timestamp::caveat_timestamp(fp.predicate_ref().as_ref()).is_some()
+            }
+            _ => false,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.caveats.is_empty()
+    }
+}
+
+/// Mints macaroons under one root key and location
+///
+/// ```rust
+/// use macaroon::{Bakery, CaveatTemplate, MacaroonKey};
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let bakery = Bakery::new(Some("https://fleet.example/".into()), MacaroonKey::generate(b"key"));
+/// let template = CaveatTemplate::new()
+///     .first_party("fleet = west".into())
+///     .first_party("op = enroll".into());
+/// let tokens = bakery.bake_batch(&template, vec!["device-1".into(), "device-2".into()])?;
+/// assert_eq!(2, tokens.len());
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct Bakery {
+    location: Option<String>,
+    root_key: MacaroonKey,
+    identifiers: Arc<dyn IdentifierGenerator>,
+    store: Option<Arc<dyn MintStore>>,
+    default_ttl: Option<Duration>,
+}
+
+impl fmt::Debug for Bakery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bakery")
+            .field("location", &self.location)
+            .field("store", &self.store.is_some())
+            .field("default_ttl", &self.default_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Bakery {
+    /// Create a bakery, generating identifiers with [UuidV4Generator] by default
+    pub fn new(location: Option<String>, root_key: MacaroonKey) -> Bakery {
+        Bakery {
+            location,
+            root_key,
+            identifiers: Arc::new(UuidV4Generator),
+            store: None,
+            default_ttl: None,
+        }
+    }
+
+    /// Add a `time < ...` expiry caveat (see [expiry_predicate](crate::expiry_predicate)) to
+    /// every minted macaroon, `ttl` after minting
+    ///
+    /// Templates can override this with [CaveatTemplate::expires_at] or
+    /// [CaveatTemplate::never_expires], and it is skipped for templates which already have an
+    /// expiry caveat.
+    pub fn with_default_ttl(mut self, ttl: Duration) -> Bakery {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Record every minted macaroon in `store`
+    pub fn with_mint_store(mut self, store: Arc<dyn MintStore>) -> Bakery {
+        self.store = Some(store);
+        self
+    }
+
+    /// Use `generator` for the identifiers of macaroons minted with [Bakery::mint] and
+    /// third-party caveats added with [Bakery::new_caveat_id]
+    pub fn with_identifier_generator<G>(mut self, generator: G) -> Bakery
+    where
+        G: IdentifierGenerator + 'static,
+    {
+        self.identifiers = Arc::new(generator);
+        self
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// Mint a macaroon with a freshly generated identifier and the caveats from `template`
+    pub fn mint(&self, template: &CaveatTemplate) -> Result<Macaroon> {
+        self.bake(self.identifiers.generate(), template)
+    }
+
+    /// Mint `count` macaroons with freshly generated identifiers; see [Bakery::bake_batch]
+    pub fn mint_batch(&self, template: &CaveatTemplate, count: usize) -> Result<Vec<Macaroon>> {
+        self.bake_batch(template, (0..count).map(|_| self.identifiers.generate()))
+    }
+
+    /// Generate an identifier for a third-party caveat, eg to pass to
+    /// [Macaroon::add_third_party_caveat] or [CaveatTemplate::third_party]
+    pub fn new_caveat_id(&self) -> ByteString {
+        self.identifiers.generate()
+    }
+
+    /// Mint a single macaroon with the caveats from `template`
+    ///
+    /// # Errors
+    ///
+    /// Fails if `identifier` is empty, or recording the macaroon in the mint store fails.
+    pub fn bake(&self, identifier: ByteString, template: &CaveatTemplate) -> Result<Macaroon> {
+        if identifier.0.is_empty() {
+            return Err(MacaroonError::IncompleteMacaroon("no identifier found"));
+        }
+        let mut signature = crypto::hmac(&self.root_key, &identifier);
+        let mut caveats: Vec<Caveat> = Vec::with_capacity(template.caveats.len() + 1);
+        for c in template.caveats.iter() {
+            let c = match c {
+                TemplateCaveat::FirstParty(fp) => fp.clone(),
+                TemplateCaveat::ThirdParty { location, key, id } => {
+                    let vid: Vec<u8> = crypto::encrypt_key(&signature, key);
+                    caveat::new_third_party(id.clone(), ByteString(vid), location)
+                }
+            };
+            signature = c.sign(&signature);
+            caveats.push(c);
+        }
+        let expiry = match template.expiry {
+            Expiry::At(at) => Some(at),
+            Expiry::Never => None,
+            Expiry::Default if template.has_expiry_caveat() => None,
+            Expiry::Default => self.default_ttl.map(|ttl| SystemTime::now() + ttl),
+        };
+        if let Some(at) = expiry {
+            let c = caveat::new_first_party(timestamp::expiry_predicate(at));
+            signature = c.sign(&signature);
+            caveats.push(c);
+        }
+        let macaroon = Macaroon {
+            identifier,
+            location: self.location.clone(),
+            signature,
+            caveats,
+            extensions: Default::default(),
+            scheme: Default::default(),
+        };
+        if let Some(store) = &self.store {
+            store.record(MintRecord::new(&macaroon, expiry))?;
+        }
+        Ok(macaroon)
+    }
+
+    /// Mint one macaroon per identifier, all with the caveats from `template`
+    ///
+    /// # Errors
+    ///
+    /// Fails without returning any macaroon if one of them fails to mint (see [Bakery::bake]).
+    /// Those minted before the failure are still recorded in the mint store.
+    pub fn bake_batch<I>(&self, template: &CaveatTemplate, identifiers: I) -> Result<Vec<Macaroon>>
+    where
+        I: IntoIterator<Item = ByteString>,
+    {
+        identifiers
+            .into_iter()
+            .map(|identifier| self.bake(identifier, template))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bakery, CaveatTemplate, FirstPartyCaveatChecker};
+    use crate::{
+        ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey, RandomBytesGenerator, Verifier,
+    };
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_bake_batch_matches_naive() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let caveat_key = MacaroonKey::generate(b"this is another key");
+        let bakery = Bakery::new(Some("http://example.org/".into()), key.clone());
+        let template = CaveatTemplate::new()
+            .first_party("fleet = west".into())
+            .third_party("http://auth.mybank/", &caveat_key, "enrollment".into())
+            .first_party("op = enroll".into());
+        let identifiers: Vec<ByteString> =
+            (0..10).map(|i| format!("device-{}", i).into()).collect();
+
+        let tokens = bakery.bake_batch(&template, identifiers.clone()).unwrap();
+        assert_eq!(10, tokens.len());
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("fleet = west".into());
+        verifier.satisfy_exact("op = enroll".into());
+        for (token, identifier) in tokens.iter().zip(identifiers) {
+            let mut naive =
+                Macaroon::create(Some("http://example.org/".into()), &key, identifier).unwrap();
+            naive.add_first_party_caveat("fleet = west".into());
+            naive.add_third_party_caveat("http://auth.mybank/", &caveat_key, "enrollment".into());
+            naive.add_first_party_caveat("op = enroll".into());
+            // verifier IDs are encrypted with a random nonce, so only the structure can match
+            assert_eq!(naive.identifier(), token.identifier());
+            assert_eq!(naive.location(), token.location());
+            assert_eq!(naive.caveats().len(), token.caveats().len());
+
+            let mut discharge = Macaroon::create(None, &caveat_key, "enrollment".into()).unwrap();
+            token.bind(&mut discharge);
+            verifier.verify(token, &key, vec![discharge]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bake_first_party_only() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let template = CaveatTemplate::new()
+            .first_party("fleet = west".into())
+            .first_party("op = enroll".into());
+        let token = Bakery::new(None, key.clone())
+            .bake("device".into(), &template)
+            .unwrap();
+        let mut naive = Macaroon::create(None, &key, "device".into()).unwrap();
+        naive.add_first_party_caveat("fleet = west".into());
+        naive.add_first_party_caveat("op = enroll".into());
+        assert_eq!(naive, token);
+    }
+
+    #[test]
+    fn test_mint_generated_identifiers() {
+        let bakery = Bakery::new(None, MacaroonKey::generate(b"this is the key"))
+            .with_identifier_generator(RandomBytesGenerator::new(20));
+        let tokens = bakery.mint_batch(&CaveatTemplate::new(), 3).unwrap();
+        assert_eq!(20, tokens[0].identifier().0.len());
+        assert_ne!(tokens[0].identifier(), tokens[1].identifier());
+        assert_eq!(
+            36,
+            Bakery::new(None, MacaroonKey::generate_random())
+                .new_caveat_id()
+                .0
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_default_ttl() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let bakery = Bakery::new(None, key.clone()).with_default_ttl(Duration::from_secs(3600));
+        let expiry = |m: &Macaroon| match m.caveats().last() {
+            Some(Caveat::FirstParty(fp)) => String::from_utf8(fp.predicate().0).unwrap(),
+            _ => String::new(),
+        };
+
+        let token = bakery.mint(&CaveatTemplate::new()).unwrap();
+        assert_eq!(1, token.caveats().len());
+        assert!(expiry(&token).starts_with("time < "));
+
+        let at = UNIX_EPOCH + Duration::from_secs(1_893_456_000);
+        let token = bakery.mint(&CaveatTemplate::new().expires_at(at)).unwrap();
+        assert_eq!("time < 2030-01-01T00:00:00Z", expiry(&token));
+
+        let token = bakery.mint(&CaveatTemplate::new().never_expires()).unwrap();
+        assert!(token.caveats().is_empty());
+
+        for predicate in [
+            "time < 2030-01-01T00:00",
+            "time-before 2030-01-01T00:00:00Z",
+            "std:time-before 2030-01-01T00:00:00Z",
+        ] {
+            let template = CaveatTemplate::new().first_party(predicate.into());
+            assert_eq!(1, bakery.mint(&template).unwrap().caveats().len());
+        }
+        let template = CaveatTemplate::new().first_party("time-before-ish".into());
+        assert_eq!(2, bakery.mint(&template).unwrap().caveats().len());
+
+        let token = Bakery::new(None, key).mint(&CaveatTemplate::new()).unwrap();
+        assert!(token.caveats().is_empty());
+    }
+
+    #[test]
+    fn test_verifier_checker_bridge() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let bakery = Bakery::new(None, key.clone());
+        let token = bakery
+            .mint(
+                &CaveatTemplate::new()
+                    .first_party("account = 3735928559".into())
+                    .first_party("user = alice".into()),
+            )
+            .unwrap();
+
+        let mut accounts = Verifier::default();
+        accounts.satisfy_exact("account = 3735928559".into());
+        let accounts: Arc<dyn FirstPartyCaveatChecker> = Arc::new(accounts);
+        assert!(accounts
+            .check_first_party_caveat(&"account = 3735928559".into())
+            .is_ok());
+        assert!(matches!(
+            accounts.check_first_party_caveat(&"user = alice".into()),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("user = alice".into());
+        verifier
+            .verify(&token, &key, Default::default())
+            .unwrap_err();
+        verifier.satisfy_checker(accounts);
+        verifier.verify(&token, &key, Default::default()).unwrap();
+    }
+
+    #[test]
+    fn test_bake_batch_empty_identifier() {
+        let bakery = Bakery::new(None, MacaroonKey::generate(b"this is the key"));
+        assert!(bakery
+            .bake_batch(&CaveatTemplate::new(), vec!["ok".into(), "".into()])
+            .is_err());
+    }
+}
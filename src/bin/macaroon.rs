@@ -0,0 +1,387 @@
+//! `macaroon`: mint, attenuate, inspect, verify, discharge and convert tokens from the shell
+//!
+//! Built with the `cli` feature (`cargo install macaroon --features cli`); run `macaroon help`
+//! for usage. Keys are never taken from the command line, where other users could see them.
+
+use macaroon::{ByteString, Format, Macaroon, MacaroonKey, Verifier};
+use std::collections::HashMap;
+use std::io::Read;
+
+const USAGE: &str = "\
+usage: macaroon <command> [options]
+
+commands:
+  mint        --id ID [--location URL] [--caveat PREDICATE]... KEY [--format FORMAT]
+  attenuate   TOKEN --caveat PREDICATE... [--format FORMAT]
+  inspect     TOKEN
+  verify      TOKEN KEY [--discharge TOKEN]... [--satisfy PREDICATE]...
+  discharge   --id CAVEAT_ID [--location URL] [--caveat PREDICATE]... KEY [--bind TOKEN]
+              [--format FORMAT]
+  convert     TOKEN --to FORMAT
+
+KEY is --key-env VARIABLE or --key-file PATH. The key is the secret string itself, with any
+trailing newline removed, as for libmacaroons and pymacaroons.
+TOKEN is a token, or - to read it from standard input.
+FORMAT is v1, v2, v2json or v2compressed (with the deflate or zstd feature). The default is v2,
+or for attenuate, the format of the token.
+";
+
+/// Command line arguments: positional arguments, and `--name value` or `--name=value` options
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Vec<String>>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut options: HashMap<String, Vec<String>> = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    let (name, value) = match option.split_once('=') {
+                        Some((name, value)) => (name, value.to_string()),
+                        None => match args.next() {
+                            Some(value) => (option, value.clone()),
+                            None => return Err(format!("missing value for --{}", option)),
+                        },
+                    };
+                    options.entry(name.to_string()).or_default().push(value);
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    /// Fail on options the command doesn't take, and on positional arguments after `count`
+    fn expect(&self, count: usize, allowed: &[&str]) -> Result<(), String> {
+        if let Some(extra) = self.positional.get(count) {
+            return Err(format!("unexpected argument {}", extra));
+        }
+        match self.options.keys().find(|o| !allowed.contains(&o.as_str())) {
+            Some(option) => Err(format!("unknown option --{}", option)),
+            None => Ok(()),
+        }
+    }
+
+    fn all(&self, name: &str) -> &[String] {
+        self.options.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    fn one(&self, name: &str) -> Result<Option<&str>, String> {
+        match self.all(name) {
+            [] => Ok(None),
+            [value] => Ok(Some(value)),
+            _ => Err(format!("--{} given more than once", name)),
+        }
+    }
+
+    fn required(&self, name: &str) -> Result<&str, String> {
+        self.one(name)?.ok_or_else(|| format!("missing --{}", name))
+    }
+
+    fn token(&self, index: usize) -> Result<Macaroon, String> {
+        let token = self.positional.get(index).ok_or("missing token")?;
+        read_token(token)
+    }
+
+    fn caveats(&self) -> Vec<ByteString> {
+        self.all("caveat")
+            .iter()
+            .map(|c| c.as_str().into())
+            .collect()
+    }
+}
+
+/// A token argument, reading it from standard input if it's `-`
+fn token_text(token: &str) -> Result<String, String> {
+    if token != "-" {
+        return Ok(token.trim().to_string());
+    }
+    let mut token = String::new();
+    std::io::stdin()
+        .read_to_string(&mut token)
+        .map_err(|e| format!("reading standard input: {}", e))?;
+    Ok(token.trim().to_string())
+}
+
+fn read_token(token: &str) -> Result<Macaroon, String> {
+    Macaroon::deserialize(token_text(token)?).map_err(|e| format!("invalid token: {}", e))
+}
+
+/// The key from `--key-env` or `--key-file`, looking up environment variables with `env`
+fn read_key(args: &Args, env: &dyn Fn(&str) -> Option<String>) -> Result<MacaroonKey, String> {
+    let secret = match (args.one("key-env")?, args.one("key-file")?) {
+        (Some(var), None) => env(var).ok_or_else(|| format!("{} is not set", var))?,
+        (None, Some(path)) => {
+            std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?
+        }
+        (Some(_), Some(_)) => return Err("give only one of --key-env and --key-file".into()),
+        (None, None) => return Err("missing --key-env or --key-file".into()),
+    };
+    let secret = secret.trim_end_matches(&['\r', '\n'][..]);
+    if secret.is_empty() {
+        return Err("the key is empty".into());
+    }
+    Ok(MacaroonKey::generate(secret.as_bytes()))
+}
+
+fn parse_format(format: &str) -> Result<Format, String> {
+    match format.to_ascii_lowercase().as_str() {
+        "v1" => Ok(Format::V1),
+        "v2" => Ok(Format::V2),
+        "v2json" => Ok(Format::V2JSON),
+        "v2compressed" => Ok(Format::V2Compressed),
+        _ => Err(format!("unknown format {}", format)),
+    }
+}
+
+/// The format of a token which has been deserialized successfully
+fn token_format(token: &str) -> Format {
+    let token = token.trim();
+    if token.starts_with('{') {
+        return Format::V2JSON;
+    }
+    let config = if token.contains(&['-', '_'][..]) {
+        base64::URL_SAFE_NO_PAD
+    } else {
+        base64::STANDARD_NO_PAD
+    };
+    match base64::decode_config(token.trim_end_matches('='), config) {
+        Ok(binary) if binary.first() == Some(&2) => Format::V2,
+        Ok(binary) if binary.first() == Some(&0xce) => Format::V2Compressed,
+        _ => Format::V1,
+    }
+}
+
+fn serialize(macaroon: &Macaroon, format: Format) -> Result<String, String> {
+    macaroon
+        .serialize(format)
+        .map_err(|e| format!("can't serialize as {:?}: {}", format, e))
+}
+
+fn output_format(args: &Args, default: Format) -> Result<Format, String> {
+    args.one("format")?.map_or(Ok(default), parse_format)
+}
+
+/// Run the command in `args` (without the program name), returning its output
+fn run(args: &[String], env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), Args::parse(args)?),
+        None => return Err(USAGE.to_string()),
+    };
+    match command {
+        "mint" => {
+            args.expect(
+                0,
+                &["id", "location", "caveat", "key-env", "key-file", "format"],
+            )?;
+            let key = read_key(&args, env)?;
+            let location = args.one("location")?.map(String::from);
+            let mut macaroon = Macaroon::create(location, &key, args.required("id")?.into())
+                .map_err(|e| e.to_string())?;
+            for caveat in args.caveats() {
+                macaroon.add_first_party_caveat(caveat);
+            }
+            serialize(&macaroon, output_format(&args, Format::V2)?)
+        }
+        "attenuate" => {
+            args.expect(1, &["caveat", "format"])?;
+            let mut macaroon = args.token(0)?;
+            let caveats = args.caveats();
+            if caveats.is_empty() {
+                return Err("missing --caveat".into());
+            }
+            for caveat in caveats {
+                macaroon.add_first_party_caveat(caveat);
+            }
+            let format = match args.positional[0].as_str() {
+                "-" => Format::V2,
+                token => token_format(token),
+            };
+            serialize(&macaroon, output_format(&args, format)?)
+        }
+        "inspect" => {
+            args.expect(1, &[])?;
+            Ok(args.token(0)?.inspect())
+        }
+        "verify" => {
+            args.expect(1, &["key-env", "key-file", "discharge", "satisfy"])?;
+            let macaroon = args.token(0)?;
+            let key = read_key(&args, env)?;
+            let discharges = args
+                .all("discharge")
+                .iter()
+                .map(|d| read_token(d))
+                .collect::<Result<Vec<Macaroon>, String>>()?;
+            let mut verifier = Verifier::default();
+            for predicate in args.all("satisfy") {
+                verifier.satisfy_exact(predicate.as_str().into());
+            }
+            verifier
+                .verify(&macaroon, &key, discharges)
+                .map_err(|e| format!("verification failed: {}", e))?;
+            Ok("valid".to_string())
+        }
+        "discharge" => {
+            args.expect(
+                0,
+                &[
+                    "id", "location", "caveat", "key-env", "key-file", "bind", "format",
+                ],
+            )?;
+            let key = read_key(&args, env)?;
+            let location = args.one("location")?.map(String::from);
+            let mut discharge = Macaroon::create_discharge(
+                &key,
+                args.required("id")?.into(),
+                location,
+                &args.caveats(),
+            )
+            .map_err(|e| e.to_string())?;
+            if let Some(token) = args.one("bind")? {
+                read_token(token)?.bind(&mut discharge);
+            }
+            serialize(&discharge, output_format(&args, Format::V2)?)
+        }
+        "convert" => {
+            args.expect(1, &["to"])?;
+            let token = token_text(args.positional.first().ok_or("missing token")?)?;
+            let to = parse_format(args.required("to")?)?;
+            Macaroon::convert(&token, token_format(&token), to).map_err(|e| e.to_string())
+        }
+        "help" | "--help" | "-h" => Ok(USAGE.trim_end().to_string()),
+        _ => Err(format!("unknown command {}\n\n{}", command, USAGE)),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args, &|var| std::env::var(var).ok()) {
+        Ok(output) => println!("{}", output),
+        Err(error) => {
+            eprintln!("{}", error.trim_end());
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, token_format};
+    use macaroon::{Format, Macaroon, MacaroonKey};
+
+    const SECRET: &str = "this is our super secret key; only we should know it";
+
+    fn macaroon(args: &[&str]) -> Result<String, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        run(&args, &|var| match var {
+            "ROOT_KEY" => Some(format!("{}\n", SECRET)),
+            "CAVEAT_KEY" => Some("caveat key".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_mint_attenuate_verify() {
+        let token = macaroon(&[
+            "mint",
+            "--id",
+            "we used our secret key",
+            "--location=http://mybank/",
+            "--key-env",
+            "ROOT_KEY",
+            "--format",
+            "v1",
+        ])
+        .unwrap();
+        // the same as libmacaroons with the same secret
+        let expected = Macaroon::create(
+            Some("http://mybank/".into()),
+            &MacaroonKey::generate(SECRET.as_bytes()),
+            "we used our secret key".into(),
+        )
+        .unwrap();
+        assert_eq!(expected.serialize(Format::V1).unwrap(), token);
+
+        let attenuated =
+            macaroon(&["attenuate", &token, "--caveat", "account = 3735928559"]).unwrap();
+        assert_eq!(Format::V1, token_format(&attenuated));
+        assert!(macaroon(&["inspect", &attenuated])
+            .unwrap()
+            .contains("cid account = 3735928559"));
+
+        let verify = |satisfy: &str| {
+            macaroon(&[
+                "verify",
+                &attenuated,
+                "--key-env",
+                "ROOT_KEY",
+                "--satisfy",
+                satisfy,
+            ])
+        };
+        assert_eq!("valid", verify("account = 3735928559").unwrap());
+        assert!(verify("account = 1").is_err());
+        assert!(macaroon(&["verify", &attenuated, "--key-env", "UNSET"]).is_err());
+    }
+
+    #[test]
+    fn test_discharge_and_convert() {
+        let mut root = Macaroon::create(
+            None,
+            &MacaroonKey::generate(SECRET.as_bytes()),
+            "root".into(),
+        )
+        .unwrap();
+        root.add_third_party_caveat(
+            "https://auth.example/",
+            &MacaroonKey::generate(b"caveat key"),
+            "caveat".into(),
+        );
+        let token = root.serialize(Format::V2).unwrap();
+
+        let discharge = macaroon(&[
+            "discharge",
+            "--id",
+            "caveat",
+            "--key-env",
+            "CAVEAT_KEY",
+            "--bind",
+            &token,
+        ])
+        .unwrap();
+        let json = macaroon(&["convert", &token, "--to", "V2JSON"]).unwrap();
+        assert_eq!(Format::V2JSON, token_format(&json));
+        assert_eq!(
+            "valid",
+            macaroon(&[
+                "verify",
+                &json,
+                "--key-env",
+                "ROOT_KEY",
+                "--discharge",
+                &discharge
+            ])
+            .unwrap()
+        );
+        assert!(macaroon(&["verify", &json, "--key-env", "ROOT_KEY"]).is_err());
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        assert!(macaroon(&[]).is_err());
+        assert!(macaroon(&["frobnicate"]).is_err());
+        assert!(macaroon(&["mint", "--key-env", "ROOT_KEY"]).is_err());
+        assert!(macaroon(&["mint", "--id", "x", "--key-env"]).is_err());
+        assert!(macaroon(&["inspect", "not a token"]).is_err());
+        assert!(macaroon(&["inspect", "--verbose", "x"]).is_err());
+        assert!(macaroon(&["convert", "x", "y", "--to", "v2"]).is_err());
+        assert!(macaroon(&["help"]).unwrap().starts_with("usage"));
+    }
+}
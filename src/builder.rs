@@ -0,0 +1,180 @@
+use crate::scheme::Scheme;
+use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, Result, SignatureScheme};
+
+/// A caveat waiting to be signed by [MacaroonBuilder::build]
+#[derive(Clone, Debug)]
+enum PendingCaveat {
+    FirstParty(ByteString),
+    ThirdParty {
+        location: String,
+        key: MacaroonKey,
+        id: ByteString,
+    },
+}
+
+/// Builder for a [Macaroon] and its caveats, created with [Macaroon::builder]
+///
+/// The macaroon is only minted by [MacaroonBuilder::build], which signs the caveats in the
+/// order they were added to the builder, so fields can be set in any order.
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, Verifier};
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let caveat_key = MacaroonKey::generate(b"caveat key");
+/// let macaroon = Macaroon::builder()
+///     .first_party_caveat("account = 3735928559".into())
+///     .third_party_caveat("https://auth.mybank/", &caveat_key, "caveat id".into())
+///     .location("https://mybank/")
+///     .identifier("keyid".into())
+///     .key(&key)
+///     .build()
+///     .unwrap();
+///
+/// let mut expected = Macaroon::create(Some("https://mybank/".into()), &key, "keyid".into()).unwrap();
+/// expected.add_first_party_caveat("account = 3735928559".into());
+/// assert_eq!(expected.caveats()[0], macaroon.caveats()[0]);
+/// assert_eq!(2, macaroon.caveats().len());
+///
+/// let mut discharge = Macaroon::create(None, &caveat_key, "caveat id".into()).unwrap();
+/// macaroon.bind(&mut discharge);
+/// let mut verifier = Verifier::default();
+/// verifier.satisfy_exact("account = 3735928559".into());
+/// verifier.verify(&macaroon, &key, vec![discharge]).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct MacaroonBuilder {
+    location: Option<String>,
+    identifier: Option<ByteString>,
+    key: Option<MacaroonKey>,
+    scheme: Scheme,
+    caveats: Vec<PendingCaveat>,
+}
+
+impl Default for MacaroonBuilder {
+    fn default() -> Self {
+        MacaroonBuilder::new()
+    }
+}
+
+impl MacaroonBuilder {
+    pub fn new() -> MacaroonBuilder {
+        MacaroonBuilder {
+            location: None,
+            identifier: None,
+            key: None,
+            scheme: Scheme::default(),
+            caveats: Vec::new(),
+        }
+    }
+
+    pub fn location(mut self, location: &str) -> Self {
+        self.location = Some(location.to_string());
+        self
+    }
+
+    pub fn identifier(mut self, identifier: ByteString) -> Self {
+        self.identifier = Some(identifier);
+        self
+    }
+
+    /// The root key to sign the macaroon with
+    pub fn key(mut self, key: &MacaroonKey) -> Self {
+        self.key = Some(key.clone());
+        self
+    }
+
+    /// See [Macaroon::create_with_scheme]. The default is HMAC-SHA-256.
+    pub fn scheme(mut self, scheme: &'static dyn SignatureScheme) -> Self {
+        self.scheme = Scheme(scheme);
+        self
+    }
+
+    /// See [Macaroon::add_first_party_caveat]
+    pub fn first_party_caveat(mut self, predicate: ByteString) -> Self {
+        self.caveats.push(PendingCaveat::FirstParty(predicate));
+        self
+    }
+
+    /// See [Macaroon::add_third_party_caveat]
+    pub fn third_party_caveat(mut self, location: &str, key: &MacaroonKey, id: ByteString) -> Self {
+        self.caveats.push(PendingCaveat::ThirdParty {
+            location: location.to_string(),
+            key: key.clone(),
+            id,
+        });
+        self
+    }
+
+    /// Mint the macaroon, then add the caveats in order
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::IncompleteMacaroon] if the key or identifier wasn't set, or the
+    /// identifier is empty.
+    pub fn build(self) -> Result<Macaroon> {
+        let key = self
+            .key
+            .ok_or(MacaroonError::IncompleteMacaroon("no key given"))?;
+        let identifier = self
+            .identifier
+            .ok_or(MacaroonError::IncompleteMacaroon("no identifier found"))?;
+        let mut macaroon =
+            Macaroon::create_with_scheme(self.location, &key, identifier, self.scheme.0)?;
+        for caveat in self.caveats {
+            match caveat {
+                PendingCaveat::FirstParty(predicate) => macaroon.add_first_party_caveat(predicate),
+                PendingCaveat::ThirdParty { location, key, id } => {
+                    macaroon.add_third_party_caveat(&location, &key, id)
+                }
+            }
+        }
+        Ok(macaroon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MacaroonBuilder;
+    use crate::{Blake2bMac, Macaroon, MacaroonError, MacaroonKey};
+
+    #[test]
+    fn test_build() {
+        let key = MacaroonKey::generate(b"key");
+        let macaroon = Macaroon::builder()
+            .identifier("keyid".into())
+            .key(&key)
+            .first_party_caveat("account = 3735928559".into())
+            .first_party_caveat("user = alice".into())
+            .build()
+            .unwrap();
+        let mut expected = Macaroon::create(None, &key, "keyid".into()).unwrap();
+        expected.add_first_party_caveat("account = 3735928559".into());
+        expected.add_first_party_caveat("user = alice".into());
+        assert_eq!(expected, macaroon);
+
+        let blake2 = Macaroon::builder()
+            .identifier("keyid".into())
+            .key(&key)
+            .scheme(&Blake2bMac)
+            .build()
+            .unwrap();
+        assert_ne!(expected.signature(), blake2.signature());
+
+        assert!(matches!(
+            MacaroonBuilder::new().key(&key).build(),
+            Err(MacaroonError::IncompleteMacaroon(_))
+        ));
+        assert!(matches!(
+            MacaroonBuilder::new().identifier("keyid".into()).build(),
+            Err(MacaroonError::IncompleteMacaroon(_))
+        ));
+        assert!(matches!(
+            MacaroonBuilder::new()
+                .identifier("".into())
+                .key(&key)
+                .build(),
+            Err(MacaroonError::IncompleteMacaroon(_))
+        ));
+    }
+}
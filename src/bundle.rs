@@ -0,0 +1,274 @@
+use crate::error::MacaroonError;
+use crate::macaroon_ref::MacaroonRef;
+use crate::serialization::{v2, DeserializeOptions};
+use crate::Result;
+use std::convert::TryInto;
+
+/// Layout of a token archive read by [BundleReader]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BundleFormat {
+    /// One base64-encoded V2 token per line. Blank lines are skipped, and surrounding
+    /// whitespace (including `\r`) is ignored.
+    Lines,
+    /// Raw V2 binary tokens, each preceded by its length as a 4-byte big-endian integer
+    LengthPrefixed,
+}
+
+/// Lazily parses the tokens in an archive, one at a time, as [MacaroonRef]s.
+///
+/// The reader only borrows the archive bytes, so with a memory-mapped file (see `MappedBundle`,
+/// behind the `mmap` feature) peak memory stays flat regardless of archive size. Length-prefixed
+/// tokens are parsed in place; line-based tokens are decoded into a single reused buffer.
+///
+/// ```rust
+/// use macaroon::{BundleFormat, BundleReader, Macaroon, MacaroonKey};
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let key = MacaroonKey::generate(b"key");
+/// let mut archive = String::new();
+/// for id in &["one", "two"] {
+///     let macaroon = Macaroon::create(None, &key, (*id).into())?;
+///     archive.push_str(&macaroon.serialize(macaroon::Format::V2)?);
+///     archive.push('\n');
+/// }
+///
+/// let mut identifiers = Vec::new();
+/// BundleReader::new(archive.as_bytes(), BundleFormat::Lines).for_each(|_, entry| {
+///     identifiers.push(entry.unwrap().identifier().to_vec());
+/// })?;
+/// assert_eq!(vec![b"one".to_vec(), b"two".to_vec()], identifiers);
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug)]
+pub struct BundleReader<'a> {
+    data: &'a [u8],
+    format: BundleFormat,
+    options: DeserializeOptions,
+}
+
+impl<'a> BundleReader<'a> {
+    pub fn new(data: &'a [u8], format: BundleFormat) -> BundleReader<'a> {
+        BundleReader {
+            data,
+            format,
+            options: DeserializeOptions::default(),
+        }
+    }
+
+    /// Use `options` when parsing each token
+    pub fn with_options(mut self, options: DeserializeOptions) -> BundleReader<'a> {
+        self.options = options;
+        self
+    }
+
+    /// Iterate over the raw records: trimmed lines for [BundleFormat::Lines], and binary
+    /// tokens for [BundleFormat::LengthPrefixed]
+    pub fn records(&self) -> BundleRecords<'a> {
+        BundleRecords {
+            data: self.data,
+            offset: 0,
+            format: self.format,
+        }
+    }
+
+    /// Parse each token in turn, passing its index and the parse result to `f`.
+    ///
+    /// A token which fails to parse is reported to `f` and skipped. Errors in the archive framing
+    /// itself (a truncated length prefix or record) stop the scan and are returned.
+    pub fn for_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, Result<MacaroonRef<'_>>),
+    {
+        let mut buf: Vec<u8> = Vec::new();
+        for (index, record) in self.records().enumerate() {
+            let record = record?;
+            match self.format {
+                BundleFormat::Lines => match crate::base64_decode_flexible_into(record, &mut buf) {
+                    Ok(()) => f(index, v2::deserialize_ref(&buf, &self.options)),
+                    Err(error) => f(index, Err(error)),
+                },
+                BundleFormat::LengthPrefixed => {
+                    f(index, v2::deserialize_ref(record, &self.options))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the raw records of an archive; see [BundleReader::records]
+pub struct BundleRecords<'a> {
+    data: &'a [u8],
+    offset: usize,
+    format: BundleFormat,
+}
+
+impl<'a> Iterator for BundleRecords<'a> {
+    type Item = Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Result<&'a [u8]>> {
+        match self.format {
+            BundleFormat::Lines => loop {
+                let rest = self.data.get(self.offset..).filter(|r| !r.is_empty())?;
+                let len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                self.offset += len + 1;
+                let line = trim_ascii(&rest[..len]);
+                if !line.is_empty() {
+                    return Some(Ok(line));
+                }
+            },
+            BundleFormat::LengthPrefixed => {
+                let rest = self.data.get(self.offset..).filter(|r| !r.is_empty())?;
+                let record = match rest.get(..4) {
+                    Some(prefix) => {
+                        let len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+                        rest.get(4..).and_then(|r| r.get(..len))
+                    }
+                    None => None,
+                };
+                match record {
+                    Some(record) => {
+                        self.offset += 4 + record.len();
+                        Some(Ok(record))
+                    }
+                    None => {
+                        let error = MacaroonError::DeserializationError(format!(
+                            "Truncated bundle record at offset {}",
+                            self.offset
+                        ));
+                        self.offset = self.data.len();
+                        Some(Err(error))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn trim_ascii(mut bytes: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = bytes {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        bytes = rest;
+    }
+    while let [rest @ .., last] = bytes {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+/// A token archive file, memory-mapped for reading with a [BundleReader]
+#[cfg(feature = "mmap")]
+pub struct MappedBundle {
+    map: memmap2::Mmap,
+    format: BundleFormat,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedBundle {
+    /// Map the file at `path`.
+    ///
+    /// The file must not be modified or truncated while it is mapped; doing so from another
+    /// process can cause reads to return inconsistent data or fault.
+    pub fn open<P: AsRef<std::path::Path>>(
+        path: P,
+        format: BundleFormat,
+    ) -> std::io::Result<MappedBundle> {
+        let file = std::fs::File::open(path)?;
+        // Safety: the mapping is read-only and private to this struct; concurrent modification
+        // of the underlying file is documented above as the caller's responsibility
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(MappedBundle { map, format })
+    }
+
+    pub fn reader(&self) -> BundleReader<'_> {
+        BundleReader::new(&self.map, self.format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BundleFormat, BundleReader};
+    use crate::{Format, Macaroon, MacaroonKey};
+
+    fn macaroons() -> Vec<Macaroon> {
+        let key = MacaroonKey::generate(b"this is the key");
+        (0..3)
+            .map(|i| {
+                let mut m = Macaroon::create(None, &key, format!("id {}", i).into()).unwrap();
+                m.add_first_party_caveat(format!("index = {}", i).into());
+                m
+            })
+            .collect()
+    }
+
+    fn length_prefixed(macaroons: &[Macaroon]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for m in macaroons {
+            let token: Vec<u8> = m.into();
+            data.extend_from_slice(&(token.len() as u32).to_be_bytes());
+            data.extend_from_slice(&token);
+        }
+        data
+    }
+
+    #[test]
+    fn test_lines() {
+        let expected = macaroons();
+        let mut data = String::from("\n");
+        for m in &expected {
+            data.push_str(&m.serialize(Format::V2).unwrap());
+            data.push_str("\r\n\n");
+        }
+        data.push_str("not a token");
+        let mut parsed: Vec<Macaroon> = Vec::new();
+        let mut errors = 0;
+        BundleReader::new(data.as_bytes(), BundleFormat::Lines)
+            .for_each(|_, entry| match entry {
+                Ok(m) => parsed.push(m.to_macaroon()),
+                Err(_) => errors += 1,
+            })
+            .unwrap();
+        assert_eq!(expected, parsed);
+        assert_eq!(1, errors);
+    }
+
+    #[test]
+    fn test_length_prefixed() {
+        let expected = macaroons();
+        let data = length_prefixed(&expected);
+        let reader = BundleReader::new(&data, BundleFormat::LengthPrefixed);
+        assert_eq!(3, reader.records().count());
+        let mut parsed: Vec<Macaroon> = Vec::new();
+        reader
+            .for_each(|_, entry| parsed.push(entry.unwrap().to_macaroon()))
+            .unwrap();
+        assert_eq!(expected, parsed);
+
+        let truncated = BundleReader::new(&data[..data.len() - 1], BundleFormat::LengthPrefixed);
+        let mut count = 0;
+        assert!(truncated.for_each(|_, _| count += 1).is_err());
+        assert_eq!(2, count);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped() {
+        let expected = macaroons();
+        let path = std::env::temp_dir().join(format!("macaroon-bundle-{}", std::process::id()));
+        std::fs::write(&path, length_prefixed(&expected)).unwrap();
+        let bundle = super::MappedBundle::open(&path, BundleFormat::LengthPrefixed).unwrap();
+        let mut parsed: Vec<Macaroon> = Vec::new();
+        bundle
+            .reader()
+            .for_each(|_, entry| parsed.push(entry.unwrap().to_macaroon()))
+            .unwrap();
+        drop(bundle);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(expected, parsed);
+    }
+}
@@ -1,9 +1,12 @@
-use crate::crypto;
+use crate::crypto::{self, Decryptor, SecretBox};
 use crate::error::MacaroonError;
+use crate::extensions::Extensions;
+use crate::scheme::SignatureScheme;
 use crate::ByteString;
 use crate::Result;
 use crypto::MacaroonKey;
 use std::fmt::Debug;
+use std::io;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Caveat {
@@ -14,12 +17,18 @@ pub enum Caveat {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FirstParty {
     predicate: ByteString,
+    extensions: Extensions,
 }
 
 impl FirstParty {
     pub fn predicate(&self) -> ByteString {
         self.predicate.clone()
     }
+
+    /// Borrow the predicate, rather than cloning it as [FirstParty::predicate] does
+    pub fn predicate_ref(&self) -> &ByteString {
+        &self.predicate
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -27,6 +36,7 @@ pub struct ThirdParty {
     id: ByteString,
     verifier_id: ByteString,
     location: String,
+    extensions: Extensions,
 }
 
 impl ThirdParty {
@@ -39,19 +49,228 @@ impl ThirdParty {
     pub fn location(&self) -> String {
         self.location.clone()
     }
+
+    /// Borrow the caveat identifier, rather than cloning it as [ThirdParty::id] does
+    pub fn id_ref(&self) -> &ByteString {
+        &self.id
+    }
+
+    /// Borrow the location, rather than cloning it as [ThirdParty::location] does
+    pub fn location_ref(&self) -> &str {
+        &self.location
+    }
+
+    /// Borrow the verifier identifier, rather than cloning it as [ThirdParty::verifier_id] does
+    pub fn verifier_id_ref(&self) -> &ByteString {
+        &self.verifier_id
+    }
+
+    /// The discharge locations to try, in order
+    ///
+    /// Caveats added with
+    /// [Macaroon::add_third_party_caveat_with_fallbacks](crate::Macaroon::add_third_party_caveat_with_fallbacks)
+    /// store a primary location followed by fallbacks, one per line; other caveats have a single
+    /// location. Line breaks can't appear in a URL, so other locations aren't split.
+    pub fn locations(&self) -> impl Iterator<Item = &str> {
+        self.location
+            .split(LOCATION_SEPARATOR)
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+    }
+
+    /// The caveat key, for caveats whose key was derived from their ID and `shared_secret`; see
+    /// [derive_discharge_key](crate::derive_discharge_key)
+    pub fn derive_discharge_key(&self, shared_secret: &MacaroonKey) -> MacaroonKey {
+        crate::derive_discharge_key(shared_secret, &self.id)
+    }
+
+    /// Decrypt the caveat key from the verifier ID, given the macaroon's signature just before
+    /// this caveat (see [Macaroon::signature_chain](crate::Macaroon::signature_chain))
+    ///
+    /// This is what verification does to check the discharge, so custom verifiers and proxies can
+    /// do the same. It only works for caveats added with the default encryption, [SecretBox];
+    /// see [ThirdParty::extract_caveat_key_with] for others.
+    ///
+    /// ```rust
+    /// use macaroon::{Caveat, Macaroon, MacaroonKey};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    /// macaroon.add_third_party_caveat("https://auth.mybank/", &caveat_key, "caveat id".into());
+    ///
+    /// let chain = macaroon.signature_chain(&key);
+    /// if let Caveat::ThirdParty(tp) = &macaroon.caveats()[1] {
+    ///     assert_eq!(caveat_key, tp.extract_caveat_key(&chain[1]).unwrap());
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::CryptoError] if the verifier ID can't be decrypted with the
+    /// signature, eg because it's the wrong one.
+    pub fn extract_caveat_key(&self, signature_at_caveat: &MacaroonKey) -> Result<MacaroonKey> {
+        self.extract_caveat_key_with(signature_at_caveat, &SecretBox)
+    }
+
+    /// Like [ThirdParty::extract_caveat_key], for caveats added with an encryptor other than the
+    /// default (see [Macaroon::add_third_party_caveat_with](crate::Macaroon::add_third_party_caveat_with))
+    pub fn extract_caveat_key_with(
+        &self,
+        signature_at_caveat: &MacaroonKey,
+        decryptor: &dyn Decryptor,
+    ) -> Result<MacaroonKey> {
+        decryptor.decrypt_key(signature_at_caveat, self.verifier_id.as_ref())
+    }
+}
+
+const LOCATION_SEPARATOR: char = '\n';
+
+/// Join discharge locations into a single location field; see [ThirdParty::locations]
+pub fn join_locations(locations: &[&str]) -> Result<String> {
+    if locations
+        .iter()
+        .any(|l| l.trim().contains(LOCATION_SEPARATOR))
+    {
+        return Err(MacaroonError::IncompleteCaveat(
+            "location contains a line break",
+        ));
+    }
+    Ok(locations
+        .iter()
+        .map(|l| l.trim())
+        .collect::<Vec<&str>>()
+        .join("\n"))
 }
 
 impl Caveat {
+    /// Extension fields carried by the V2JSON format (see [Extensions])
+    pub fn extensions(&self) -> &Extensions {
+        match self {
+            Self::FirstParty(fp) => &fp.extensions,
+            Self::ThirdParty(tp) => &tp.extensions,
+        }
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        match self {
+            Self::FirstParty(fp) => &mut fp.extensions,
+            Self::ThirdParty(tp) => &mut tp.extensions,
+        }
+    }
+
+    /// Sign the caveat with HMAC-SHA-256, chaining from the previous signature `key`
     pub fn sign(&self, key: &MacaroonKey) -> MacaroonKey {
         match self {
-            Self::FirstParty(fp) => crypto::hmac(key, &fp.predicate),
+            Self::FirstParty(fp) => {
+                let mut writer = crypto::HmacWriter::new(key);
+                writer.update(fp.predicate.as_ref());
+                writer.finalize()
+            }
             Self::ThirdParty(tp) => crypto::hmac2(key, &tp.verifier_id, &tp.id),
         }
     }
+
+    pub(crate) fn sign_with(&self, scheme: &dyn SignatureScheme, key: &MacaroonKey) -> MacaroonKey {
+        match self {
+            Self::FirstParty(fp) => scheme.hmac(key, fp.predicate.as_ref()),
+            Self::ThirdParty(tp) => scheme.hmac2(key, tp.verifier_id.as_ref(), tp.id.as_ref()),
+        }
+    }
+}
+
+/// Normalize a first-party caveat predicate for matching
+///
+/// Surrounding whitespace is trimmed, and any whitespace around a comparison operator containing
+/// `=` (`=`, `==`, `!=`, `<=`, `>=`) is collapsed to exactly one space on each side, so that
+/// `account=1` and `account  =  1` both become `account = 1`. Everything else is left untouched.
+///
+/// Normalization changes the predicate bytes, and so the signature of any macaroon it is applied
+/// to at mint time. It is therefore only ever applied when explicitly requested, via
+/// [Macaroon::add_normalized_first_party_caveat](crate::Macaroon::add_normalized_first_party_caveat)
+/// or [Verifier::enable_predicate_normalization](crate::Verifier::enable_predicate_normalization).
+///
+/// ```rust
+/// use macaroon::{normalize_predicate, ByteString};
+///
+/// assert_eq!(
+///     ByteString::from("account = 1"),
+///     normalize_predicate(&" account=1 ".into())
+/// );
+/// ```
+pub fn normalize_predicate(predicate: &ByteString) -> ByteString {
+    let is_operator = |b: &u8| matches!(b, b'=' | b'!' | b'<' | b'>');
+    let bytes = predicate.as_ref();
+    let mut normalized: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if !is_operator(&bytes[i]) {
+            normalized.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let end = i + bytes[i..].iter().take_while(|b| is_operator(b)).count();
+        let operator = &bytes[i..end];
+        if !operator.contains(&b'=') {
+            normalized.extend_from_slice(operator);
+            i = end;
+            continue;
+        }
+        while normalized.last().map_or(false, u8::is_ascii_whitespace) {
+            normalized.pop();
+        }
+        if !normalized.is_empty() {
+            normalized.push(b' ');
+        }
+        normalized.extend_from_slice(operator);
+        i = end;
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i < bytes.len() {
+            normalized.push(b' ');
+        }
+    }
+    let start = normalized
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(normalized.len());
+    let end = normalized
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |p| p + 1);
+    ByteString(normalized[start..end].to_vec())
+}
+
+/// Build a first-party caveat by reading its predicate from `reader`, signing it as it is read.
+///
+/// Returns the caveat along with its signature under `key` (as [Caveat::sign] would).
+pub fn read_first_party<R: io::Read>(
+    key: &MacaroonKey,
+    mut reader: R,
+) -> io::Result<(Caveat, MacaroonKey)> {
+    let mut writer = crypto::HmacWriter::new(key);
+    let mut predicate: Vec<u8> = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.update(&chunk[..n]);
+        predicate.extend_from_slice(&chunk[..n]);
+    }
+    Ok((new_first_party(ByteString(predicate)), writer.finalize()))
 }
 
 pub fn new_first_party(predicate: ByteString) -> Caveat {
-    Caveat::FirstParty(FirstParty { predicate })
+    Caveat::FirstParty(FirstParty {
+        predicate,
+        extensions: Extensions::default(),
+    })
 }
 
 pub fn new_third_party(id: ByteString, verifier_id: ByteString, location: &str) -> Caveat {
@@ -59,6 +278,7 @@ pub fn new_third_party(id: ByteString, verifier_id: ByteString, location: &str)
         id,
         verifier_id,
         location: String::from(location),
+        extensions: Extensions::default(),
     })
 }
 
@@ -67,6 +287,7 @@ pub struct CaveatBuilder {
     id: Option<ByteString>,
     verifier_id: Option<ByteString>,
     location: Option<String>,
+    extensions: Extensions,
 }
 
 impl CaveatBuilder {
@@ -90,27 +311,120 @@ impl CaveatBuilder {
         self.location = Some(location);
     }
 
-    pub fn has_location(&self) -> bool {
-        self.location.is_some()
+    pub fn set_extensions(&mut self, extensions: Extensions) {
+        self.extensions = extensions;
     }
 
     pub fn build(self) -> Result<Caveat> {
-        if self.id.is_none() {
-            return Err(MacaroonError::IncompleteCaveat("no identifier found"));
-        }
-        if self.verifier_id.is_none() && self.location.is_none() {
-            return Ok(new_first_party(self.id.unwrap()));
-        }
-        if self.verifier_id.is_some() && self.location.is_some() {
-            return Ok(new_third_party(
-                self.id.unwrap(),
-                self.verifier_id.unwrap(),
-                &self.location.unwrap(),
-            ));
-        }
-        if self.verifier_id.is_none() {
-            return Err(MacaroonError::IncompleteCaveat("no verifier ID found"));
+        let id = match self.id {
+            Some(id) => id,
+            None => return Err(MacaroonError::IncompleteCaveat("no identifier found")),
+        };
+        let mut caveat = match (self.verifier_id, self.location) {
+            (None, None) => new_first_party(id),
+            (Some(verifier_id), Some(location)) => new_third_party(id, verifier_id, &location),
+            (None, Some(_)) => return Err(MacaroonError::IncompleteCaveat("no verifier ID found")),
+            (Some(_), None) => return Err(MacaroonError::IncompleteCaveat("no location found")),
+        };
+        *caveat.extensions_mut() = self.extensions;
+        Ok(caveat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_predicate;
+    use crate::{ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey, XChaCha20Poly1305};
+
+    #[test]
+    fn test_normalize_predicate() {
+        let cases = [
+            ("account = 1", "account = 1"),
+            ("account=1", "account = 1"),
+            ("  account \t=   1\n", "account = 1"),
+            ("time>=2020-01-01T00:00", "time >= 2020-01-01T00:00"),
+            ("user != bob", "user != bob"),
+            ("time < 2020-01-01T00:00", "time < 2020-01-01T00:00"),
+            ("key ==  value  with  spaces", "key == value  with  spaces"),
+            ("=", "="),
+            ("", ""),
+        ];
+        for (input, expected) in cases.iter() {
+            assert_eq!(
+                ByteString::from(*expected),
+                normalize_predicate(&(*input).into()),
+                "normalizing {:?}",
+                input
+            );
         }
-        Err(MacaroonError::IncompleteCaveat("no location found"))
+    }
+
+    #[test]
+    fn test_locations() {
+        let location = super::join_locations(&["https://auth.us/", "https://auth.eu/a,b"]).unwrap();
+        assert_eq!("https://auth.us/\nhttps://auth.eu/a,b", location);
+        assert!(super::join_locations(&["https://auth.us/\nhttps://auth.eu/"]).is_err());
+        let caveat = super::new_third_party("id".into(), "vid".into(), &location);
+        let tp = match caveat {
+            super::Caveat::ThirdParty(tp) => tp,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            vec!["https://auth.us/", "https://auth.eu/a,b"],
+            tp.locations().collect::<Vec<&str>>()
+        );
+
+        // locations not written by join_locations aren't split
+        let caveat = super::new_third_party("id".into(), "vid".into(), "https://auth.us/a,b");
+        let tp = match caveat {
+            super::Caveat::ThirdParty(tp) => tp,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            vec!["https://auth.us/a,b"],
+            tp.locations().collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn test_extract_caveat_key() {
+        let key = MacaroonKey::generate(b"key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        macaroon.add_third_party_caveat("https://auth/", &caveat_key, "secretbox".into());
+        macaroon.add_third_party_caveat_with(
+            "https://auth/",
+            &caveat_key,
+            "xchacha".into(),
+            &XChaCha20Poly1305,
+        );
+        let chain = macaroon.signature_chain(&key);
+        let caveats: Vec<_> = macaroon
+            .caveats()
+            .into_iter()
+            .map(|c| match c {
+                Caveat::ThirdParty(tp) => tp,
+                _ => unreachable!(),
+            })
+            .collect();
+
+        assert_eq!(
+            caveat_key,
+            caveats[0].extract_caveat_key(&chain[0]).unwrap()
+        );
+        assert_eq!(
+            caveat_key,
+            caveats[1]
+                .extract_caveat_key_with(&chain[1], &XChaCha20Poly1305)
+                .unwrap()
+        );
+        assert!(matches!(
+            caveats[0].extract_caveat_key(&chain[1]),
+            Err(MacaroonError::CryptoError(_))
+        ));
+        assert!(matches!(
+            caveats[1].extract_caveat_key(&chain[1]),
+            Err(MacaroonError::CryptoError(_))
+        ));
     }
 }
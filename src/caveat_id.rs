@@ -0,0 +1,449 @@
+use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, Result};
+use sodiumoxide::crypto::box_;
+use sodiumoxide::randombytes::randombytes;
+use std::fmt;
+
+/// Version byte of the caveat IDs written by [encode_caveat_id] (go-macaroon-bakery's version 2)
+const CAVEAT_ID_VERSION: u8 = 2;
+
+/// First byte of a base64url-encoded version 2 caveat ID, as go-macaroon-bakery sends them
+const ENCODED_VERSION_TAG: u8 = b'A';
+
+/// How many bytes of the third party's public key are included as a hint
+const PUBLIC_KEY_PREFIX_LEN: usize = 4;
+
+/// Length of the random root keys of public-key third-party caveats, as in go-macaroon-bakery
+const ROOT_KEY_LEN: usize = 24;
+
+/// A Curve25519 public key, identifying a third party (or the first party that encrypted a
+/// caveat to it)
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; box_::PUBLICKEYBYTES]);
+
+impl PublicKey {
+    /// Standard base64, as public keys appear in go-macaroon-bakery's JSON
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.0)
+    }
+
+    /// Parse a public key from standard or URL-safe base64, with or without padding
+    pub fn from_base64(s: &str) -> Result<PublicKey> {
+        let config = if s.contains(&['-', '_'][..]) {
+            base64::URL_SAFE_NO_PAD
+        } else {
+            base64::STANDARD_NO_PAD
+        };
+        let bytes = base64::decode_config(s.trim_end_matches('='), config)?;
+        box_::PublicKey::from_slice(&bytes)
+            .map(|k| PublicKey(k.0))
+            .ok_or_else(|| {
+                MacaroonError::DeserializationError("public key must be 32 bytes".to_string())
+            })
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({})", self.to_base64())
+    }
+}
+
+impl From<[u8; box_::PUBLICKEYBYTES]> for PublicKey {
+    fn from(b: [u8; box_::PUBLICKEYBYTES]) -> Self {
+        PublicKey(b)
+    }
+}
+
+impl AsRef<[u8]> for PublicKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A Curve25519 key pair, for a third party to decrypt the caveat IDs encrypted to it
+///
+/// As with [MacaroonKey], the secret key is redacted from the `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyPair {
+    public: PublicKey,
+    secret: box_::SecretKey,
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyPair {
+    /// Generate a new random key pair
+    pub fn generate() -> KeyPair {
+        let (public, secret) = box_::gen_keypair();
+        KeyPair {
+            public: PublicKey(public.0),
+            secret,
+        }
+    }
+
+    /// The key pair with the given secret key, eg loaded from configuration
+    pub fn from_secret_key(secret: [u8; box_::SECRETKEYBYTES]) -> KeyPair {
+        let secret = box_::SecretKey(secret);
+        KeyPair {
+            public: PublicKey(secret.public_key().0),
+            secret,
+        }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public
+    }
+
+    pub fn secret_key(&self) -> &[u8; box_::SECRETKEYBYTES] {
+        &self.secret.0
+    }
+}
+
+/// The contents of a caveat ID decrypted with [decode_caveat_id]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedCaveatId {
+    /// The public key the first party encrypted the caveat ID with
+    pub first_party_key: PublicKey,
+    /// The root key of the discharge macaroon, as chosen by the first party
+    pub root_key: Vec<u8>,
+    pub condition: ByteString,
+}
+
+impl DecodedCaveatId {
+    /// The key to sign the discharge macaroon with, derived from the root key as in
+    /// [MacaroonKey::generate]
+    pub fn caveat_key(&self) -> MacaroonKey {
+        MacaroonKey::generate(&self.root_key)
+    }
+
+    /// Mint an (unbound) discharge for the caveat with ID `caveat_id`, which this was decoded
+    /// from; see [Macaroon::create_discharge]
+    ///
+    /// The third party should only do this once it has checked
+    /// [DecodedCaveatId::condition].
+    pub fn discharge(
+        &self,
+        caveat_id: ByteString,
+        location: Option<String>,
+        extra_caveats: &[ByteString],
+    ) -> Result<Macaroon> {
+        Macaroon::create_discharge(&self.caveat_key(), caveat_id, location, extra_caveats)
+    }
+}
+
+impl Macaroon {
+    /// Add a third-party caveat for the third party with the public key `third_party`, with the
+    /// caveat key and `condition` encrypted into the caveat ID (see [encode_caveat_id])
+    ///
+    /// This is how go-macaroon-bakery (version 2) adds third-party caveats, so no keys need to be
+    /// shared with the third party beforehand: it decrypts the caveat ID (with
+    /// [decode_caveat_id], or go-macaroon-bakery's own decoding, as Candid and Juju dischargers
+    /// do), checks the condition, and mints the discharge (eg with [DecodedCaveatId::discharge]).
+    ///
+    /// ```rust
+    /// use macaroon::{decode_caveat_id, Caveat, KeyPair, Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let root_key = MacaroonKey::generate_random();
+    /// let third_party = KeyPair::generate();
+    /// let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+    /// macaroon.add_public_key_third_party_caveat(
+    ///     "https://auth.example/",
+    ///     third_party.public_key(),
+    ///     b"is-authenticated-user",
+    /// );
+    ///
+    /// // The third party only sees the caveat ID
+    /// let caveat_id = match &macaroon.third_party_caveats()[0] {
+    ///     Caveat::ThirdParty(tp) => tp.id(),
+    ///     Caveat::FirstParty(_) => unreachable!(),
+    /// };
+    /// let decoded = decode_caveat_id(&third_party, caveat_id.as_ref()).unwrap();
+    /// assert_eq!(b"is-authenticated-user".to_vec(), decoded.condition.0);
+    /// let mut discharge = decoded.discharge(caveat_id, None, &[]).unwrap();
+    ///
+    /// macaroon.bind(&mut discharge);
+    /// Verifier::default()
+    ///     .verify(&macaroon, &root_key, vec![discharge])
+    ///     .unwrap();
+    /// ```
+    pub fn add_public_key_third_party_caveat(
+        &mut self,
+        location: &str,
+        third_party: &PublicKey,
+        condition: &[u8],
+    ) {
+        let root_key = randombytes(ROOT_KEY_LEN);
+        let caveat_id = encode_caveat_id(third_party, &root_key, condition);
+        self.add_third_party_caveat(location, &MacaroonKey::generate(&root_key), caveat_id);
+    }
+}
+
+/// Encrypt `root_key` and `condition` into a third-party caveat ID for the third party with the
+/// public key `third_party`, so it needs no stored state to discharge the caveat
+///
+/// The caveat key to add the caveat with is `MacaroonKey::generate(root_key)`. The format is
+/// version 2 of go-macaroon-bakery's: unpadded base64url (so it starts with `A`) of
+///
+/// ```text
+/// version (2)
+/// first 4 bytes of the third party's public key
+/// first party's public key (32 bytes)
+/// nonce (24 bytes)
+/// NaCl box, from the first party to the third party, of:
+///     version (2)
+///     root key length (unsigned LEB128 varint)
+///     root key
+///     condition
+/// ```
+///
+/// The first party's key pair is generated afresh for each caveat ID. go-macaroon-bakery's
+/// version 3 caveat IDs, which add a namespace, aren't supported.
+///
+/// ```rust
+/// use macaroon::{decode_caveat_id, encode_caveat_id, KeyPair};
+///
+/// let third_party = KeyPair::generate();
+/// let id = encode_caveat_id(third_party.public_key(), b"root key", b"user-is alice");
+/// assert!(id.as_ref().starts_with(b"A"));
+/// let decoded = decode_caveat_id(&third_party, id.as_ref()).unwrap();
+/// assert_eq!(b"root key".to_vec(), decoded.root_key);
+/// assert_eq!("user-is alice", String::from_utf8(decoded.condition.0).unwrap());
+/// ```
+pub fn encode_caveat_id(third_party: &PublicKey, root_key: &[u8], condition: &[u8]) -> ByteString {
+    let first_party = KeyPair::generate();
+    let mut plaintext = Vec::with_capacity(1 + 2 + root_key.len() + condition.len());
+    plaintext.push(CAVEAT_ID_VERSION);
+    let mut len = root_key.len();
+    while len >= 0x80 {
+        plaintext.push((len as u8 & 0x7f) | 0x80);
+        len >>= 7;
+    }
+    plaintext.push(len as u8);
+    plaintext.extend_from_slice(root_key);
+    plaintext.extend_from_slice(condition);
+
+    let nonce = box_::gen_nonce();
+    let sealed = box_::seal(
+        &plaintext,
+        &nonce,
+        &box_::PublicKey(third_party.0),
+        &first_party.secret,
+    );
+    let mut id = Vec::with_capacity(
+        1 + PUBLIC_KEY_PREFIX_LEN + box_::PUBLICKEYBYTES + box_::NONCEBYTES + sealed.len(),
+    );
+    id.push(CAVEAT_ID_VERSION);
+    id.extend_from_slice(&third_party.0[..PUBLIC_KEY_PREFIX_LEN]);
+    id.extend_from_slice(&first_party.public.0);
+    id.extend_from_slice(&nonce.0);
+    id.extend(sealed);
+    base64::encode_config(&id, base64::URL_SAFE_NO_PAD).into()
+}
+
+/// Decrypt a caveat ID written by [encode_caveat_id] or go-macaroon-bakery (version 2), either
+/// base64url-encoded, as they are sent, or raw
+///
+/// # Errors
+///
+/// Fails with [MacaroonError::DeserializationError] if the caveat ID isn't in the expected
+/// format, or was encrypted for a different public key, and [MacaroonError::CryptoError] if it
+/// can't be decrypted.
+pub fn decode_caveat_id(key_pair: &KeyPair, caveat_id: &[u8]) -> Result<DecodedCaveatId> {
+    if caveat_id.first() == Some(&ENCODED_VERSION_TAG) {
+        let raw = base64::decode_config(caveat_id, base64::URL_SAFE_NO_PAD)?;
+        return decode_raw_caveat_id(key_pair, &raw);
+    }
+    decode_raw_caveat_id(key_pair, caveat_id)
+}
+
+/// Decrypt a caveat ID after any base64url encoding has been removed
+fn decode_raw_caveat_id(key_pair: &KeyPair, caveat_id: &[u8]) -> Result<DecodedCaveatId> {
+    let header_len = 1 + PUBLIC_KEY_PREFIX_LEN + box_::PUBLICKEYBYTES + box_::NONCEBYTES;
+    if caveat_id.len() < header_len + box_::MACBYTES {
+        return Err(MacaroonError::DeserializationError(
+            "caveat id too short".to_string(),
+        ));
+    }
+    if caveat_id[0] != CAVEAT_ID_VERSION {
+        return Err(MacaroonError::DeserializationError(format!(
+            "unsupported caveat id version {}",
+            caveat_id[0]
+        )));
+    }
+    let (prefix, rest) = caveat_id[1..].split_at(PUBLIC_KEY_PREFIX_LEN);
+    if prefix != &key_pair.public.0[..PUBLIC_KEY_PREFIX_LEN] {
+        return Err(MacaroonError::DeserializationError(
+            "caveat id encrypted for a different public key".to_string(),
+        ));
+    }
+    let (first_party, rest) = rest.split_at(box_::PUBLICKEYBYTES);
+    let (nonce, sealed) = rest.split_at(box_::NONCEBYTES);
+    let first_party = box_::PublicKey::from_slice(first_party).unwrap();
+    let plaintext = box_::open(
+        sealed,
+        &box_::Nonce::from_slice(nonce).unwrap(),
+        &first_party,
+        &key_pair.secret,
+    )
+    .map_err(|()| MacaroonError::CryptoError("failed to decrypt caveat id"))?;
+
+    let malformed = || MacaroonError::DeserializationError("malformed caveat id".to_string());
+    if plaintext.first() != Some(&CAVEAT_ID_VERSION) {
+        return Err(malformed());
+    }
+    let mut root_key_len: usize = 0;
+    let mut index = 1;
+    loop {
+        let byte = *plaintext.get(index).ok_or_else(malformed)?;
+        // The root key can't be longer than the plaintext, so 4 bytes of varint are plenty
+        if index > 4 {
+            return Err(malformed());
+        }
+        root_key_len |= ((byte & 0x7f) as usize) << (7 * (index - 1));
+        index += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if plaintext.len() - index < root_key_len {
+        return Err(malformed());
+    }
+    let (root_key, condition) = plaintext[index..].split_at(root_key_len);
+    Ok(DecodedCaveatId {
+        first_party_key: PublicKey(first_party.0),
+        root_key: root_key.to_vec(),
+        condition: condition.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_caveat_id, encode_caveat_id, KeyPair, PublicKey};
+    use crate::{Caveat, Macaroon, MacaroonError, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_round_trip() {
+        let third_party = KeyPair::generate();
+        let root_key = vec![7; 200];
+        let id = encode_caveat_id(third_party.public_key(), &root_key, b"user-is alice");
+        assert_eq!(b'A', id.as_ref()[0]);
+        let raw = base64::decode_config(&id, base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(2, raw[0]);
+        assert_eq!(&third_party.public_key().as_ref()[..4], &raw[1..5]);
+
+        let decoded = decode_caveat_id(&third_party, id.as_ref()).unwrap();
+        assert_eq!(root_key, decoded.root_key);
+        assert_eq!(b"user-is alice".to_vec(), decoded.condition.0);
+        assert_eq!(&raw[5..37], decoded.first_party_key.as_ref());
+        assert_eq!(decoded, decode_caveat_id(&third_party, &raw).unwrap());
+
+        let restored = KeyPair::from_secret_key(*third_party.secret_key());
+        assert_eq!(third_party, restored);
+        assert!(decode_caveat_id(&restored, id.as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let third_party = KeyPair::generate();
+        let id = encode_caveat_id(third_party.public_key(), b"key", b"condition");
+        assert!(matches!(
+            decode_caveat_id(&KeyPair::generate(), id.as_ref()),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+        let raw = base64::decode_config(&id, base64::URL_SAFE_NO_PAD).unwrap();
+        let mut tampered = raw.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(
+            decode_caveat_id(&third_party, &tampered),
+            Err(MacaroonError::CryptoError(_))
+        ));
+        let mut version = raw.clone();
+        version[0] = 3;
+        assert!(decode_caveat_id(&third_party, &version).is_err());
+        assert!(decode_caveat_id(&third_party, &raw[..60]).is_err());
+        assert!(decode_caveat_id(&third_party, b"A!!").is_err());
+    }
+
+    /// A version 2 caveat ID written by go-macaroon-bakery, as used in py-macaroon-bakery's
+    /// interoperability tests
+    #[test]
+    fn test_decode_go_caveat_id() {
+        let secret = |b64: &str| {
+            let mut key = [0; 32];
+            key.copy_from_slice(&base64::decode(b64).unwrap());
+            KeyPair::from_secret_key(key)
+        };
+        let third_party = secret("TSpvLpQkRj+T3JXnsW2n43n5zP/0X4zn0RvDiWC3IJ0=");
+        let first_party = secret("KXpsoJ9ujZYi/O2Cca6kaWh65MSawzy79LWkrjOfzcs=");
+        let id = concat!(
+            "AvD-xlUf2MdGMgtu7OKRQnCP1OQJk6PKeFWRK26WIBA6DNwKGIHq9xGcHS9IZ",
+            "Lh0cL6D9qpeKI0mXmCPfnwRQDuVYC8y5gVWd-oCGZaj5TGtk3byp2Vnw6ojmt",
+            "sULDhY59YA_J_Y0ATkERO5T9ajoRWBxU2OXBoX6bImXA",
+        );
+
+        let decoded = decode_caveat_id(&third_party, id.as_bytes()).unwrap();
+        assert_eq!(
+            base64::decode("wh0HSM65wWHOIxoGjgJJOFvQKn2jJFhC").unwrap(),
+            decoded.root_key
+        );
+        assert_eq!(b"third party condition".to_vec(), decoded.condition.0);
+        assert_eq!(first_party.public_key(), &decoded.first_party_key);
+        let raw = base64::decode_config(id, base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(decoded, decode_caveat_id(&third_party, &raw).unwrap());
+    }
+
+    #[test]
+    fn test_public_key_base64() {
+        let key = *KeyPair::generate().public_key();
+        assert_eq!(key, PublicKey::from_base64(&key.to_base64()).unwrap());
+        let url_safe = base64::encode_config(key, base64::URL_SAFE_NO_PAD);
+        assert_eq!(key, PublicKey::from_base64(&url_safe).unwrap());
+        assert!(PublicKey::from_base64("AAAA").is_err());
+    }
+
+    #[test]
+    fn test_public_key_third_party_caveat() {
+        let root_key = MacaroonKey::generate(b"root");
+        let auth = KeyPair::generate();
+        let mfa = KeyPair::generate();
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        macaroon.add_public_key_third_party_caveat(
+            "https://auth.example/",
+            auth.public_key(),
+            b"user-is alice",
+        );
+        macaroon.add_public_key_third_party_caveat("https://mfa.example/", mfa.public_key(), b"");
+
+        let mut discharges = Vec::new();
+        for (caveat, key_pair) in macaroon.third_party_caveats().iter().zip([&auth, &mfa]) {
+            let id = match caveat {
+                Caveat::ThirdParty(tp) => tp.id(),
+                Caveat::FirstParty(_) => panic!("expected a third-party caveat"),
+            };
+            assert!(decode_caveat_id(&KeyPair::generate(), id.as_ref()).is_err());
+            let decoded = decode_caveat_id(key_pair, id.as_ref()).unwrap();
+            assert_eq!(24, decoded.root_key.len());
+            let mut discharge = decoded
+                .discharge(id, None, &["ip = 10.0.0.1".into()])
+                .unwrap();
+            macaroon.bind(&mut discharge);
+            discharges.push(discharge);
+        }
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("ip = 10.0.0.1".into());
+        verifier
+            .verify(&macaroon, &root_key, discharges.clone())
+            .unwrap();
+        assert!(verifier
+            .verify(&macaroon, &root_key, discharges[..1].to_vec())
+            .is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{ByteString, Macaroon, Verifier};
+
+/// Prefix of TLS channel-binding caveats
+pub const TLS_EKM_PREFIX: &str = "tls-ekm ";
+
+/// Exporter label for TLS channel binding, from RFC 9266 (`tls-exporter`), to use with
+/// [TLS_EKM_LENGTH] and an empty context when exporting keying material
+pub const TLS_EKM_LABEL: &str = "EXPORTER-Channel-Binding";
+
+/// Length of keying material to export for [TLS_EKM_LABEL]
+pub const TLS_EKM_LENGTH: usize = 32;
+
+/// The channel-binding predicate, `tls-ekm <base64>`, for TLS exported keying material.
+///
+/// The material is encoded as unpadded URL-safe base64, so predicate normalization leaves it
+/// unchanged.
+pub fn tls_ekm_predicate(ekm: &[u8]) -> ByteString {
+    format!(
+        "{}{}",
+        TLS_EKM_PREFIX,
+        base64::encode_config(ekm, base64::URL_SAFE_NO_PAD)
+    )
+    .into()
+}
+
+impl Macaroon {
+    /// Bind the macaroon to a TLS connection, by adding a caveat only satisfied when verifying
+    /// with the same exported keying material (see [Verifier::satisfy_tls_ekm])
+    ///
+    /// Since keying material is unique to each TLS session, a macaroon stolen from one connection
+    /// can't be replayed on another. This only provides that guarantee when TLS is terminated by
+    /// the verifying service itself.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    ///
+    /// // from the TLS library, eg rustls `export_keying_material` with TLS_EKM_LABEL
+    /// let ekm = [7u8; macaroon::TLS_EKM_LENGTH];
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_tls_ekm_caveat(&ekm);
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_tls_ekm(&ekm);
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_ok());
+    /// ```
+    pub fn add_tls_ekm_caveat(&mut self, ekm: &[u8]) {
+        self.add_first_party_caveat(tls_ekm_predicate(ekm));
+    }
+}
+
+impl<C> Verifier<C> {
+    /// Satisfy channel-binding caveats for the TLS connection the macaroon was presented on,
+    /// identified by its exported keying material
+    pub fn satisfy_tls_ekm(&mut self, ekm: &[u8]) {
+        self.satisfy_exact(tls_ekm_predicate(ekm));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tls_ekm_predicate;
+    use crate::{ByteString, Macaroon, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_tls_ekm_caveat() {
+        assert_eq!(
+            ByteString::from("tls-ekm AAH_"),
+            tls_ekm_predicate(&[0, 1, 255])
+        );
+
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_tls_ekm_caveat(&[1; 32]);
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_tls_ekm(&[2; 32]);
+        verifier
+            .verify(&macaroon, &key, Default::default())
+            .unwrap_err();
+
+        let mut verifier = Verifier::default();
+        verifier.enable_predicate_normalization();
+        verifier.satisfy_tls_ekm(&[1; 32]);
+        verifier
+            .verify(&macaroon, &key, Default::default())
+            .unwrap();
+    }
+}
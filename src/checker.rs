@@ -0,0 +1,282 @@
+use crate::bakery::FirstPartyCaveatChecker;
+use crate::{ByteString, MacaroonError, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// URI of the standard caveat namespace, whose conditions are written without a prefix
+pub const STD_NAMESPACE: &str = "std";
+
+/// Maps caveat namespace URIs to the short prefixes written in caveat conditions, as in
+/// go-macaroon-bakery
+///
+/// A condition `name arg` in the namespace registered with prefix `p` is written as
+/// `p:name arg`; conditions in the standard namespace (prefix `""`) are written as is.
+///
+/// ```rust
+/// use macaroon::{Namespace, NamespacedCaveat};
+///
+/// let mut namespace = Namespace::new();
+/// namespace.register("https://example.com/fleet", "fleet").unwrap();
+/// assert_eq!(
+///     Some("fleet:region west".to_string()),
+///     namespace.resolve(&NamespacedCaveat::new("https://example.com/fleet", "region west"))
+/// );
+/// assert_eq!(
+///     Some("time-before 2030-01-01T00:00:00Z".to_string()),
+///     namespace.resolve(&NamespacedCaveat::std("time-before 2030-01-01T00:00:00Z"))
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Namespace {
+    prefixes: BTreeMap<String, String>,
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Namespace::new()
+    }
+}
+
+impl Namespace {
+    /// A namespace containing only [STD_NAMESPACE]
+    pub fn new() -> Namespace {
+        let mut prefixes = BTreeMap::new();
+        prefixes.insert(STD_NAMESPACE.to_string(), String::new());
+        Namespace { prefixes }
+    }
+
+    /// Register `uri` with `prefix`. Registering a URI which is already registered has no effect.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `prefix` contains a colon or whitespace.
+    pub fn register(&mut self, uri: &str, prefix: &str) -> Result<()> {
+        if prefix.contains(|c: char| c == ':' || c.is_whitespace()) {
+            return Err(MacaroonError::DeserializationError(format!(
+                "invalid caveat namespace prefix {:?}",
+                prefix
+            )));
+        }
+        self.prefixes
+            .entry(uri.to_string())
+            .or_insert_with(|| prefix.to_string());
+        Ok(())
+    }
+
+    pub fn prefix(&self, uri: &str) -> Option<&str> {
+        self.prefixes.get(uri).map(String::as_str)
+    }
+
+    /// The condition to put in a first-party caveat, or `None` if its namespace isn't registered
+    pub fn resolve(&self, caveat: &NamespacedCaveat) -> Option<String> {
+        match self.prefix(&caveat.namespace)? {
+            "" => Some(caveat.condition.clone()),
+            prefix => Some(format!("{}:{}", prefix, caveat.condition)),
+        }
+    }
+
+    fn uri(&self, prefix: &str) -> Option<&str> {
+        self.prefixes
+            .iter()
+            .find(|(_, p)| p.as_str() == prefix)
+            .map(|(uri, _)| uri.as_str())
+    }
+}
+
+/// A first-party caveat condition (`name arg`) in a namespace, to be minted by an
+/// [Oven](crate::Oven)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamespacedCaveat {
+    pub namespace: String,
+    pub condition: String,
+}
+
+impl NamespacedCaveat {
+    pub fn new(namespace: &str, condition: &str) -> NamespacedCaveat {
+        NamespacedCaveat {
+            namespace: namespace.to_string(),
+            condition: condition.to_string(),
+        }
+    }
+
+    /// A condition in [STD_NAMESPACE]
+    pub fn std(condition: &str) -> NamespacedCaveat {
+        NamespacedCaveat::new(STD_NAMESPACE, condition)
+    }
+}
+
+type ConditionChecker = Box<dyn Fn(&str) -> std::result::Result<(), String> + Send + Sync>;
+
+/// Checks first-party caveats by namespace and condition name, as written by an
+/// [Oven](crate::Oven)
+///
+/// Each checker is passed the condition's argument (everything after the first space), and
+/// returns an error explaining why it isn't satisfied. Caveats with an unregistered namespace
+/// prefix or condition name are never satisfied.
+///
+/// ```rust
+/// use macaroon::{Checker, FirstPartyCaveatChecker, Namespace};
+///
+/// let mut namespace = Namespace::new();
+/// namespace.register("https://example.com/fleet", "fleet").unwrap();
+/// let mut checker = Checker::new(namespace);
+/// checker
+///     .register("https://example.com/fleet", "region", |arg| {
+///         if arg == "west" {
+///             Ok(())
+///         } else {
+///             Err(format!("wrong region {}", arg))
+///         }
+///     })
+///     .unwrap();
+///
+/// assert!(checker.check_first_party_caveat(&"fleet:region west".into()).is_ok());
+/// assert!(checker.check_first_party_caveat(&"fleet:region east".into()).is_err());
+/// assert!(checker.check_first_party_caveat(&"region west".into()).is_err());
+/// ```
+pub struct Checker {
+    namespace: Namespace,
+    conditions: BTreeMap<(String, String), ConditionChecker>,
+}
+
+impl fmt::Debug for Checker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checker")
+            .field("namespace", &self.namespace)
+            .field("conditions", &self.conditions.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Checker {
+    pub fn new(namespace: Namespace) -> Checker {
+        Checker {
+            namespace,
+            conditions: BTreeMap::new(),
+        }
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// Check conditions named `condition` in the namespace `uri` with `f`, replacing any previous
+    /// checker for them
+    ///
+    /// # Errors
+    ///
+    /// Fails if `uri` isn't registered in the checker's [Namespace].
+    pub fn register<F>(&mut self, uri: &str, condition: &str, f: F) -> Result<()>
+    where
+        F: Fn(&str) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        if self.namespace.prefix(uri).is_none() {
+            return Err(MacaroonError::DeserializationError(format!(
+                "caveat namespace {:?} not registered",
+                uri
+            )));
+        }
+        self.conditions
+            .insert((uri.to_string(), condition.to_string()), Box::new(f));
+        Ok(())
+    }
+
+    fn check_condition(&self, condition: &str) -> std::result::Result<(), String> {
+        let (name, arg) = match condition.split_once(' ') {
+            Some((name, arg)) => (name, arg),
+            None => (condition, ""),
+        };
+        let (prefix, name) = match name.split_once(':') {
+            Some((prefix, name)) => (prefix, name),
+            None => ("", name),
+        };
+        let f = self
+            .namespace
+            .uri(prefix)
+            .and_then(|uri| self.conditions.get(&(uri.to_string(), name.to_string())))
+            .ok_or_else(|| "caveat not recognized".to_string())?;
+        f(arg)
+    }
+}
+
+impl FirstPartyCaveatChecker for Checker {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        let result = match std::str::from_utf8(predicate.as_ref()) {
+            Ok(condition) => self.check_condition(condition),
+            Err(_) => Err("caveat is not UTF-8".to_string()),
+        };
+        result.map_err(|reason| {
+            MacaroonError::caveat_not_satisfied(
+                predicate.as_ref(),
+                format!(
+                    "first party caveat not satisfied: {}: {}",
+                    String::from_utf8_lossy(predicate.as_ref()),
+                    reason
+                ),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checker, Namespace, NamespacedCaveat, STD_NAMESPACE};
+    use crate::{FirstPartyCaveatChecker, MacaroonError};
+
+    #[test]
+    fn test_namespace() {
+        let mut namespace = Namespace::new();
+        namespace.register("https://example.com/a", "a").unwrap();
+        namespace
+            .register("https://example.com/a", "other")
+            .unwrap();
+        assert!(namespace.register("https://example.com/b", "b:c").is_err());
+        assert_eq!(Some("a"), namespace.prefix("https://example.com/a"));
+        assert_eq!(Some(""), namespace.prefix(STD_NAMESPACE));
+        assert_eq!(
+            None,
+            namespace.resolve(&NamespacedCaveat::new("https://example.com/b", "x"))
+        );
+    }
+
+    #[test]
+    fn test_checker() {
+        let mut namespace = Namespace::new();
+        namespace.register("https://example.com/a", "a").unwrap();
+        let mut checker = Checker::new(namespace);
+        checker
+            .register(STD_NAMESPACE, "allow", |_| Ok(()))
+            .unwrap();
+        checker
+            .register("https://example.com/a", "user", |arg| {
+                if arg == "alice" {
+                    Ok(())
+                } else {
+                    Err("not alice".to_string())
+                }
+            })
+            .unwrap();
+        assert!(checker
+            .register("https://example.com/b", "user", |_| Ok(()))
+            .is_err());
+
+        assert!(checker.check_first_party_caveat(&"allow".into()).is_ok());
+        assert!(checker
+            .check_first_party_caveat(&"a:user alice".into())
+            .is_ok());
+        for (predicate, reason) in [
+            ("a:user bob", "not alice"),
+            ("user alice", "caveat not recognized"),
+            ("b:user alice", "caveat not recognized"),
+        ]
+        .iter()
+        {
+            match checker.check_first_party_caveat(&(*predicate).into()) {
+                Err(MacaroonError::CaveatNotSatisfied(caveat)) => {
+                    assert!(caveat.reason.ends_with(reason), "{}", caveat)
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
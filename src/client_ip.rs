@@ -0,0 +1,222 @@
+use crate::{ByteString, Macaroon, MacaroonError, Result, Verifier};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Prefix of client IP caveats, followed by an IP address or CIDR range
+pub const CLIENT_IP_PREFIX: &str = "client-ip ";
+
+/// An address or range of addresses, as a network address and prefix length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix_len: u32,
+}
+
+impl Network {
+    /// Parse an IPv4 or IPv6 address, or a CIDR range like `10.0.0.0/8`. Host bits set in a
+    /// range are cleared.
+    fn parse(s: &str) -> Option<Network> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().ok()?;
+        let width = width(&addr);
+        let prefix_len = match prefix_len {
+            Some(len) if !len.is_empty() && len.bytes().all(|b| b.is_ascii_digit()) => {
+                len.parse().ok().filter(|len| *len <= width)?
+            }
+            Some(_) => return None,
+            None => width,
+        };
+        let addr = match addr {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(
+                mask(u32::from(v4).into(), 32, prefix_len) as u32,
+            )),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(mask(u128::from(v6), 128, prefix_len))),
+        };
+        Some(Network { addr, prefix_len })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, unmap(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                mask(u32::from(ip).into(), 32, self.prefix_len) == u128::from(u32::from(net))
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                mask(u128::from(ip), 128, self.prefix_len) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.prefix_len == width(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix_len)
+        }
+    }
+}
+
+fn width(addr: &IpAddr) -> u32 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Keep the first `prefix_len` of the `width` low bits of `bits`
+fn mask(bits: u128, width: u32, prefix_len: u32) -> u128 {
+    let host_bits = width - prefix_len;
+    if host_bits >= 128 {
+        0
+    } else {
+        bits >> host_bits << host_bits
+    }
+}
+
+/// IPv4 clients seen through a dual-stack socket have IPv4-mapped addresses (`::ffff:a.b.c.d`)
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+                IpAddr::V4(Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo)))
+            }
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// The client IP caveat predicate, `client-ip <address or range>`, for an IPv4 or IPv6 address
+/// or a CIDR range such as `192.0.2.0/24` or `2001:db8::/32`
+///
+/// # Errors
+///
+/// Fails with [MacaroonError::IncompleteCaveat] if `ip_or_cidr` isn't an address or range.
+pub fn client_ip_predicate(ip_or_cidr: &str) -> Result<ByteString> {
+    let network = Network::parse(ip_or_cidr.trim()).ok_or(MacaroonError::IncompleteCaveat(
+        "invalid IP address or CIDR range",
+    ))?;
+    Ok(format!("{}{}", CLIENT_IP_PREFIX, network).into())
+}
+
+/// Network of a client IP caveat predicate, or `None` if it isn't one
+fn predicate_network(predicate: &ByteString) -> Option<Network> {
+    let predicate = std::str::from_utf8(predicate.as_ref()).ok()?;
+    Network::parse(predicate.strip_prefix(CLIENT_IP_PREFIX)?)
+}
+
+impl Macaroon {
+    /// Restrict the macaroon to clients with the given IP address, or in the given CIDR range
+    ///
+    /// With several client IP caveats, the client has to be in all of them.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_client_ip_caveat("10.0.0.0/8").unwrap();
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_client_ip("10.1.2.3".parse().unwrap());
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_ok());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_client_ip("192.0.2.1".parse().unwrap());
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::IncompleteCaveat] if `ip_or_cidr` isn't an address or range.
+    pub fn add_client_ip_caveat(&mut self, ip_or_cidr: &str) -> Result<()> {
+        self.add_first_party_caveat(client_ip_predicate(ip_or_cidr)?);
+        Ok(())
+    }
+}
+
+impl<C> Verifier<C> {
+    /// Satisfy client IP caveats whose address or range includes `client`
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) match IPv4 caveats.
+    pub fn satisfy_client_ip(&mut self, client: IpAddr) {
+        self.satisfy_general(move |predicate| match predicate_network(predicate) {
+            Some(network) => network.contains(client),
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_ip_predicate, Network};
+    use crate::{ByteString, Macaroon, MacaroonKey, Verifier};
+    use std::net::IpAddr;
+
+    fn contains(network: &str, ip: &str) -> bool {
+        Network::parse(network)
+            .unwrap()
+            .contains(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn test_networks() {
+        assert!(contains("192.0.2.1", "192.0.2.1"));
+        assert!(!contains("192.0.2.1", "192.0.2.2"));
+        assert!(contains("10.0.0.0/8", "10.255.0.1"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("0.0.0.0/0", "203.0.113.9"));
+        assert!(contains("192.0.2.0/23", "192.0.3.255"));
+        assert!(!contains("192.0.2.0/23", "192.0.4.0"));
+        assert!(contains("2001:db8::/32", "2001:db8:1::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+        assert!(contains("::/0", "::1"));
+        assert!(!contains("::/0", "127.0.0.1"));
+        assert!(contains("10.0.0.0/8", "::ffff:10.1.2.3"));
+        assert!(!contains("10.0.0.0/8", "2001:db8::1"));
+
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "10.0.0.0/",
+            "10.0.0.0/+8",
+            "::/129",
+            "10.0.0",
+            "host/8",
+        ] {
+            assert!(client_ip_predicate(invalid).is_err(), "{}", invalid);
+        }
+        // canonical form, with host bits cleared
+        assert_eq!(
+            ByteString::from("client-ip 10.0.0.0/8"),
+            client_ip_predicate("10.1.2.3/8").unwrap()
+        );
+        assert_eq!(
+            ByteString::from("client-ip 2001:db8::1"),
+            client_ip_predicate("2001:0db8:0::1/128").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_client_ip_caveats() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        macaroon.add_client_ip_caveat("10.0.0.0/8").unwrap();
+        macaroon.add_client_ip_caveat("10.1.0.0/16").unwrap();
+        assert!(macaroon.add_client_ip_caveat("not an ip").is_err());
+
+        let verify = |client: &str| {
+            let mut verifier = Verifier::default();
+            verifier.satisfy_client_ip(client.parse().unwrap());
+            verifier.verify(&macaroon, &key, vec![])
+        };
+        assert!(verify("10.1.2.3").is_ok());
+        assert!(verify("::ffff:10.1.2.3").is_ok());
+        // has to be in every range
+        assert!(verify("10.2.0.1").is_err());
+    }
+}
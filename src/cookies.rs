@@ -0,0 +1,238 @@
+use crate::timestamp::format_http_date;
+use crate::{decode_macaroons_header, encode_macaroons_header, Macaroon, MacaroonError, Result};
+use std::time::SystemTime;
+
+/// Prefix of the names of cookies carrying a root macaroon and its discharges
+pub const MACAROON_COOKIE_PREFIX: &str = "macaroon-";
+/// Default bound on the value of each cookie written by [encode_macaroon_cookies], leaving
+/// room for the name and attributes within browsers' 4096 byte limit
+pub const DEFAULT_MAX_COOKIE_LEN: usize = 3800;
+
+/// A cookie to set with `Set-Cookie`
+///
+/// Only the expiry is derived from the macaroons; other attributes (path, domain, `Secure`...)
+/// are left to the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacaroonCookie {
+    pub name: String,
+    pub value: String,
+    /// When the macaroons expire, from their expiry caveats (see [crate::expiry::expiry]). Only
+    /// set with the `time` feature.
+    pub expires: Option<SystemTime>,
+}
+
+impl MacaroonCookie {
+    /// The `Set-Cookie` header value, `<name>=<value>`, with an `Expires` attribute if the
+    /// macaroons expire. Further attributes can be appended, eg `; Path=/; Secure; HttpOnly`.
+    ///
+    /// ```rust
+    /// use macaroon::MacaroonCookie;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let cookie = MacaroonCookie {
+    ///     name: "macaroon-1234".into(),
+    ///     value: "W10=".into(),
+    ///     expires: Some(UNIX_EPOCH + Duration::from_secs(1_893_456_000)),
+    /// };
+    /// assert_eq!(
+    ///     "macaroon-1234=W10=; Expires=Tue, 01 Jan 2030 00:00:00 GMT",
+    ///     cookie.to_set_cookie()
+    /// );
+    /// ```
+    pub fn to_set_cookie(&self) -> String {
+        match self.expires {
+            Some(at) => format!(
+                "{}={}; Expires={}",
+                self.name,
+                self.value,
+                format_http_date(at)
+            ),
+            None => format!("{}={}", self.name, self.value),
+        }
+    }
+}
+
+/// Encode a root macaroon and its discharges as cookies, named after the root macaroon's
+/// signature as httpbakery does (`macaroon-<hex signature>`)
+///
+/// If the value is longer than `max_len`, it is split across several cookies: the first keeps
+/// the name above and the others are suffixed with `.1`, `.2`... [decode_macaroon_cookies]
+/// joins them back. Only a single cookie is understood by httpbakery itself.
+///
+/// With the `time` feature, every cookie expires with the earliest expiry caveat of the root
+/// macaroon and the discharges it uses, as httpbakery's cookies do, so browsers drop them once
+/// they no longer verify.
+///
+/// # Errors
+///
+/// Fails if `macaroons` is empty or `max_len` is 0.
+pub fn encode_macaroon_cookies(
+    macaroons: &[Macaroon],
+    max_len: usize,
+) -> Result<Vec<MacaroonCookie>> {
+    let root = macaroons
+        .first()
+        .ok_or(MacaroonError::IncompleteMacaroon("no macaroons to encode"))?;
+    if max_len == 0 {
+        return Err(MacaroonError::LimitExceeded(
+            "cookie length must be positive".to_string(),
+        ));
+    }
+    let mut name = MACAROON_COOKIE_PREFIX.to_string();
+    for b in root.signature[..].iter() {
+        name.push_str(&format!("{:02x}", b));
+    }
+    let expires = expiry(root, &macaroons[1..]);
+    let value = encode_macaroons_header(macaroons)?;
+    // The value is base64, so it can be split at any byte
+    Ok(value
+        .as_bytes()
+        .chunks(max_len)
+        .enumerate()
+        .map(|(i, chunk)| MacaroonCookie {
+            name: match i {
+                0 => name.clone(),
+                i => format!("{}.{}", name, i),
+            },
+            value: String::from_utf8_lossy(chunk).into_owned(),
+            expires,
+        })
+        .collect())
+}
+
+#[cfg(feature = "time")]
+fn expiry(root: &Macaroon, discharges: &[Macaroon]) -> Option<SystemTime> {
+    crate::expiry::expiry(root, discharges).map(SystemTime::from)
+}
+
+#[cfg(not(feature = "time"))]
+fn expiry(_root: &Macaroon, _discharges: &[Macaroon]) -> Option<SystemTime> {
+    None
+}
+
+/// Decode the macaroon cookies in a `Cookie` request header value, returning one slice (a root
+/// macaroon and its discharges) per cookie, in the order they appear
+///
+/// Other cookies are ignored, and so are macaroon cookies that can't be decoded or are missing
+/// parts, as httpbakery does, so a stale cookie doesn't lock the client out.
+pub fn decode_macaroon_cookies(header: &str) -> Vec<Vec<Macaroon>> {
+    // (name, parts by index)
+    let mut cookies: Vec<(&str, Vec<(usize, &str)>)> = Vec::new();
+    for cookie in header.split(';') {
+        let (name, value) = match cookie.trim().split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let name = match name.strip_prefix(MACAROON_COOKIE_PREFIX) {
+            Some(name) => name,
+            None => continue,
+        };
+        let (name, index) = match name.rsplit_once('.') {
+            Some((name, index)) => match index.parse() {
+                Ok(index) => (name, index),
+                Err(_) => continue,
+            },
+            None => (name, 0),
+        };
+        let value = value.trim_matches('"');
+        match cookies.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, parts)) => parts.push((index, value)),
+            None => cookies.push((name, vec![(index, value)])),
+        }
+    }
+
+    let mut slices = Vec::new();
+    for (name, mut parts) in cookies {
+        parts.sort_by_key(|(index, _)| *index);
+        if parts.iter().enumerate().any(|(i, (index, _))| i != *index) {
+            warn!("macaroon cookie {} is missing parts", name);
+            continue;
+        }
+        let value: String = parts.into_iter().map(|(_, value)| value).collect();
+        match decode_macaroons_header(&value) {
+            Ok(macaroons) => slices.push(macaroons),
+            Err(e) => warn!("cannot decode macaroon cookie {}: {}", name, e),
+        }
+    }
+    slices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MacaroonKey;
+
+    fn bound_slice() -> Vec<Macaroon> {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut root = Macaroon::create(
+            Some("https://service.example/".into()),
+            &root_key,
+            "root".into(),
+        )
+        .unwrap();
+        root.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        root.bind(&mut discharge);
+        vec![root, discharge]
+    }
+
+    #[test]
+    fn test_cookies() {
+        let slice = bound_slice();
+        let cookies = encode_macaroon_cookies(&slice, DEFAULT_MAX_COOKIE_LEN).unwrap();
+        assert_eq!(1, cookies.len());
+        assert_eq!(MACAROON_COOKIE_PREFIX.len() + 64, cookies[0].name.len());
+        assert_eq!(None, cookies[0].expires);
+
+        let split = encode_macaroon_cookies(&slice, 100).unwrap();
+        assert!(split.len() > 2);
+        assert_eq!(format!("{}.1", split[0].name), split[1].name);
+        assert_eq!(
+            cookies[0].value,
+            split.iter().map(|c| &*c.value).collect::<String>()
+        );
+
+        // Parts in any order, among other cookies
+        let mut pairs: Vec<String> = split.iter().rev().map(|c| c.to_set_cookie()).collect();
+        pairs.insert(1, "session=abc".to_string());
+        pairs.push("macaroon-stale=bm90IGEgbWFjYXJvb24".to_string());
+        let header = pairs.join("; ");
+        assert_eq!(vec![slice.clone()], decode_macaroon_cookies(&header));
+
+        // A missing part drops that cookie only
+        let header = format!(
+            "{}={}; {}={}",
+            split[0].name, split[0].value, cookies[0].name, cookies[0].value
+        )
+        .replacen("macaroon-", "macaroon-y", 1);
+        assert_eq!(vec![slice.clone()], decode_macaroon_cookies(&header));
+
+        assert!(encode_macaroon_cookies(&[], 100).is_err());
+        assert!(encode_macaroon_cookies(&slice, 0).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_cookie_expiry() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut slice = bound_slice();
+        let at = UNIX_EPOCH + Duration::from_secs(1_893_456_000);
+        slice[0].add_first_party_caveat(crate::expiry_predicate(at + Duration::from_secs(60)));
+        let mut discharge =
+            Macaroon::create(None, &MacaroonKey::generate(b"caveat key"), "caveat".into()).unwrap();
+        discharge.add_first_party_caveat(crate::expiry_predicate(at));
+        slice[0].bind(&mut discharge);
+
+        let cookies = encode_macaroon_cookies(&[slice[0].clone()], 100).unwrap();
+        assert!(cookies
+            .iter()
+            .all(|c| c.expires == Some(at + Duration::from_secs(60))));
+        let cookies = encode_macaroon_cookies(&[slice[0].clone(), discharge], 100).unwrap();
+        assert!(cookies.iter().all(|c| c.expires == Some(at)));
+        assert!(cookies[0]
+            .to_set_cookie()
+            .ends_with("; Expires=Tue, 01 Jan 2030 00:00:00 GMT"));
+    }
+}
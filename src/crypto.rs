@@ -1,8 +1,13 @@
 use crate::error::MacaroonError;
-use crate::Result;
-use sodiumoxide::crypto::auth::hmacsha256::{authenticate, gen_key, Key, Tag};
+use crate::{base64_decode_flexible, decode_hex, encode_hex, Result};
+use sodiumoxide::crypto::aead::xchacha20poly1305_ietf as xchacha;
+use sodiumoxide::crypto::auth::hmacsha256::{authenticate, gen_key, Key, State, Tag};
+use sodiumoxide::crypto::hash::sha256;
 use sodiumoxide::crypto::secretbox;
 use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
 use std::ops::{Deref, DerefMut};
 
 const KEY_GENERATOR: MacaroonKey = MacaroonKey(*b"macaroons-key-generator\0\0\0\0\0\0\0\0\0");
@@ -14,8 +19,13 @@ const KEY_GENERATOR: MacaroonKey = MacaroonKey(*b"macaroons-key-generator\0\0\0\
 /// bytes; generated randomly; or generated via an HMAC from a byte string of any length. For
 /// security, keys should be generated using at least 32 bytes of entropy, and stored securely.
 ///
-/// No special techniques are used by this crate to keep key material safe in memory. The `Debug`
-/// trait will output the secret key material, which could end up leaked in logs.
+/// Keys aren't `Copy`, so that copies of them are explicit. With the `zeroize` feature, they
+/// implement `Zeroize` and `ZeroizeOnDrop`, so key material is scrubbed from memory when a key is
+/// dropped; otherwise no special techniques are used to keep it safe in memory.
+///
+/// The `Debug` output is redacted, showing only a short fingerprint (the start of the key's
+/// SHA-256 hash), so keys and signatures can be told apart in logs without being leaked. Use
+/// [MacaroonKey::dangerous_debug] to see the key material, eg in tests.
 ///
 /// ## Creation
 ///
@@ -24,6 +34,7 @@ const KEY_GENERATOR: MacaroonKey = MacaroonKey(*b"macaroons-key-generator\0\0\0\
 /// #
 /// # fn main() -> Result<(), Box<dyn Error>> {
 /// use macaroon::MacaroonKey;
+/// use std::convert::TryFrom;
 /// extern crate base64;
 ///
 /// // generate a new random key from scratch
@@ -33,15 +44,68 @@ const KEY_GENERATOR: MacaroonKey = MacaroonKey(*b"macaroons-key-generator\0\0\0\
 /// let weak_example_key = MacaroonKey::generate(b"some-secret-here");
 ///
 /// // import a base64-encoded key (eg, from a secrets vault)
-/// let mut key_bytes: [u8; 32] = [0; 32];
-/// key_bytes.copy_from_slice(&base64::decode("zV/IaqNgsWe2c22J5ilLY/d9DbxEir2z1bYBrzBemsM=")?);
-/// let secret_key: MacaroonKey = key_bytes.into();
+/// let secret_key = MacaroonKey::from_base64("zV/IaqNgsWe2c22J5ilLY/d9DbxEir2z1bYBrzBemsM=")?;
+///
+/// // or raw bytes, which must be exactly 32 bytes long
+/// let key_bytes: Vec<u8> = base64::decode("zV/IaqNgsWe2c22J5ilLY/d9DbxEir2z1bYBrzBemsM=")?;
+/// assert_eq!(secret_key, MacaroonKey::try_from(key_bytes)?);
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///
+/// Keys (and signatures, which share the type) are compared in constant time.
+#[derive(Clone, Eq)]
 pub struct MacaroonKey([u8; sodiumoxide::crypto::auth::KEYBYTES]);
 
+impl fmt::Debug for MacaroonKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = sha256::hash(&self.0);
+        write!(f, "MacaroonKey(<redacted ")?;
+        for b in hash.0[..4].iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, ">)")
+    }
+}
+
+/// The unredacted `Debug` output of a [MacaroonKey], from [MacaroonKey::dangerous_debug]
+pub struct DangerousDebug<'a>(&'a MacaroonKey);
+
+impl fmt::Debug for DangerousDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MacaroonKey").field(&self.0 .0).finish()
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for MacaroonKey {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for MacaroonKey {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::ZeroizeOnDrop for MacaroonKey {}
+
+impl PartialEq for MacaroonKey {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+/// Compare secret values (keys, signatures and HMACs) without leaking where they differ through
+/// timing. Only the lengths are compared in variable time.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    sodiumoxide::utils::memcmp(a, b)
+}
+
 impl AsRef<[u8; sodiumoxide::crypto::auth::KEYBYTES]> for MacaroonKey {
     fn as_ref(&self) -> &[u8; sodiumoxide::crypto::auth::KEYBYTES] {
         &self.0
@@ -94,6 +158,28 @@ impl From<&[u8; sodiumoxide::crypto::auth::KEYBYTES]> for MacaroonKey {
     }
 }
 
+impl TryFrom<&[u8]> for MacaroonKey {
+    type Error = MacaroonError;
+
+    /// Uses bytes directly as a MacaroonKey (with no HMAC), failing unless there are exactly 32
+    fn try_from(b: &[u8]) -> Result<Self> {
+        <[u8; sodiumoxide::crypto::auth::KEYBYTES]>::try_from(b)
+            .map(MacaroonKey)
+            .map_err(|_| {
+                MacaroonError::CryptoError("supplied key has wrong length (expected 32 bytes)")
+            })
+    }
+}
+
+impl TryFrom<Vec<u8>> for MacaroonKey {
+    type Error = MacaroonError;
+
+    /// Uses bytes directly as a MacaroonKey (with no HMAC), failing unless there are exactly 32
+    fn try_from(b: Vec<u8>) -> Result<Self> {
+        MacaroonKey::try_from(&b[..])
+    }
+}
+
 impl MacaroonKey {
     /// Generate a new random key, using a secure random number generator.
     ///
@@ -118,6 +204,98 @@ impl MacaroonKey {
     pub fn generate(seed: &[u8]) -> Self {
         generate_derived_key(seed)
     }
+
+    /// Formats the key material itself with `Debug`, unlike the redacted `Debug` impl
+    ///
+    /// This is meant for tests and local debugging: never log it in production.
+    ///
+    /// ```rust
+    /// # use macaroon::MacaroonKey;
+    /// let key = MacaroonKey::from([7; 32]);
+    /// assert!(!format!("{:?}", key).contains("7, 7"));
+    /// assert!(format!("{:?}", key.dangerous_debug()).contains("7, 7"));
+    /// ```
+    pub fn dangerous_debug(&self) -> DangerousDebug<'_> {
+        DangerousDebug(self)
+    }
+
+    /// The raw key bytes, eg to store the key in a secrets vault
+    pub fn expose_secret(&self) -> &[u8; sodiumoxide::crypto::auth::KEYBYTES] {
+        &self.0
+    }
+
+    /// Decode a key from base64, in standard or URL-safe encoding, with or without padding
+    ///
+    /// # Errors
+    ///
+    /// Fails if `encoded` isn't base64, or doesn't decode to exactly 32 bytes.
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        MacaroonKey::try_from(base64_decode_flexible(encoded.as_bytes())?)
+    }
+
+    /// The key in standard base64, with padding
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.0)
+    }
+
+    /// Decode a key from 64 hex digits, in either case
+    ///
+    /// # Errors
+    ///
+    /// Fails if `encoded` isn't hex, or doesn't decode to exactly 32 bytes.
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let bytes = decode_hex(encoded).ok_or(MacaroonError::CryptoError("invalid hex key"))?;
+        MacaroonKey::try_from(bytes)
+    }
+
+    /// The key as 64 lower-case hex digits
+    pub fn to_hex(&self) -> String {
+        encode_hex(&self.0)
+    }
+}
+
+/// Incremental HMAC computation, using the same algorithm as macaroon signatures.
+///
+/// Data can be fed in pieces, either with [HmacWriter::update] or through the [io::Write]
+/// implementation (eg, with [io::copy]), so that large caveat payloads can be signed while they
+/// are streamed, without first being assembled in memory.
+///
+/// ```rust
+/// use macaroon::{HmacWriter, MacaroonKey};
+/// use std::io::Write;
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let mut writer = HmacWriter::new(&key);
+/// writer.update(b"account = ");
+/// write!(writer, "{}", 3735928559u32).unwrap();
+/// let tag: MacaroonKey = writer.finalize();
+/// ```
+pub struct HmacWriter(State);
+
+impl HmacWriter {
+    pub fn new(key: &MacaroonKey) -> HmacWriter {
+        HmacWriter(State::init(key))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> MacaroonKey {
+        let Tag(result_bytes) = self.0.finalize();
+        MacaroonKey(result_bytes)
+    }
+}
+
+impl io::Write for HmacWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 fn generate_derived_key(key: &[u8]) -> MacaroonKey {
@@ -140,15 +318,23 @@ where
 {
     let MacaroonKey(tmp1) = hmac(key, text1);
     let MacaroonKey(tmp2) = hmac(key, text2);
-    let tmp = [tmp1, tmp2].concat();
-    hmac(key, &tmp)
+    let mut tmp = [0; 64];
+    tmp[..32].copy_from_slice(&tmp1);
+    tmp[32..].copy_from_slice(&tmp2);
+    hmac(key, &tmp[..])
 }
 
 pub fn encrypt_key<T>(key: &T, plaintext: &T) -> Vec<u8>
 where
     T: AsRef<[u8; sodiumoxide::crypto::auth::KEYBYTES]> + ?Sized,
 {
-    let nonce = secretbox::gen_nonce();
+    encrypt_key_with_nonce(key, plaintext, secretbox::gen_nonce())
+}
+
+fn encrypt_key_with_nonce<T>(key: &T, plaintext: &T, nonce: secretbox::Nonce) -> Vec<u8>
+where
+    T: AsRef<[u8; sodiumoxide::crypto::auth::KEYBYTES]> + ?Sized,
+{
     let encrypted = secretbox::seal(plaintext.as_ref(), &nonce, &secretbox::Key(*key.as_ref()));
     let mut ret: Vec<u8> = Vec::new();
     ret.extend(&nonce.0);
@@ -156,6 +342,36 @@ where
     ret
 }
 
+/// Length of the nonces a [NonceSource] fills in
+pub const NONCE_LEN: usize = secretbox::NONCEBYTES;
+
+/// Where the nonce encrypting a third-party caveat key comes from, for
+/// [Macaroon::add_third_party_caveat_with_rng](crate::Macaroon::add_third_party_caveat_with_rng)
+///
+/// Nonces must never repeat under the same key, so anything other than a secure random number
+/// generator is only fit for tests reproducing reference vectors. Closures filling in the nonce
+/// are nonce sources.
+pub trait NonceSource {
+    fn fill_nonce(&mut self, nonce: &mut [u8; NONCE_LEN]);
+}
+
+impl<F: FnMut(&mut [u8; NONCE_LEN])> NonceSource for F {
+    fn fill_nonce(&mut self, nonce: &mut [u8; NONCE_LEN]) {
+        self(nonce)
+    }
+}
+
+/// Like [SecretBox]'s encryption, with a nonce from `rng`
+pub(crate) fn encrypt_key_with_rng<R: NonceSource + ?Sized>(
+    key: &MacaroonKey,
+    plaintext: &MacaroonKey,
+    rng: &mut R,
+) -> Vec<u8> {
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill_nonce(&mut nonce);
+    encrypt_key_with_nonce(key, plaintext, secretbox::Nonce(nonce))
+}
+
 pub fn decrypt_key<T, U>(key: &T, data: &U) -> Result<MacaroonKey>
 where
     T: AsRef<[u8; sodiumoxide::crypto::auth::KEYBYTES]> + ?Sized,
@@ -168,9 +384,7 @@ where
     }
     let mut nonce: [u8; secretbox::NONCEBYTES] = [0; secretbox::NONCEBYTES];
     nonce.clone_from_slice(&raw_data[..secretbox::NONCEBYTES]);
-    let mut temp: Vec<u8> = Vec::new();
-    temp.extend(&raw_data[secretbox::NONCEBYTES..]);
-    let ciphertext = temp.as_slice();
+    let ciphertext = &raw_data[secretbox::NONCEBYTES..];
     match secretbox::open(
         ciphertext,
         &secretbox::Nonce(nonce),
@@ -191,9 +405,96 @@ where
     }
 }
 
+/// Encrypts the caveat key of a third-party caveat (its "verifier ID") under the signature of
+/// the macaroon it's added to
+///
+/// The verifier of the macaroon has to use the matching [Decryptor] (see
+/// [Verifier::with_decryptor](crate::Verifier::with_decryptor)).
+pub trait Encryptor: Send + Sync {
+    fn encrypt_key(&self, key: &MacaroonKey, plaintext: &MacaroonKey) -> Vec<u8>;
+}
+
+/// Decrypts the verifier ID of a third-party caveat, as encrypted by the matching [Encryptor]
+pub trait Decryptor: Send + Sync {
+    fn decrypt_key(&self, key: &MacaroonKey, data: &[u8]) -> Result<MacaroonKey>;
+}
+
+/// XSalsa20-Poly1305 (NaCl `secretbox`), the default, as used by libmacaroons and pymacaroons
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SecretBox;
+
+impl Encryptor for SecretBox {
+    fn encrypt_key(&self, key: &MacaroonKey, plaintext: &MacaroonKey) -> Vec<u8> {
+        encrypt_key(key, plaintext)
+    }
+}
+
+impl Decryptor for SecretBox {
+    fn decrypt_key(&self, key: &MacaroonKey, data: &[u8]) -> Result<MacaroonKey> {
+        decrypt_key(key, data)
+    }
+}
+
+/// XChaCha20-Poly1305 (IETF), with the random nonce prepended to the ciphertext as for
+/// [SecretBox]
+///
+/// This isn't understood by other macaroon implementations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XChaCha20Poly1305;
+
+impl Encryptor for XChaCha20Poly1305 {
+    fn encrypt_key(&self, key: &MacaroonKey, plaintext: &MacaroonKey) -> Vec<u8> {
+        let nonce = xchacha::gen_nonce();
+        let encrypted = xchacha::seal(plaintext, None, &nonce, &xchacha::Key(key.0));
+        let mut ret = nonce.0.to_vec();
+        ret.extend(encrypted);
+        ret
+    }
+}
+
+impl Decryptor for XChaCha20Poly1305 {
+    fn decrypt_key(&self, key: &MacaroonKey, data: &[u8]) -> Result<MacaroonKey> {
+        if data.len() <= xchacha::NONCEBYTES + xchacha::TAGBYTES {
+            return Err(MacaroonError::CryptoError("encrypted data too short"));
+        }
+        let nonce = xchacha::Nonce::from_slice(&data[..xchacha::NONCEBYTES])
+            .ok_or(MacaroonError::CryptoError("encrypted data too short"))?;
+        let plaintext = xchacha::open(
+            &data[xchacha::NONCEBYTES..],
+            None,
+            &nonce,
+            &xchacha::Key(key.0),
+        )
+        .map_err(|()| MacaroonError::CryptoError("failed to decrypt ciphertext"))?;
+        Ok(Key::from_slice(&plaintext)
+            .ok_or(MacaroonError::CryptoError(
+                "supplied key has wrong length (expected 32 bytes)",
+            ))?
+            .into())
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{decrypt_key, encrypt_key, MacaroonKey};
+    use super::{
+        constant_time_eq, decrypt_key, encrypt_key, hmac, Decryptor, Encryptor, HmacWriter,
+        MacaroonKey, SecretBox, XChaCha20Poly1305,
+    };
+    use crate::MacaroonError;
+    use std::convert::TryFrom;
+    use std::io::Write;
+
+    #[test]
+    fn test_hmac_writer() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = HmacWriter::new(&key);
+        for chunk in data.chunks(777) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(hmac(&key, &data), writer.finalize());
+        assert_eq!(hmac(&key, b""), HmacWriter::new(&key).finalize());
+    }
 
     #[test]
     fn test_encrypt_decrypt() {
@@ -204,4 +505,84 @@ mod test {
         let decrypted = decrypt_key(&key, &encrypted).unwrap();
         assert_eq!(secret, decrypted);
     }
+
+    #[test]
+    fn test_encryptors() {
+        let secret = MacaroonKey::generate(b"secret");
+        let key = MacaroonKey::generate(b"key");
+        let secretbox = SecretBox.encrypt_key(&key, &secret);
+        assert_eq!(secret, decrypt_key(&key, &secretbox).unwrap());
+        assert_eq!(secret, SecretBox.decrypt_key(&key, &secretbox).unwrap());
+        assert!(XChaCha20Poly1305.decrypt_key(&key, &secretbox).is_err());
+
+        let chacha = XChaCha20Poly1305.encrypt_key(&key, &secret);
+        assert_eq!(
+            secret,
+            XChaCha20Poly1305.decrypt_key(&key, &chacha).unwrap()
+        );
+        assert!(SecretBox.decrypt_key(&key, &chacha).is_err());
+        assert!(XChaCha20Poly1305
+            .decrypt_key(&MacaroonKey::generate(b"other"), &chacha)
+            .is_err());
+        assert!(XChaCha20Poly1305.decrypt_key(&key, &chacha[..40]).is_err());
+    }
+
+    #[test]
+    fn test_fallible_conversions() {
+        let key = MacaroonKey::generate(b"key");
+        assert_eq!(key, MacaroonKey::try_from(&key[..]).unwrap());
+        assert_eq!(key, MacaroonKey::try_from(key.to_vec()).unwrap());
+        assert!(matches!(
+            MacaroonKey::try_from(&key[..31]),
+            Err(MacaroonError::CryptoError(_))
+        ));
+        assert!(MacaroonKey::try_from(vec![0; 33]).is_err());
+
+        assert_eq!(key, MacaroonKey::from_base64(&key.to_base64()).unwrap());
+        let url_safe = base64::encode_config(&key[..], base64::URL_SAFE_NO_PAD);
+        assert_eq!(key, MacaroonKey::from_base64(&url_safe).unwrap());
+        assert!(MacaroonKey::from_base64("c2hvcnQ=").is_err());
+        assert!(MacaroonKey::from_base64("not base64!").is_err());
+
+        assert_eq!(64, key.to_hex().len());
+        assert_eq!(key, MacaroonKey::from_hex(&key.to_hex()).unwrap());
+        assert_eq!(
+            key,
+            MacaroonKey::from_hex(&key.to_hex().to_uppercase()).unwrap()
+        );
+        assert!(MacaroonKey::from_hex(&key.to_hex()[..62]).is_err());
+        assert!(MacaroonKey::from_hex(&"zz".repeat(32)).is_err());
+        assert!(MacaroonKey::from_hex(&"+f".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        let key = MacaroonKey::generate(b"key");
+        let mut other = key.clone();
+        assert_eq!(key, other);
+        other[31] ^= 1;
+        assert_ne!(key, other);
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(&key[..], &key[..31]));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize() {
+        use zeroize::Zeroize;
+        let mut key = MacaroonKey::generate(b"key");
+        key.zeroize();
+        assert_eq!(&[0; 32], key.expose_secret());
+    }
+
+    #[test]
+    fn test_redacted_debug() {
+        let key = MacaroonKey::from(b"This is my secret key\0\0\0\0\0\0\0\0\0\0\0");
+        let debug = format!("{:?}", key);
+        assert!(debug.starts_with("MacaroonKey(<redacted "), "{}", debug);
+        assert!(!debug.contains("84, 104"), "{}", debug);
+        assert_eq!(debug, format!("{:?}", key.clone()));
+        assert_ne!(debug, format!("{:?}", MacaroonKey::generate(b"other")));
+        assert!(format!("{:?}", key.dangerous_debug()).starts_with("MacaroonKey([84, 104, "));
+    }
 }
@@ -0,0 +1,713 @@
+use crate::caveat::ThirdParty;
+use crate::crypto;
+use crate::identifier::{IdentifierGenerator, UuidV4Generator};
+use crate::serialization::v2json;
+use crate::{ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Error code asking the client to interact with the discharger (eg, log in) and retry
+pub const CODE_INTERACTION_REQUIRED: &str = "interaction required";
+
+/// A request to discharge a third-party caveat
+///
+/// The caveat ID is sent as `id` when it's valid UTF-8 and as `id64` (unpadded URL-safe base64)
+/// otherwise. `caveat64` carries the encrypted caveat itself, for dischargers which don't store
+/// caveats by ID.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DischargeRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id64: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caveat64: Option<String>,
+}
+
+impl DischargeRequest {
+    pub fn new(caveat_id: &ByteString) -> DischargeRequest {
+        match std::str::from_utf8(caveat_id.as_ref()) {
+            Ok(id) => DischargeRequest {
+                id: Some(id.to_string()),
+                ..Default::default()
+            },
+            Err(_) => DischargeRequest {
+                id64: Some(base64::encode_config(caveat_id, base64::URL_SAFE_NO_PAD)),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Attach the encrypted caveat, sent as `caveat64`
+    pub fn with_caveat(mut self, caveat: &[u8]) -> DischargeRequest {
+        self.caveat64 = Some(base64::encode_config(caveat, base64::URL_SAFE_NO_PAD));
+        self
+    }
+
+    /// The caveat ID, from `id` or `id64`
+    ///
+    /// # Errors
+    ///
+    /// Fails if neither or both are present, or `id64` isn't valid base64.
+    pub fn caveat_id(&self) -> Result<ByteString> {
+        match (&self.id, &self.id64) {
+            (Some(id), None) => Ok(id.as_str().into()),
+            (None, Some(id64)) => Ok(decode_base64(id64)?.into()),
+            (Some(_), Some(_)) => Err(MacaroonError::DeserializationError(
+                "found both id and id64 in discharge request".to_string(),
+            )),
+            (None, None) => Err(MacaroonError::DeserializationError(
+                "no caveat id in discharge request".to_string(),
+            )),
+        }
+    }
+
+    /// The encrypted caveat from `caveat64`, if present
+    pub fn caveat(&self) -> Result<Option<Vec<u8>>> {
+        self.caveat64.as_deref().map(decode_base64).transpose()
+    }
+
+    /// Form fields, for the `application/x-www-form-urlencoded` POST body used by bakery
+    /// dischargers
+    pub fn form_fields(&self) -> Vec<(&'static str, &str)> {
+        let mut fields = Vec::new();
+        if let Some(id) = &self.id {
+            fields.push(("id", id.as_str()));
+        }
+        if let Some(id64) = &self.id64 {
+            fields.push(("id64", id64.as_str()));
+        }
+        if let Some(caveat64) = &self.caveat64 {
+            fields.push(("caveat64", caveat64.as_str()));
+        }
+        fields
+    }
+}
+
+/// A successful discharge: `{"Macaroon": <V2JSON macaroon>}`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DischargeResponse {
+    #[serde(rename = "Macaroon", with = "macaroon_json")]
+    pub macaroon: Macaroon,
+}
+
+/// An error body returned by a discharger, eg `{"Code": "interaction required", ...}`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DischargeError {
+    #[serde(rename = "Code", default)]
+    pub code: String,
+    #[serde(rename = "Message", default)]
+    pub message: String,
+    /// Code-specific details, such as interaction methods
+    #[serde(rename = "Info", default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<serde_json::Value>,
+}
+
+impl DischargeError {
+    pub fn is_interaction_required(&self) -> bool {
+        self.code == CODE_INTERACTION_REQUIRED
+    }
+}
+
+impl std::fmt::Display for DischargeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for DischargeError {}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>> {
+    let config = if s.contains(&['+', '/'][..]) {
+        base64::STANDARD_NO_PAD
+    } else {
+        base64::URL_SAFE_NO_PAD
+    };
+    Ok(base64::decode_config(s.trim_end_matches('='), config)?)
+}
+
+mod macaroon_json {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        macaroon: &Macaroon,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        v2json::to_value(macaroon)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Macaroon, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        v2json::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// What a first party shares with a third party for a third-party caveat: the caveat key and
+/// ID, and the condition the third party should check before discharging it
+///
+/// The first party adds the caveat with [Macaroon::add_third_party_caveat_info] and sends the
+/// info to the third party (over a secure channel, as it contains the caveat key), or stores it
+/// where the third party can look it up by ID. The third party then mints discharges with
+/// [ThirdPartyCaveatInfo::discharge] or [Macaroon::create_discharge].
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, ThirdPartyCaveatInfo, Verifier};
+///
+/// // First party
+/// let root_key = MacaroonKey::generate_random();
+/// let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+/// let info = ThirdPartyCaveatInfo::new("https://auth.example/", "user-is alice".into());
+/// macaroon.add_third_party_caveat_info(&info);
+///
+/// // Third party, having checked the condition
+/// let mut discharge = info.discharge(&["time < 2030-01-01T00:00".into()]).unwrap();
+///
+/// // Client
+/// macaroon.bind(&mut discharge);
+/// let mut verifier = Verifier::default();
+/// verifier.satisfy_exact("time < 2030-01-01T00:00".into());
+/// verifier.verify(&macaroon, &root_key, vec![discharge]).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThirdPartyCaveatInfo {
+    pub location: String,
+    pub caveat_key: MacaroonKey,
+    pub caveat_id: ByteString,
+    pub condition: ByteString,
+}
+
+impl ThirdPartyCaveatInfo {
+    /// Info for a caveat at `location`, with a random caveat key and a UUID caveat ID
+    pub fn new(location: &str, condition: ByteString) -> ThirdPartyCaveatInfo {
+        ThirdPartyCaveatInfo {
+            location: location.to_string(),
+            caveat_key: MacaroonKey::generate_random(),
+            caveat_id: UuidV4Generator.generate(),
+            condition,
+        }
+    }
+
+    /// Info for a caveat at `location` whose key the third party can recompute from the caveat
+    /// ID and `shared_secret`, so it needn't store anything (see [derive_discharge_key])
+    ///
+    /// The caveat ID is a UUID followed by a space and the condition, which the third party
+    /// reads back with [ThirdPartyCaveatInfo::from_shared_secret]. The condition is visible to
+    /// anyone holding the macaroon: encrypt it into the ID instead when it's sensitive.
+    ///
+    /// ```rust
+    /// use macaroon::{Caveat, Macaroon, MacaroonKey, ThirdPartyCaveatInfo, Verifier};
+    ///
+    /// // agreed between the first and third parties beforehand
+    /// let shared_secret = MacaroonKey::generate(b"shared secret");
+    ///
+    /// // First party
+    /// let root_key = MacaroonKey::generate_random();
+    /// let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+    /// let info = ThirdPartyCaveatInfo::with_shared_secret(
+    ///     "https://auth.example/",
+    ///     "user-is alice".into(),
+    ///     &shared_secret,
+    /// );
+    /// macaroon.add_third_party_caveat_info(&info);
+    ///
+    /// // Third party, given only the caveat ID by the client
+    /// let caveat_id = match &macaroon.third_party_caveats()[0] {
+    ///     Caveat::ThirdParty(tp) => tp.id(),
+    ///     Caveat::FirstParty(_) => unreachable!(),
+    /// };
+    /// let info = ThirdPartyCaveatInfo::from_shared_secret(
+    ///     "https://auth.example/",
+    ///     caveat_id,
+    ///     &shared_secret,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(b"user-is alice", info.condition.as_ref());
+    /// let mut discharge = info.discharge(&[]).unwrap();
+    ///
+    /// // Client
+    /// macaroon.bind(&mut discharge);
+    /// Verifier::default()
+    ///     .verify(&macaroon, &root_key, vec![discharge])
+    ///     .unwrap();
+    /// ```
+    pub fn with_shared_secret(
+        location: &str,
+        condition: ByteString,
+        shared_secret: &MacaroonKey,
+    ) -> ThirdPartyCaveatInfo {
+        let mut caveat_id = UuidV4Generator.generate().0;
+        caveat_id.push(CONDITION_SEPARATOR);
+        caveat_id.extend_from_slice(condition.as_ref());
+        let caveat_id = ByteString(caveat_id);
+        ThirdPartyCaveatInfo {
+            location: location.to_string(),
+            caveat_key: derive_discharge_key(shared_secret, &caveat_id),
+            caveat_id,
+            condition,
+        }
+    }
+
+    /// Recover the info for a caveat added with [ThirdPartyCaveatInfo::with_shared_secret] from
+    /// its ID, as the third party
+    ///
+    /// The condition isn't authenticated: anyone can make up a caveat ID, so the third party
+    /// must still check the condition before discharging the caveat.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::DeserializationError] if `caveat_id` has no condition.
+    pub fn from_shared_secret(
+        location: &str,
+        caveat_id: ByteString,
+        shared_secret: &MacaroonKey,
+    ) -> Result<ThirdPartyCaveatInfo> {
+        let condition = match caveat_id
+            .as_ref()
+            .iter()
+            .position(|b| *b == CONDITION_SEPARATOR)
+        {
+            Some(separator) => ByteString::from(&caveat_id.as_ref()[separator + 1..]),
+            None => {
+                return Err(MacaroonError::DeserializationError(
+                    "no condition in caveat id".to_string(),
+                ))
+            }
+        };
+        Ok(ThirdPartyCaveatInfo {
+            location: location.to_string(),
+            caveat_key: derive_discharge_key(shared_secret, &caveat_id),
+            caveat_id,
+            condition,
+        })
+    }
+
+    /// Mint an (unbound) discharge for the caveat, located at [ThirdPartyCaveatInfo::location]
+    pub fn discharge(&self, extra_caveats: &[ByteString]) -> Result<Macaroon> {
+        Macaroon::create_discharge(
+            &self.caveat_key,
+            self.caveat_id.clone(),
+            Some(self.location.clone()),
+            extra_caveats,
+        )
+    }
+}
+
+/// Separates the UUID from the condition in caveat IDs from
+/// [ThirdPartyCaveatInfo::with_shared_secret]
+const CONDITION_SEPARATOR: u8 = b' ';
+
+/// Domain separation label for [derive_discharge_key], so discharge keys can't collide with other
+/// uses of the shared secret
+const DISCHARGE_KEY_LABEL: &[u8] = b"macaroon-discharge-key-v1";
+
+/// The caveat key for the third-party caveat `caveat_id`, derived from a secret shared between
+/// the first and third parties
+///
+/// The key is `HMAC-SHA256(HMAC-SHA256(shared_secret, "macaroon-discharge-key-v1"), caveat_id)`,
+/// so a third party can recompute it from the caveat ID alone and discharge caveats without
+/// storing their keys. Caveat IDs must be unique (eg, include a random UUID, as
+/// [ThirdPartyCaveatInfo::with_shared_secret] does), or their caveats share a key.
+pub fn derive_discharge_key(shared_secret: &MacaroonKey, caveat_id: &ByteString) -> MacaroonKey {
+    let subkey = crypto::hmac(shared_secret, DISCHARGE_KEY_LABEL);
+    crypto::hmac(&subkey, caveat_id)
+}
+
+impl Macaroon {
+    /// Add the third-party caveat described by `info`
+    pub fn add_third_party_caveat_info(&mut self, info: &ThirdPartyCaveatInfo) {
+        self.add_third_party_caveat(&info.location, &info.caveat_key, info.caveat_id.clone());
+    }
+
+    /// Mint a discharge macaroon for the third-party caveat with `caveat_key` and `caveat_id`,
+    /// with `extra_caveats` as first-party caveats
+    ///
+    /// The discharge isn't bound: the client binds it to the macaroon being authorized with
+    /// [Macaroon::bind] before sending both.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `caveat_id` is empty.
+    pub fn create_discharge(
+        caveat_key: &MacaroonKey,
+        caveat_id: ByteString,
+        location: Option<String>,
+        extra_caveats: &[ByteString],
+    ) -> Result<Macaroon> {
+        let mut discharge = Macaroon::create(location, caveat_key, caveat_id)?;
+        for predicate in extra_caveats {
+            discharge.add_first_party_caveat(predicate.clone());
+        }
+        Ok(discharge)
+    }
+
+    /// The third-party caveats still lacking a discharge among `existing`, including those of
+    /// the discharges held
+    ///
+    /// The macaroon and its discharges are walked as verification would, depth-first, matching
+    /// discharges to caveats by identifier. Each caveat ID is reported once, in the order
+    /// reached, so a client can fetch the discharges, add them to those it holds and repeat
+    /// until nothing is left; the caveats of the new discharges are then reported too. Neither
+    /// signatures nor bindings are checked.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_third_party_caveat("https://auth.mybank/", &caveat_key, "login".into());
+    ///
+    /// let required = macaroon.required_discharges(&[]);
+    /// assert_eq!("https://auth.mybank/", required[0].location());
+    /// assert_eq!(macaroon::ByteString::from("login"), required[0].id());
+    ///
+    /// let discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+    /// assert!(macaroon.required_discharges(&[discharge]).is_empty());
+    /// ```
+    pub fn required_discharges(&self, existing: &[Macaroon]) -> Vec<ThirdParty> {
+        let mut required = Vec::new();
+        find_required_discharges(self, existing, &mut HashSet::new(), &mut required);
+        required
+    }
+}
+
+fn find_required_discharges<'m>(
+    macaroon: &'m Macaroon,
+    existing: &'m [Macaroon],
+    seen: &mut HashSet<&'m ByteString>,
+    required: &mut Vec<ThirdParty>,
+) {
+    for caveat in macaroon.caveats.iter() {
+        if let Caveat::ThirdParty(tp) = caveat {
+            if !seen.insert(tp.id_ref()) {
+                continue;
+            }
+            match existing.iter().find(|d| d.identifier == *tp.id_ref()) {
+                Some(discharge) => find_required_discharges(discharge, existing, seen, required),
+                None => required.push(tp.clone()),
+            }
+        }
+    }
+}
+
+/// The future returned by [DischargeClient::discharge]
+pub type DischargeFuture<'a> = Pin<Box<dyn Future<Output = Result<Macaroon>> + Send + 'a>>;
+
+/// Fetches discharge macaroons from third-party dischargers, for [Macaroon::discharge_all]
+///
+/// Implementations typically POST a [DischargeRequest] to the caveat's location and decode the
+/// [DischargeResponse], using whichever HTTP client and async runtime the application already
+/// has. The returned discharge should not be bound yet: [Macaroon::discharge_all] binds it.
+pub trait DischargeClient: Sync {
+    fn discharge<'a>(&'a self, location: &'a str, caveat_id: &'a ByteString)
+        -> DischargeFuture<'a>;
+}
+
+impl Macaroon {
+    /// Acquire discharges for all of this macaroon's third-party caveats, including those added
+    /// by the dischargers themselves, like go-macaroon-bakery's `DischargeAll`
+    ///
+    /// Each caveat is discharged once, trying its locations in order (see
+    /// [Macaroon::add_third_party_caveat_with_fallbacks]) until one succeeds; if none do, the last
+    /// error is returned. The discharges are bound to this macaroon, ready to pass to
+    /// [Verifier::verify](crate::Verifier::verify).
+    pub async fn discharge_all<C: DischargeClient + ?Sized>(
+        &self,
+        client: &C,
+    ) -> Result<Vec<Macaroon>> {
+        let mut pending: VecDeque<(Vec<String>, ByteString)> = VecDeque::new();
+        let mut seen: HashSet<ByteString> = HashSet::new();
+        queue_third_party_caveats(self, &mut pending, &mut seen);
+
+        let mut discharges = Vec::new();
+        while let Some((locations, caveat_id)) = pending.pop_front() {
+            let mut result = Err(MacaroonError::IncompleteCaveat("no location found"));
+            for location in locations.iter() {
+                result = client.discharge(location, &caveat_id).await;
+                if result.is_ok() {
+                    break;
+                }
+            }
+            let mut discharge = result?;
+            queue_third_party_caveats(&discharge, &mut pending, &mut seen);
+            self.bind(&mut discharge);
+            discharges.push(discharge);
+        }
+        Ok(discharges)
+    }
+}
+
+fn queue_third_party_caveats(
+    macaroon: &Macaroon,
+    pending: &mut VecDeque<(Vec<String>, ByteString)>,
+    seen: &mut HashSet<ByteString>,
+) {
+    for caveat in macaroon.caveats.iter() {
+        if let Caveat::ThirdParty(tp) = caveat {
+            if seen.insert(tp.id()) {
+                let locations = tp.locations().map(String::from).collect();
+                pending.push_back((locations, tp.id()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DischargeClient, DischargeError, DischargeFuture, DischargeRequest, DischargeResponse,
+        ThirdPartyCaveatInfo,
+    };
+    use crate::test_util::block_on;
+    use crate::{
+        derive_discharge_key, ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey,
+        UnsatisfiedCaveat, Verifier,
+    };
+    use std::sync::Mutex;
+
+    /// Discharges "auth" caveats, adding a nested "mfa" caveat, and "mfa" caveats at the
+    /// second location only
+    struct TestClient {
+        auth_key: MacaroonKey,
+        mfa_key: MacaroonKey,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl DischargeClient for TestClient {
+        fn discharge<'a>(
+            &'a self,
+            location: &'a str,
+            caveat_id: &'a ByteString,
+        ) -> DischargeFuture<'a> {
+            Box::pin(async move {
+                self.calls.lock().unwrap().push(location.to_string());
+                match (location, caveat_id.as_ref()) {
+                    ("https://auth.example/", b"auth") => {
+                        let mut m = Macaroon::create(None, &self.auth_key, "auth".into())?;
+                        m.add_third_party_caveat_with_fallbacks(
+                            &["https://mfa1.example/", "https://mfa2.example/"],
+                            &self.mfa_key,
+                            "mfa".into(),
+                        )?;
+                        Ok(m)
+                    }
+                    ("https://mfa2.example/", b"mfa") => {
+                        Macaroon::create(None, &self.mfa_key, "mfa".into())
+                    }
+                    _ => Err(MacaroonError::CaveatNotSatisfied(UnsatisfiedCaveat::new(
+                        caveat_id.clone(),
+                        format!("can't discharge at {}", location),
+                    ))),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_discharge_all() {
+        let root_key = MacaroonKey::generate(b"root");
+        let client = TestClient {
+            auth_key: MacaroonKey::generate(b"auth"),
+            mfa_key: MacaroonKey::generate(b"mfa"),
+            calls: Mutex::new(Vec::new()),
+        };
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_third_party_caveat("https://auth.example/", &client.auth_key, "auth".into());
+
+        let discharges = block_on(macaroon.discharge_all(&client)).unwrap();
+        assert_eq!(2, discharges.len());
+        assert_eq!(
+            vec![
+                "https://auth.example/",
+                "https://mfa1.example/",
+                "https://mfa2.example/"
+            ],
+            *client.calls.lock().unwrap()
+        );
+        Verifier::default()
+            .verify(&macaroon, &root_key, discharges)
+            .unwrap();
+
+        macaroon.add_third_party_caveat("https://other.example/", &client.auth_key, "other".into());
+        assert!(matches!(
+            block_on(macaroon.discharge_all(&client)),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+    }
+
+    #[test]
+    fn test_required_discharges() {
+        let root_key = MacaroonKey::generate(b"root");
+        let caveat_key = MacaroonKey::generate(b"caveat");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "auth".into());
+        macaroon.add_third_party_caveat("https://ops.example/", &caveat_key, "ops".into());
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "auth".into());
+        let ids = |required: Vec<super::ThirdParty>| -> Vec<ByteString> {
+            required.iter().map(|tp| tp.id()).collect()
+        };
+        assert_eq!(
+            vec![ByteString::from("auth"), "ops".into()],
+            ids(macaroon.required_discharges(&[]))
+        );
+
+        // discharging one caveat can require more, which are reported in verification order
+        let mut auth = Macaroon::create(None, &caveat_key, "auth".into()).unwrap();
+        auth.add_third_party_caveat("https://mfa.example/", &caveat_key, "mfa".into());
+        auth.add_third_party_caveat("https://auth.example/", &caveat_key, "auth".into());
+        let mfa = Macaroon::create(None, &caveat_key, "mfa".into()).unwrap();
+        let unrelated = Macaroon::create(None, &caveat_key, "unrelated".into()).unwrap();
+        assert_eq!(
+            vec![ByteString::from("mfa"), "ops".into()],
+            ids(macaroon.required_discharges(&[unrelated, auth.clone()]))
+        );
+        let required = macaroon.required_discharges(&[auth, mfa]);
+        assert_eq!(vec![ByteString::from("ops")], ids(required.clone()));
+        assert_eq!("https://ops.example/", required[0].location());
+    }
+
+    #[test]
+    fn test_request() {
+        let request = DischargeRequest::new(&"caveat id".into());
+        assert_eq!(
+            r#"{"id":"caveat id"}"#,
+            serde_json::to_string(&request).unwrap()
+        );
+        assert_eq!(ByteString::from("caveat id"), request.caveat_id().unwrap());
+
+        let binary = ByteString(vec![0xff, 0xfe, 0x00]);
+        let request = DischargeRequest::new(&binary).with_caveat(b"encrypted");
+        assert_eq!(Some("__4A"), request.id64.as_deref());
+        assert_eq!(binary, request.caveat_id().unwrap());
+        assert_eq!(b"encrypted".to_vec(), request.caveat().unwrap().unwrap());
+        assert_eq!(2, request.form_fields().len());
+
+        let request: DischargeRequest = serde_json::from_str(r#"{"id64":"__4A"}"#).unwrap();
+        assert_eq!(binary, request.caveat_id().unwrap());
+        assert!(DischargeRequest::default().caveat_id().is_err());
+    }
+
+    #[test]
+    fn test_response() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "caveat id".into()).unwrap();
+        macaroon.add_first_party_caveat("user = alice".into());
+        let response = DischargeResponse { macaroon };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.starts_with(r#"{"Macaroon":{"#));
+        let parsed: DischargeResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(response, parsed);
+    }
+
+    #[test]
+    fn test_error() {
+        let error: DischargeError = serde_json::from_str(
+            r#"{"Code":"interaction required","Message":"login needed","Info":{"VisitURL":"https://idm/visit"}}"#,
+        )
+        .unwrap();
+        assert!(error.is_interaction_required());
+        assert_eq!("interaction required: login needed", error.to_string());
+        let error: DischargeError = serde_json::from_str(r#"{"Code":"bad request"}"#).unwrap();
+        assert!(!error.is_interaction_required());
+        assert!(error.info.is_none());
+    }
+
+    #[test]
+    fn test_third_party_caveat_info() {
+        let root_key = MacaroonKey::generate(b"root");
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        let info = ThirdPartyCaveatInfo::new("https://auth.example/", "user-is alice".into());
+        assert_ne!(
+            info.caveat_id,
+            ThirdPartyCaveatInfo::new("https://auth.example/", "user-is alice".into()).caveat_id
+        );
+        macaroon.add_third_party_caveat_info(&info);
+        match &macaroon.third_party_caveats()[0] {
+            Caveat::ThirdParty(tp) => {
+                assert_eq!(info.caveat_id, tp.id());
+                assert_eq!("https://auth.example/", tp.location());
+            }
+            Caveat::FirstParty(_) => panic!("expected a third-party caveat"),
+        }
+
+        let mut discharge = info.discharge(&["ip = 10.0.0.1".into()]).unwrap();
+        assert_eq!(
+            Some("https://auth.example/".to_string()),
+            discharge.location()
+        );
+        assert_eq!(1, discharge.first_party_caveats().len());
+        macaroon.bind(&mut discharge);
+        let mut verifier = Verifier::default();
+        assert!(verifier
+            .verify(&macaroon, &root_key, vec![discharge.clone()])
+            .is_err());
+        verifier.satisfy_exact("ip = 10.0.0.1".into());
+        verifier
+            .verify(&macaroon, &root_key, vec![discharge])
+            .unwrap();
+
+        assert!(Macaroon::create_discharge(&info.caveat_key, "".into(), None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_shared_secret_discharge() {
+        let shared_secret = MacaroonKey::generate(b"shared secret");
+        let root_key = MacaroonKey::generate(b"root");
+
+        // first party: nothing is sent to the third party or stored
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        let info = ThirdPartyCaveatInfo::with_shared_secret(
+            "https://auth.example/",
+            "user-is alice".into(),
+            &shared_secret,
+        );
+        assert!(info.caveat_id.as_ref().ends_with(b" user-is alice"));
+        macaroon.add_third_party_caveat_info(&info);
+        let caveat_id = match &macaroon.third_party_caveats()[0] {
+            Caveat::ThirdParty(tp) => {
+                assert_eq!(info.caveat_key, tp.derive_discharge_key(&shared_secret));
+                tp.id()
+            }
+            Caveat::FirstParty(_) => panic!("expected a third-party caveat"),
+        };
+
+        // third party: recompute the key from the caveat ID
+        let recovered = ThirdPartyCaveatInfo::from_shared_secret(
+            "https://auth.example/",
+            caveat_id,
+            &shared_secret,
+        )
+        .unwrap();
+        assert_eq!(info, recovered);
+        let mut discharge = recovered.discharge(&[]).unwrap();
+        macaroon.bind(&mut discharge);
+        Verifier::default()
+            .verify(&macaroon, &root_key, vec![discharge])
+            .unwrap();
+
+        // a different secret gives a key which doesn't discharge the caveat
+        let other = MacaroonKey::generate(b"other secret");
+        assert_ne!(
+            info.caveat_key,
+            derive_discharge_key(&other, &info.caveat_id)
+        );
+        let forged =
+            ThirdPartyCaveatInfo::from_shared_secret("", info.caveat_id.clone(), &other).unwrap();
+        let mut discharge = forged.discharge(&[]).unwrap();
+        macaroon.bind(&mut discharge);
+        assert!(Verifier::default()
+            .verify(&macaroon, &root_key, vec![discharge])
+            .is_err());
+
+        assert!(matches!(
+            ThirdPartyCaveatInfo::from_shared_secret("", "no-condition".into(), &shared_secret),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+    }
+}
@@ -1,4 +1,5 @@
-use std::{num, str, string};
+use crate::ByteString;
+use std::{fmt, num, str, string};
 
 /// Represents all of the errors that can arise when creating, deserializing, or verifying macaroons.
 #[derive(Debug)]
@@ -17,13 +18,29 @@ pub enum MacaroonError {
     /// Can occur when constructing or deserializing [`Caveat`](crate::Caveat) and expected fields are not present.
     IncompleteCaveat(&'static str),
 
-    /// Represents a broad category of issues when parsing a macaroon token in any format.
+    /// Represents a broad category of issues when parsing a macaroon token in any format, other
+    /// than the more specific ones below.
     DeserializationError(String),
 
+    /// Can occur when deserializing a V1 or V2 token, or a base64 field of a V2JSON token, which
+    /// isn't valid base64.
+    InvalidBase64(String),
+
+    /// Can occur when deserializing a V1 token whose packet at this byte offset (of the binary
+    /// token) has a malformed or out of range size header.
+    InvalidPacketHeader { offset: usize },
+
+    /// Can occur when deserializing a binary or JSON token with a version other than 2 (V1
+    /// tokens have no version).
+    UnsupportedVersion(u8),
+
+    /// Can occur when deserializing a token whose signature isn't 32 bytes long.
+    SignatureLengthMismatch { got: usize },
+
     /// Arises when verifying a [`Macaroon`](crate::Macaroon), when it has any caveat not
     /// satisfied by any "exact" or "general" satisfiers configured on the
     /// [`Verifier`](crate::Verifier). Indicates a failure to authenticate the macaroon.
-    CaveatNotSatisfied(String),
+    CaveatNotSatisfied(UnsatisfiedCaveat),
 
     /// Arises when verifying a [`Macaroon`](crate::Macaroon), with a set of discharges configured
     /// on the verifier, and one or more are not used during the verification process. Indicates a
@@ -33,6 +50,100 @@ pub enum MacaroonError {
     /// Arises when verifying a [`Macaroon`](crate::Macaroon), and the signature does not match
     /// what is expected. Indicates a failure to authenticate the macaroon.
     InvalidSignature,
+
+    /// Arises when verifying a [`Macaroon`](crate::Macaroon), when the discharge macaroon with
+    /// this identifier is valid but was never bound to the macaroon being verified (see
+    /// [`Macaroon::bind`](crate::Macaroon::bind)).
+    DischargeNotBound(ByteString),
+
+    /// Arises when verifying a [`Macaroon`](crate::Macaroon), when the discharge macaroon with
+    /// this identifier was bound to the macaroon being verified more than once. Also returned by
+    /// [`MacaroonBundle::bind_all`](crate::MacaroonBundle::bind_all) once its discharges are
+    /// bound.
+    DischargeBoundTwice(ByteString),
+
+    /// Arises when a configured limit is reached, such as the maximum discharge depth or the
+    /// deadline in [`VerifierOptions`](crate::VerifierOptions), or the
+    /// [`DeserializationLimits`](crate::DeserializationLimits) of a token. Also returned when
+    /// a macaroon is too large to serialize in the requested format, or a
+    /// [`Macaroon::create_with_ttl`](crate::Macaroon::create_with_ttl) TTL overflows the clock.
+    LimitExceeded(String),
+
+    /// Arises when verifying a [`Macaroon`](crate::Macaroon) with a
+    /// [`RevocationChecker`](crate::RevocationChecker), if the macaroon or one of its discharges
+    /// (whose identifier this is) has been revoked.
+    Revoked(ByteString),
+
+    /// Arises when verifying a [`Macaroon`](crate::Macaroon) with a
+    /// [`Verifier`](crate::Verifier) expecting a location (see
+    /// [`Verifier::expect_location`](crate::Verifier::expect_location)), if the macaroon's
+    /// location (carried here) doesn't match it, eg because it was minted for another service.
+    LocationMismatch(Option<String>),
+
+    /// Arises when a [`Macaroon`](crate::Macaroon) can't be written in the requested format
+    /// without losing or corrupting some of its content.
+    SerializationError(String),
+}
+
+/// The caveat which failed verification, carried by [MacaroonError::CaveatNotSatisfied]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsatisfiedCaveat {
+    /// Why the caveat isn't satisfied
+    pub reason: String,
+    /// The predicate of a first-party caveat, or the caveat ID of a third-party caveat
+    pub caveat: ByteString,
+    /// The caveat's position in the macaroon it belongs to
+    pub index: Option<usize>,
+    /// The identifier of the discharge macaroon the caveat belongs to, or `None` if it belongs to
+    /// the macaroon being verified
+    pub discharge: Option<ByteString>,
+}
+
+impl UnsatisfiedCaveat {
+    /// An unsatisfied caveat whose position isn't known yet; verification fills it in
+    pub fn new(caveat: ByteString, reason: String) -> UnsatisfiedCaveat {
+        UnsatisfiedCaveat {
+            reason,
+            caveat,
+            index: None,
+            discharge: None,
+        }
+    }
+}
+
+impl fmt::Display for UnsatisfiedCaveat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.reason)?;
+        match (self.index, &self.discharge) {
+            (Some(index), None) => write!(f, " (caveat {})", index),
+            (Some(index), Some(discharge)) => write!(
+                f,
+                " (caveat {} of discharge {})",
+                index,
+                String::from_utf8_lossy(discharge.as_ref())
+            ),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+impl MacaroonError {
+    /// A [MacaroonError::CaveatNotSatisfied] for `caveat`, failing for `reason`
+    pub(crate) fn caveat_not_satisfied(caveat: &[u8], reason: String) -> MacaroonError {
+        MacaroonError::CaveatNotSatisfied(UnsatisfiedCaveat::new(caveat.into(), reason))
+    }
+
+    /// Record the position of the unsatisfied caveat, unless it's already known (eg from a
+    /// nested discharge)
+    pub(crate) fn at_caveat(mut self, index: usize, discharge: Option<&[u8]>) -> Self {
+        if let MacaroonError::CaveatNotSatisfied(caveat) = &mut self {
+            if caveat.index.is_none() {
+                caveat.index = Some(index);
+                caveat.discharge = discharge.map(ByteString::from);
+            }
+        }
+        self
+    }
 }
 
 impl From<serde_json::Error> for MacaroonError {
@@ -49,7 +160,7 @@ impl From<string::FromUtf8Error> for MacaroonError {
 
 impl From<base64::DecodeError> for MacaroonError {
     fn from(error: base64::DecodeError) -> MacaroonError {
-        MacaroonError::DeserializationError(format!("{}", error))
+        MacaroonError::InvalidBase64(format!("{}", error))
     }
 }
 
@@ -85,9 +196,24 @@ impl std::fmt::Display for MacaroonError {
             MacaroonError::IncompleteCaveat(s) => {
                 write!(f, "Caveat was missing required field: {}", s)
             }
-            MacaroonError::DeserializationError(s) => {
+            MacaroonError::DeserializationError(s) | MacaroonError::InvalidBase64(s) => {
                 write!(f, "Failed to deserialize macaroon: {}", s)
             }
+            MacaroonError::InvalidPacketHeader { offset } => write!(
+                f,
+                "Failed to deserialize macaroon: invalid packet header at offset {}",
+                offset
+            ),
+            MacaroonError::UnsupportedVersion(version) => write!(
+                f,
+                "Failed to deserialize macaroon: unsupported version {}",
+                version
+            ),
+            MacaroonError::SignatureLengthMismatch { got } => write!(
+                f,
+                "Failed to deserialize macaroon: signature is {} bytes, expected 32",
+                got
+            ),
             MacaroonError::CaveatNotSatisfied(s) => write!(
                 f,
                 "Macaroon failed to verify because one or more caveats were not satisfied: {}",
@@ -101,6 +227,30 @@ impl std::fmt::Display for MacaroonError {
                 f,
                 "Macaroon failed to verify because signature did not match"
             ),
+            MacaroonError::DischargeNotBound(identifier) => write!(
+                f,
+                "Macaroon failed to verify because discharge {} is not bound to it",
+                identifier
+            ),
+            MacaroonError::DischargeBoundTwice(identifier) => write!(
+                f,
+                "Macaroon failed to verify because discharge {} was bound to it twice",
+                identifier
+            ),
+            MacaroonError::LimitExceeded(s) => write!(f, "Limit exceeded: {}", s),
+            MacaroonError::Revoked(identifier) => write!(
+                f,
+                "Macaroon failed to verify because macaroon {} has been revoked",
+                identifier
+            ),
+            MacaroonError::LocationMismatch(location) => write!(
+                f,
+                "Macaroon failed to verify because its location {:?} is not the expected one",
+                location
+            ),
+            MacaroonError::SerializationError(s) => {
+                write!(f, "Failed to serialize macaroon: {}", s)
+            }
         }
     }
 }
@@ -0,0 +1,177 @@
+//! Expiry of macaroons, with [OffsetDateTime] from the `time` crate
+//!
+//! Enable the `time` feature to use this module.
+
+use crate::timestamp::{caveat_timestamp, parse_timestamp};
+use crate::{Caveat, Macaroon, MacaroonError, Result};
+use ::time::OffsetDateTime;
+use std::time::Duration;
+
+/// The time a first-party caveat predicate expires at, if it's a `time < ...` or
+/// `[std:]time-before ...` caveat with a well-formed timestamp
+pub(crate) fn caveat_expiry(predicate: &[u8]) -> Option<OffsetDateTime> {
+    caveat_timestamp(predicate).and_then(parse_timestamp)
+}
+
+/// The earliest expiry of `macaroon` and the discharges verifying it would use, or `None` if
+/// none of them has an expiry caveat
+///
+/// Expiry caveats are `time < ...` (see [expiry_predicate](crate::expiry_predicate)) and
+/// `time-before ...` (see [StdContext](crate::StdContext)), with or without the `std:` prefix.
+/// Discharges are followed from the third-party caveats they discharge, as verification does, so
+/// unrelated discharges in `discharges` don't count. Nothing is verified, and expiry caveats
+/// with malformed timestamps, which would fail verification anyway, are skipped.
+///
+/// Servers can use this to set cache lifetimes or cookie `Expires` attributes.
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey};
+/// use time::{Duration, OffsetDateTime};
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let now = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_expiry_caveat(now + Duration::days(30));
+///
+/// let caveat_key = MacaroonKey::generate_random();
+/// macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "login".into());
+/// let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+/// discharge.add_expiry_caveat(now + Duration::hours(1));
+/// macaroon.bind(&mut discharge);
+///
+/// assert_eq!(Some(now + Duration::days(30)), macaroon::expiry::expiry(&macaroon, &[]));
+/// assert_eq!(Some(now + Duration::hours(1)), macaroon::expiry::expiry(&macaroon, &[discharge]));
+/// ```
+pub fn expiry(macaroon: &Macaroon, discharges: &[Macaroon]) -> Option<OffsetDateTime> {
+    let mut used = vec![false; discharges.len()];
+    let mut pending = vec![macaroon];
+    let mut earliest: Option<OffsetDateTime> = None;
+    while let Some(m) = pending.pop() {
+        for c in m.caveats.iter() {
+            match c {
+                Caveat::FirstParty(fp) => {
+                    if let Some(at) = caveat_expiry(fp.predicate_ref().as_ref()) {
+                        earliest = Some(earliest.map_or(at, |e| e.min(at)));
+                    }
+                }
+                Caveat::ThirdParty(tp) => {
+                    // each discharge is used once, as in verification
+                    let found = discharges
+                        .iter()
+                        .enumerate()
+                        .find(|(i, d)| !used[*i] && d.identifier.as_ref() == tp.id_ref().as_ref());
+                    if let Some((i, d)) = found {
+                        used[i] = true;
+                        pending.push(d);
+                    }
+                }
+            }
+        }
+    }
+    earliest
+}
+
+/// Check that `discharge` has an expiry caveat of its own expiring at most `max_lifetime` after
+/// `now`, see [VerifierOptionsBuilder::max_discharge_lifetime](crate::VerifierOptionsBuilder::max_discharge_lifetime)
+pub(crate) fn check_discharge_lifetime(
+    discharge: &Macaroon,
+    max_lifetime: Duration,
+    now: OffsetDateTime,
+) -> Result<()> {
+    let expires = discharge
+        .caveats
+        .iter()
+        .filter_map(|c| match c {
+            Caveat::FirstParty(fp) => caveat_expiry(fp.predicate_ref().as_ref()),
+            Caveat::ThirdParty(_) => None,
+        })
+        .min();
+    match expires {
+        Some(at) if at <= now + max_lifetime => Ok(()),
+        Some(_) => Err(MacaroonError::LimitExceeded(format!(
+            "discharge {} expires more than {:?} from now",
+            discharge.identifier, max_lifetime
+        ))),
+        None => Err(MacaroonError::LimitExceeded(format!(
+            "discharge {} has no expiry caveat",
+            discharge.identifier
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{caveat_expiry, check_discharge_lifetime, expiry};
+    use crate::{parse_timestamp, Macaroon, MacaroonKey};
+    use std::time::Duration;
+
+    #[test]
+    fn test_caveat_expiry() {
+        assert_eq!(
+            parse_timestamp("2030-01-01T00:00:00Z"),
+            caveat_expiry(b"time < 2030-01-01T00:00")
+        );
+        assert_eq!(
+            parse_timestamp("2030-01-01T01:00:00Z"),
+            caveat_expiry(b"std:time-before 2030-01-01T02:00:00+01:00")
+        );
+        assert_eq!(None, caveat_expiry(b"time-before tomorrow"));
+        assert_eq!(None, caveat_expiry(b"time-after 2030-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        assert_eq!(None, expiry(&macaroon, &[]));
+        macaroon.add_first_party_caveat("time < 2031-01-01T00:00:00Z".into());
+        macaroon.add_first_party_caveat("time-before 2030-06-01T00:00:00Z".into());
+
+        // a nested discharge expiring sooner, and an unrelated one sooner still
+        let mut discharges = Vec::new();
+        for (id, nested, at) in [
+            ("outer", Some("inner"), "2030-03-01T00:00:00Z"),
+            ("inner", None, "2030-02-01T00:00:00Z"),
+            ("unrelated", None, "2030-01-01T00:00:00Z"),
+        ] {
+            let caveat_key = MacaroonKey::generate(id.as_bytes());
+            if id == "outer" {
+                macaroon.add_third_party_caveat("http://auth/", &caveat_key, id.into());
+            }
+            let mut discharge = Macaroon::create(None, &caveat_key, id.into()).unwrap();
+            discharge.add_first_party_caveat(format!("time < {}", at).into());
+            if let Some(nested) = nested {
+                let nested_key = MacaroonKey::generate(nested.as_bytes());
+                discharge.add_third_party_caveat("http://auth/", &nested_key, nested.into());
+            }
+            discharges.push(discharge);
+        }
+        assert_eq!(
+            parse_timestamp("2030-06-01T00:00:00Z"),
+            expiry(&macaroon, &[])
+        );
+        assert_eq!(
+            parse_timestamp("2030-03-01T00:00:00Z"),
+            expiry(&macaroon, &discharges[..1])
+        );
+        assert_eq!(
+            parse_timestamp("2030-02-01T00:00:00Z"),
+            expiry(&macaroon, &discharges)
+        );
+    }
+
+    #[test]
+    fn test_check_discharge_lifetime() {
+        let now = parse_timestamp("2030-01-01T00:00:00Z").unwrap();
+        let key = MacaroonKey::generate(b"key");
+        let mut discharge = Macaroon::create(None, &key, "id".into()).unwrap();
+        let hour = Duration::from_secs(3600);
+        assert!(check_discharge_lifetime(&discharge, hour, now).is_err());
+        discharge.add_first_party_caveat("time-before 2030-01-02T00:00:00Z".into());
+        assert!(check_discharge_lifetime(&discharge, hour, now).is_err());
+        assert!(check_discharge_lifetime(&discharge, 24 * hour, now).is_ok());
+        // the earliest expiry counts
+        discharge.add_first_party_caveat("time < 2030-01-01T00:30".into());
+        assert!(check_discharge_lifetime(&discharge, hour, now).is_ok());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{MacaroonError, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Prefix required for extension field names, so they can't clash with standard V2JSON fields
+pub const EXTENSION_PREFIX: &str = "x-";
+
+/// Implementation-specific JSON fields attached to a macaroon or caveat, eg `"x-ns"`
+///
+/// Extensions are carried by the V2JSON format only: they are written alongside the standard
+/// fields, read back from any field whose name starts with `x-`, and ignored by other parsers.
+/// They are not covered by the signature, so they must not be trusted for authorization, and the
+/// binary V1 and V2 formats drop them.
+///
+/// ```rust
+/// use macaroon::{Format, Macaroon, MacaroonKey};
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let mut macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "id".into())?;
+/// macaroon.extensions_mut().insert("x-issued-by", &"billing-service")?;
+///
+/// let json = macaroon.serialize(Format::V2JSON)?;
+/// let parsed = Macaroon::deserialize(&json)?;
+/// let issuer: Option<String> = parsed.extensions().get("x-issued-by")?;
+/// assert_eq!(Some("billing-service".to_string()), issuer);
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Extensions(BTreeMap<String, Value>);
+
+impl Extensions {
+    /// Decode the extension `name` as a `T`, if present
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        match self.0.get(name) {
+            Some(value) => Ok(Some(T::deserialize(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_raw(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    /// Set the extension `name` to `value`, replacing any previous value
+    ///
+    /// # Errors
+    ///
+    /// Fails if `name` doesn't start with [EXTENSION_PREFIX], or `value` can't be represented as
+    /// JSON.
+    pub fn insert<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<()> {
+        if !name.starts_with(EXTENSION_PREFIX) {
+            return Err(MacaroonError::DeserializationError(format!(
+                "extension name {:?} doesn't start with {:?}",
+                name, EXTENSION_PREFIX
+            )));
+        }
+        self.0
+            .insert(name.to_string(), serde_json::to_value(value)?);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.0.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Keep the extension fields from a parsed JSON object, dropping any other unknown fields
+    pub(crate) fn from_json_fields(fields: BTreeMap<String, Value>) -> Extensions {
+        Extensions(
+            fields
+                .into_iter()
+                .filter(|(k, _)| k.starts_with(EXTENSION_PREFIX))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn to_json_fields(&self) -> BTreeMap<String, Value> {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Meta {
+        owner: String,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_typed_extensions() {
+        let mut extensions = Extensions::default();
+        let meta = Meta {
+            owner: "alice".into(),
+            tags: vec!["ci".into()],
+        };
+        extensions.insert("x-meta", &meta).unwrap();
+        assert!(extensions.insert("meta", &meta).is_err());
+        assert_eq!(Some(meta), extensions.get("x-meta").unwrap());
+        assert!(extensions.get::<u32>("x-meta").is_err());
+        assert_eq!(None, extensions.get::<u32>("x-missing").unwrap());
+        assert_eq!(1, extensions.len());
+    }
+}
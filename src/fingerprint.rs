@@ -0,0 +1,147 @@
+use crate::crypto::constant_time_eq;
+use crate::serialization::v2;
+use crate::Macaroon;
+use sodiumoxide::crypto::hash::sha256;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// A short, stable hash of a macaroon, for cache keys, revocation lists and log correlation
+/// without handling the token itself
+///
+/// It is the first 16 bytes of the SHA-256 hash of the macaroon's V2 binary serialization, and
+/// prints as 32 hex digits.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fingerprint([u8; Fingerprint::LEN]);
+
+impl Fingerprint {
+    pub const LEN: usize = 16;
+
+    fn of(data: &[u8]) -> Fingerprint {
+        let mut fingerprint = [0; Fingerprint::LEN];
+        fingerprint.copy_from_slice(&sha256::hash(data).0[..Fingerprint::LEN]);
+        Fingerprint(fingerprint)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; Fingerprint::LEN] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+impl Macaroon {
+    /// A short hash identifying this exact token, signature included
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// let mut attenuated = macaroon.clone();
+    /// attenuated.add_first_party_caveat("account = 3735928559".into());
+    /// assert_ne!(macaroon.fingerprint(), attenuated.fingerprint());
+    /// assert_eq!(32, macaroon.fingerprint().to_string().len());
+    /// ```
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&v2::encode(self))
+    }
+
+    /// Like [Macaroon::fingerprint], ignoring the signature, so a discharge has the same content
+    /// fingerprint whether or not, and to whichever macaroon, it is bound
+    pub fn content_fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&v2::encode_content(self))
+    }
+
+    /// Whether both macaroons have the same location, identifier and caveats, whatever their
+    /// signatures, eg to compare a discharge before and after binding
+    pub fn eq_ignoring_signature(&self, other: &Macaroon) -> bool {
+        self.location == other.location
+            && self.identifier == other.identifier
+            && self.caveats == other.caveats
+    }
+
+    /// Whether both macaroons have the same V2 binary serialization, compared in constant time
+    /// so the comparison doesn't leak how much of a secret token matches
+    pub fn eq_constant_time(&self, other: &Macaroon) -> bool {
+        constant_time_eq(&v2::encode(self), &v2::encode(other))
+    }
+}
+
+/// Hashes the V2 binary serialization, so macaroons can be kept in a `HashSet` or used as
+/// `HashMap` keys, eg to deduplicate discharges
+impl Hash for Macaroon {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        v2::encode(self).hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fingerprint;
+    use crate::{Macaroon, MacaroonKey};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_fingerprints() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        let mut bound = discharge.clone();
+        macaroon.bind(&mut bound);
+
+        assert_ne!(discharge.fingerprint(), bound.fingerprint());
+        assert_eq!(discharge.content_fingerprint(), bound.content_fingerprint());
+        assert!(discharge.eq_ignoring_signature(&bound));
+        assert_ne!(discharge, bound);
+        assert!(!discharge.eq_ignoring_signature(&macaroon));
+        assert_ne!(
+            macaroon.content_fingerprint(),
+            discharge.content_fingerprint()
+        );
+
+        // Stable across serialization
+        let deserialized =
+            Macaroon::deserialize(macaroon.serialize(crate::Format::V1).unwrap()).unwrap();
+        assert_eq!(macaroon.fingerprint(), deserialized.fingerprint());
+
+        let fingerprint = macaroon.fingerprint();
+        assert_eq!(Fingerprint::LEN, fingerprint.as_bytes().len());
+        assert_eq!(
+            format!("Fingerprint({})", fingerprint),
+            format!("{:?}", fingerprint)
+        );
+    }
+
+    #[test]
+    fn test_hash_and_constant_time_eq() {
+        let key = MacaroonKey::generate(b"key");
+        let macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        let mut attenuated = macaroon.clone();
+        attenuated.add_first_party_caveat("account = 3735928559".into());
+        let deserialized =
+            Macaroon::deserialize(macaroon.serialize(crate::Format::V2JSON).unwrap()).unwrap();
+
+        assert!(macaroon.eq_constant_time(&deserialized));
+        assert!(!macaroon.eq_constant_time(&attenuated));
+
+        let set: HashSet<Macaroon> = vec![macaroon, attenuated, deserialized]
+            .into_iter()
+            .collect();
+        assert_eq!(2, set.len());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{base64_decode_flexible, Format, Macaroon, MacaroonError, Result};
+
+/// Request header carrying a root macaroon and its discharges, as in go-macaroon-bakery's
+/// httpbakery. It may be repeated, one slice per header.
+pub const MACAROONS_HEADER: &str = "Macaroons";
+/// `Authorization` header scheme, as in `Authorization: Macaroon <token>`
+pub const AUTHORIZATION_SCHEME: &str = "Macaroon";
+
+/// Encode a root macaroon and its discharges as httpbakery does: a JSON array of V2JSON
+/// macaroons, encoded as standard base64
+///
+/// The result is the value of a [MACAROONS_HEADER] header, or of a macaroon cookie.
+pub fn encode_macaroons_header(macaroons: &[Macaroon]) -> Result<String> {
+    let json = Macaroon::serialize_slice(macaroons, Format::V2JSON)?;
+    Ok(base64::encode(json))
+}
+
+/// Decode the value of a [MACAROONS_HEADER] header or macaroon cookie
+///
+/// Either base64 alphabet is accepted, with or without padding, and the base64 may hold a JSON
+/// array or binary macaroons (see [Macaroon::deserialize_slice]).
+pub fn decode_macaroons_header(value: &str) -> Result<Vec<Macaroon>> {
+    Macaroon::deserialize_slice(base64_decode_flexible(value.trim().as_bytes())?)
+}
+
+/// Encode a root macaroon and its discharges as an `Authorization` header value,
+/// `Macaroon <token>`
+pub fn encode_authorization_header(macaroons: &[Macaroon]) -> Result<String> {
+    Ok(format!(
+        "{} {}",
+        AUTHORIZATION_SCHEME,
+        encode_macaroons_header(macaroons)?
+    ))
+}
+
+/// Decode an `Authorization: Macaroon <token>` header value. The scheme is matched case
+/// insensitively.
+pub fn decode_authorization_header(value: &str) -> Result<Vec<Macaroon>> {
+    let value = value.trim();
+    let (scheme, token) = value.split_once(' ').unwrap_or((value, ""));
+    if !scheme.eq_ignore_ascii_case(AUTHORIZATION_SCHEME) || token.trim().is_empty() {
+        return Err(MacaroonError::DeserializationError(
+            "not a Macaroon authorization header".to_string(),
+        ));
+    }
+    decode_macaroons_header(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MacaroonKey;
+
+    fn bound_slice() -> Vec<Macaroon> {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut root = Macaroon::create(
+            Some("https://service.example/".into()),
+            &root_key,
+            "root".into(),
+        )
+        .unwrap();
+        root.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        root.bind(&mut discharge);
+        vec![root, discharge]
+    }
+
+    #[test]
+    fn test_headers() {
+        let slice = bound_slice();
+        let header = encode_macaroons_header(&slice).unwrap();
+        assert_eq!(slice, decode_macaroons_header(&header).unwrap());
+        // httpbakery's JSON, unpadded URL-safe base64 as some clients send it
+        let json = Macaroon::serialize_slice(&slice, Format::V2JSON).unwrap();
+        let header = base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+        assert_eq!(slice, decode_macaroons_header(&header).unwrap());
+
+        let authorization = encode_authorization_header(&slice).unwrap();
+        assert!(authorization.starts_with("Macaroon "));
+        assert_eq!(slice, decode_authorization_header(&authorization).unwrap());
+        let lower = authorization.replacen("Macaroon", "macaroon", 1);
+        assert_eq!(slice, decode_authorization_header(&lower).unwrap());
+        assert!(decode_authorization_header("Bearer abc").is_err());
+        assert!(decode_authorization_header("Macaroon").is_err());
+    }
+}
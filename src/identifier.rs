@@ -0,0 +1,307 @@
+use crate::{ByteString, Macaroon, MacaroonError, Result};
+use sodiumoxide::randombytes::randombytes_into;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of fresh macaroon and caveat identifiers
+///
+/// Identifiers must be unique per root key, and should never be derived from user-controlled
+/// input. The implementations here all draw from libsodium's secure random number generator.
+pub trait IdentifierGenerator: Send + Sync {
+    fn generate(&self) -> ByteString;
+}
+
+/// Random (version 4) UUIDs in their hyphenated text form, eg
+/// `"3b241101-e2bb-4255-8caf-4136c566a962"`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UuidV4Generator;
+
+impl IdentifierGenerator for UuidV4Generator {
+    fn generate(&self) -> ByteString {
+        let mut b = [0u8; 16];
+        randombytes_into(&mut b);
+        b[6] = (b[6] & 0x0f) | 0x40;
+        b[8] = (b[8] & 0x3f) | 0x80;
+        let mut id = String::with_capacity(36);
+        for (i, byte) in b.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                id.push('-');
+            }
+            id.push_str(&format!("{:02x}", byte));
+        }
+        id.into()
+    }
+}
+
+/// [ULIDs](https://github.com/ulid/spec): a millisecond timestamp and 80 random bits, as 26
+/// Crockford base32 characters, so identifiers sort by mint time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UlidGenerator;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+impl IdentifierGenerator for UlidGenerator {
+    fn generate(&self) -> ByteString {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut random = [0u8; 10];
+        randombytes_into(&mut random);
+        let mut value: u128 = u128::from(millis & 0xffff_ffff_ffff) << 80;
+        for (i, byte) in random.iter().enumerate() {
+            value |= u128::from(*byte) << (72 - 8 * i);
+        }
+        let id: Vec<u8> = (0..26)
+            .rev()
+            .map(|i| CROCKFORD[((value >> (5 * i)) & 0x1f) as usize])
+            .collect();
+        ByteString(id)
+    }
+}
+
+/// Raw random bytes. Lengths below 16 bytes are raised to 16, to keep collisions negligible.
+#[derive(Clone, Copy, Debug)]
+pub struct RandomBytesGenerator {
+    len: usize,
+}
+
+impl RandomBytesGenerator {
+    pub const MIN_LEN: usize = 16;
+
+    pub fn new(len: usize) -> RandomBytesGenerator {
+        RandomBytesGenerator {
+            len: len.max(Self::MIN_LEN),
+        }
+    }
+}
+
+impl Default for RandomBytesGenerator {
+    fn default() -> Self {
+        RandomBytesGenerator::new(Self::MIN_LEN)
+    }
+}
+
+impl IdentifierGenerator for RandomBytesGenerator {
+    fn generate(&self) -> ByteString {
+        let mut id = vec![0u8; self.len];
+        randombytes_into(&mut id);
+        ByteString(id)
+    }
+}
+
+/// Version byte at the start of identifiers built by [IdentifierBuilder]
+pub const IDENTIFIER_VERSION: u8 = 1;
+
+/// Length in bytes of the random nonce in identifiers built by [IdentifierBuilder]
+pub const IDENTIFIER_NONCE_LEN: usize = 16;
+
+/// Builds binary macaroon identifiers carrying the ID of the root key, a random nonce and
+/// optional application metadata, which [IdentifierPayload::parse] reads back
+///
+/// The layout is the version byte ([IDENTIFIER_VERSION]), the length of the root key ID as one
+/// byte, the root key ID, the [IDENTIFIER_NONCE_LEN]-byte nonce, and then the metadata up to the
+/// end. The identifier is signed like any other, but not encrypted, so the metadata shouldn't be
+/// secret.
+///
+/// ```rust
+/// use macaroon::{IdentifierBuilder, IdentifierPayload, Macaroon, MacaroonKey};
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let identifier = IdentifierBuilder::new("2024-06".into())
+///     .metadata("user=alice".into())
+///     .build()
+///     .unwrap();
+/// let macaroon = Macaroon::create(None, &key, identifier).unwrap();
+///
+/// // when verifying, find the root key from its ID
+/// let payload = macaroon.identifier_payload().unwrap();
+/// assert_eq!(b"2024-06", payload.key_id.as_ref());
+/// assert_eq!(b"user=alice", payload.metadata.as_ref());
+/// ```
+#[derive(Clone, Debug)]
+pub struct IdentifierBuilder {
+    key_id: ByteString,
+    nonce: Option<[u8; IDENTIFIER_NONCE_LEN]>,
+    metadata: ByteString,
+}
+
+impl IdentifierBuilder {
+    /// An identifier for a macaroon minted with the root key `key_id`, with a random nonce and
+    /// no metadata
+    pub fn new(key_id: ByteString) -> IdentifierBuilder {
+        IdentifierBuilder {
+            key_id,
+            nonce: None,
+            metadata: ByteString::default(),
+        }
+    }
+
+    /// Use `nonce` rather than a random one, eg to reproduce an identifier in tests
+    pub fn nonce(mut self, nonce: [u8; IDENTIFIER_NONCE_LEN]) -> IdentifierBuilder {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: ByteString) -> IdentifierBuilder {
+        self.metadata = metadata;
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::IncompleteMacaroon] if the root key ID is empty or longer than
+    /// 255 bytes.
+    pub fn build(self) -> Result<ByteString> {
+        let key_id_len = match self.key_id.0.len() {
+            0 => return Err(MacaroonError::IncompleteMacaroon("empty root key ID")),
+            len if len > usize::from(u8::MAX) => {
+                return Err(MacaroonError::IncompleteMacaroon("root key ID too long"))
+            }
+            len => len as u8,
+        };
+        let nonce = self.nonce.unwrap_or_else(|| {
+            let mut nonce = [0; IDENTIFIER_NONCE_LEN];
+            randombytes_into(&mut nonce);
+            nonce
+        });
+        let mut id = Vec::with_capacity(
+            2 + self.key_id.0.len() + IDENTIFIER_NONCE_LEN + self.metadata.0.len(),
+        );
+        id.push(IDENTIFIER_VERSION);
+        id.push(key_id_len);
+        id.extend_from_slice(self.key_id.as_ref());
+        id.extend_from_slice(&nonce);
+        id.extend_from_slice(self.metadata.as_ref());
+        Ok(ByteString(id))
+    }
+}
+
+/// The parts of an identifier built by [IdentifierBuilder]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentifierPayload {
+    pub version: u8,
+    pub key_id: ByteString,
+    pub nonce: [u8; IDENTIFIER_NONCE_LEN],
+    /// Application metadata, empty if there is none
+    pub metadata: ByteString,
+}
+
+impl IdentifierPayload {
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::DeserializationError] if `identifier` wasn't built by
+    /// [IdentifierBuilder], or has an unknown version.
+    pub fn parse(identifier: &[u8]) -> Result<IdentifierPayload> {
+        let invalid = |reason: &str| {
+            MacaroonError::DeserializationError(format!("invalid identifier: {}", reason))
+        };
+        let (version, rest) = identifier.split_first().ok_or_else(|| invalid("empty"))?;
+        if *version != IDENTIFIER_VERSION {
+            return Err(invalid(&format!("unknown version {}", version)));
+        }
+        let (key_id_len, rest) = rest
+            .split_first()
+            .ok_or_else(|| invalid("missing root key ID"))?;
+        let key_id_len = usize::from(*key_id_len);
+        if key_id_len == 0 || rest.len() < key_id_len + IDENTIFIER_NONCE_LEN {
+            return Err(invalid("too short"));
+        }
+        let (key_id, rest) = rest.split_at(key_id_len);
+        let (nonce, metadata) = rest.split_at(IDENTIFIER_NONCE_LEN);
+        let mut payload_nonce = [0; IDENTIFIER_NONCE_LEN];
+        payload_nonce.copy_from_slice(nonce);
+        Ok(IdentifierPayload {
+            version: *version,
+            key_id: key_id.into(),
+            nonce: payload_nonce,
+            metadata: metadata.into(),
+        })
+    }
+}
+
+impl Macaroon {
+    /// Parse the macaroon's identifier, if it was built by [IdentifierBuilder]
+    pub fn identifier_payload(&self) -> Result<IdentifierPayload> {
+        IdentifierPayload::parse(self.identifier.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        IdentifierBuilder, IdentifierGenerator, IdentifierPayload, RandomBytesGenerator,
+        UlidGenerator, UuidV4Generator, IDENTIFIER_VERSION,
+    };
+    use crate::{ByteString, MacaroonError};
+
+    #[test]
+    fn test_uuid_v4() {
+        let id = UuidV4Generator.generate();
+        let id = std::str::from_utf8(id.as_ref()).unwrap();
+        assert_eq!(36, id.len());
+        assert_eq!(Some('4'), id.chars().nth(14));
+        assert!("89ab".contains(id.chars().nth(19).unwrap()));
+        assert_ne!(UuidV4Generator.generate(), UuidV4Generator.generate());
+    }
+
+    #[test]
+    fn test_ulid() {
+        let first = UlidGenerator.generate();
+        assert_eq!(26, first.0.len());
+        assert!(first.0[0] <= b'7');
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = UlidGenerator.generate();
+        assert!(first.0[..10] < second.0[..10]);
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        assert_eq!(16, RandomBytesGenerator::new(4).generate().0.len());
+        assert_eq!(32, RandomBytesGenerator::new(32).generate().0.len());
+    }
+
+    #[test]
+    fn test_identifier_payload() {
+        let id = IdentifierBuilder::new("key-1".into())
+            .nonce([7; 16])
+            .metadata(vec![0, 1, 2].into())
+            .build()
+            .unwrap();
+        let mut expected = vec![IDENTIFIER_VERSION, 5];
+        expected.extend_from_slice(b"key-1");
+        expected.extend_from_slice(&[7; 16]);
+        expected.extend_from_slice(&[0, 1, 2]);
+        assert_eq!(ByteString(expected), id);
+
+        let payload = IdentifierPayload::parse(id.as_ref()).unwrap();
+        assert_eq!(ByteString::from("key-1"), payload.key_id);
+        assert_eq!([7; 16], payload.nonce);
+        assert_eq!(ByteString::from(vec![0, 1, 2]), payload.metadata);
+
+        // random nonces, and no metadata
+        let first = IdentifierBuilder::new("k".into()).build().unwrap();
+        let second = IdentifierBuilder::new("k".into()).build().unwrap();
+        assert_ne!(first, second);
+        assert!(IdentifierPayload::parse(first.as_ref())
+            .unwrap()
+            .metadata
+            .0
+            .is_empty());
+
+        assert!(IdentifierBuilder::new("".into()).build().is_err());
+        assert!(IdentifierBuilder::new(vec![b'k'; 256].into())
+            .build()
+            .is_err());
+        for invalid in [
+            &b""[..],
+            b"\x01",
+            b"\x02\x01k0123456789abcdef",
+            &id.0[..id.0.len() - 5],
+        ] {
+            assert!(matches!(
+                IdentifierPayload::parse(invalid),
+                Err(MacaroonError::DeserializationError(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Verifying request macaroons in [actix-web](https://docs.rs/actix-web) applications
+//!
+//! Enable the `actix` feature to use this module. The [MacaroonMiddleware] verifies the
+//! macaroons of each request as described in the [integrations](super) documentation, and
+//! stores the result in the request extensions, where handlers get it with the
+//! [VerifiedMacaroon] extractor. Requests without a valid macaroon are rejected with
+//! `401 Unauthorized`, or the response of the middleware's error handler.
+//!
+//! ```rust
+//! use actix_web::{web, App};
+//! use macaroon::integrations::actix::{MacaroonMiddleware, VerifiedMacaroon};
+//! use macaroon::{MacaroonKey, Verifier};
+//!
+//! async fn whoami(verified: VerifiedMacaroon) -> String {
+//!     verified.attributes.get("username").unwrap_or("anonymous").to_string()
+//! }
+//!
+//! let root_key = MacaroonKey::generate(b"root key");
+//! let verifier = Verifier::builder()
+//!     .satisfy_exact("service = whoami".into())
+//!     .build_shared();
+//! let app = App::new().route("/whoami", web::get().to(whoami)).wrap(MacaroonMiddleware::new(
+//!     move |_request, _macaroon| Ok((verifier.clone(), root_key.clone())),
+//! ));
+//! ```
+
+pub use super::{MacaroonRejection, TokenSource, VerifiedMacaroon};
+
+use super::{verify_request, VerifierFactory};
+use crate::{Macaroon, MacaroonError, MacaroonKey, Verifier};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::WWW_AUTHENTICATE;
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use std::fmt;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::Arc;
+
+impl FromRequest for VerifiedMacaroon {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            request
+                .extensions()
+                .get::<VerifiedMacaroon>()
+                .cloned()
+                .ok_or_else(|| {
+                    MacaroonRejection(MacaroonError::IncompleteMacaroon(
+                        "no verified macaroon in the request",
+                    ))
+                    .into()
+                }),
+        )
+    }
+}
+
+impl ResponseError for MacaroonRejection {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNAUTHORIZED
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        debug!("rejecting request: {}", self.0);
+        HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, crate::AUTHORIZATION_SCHEME))
+            .body("invalid or missing macaroon")
+    }
+}
+
+/// Returns the response to a request without a valid macaroon
+type ErrorHandler = dyn Fn(&HttpRequest, MacaroonError) -> HttpResponse + Send + Sync;
+
+/// Middleware verifying the macaroons of each request, see the [module](self) documentation
+#[derive(Clone)]
+pub struct MacaroonMiddleware {
+    factory: Arc<VerifierFactory<HttpRequest>>,
+    sources: Vec<TokenSource>,
+    optional: bool,
+    error_handler: Option<Arc<ErrorHandler>>,
+}
+
+impl MacaroonMiddleware {
+    /// Verify macaroons with the verifier and root key returned by `factory`, which is given the
+    /// request and the root macaroon, eg to look up the key by identifier in a
+    /// [RootKeyStore](crate::RootKeyStore)
+    pub fn new<F>(factory: F) -> MacaroonMiddleware
+    where
+        F: Fn(&HttpRequest, &Macaroon) -> crate::Result<(Arc<Verifier>, MacaroonKey)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        MacaroonMiddleware {
+            factory: Arc::new(factory),
+            sources: TokenSource::defaults(),
+            optional: false,
+            error_handler: None,
+        }
+    }
+
+    /// Where to look for macaroons, in order. Each macaroon slice found is tried until one
+    /// verifies.
+    pub fn sources(mut self, sources: Vec<TokenSource>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Pass requests without a valid macaroon on to the service, without a [VerifiedMacaroon],
+    /// rather than rejecting them; handlers can then take an `Option<VerifiedMacaroon>`
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    /// Respond to requests without a valid macaroon with `handler`, given the last verification
+    /// error, rather than with a [MacaroonRejection]
+    pub fn error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, MacaroonError) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    fn verify(&self, request: &HttpRequest) -> crate::Result<VerifiedMacaroon> {
+        let headers = |name: &str| request.headers().get_all(name).flat_map(|v| v.to_str());
+        verify_request(request, headers, &self.sources, &*self.factory)
+    }
+
+    fn reject(&self, request: &HttpRequest, error: MacaroonError) -> HttpResponse {
+        match &self.error_handler {
+            Some(handler) => handler(request, error),
+            None => MacaroonRejection(error).error_response(),
+        }
+    }
+}
+
+impl fmt::Debug for MacaroonMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MacaroonMiddleware")
+            .field("sources", &self.sources)
+            .field("optional", &self.optional)
+            .finish()
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for MacaroonMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = MacaroonMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MacaroonMiddlewareService {
+            middleware: self.clone(),
+            service,
+        }))
+    }
+}
+
+/// The service wrapped by a [MacaroonMiddleware]
+pub struct MacaroonMiddlewareService<S> {
+    middleware: MacaroonMiddleware,
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for MacaroonMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        match self.middleware.verify(request.request()) {
+            Ok(verified) => {
+                request.extensions_mut().insert(verified);
+            }
+            Err(e) if self.middleware.optional => debug!("no verified macaroon: {}", e),
+            Err(e) => {
+                let response = self.middleware.reject(request.request(), e);
+                let response = request.into_response(response).map_into_right_body();
+                return Box::pin(ready(Ok(response)));
+            }
+        }
+        let response = self.service.call(request);
+        Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MacaroonMiddleware, TokenSource, VerifiedMacaroon};
+    use crate::test_util::block_on;
+    use crate::{encode_authorization_header, encode_macaroons_header, Macaroon, MacaroonKey};
+    use crate::{MacaroonError, Verifier};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    async fn whoami(verified: VerifiedMacaroon) -> String {
+        verified
+            .attributes
+            .get("username")
+            .unwrap_or("")
+            .to_string()
+    }
+
+    async fn maybe(verified: Option<VerifiedMacaroon>) -> String {
+        verified.map_or("anonymous".to_string(), |v| {
+            String::from_utf8_lossy(v.macaroon.identifier().as_ref()).into_owned()
+        })
+    }
+
+    /// Send a request with `header` through `middleware`, returning the status and body
+    fn send(
+        middleware: &MacaroonMiddleware,
+        path: &str,
+        header: Option<(&str, String)>,
+    ) -> (StatusCode, String) {
+        let app = App::new()
+            .route("/whoami", web::get().to(whoami))
+            .route("/maybe", web::get().to(maybe))
+            .wrap(middleware.clone());
+        let app = block_on(test::init_service(app));
+        let mut request = test::TestRequest::get().uri(path);
+        if let Some(header) = header {
+            request = request.insert_header(header);
+        }
+        let response = block_on(test::call_service(&app, request.to_request()));
+        let status = response.status();
+        let body = block_on(test::read_body(response));
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_middleware() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        macaroon.add_first_party_caveat("service = whoami".into());
+        macaroon.add_third_party_caveat("https://login/", &caveat_key, "login".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        discharge.add_declared("username", "alice");
+        macaroon.bind(&mut discharge);
+        let slice = vec![macaroon.clone(), discharge];
+
+        let verifier = Verifier::builder()
+            .satisfy_exact("service = whoami".into())
+            .build_shared();
+        let middleware = MacaroonMiddleware::new(move |_request, macaroon| {
+            if macaroon.identifier() != "id".into() {
+                return Err(MacaroonError::InvalidSignature);
+            }
+            Ok((verifier.clone(), root_key.clone()))
+        });
+
+        let header = encode_macaroons_header(&slice).unwrap();
+        let ok = (StatusCode::OK, "alice".to_string());
+        let authorization = encode_authorization_header(&slice).unwrap();
+        assert_eq!(
+            ok,
+            send(
+                &middleware,
+                "/whoami",
+                Some(("Authorization", authorization))
+            )
+        );
+        assert_eq!(
+            ok,
+            send(&middleware, "/whoami", Some(("Macaroons", header.clone())))
+        );
+
+        // missing, undischarged and unknown macaroons
+        let unauthorized = send(&middleware, "/whoami", None);
+        assert_eq!(StatusCode::UNAUTHORIZED, unauthorized.0);
+        let undischarged = encode_macaroons_header(&slice[..1]).unwrap();
+        let unauthorized = send(&middleware, "/whoami", Some(("Macaroons", undischarged)));
+        assert_eq!(StatusCode::UNAUTHORIZED, unauthorized.0);
+        let other = Macaroon::create(None, &caveat_key, "other".into()).unwrap();
+        let other = encode_macaroons_header(&[other]).unwrap();
+        let unauthorized = send(&middleware, "/whoami", Some(("Macaroons", other.clone())));
+        assert_eq!(StatusCode::UNAUTHORIZED, unauthorized.0);
+
+        // a custom error response
+        let forbidden = middleware.clone().error_handler(|_request, error| {
+            HttpResponse::Forbidden().body(format!(
+                "{}",
+                matches!(error, MacaroonError::InvalidSignature)
+            ))
+        });
+        assert_eq!(
+            (StatusCode::FORBIDDEN, "true".to_string()),
+            send(&forbidden, "/whoami", Some(("Macaroons", other.clone())))
+        );
+
+        // other sources, and optional verification
+        let middleware = middleware
+            .sources(vec![TokenSource::Header("X-Token".to_string())])
+            .optional(true);
+        assert_eq!(
+            ok,
+            send(&middleware, "/whoami", Some(("X-Token", header.clone())))
+        );
+        assert_eq!(
+            (StatusCode::OK, "id".to_string()),
+            send(&middleware, "/maybe", Some(("X-Token", header.clone())))
+        );
+        assert_eq!(
+            (StatusCode::OK, "anonymous".to_string()),
+            send(&middleware, "/maybe", Some(("Macaroons", header)))
+        );
+        assert_eq!(
+            (StatusCode::OK, "anonymous".to_string()),
+            send(&middleware, "/maybe", Some(("X-Token", other)))
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            send(&middleware, "/whoami", None).0
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Verifying request macaroons in [axum](https://docs.rs/axum) applications
+//!
+//! Enable the `axum` feature to use this module. A [MacaroonLayer] verifies the macaroons of
+//! each request as described in the [integrations](super) documentation, and stores the result
+//! in the request extensions, where handlers get it with the [VerifiedMacaroon] extractor.
+//! Requests without a valid macaroon are rejected with `401 Unauthorized`.
+//!
+//! ```rust
+//! use axum::{routing::get, Router};
+//! use macaroon::integrations::axum::{MacaroonLayer, VerifiedMacaroon};
+//! use macaroon::{MacaroonKey, Verifier};
+//!
+//! async fn whoami(verified: VerifiedMacaroon) -> String {
+//!     verified.attributes.get("username").unwrap_or("anonymous").to_string()
+//! }
+//!
+//! let root_key = MacaroonKey::generate(b"root key");
+//! let verifier = Verifier::builder()
+//!     .satisfy_exact("service = whoami".into())
+//!     .build_shared();
+//! let app: Router = Router::new().route("/whoami", get(whoami)).layer(MacaroonLayer::new(
+//!     move |_request, _macaroon| Ok((verifier.clone(), root_key.clone())),
+//! ));
+//! ```
+
+pub use super::{MacaroonRejection, TokenSource, VerifiedMacaroon};
+
+use super::{verify_request, VerifierFactory};
+use crate::{Macaroon, MacaroonError, MacaroonKey, Result, Verifier};
+use ::axum::body::Body;
+use ::axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use ::axum::http::header::WWW_AUTHENTICATE;
+use ::axum::http::request::Parts;
+use ::axum::http::{Request, StatusCode};
+use ::axum::response::{IntoResponse, Response};
+use std::convert::Infallible;
+use std::fmt;
+use std::future::{ready, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+impl<S: Send + Sync> FromRequestParts<S> for VerifiedMacaroon {
+    type Rejection = MacaroonRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<VerifiedMacaroon>()
+            .cloned()
+            .ok_or(MacaroonRejection(MacaroonError::IncompleteMacaroon(
+                "no verified macaroon in the request",
+            )))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for VerifiedMacaroon {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<VerifiedMacaroon>().cloned())
+    }
+}
+
+impl IntoResponse for MacaroonRejection {
+    fn into_response(self) -> Response {
+        debug!("rejecting request: {}", self.0);
+        (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, crate::AUTHORIZATION_SCHEME)],
+            "invalid or missing macaroon",
+        )
+            .into_response()
+    }
+}
+
+/// A tower layer verifying the macaroons of each request, see the [module](self) documentation
+#[derive(Clone)]
+pub struct MacaroonLayer {
+    factory: Arc<VerifierFactory<Parts>>,
+    sources: Vec<TokenSource>,
+    optional: bool,
+}
+
+impl MacaroonLayer {
+    /// Verify macaroons with the verifier and root key returned by `factory`, which is given the
+    /// request and the root macaroon, eg to look up the key by identifier in a
+    /// [RootKeyStore](crate::RootKeyStore)
+    pub fn new<F>(factory: F) -> MacaroonLayer
+    where
+        F: Fn(&Parts, &Macaroon) -> Result<(Arc<Verifier>, MacaroonKey)> + Send + Sync + 'static,
+    {
+        MacaroonLayer {
+            factory: Arc::new(factory),
+            sources: TokenSource::defaults(),
+            optional: false,
+        }
+    }
+
+    /// Where to look for macaroons, in order. Each macaroon slice found is tried until one
+    /// verifies.
+    pub fn sources(mut self, sources: Vec<TokenSource>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Pass requests without a valid macaroon on to the service, without a [VerifiedMacaroon],
+    /// rather than rejecting them; handlers can then take an `Option<VerifiedMacaroon>`
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    fn verify(&self, parts: &Parts) -> Result<VerifiedMacaroon> {
+        let headers = |name: &str| {
+            parts
+                .headers
+                .get_all(name)
+                .into_iter()
+                .flat_map(|v| v.to_str())
+        };
+        verify_request(parts, headers, &self.sources, &*self.factory)
+    }
+}
+
+impl fmt::Debug for MacaroonLayer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MacaroonLayer")
+            .field("sources", &self.sources)
+            .field("optional", &self.optional)
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for MacaroonLayer {
+    type Service = MacaroonService<S>;
+
+    fn layer(&self, inner: S) -> MacaroonService<S> {
+        MacaroonService {
+            layer: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// The service wrapped by a [MacaroonLayer]
+#[derive(Clone, Debug)]
+pub struct MacaroonService<S> {
+    layer: MacaroonLayer,
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for MacaroonService<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Response, S::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+        match self.layer.verify(&parts) {
+            Ok(verified) => {
+                parts.extensions.insert(verified);
+            }
+            Err(e) if self.layer.optional => debug!("no verified macaroon: {}", e),
+            Err(e) => return Box::pin(ready(Ok(MacaroonRejection(e).into_response()))),
+        }
+        Box::pin(self.inner.call(Request::from_parts(parts, body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MacaroonLayer, TokenSource, VerifiedMacaroon};
+    use crate::test_util::block_on;
+    use crate::{encode_authorization_header, encode_macaroons_header, Macaroon, MacaroonKey};
+    use crate::{MacaroonError, Verifier};
+    use ::axum::body::Body;
+    use ::axum::http::{Request, StatusCode};
+    use ::axum::response::Response;
+    use ::axum::routing::get;
+    use ::axum::Router;
+    use tower_service::Service;
+
+    async fn whoami(verified: VerifiedMacaroon) -> String {
+        verified
+            .attributes
+            .get("username")
+            .unwrap_or("")
+            .to_string()
+    }
+
+    async fn maybe(verified: Option<VerifiedMacaroon>) -> String {
+        verified.map_or("anonymous".to_string(), |v| {
+            String::from_utf8_lossy(v.macaroon.identifier().as_ref()).into_owned()
+        })
+    }
+
+    /// Send a request with `header` to `app`, returning the status and body
+    fn send(app: &mut Router, path: &str, header: Option<(&str, String)>) -> (StatusCode, String) {
+        let mut request = Request::get(path);
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        let response: Response = block_on(app.call(request.body(Body::empty()).unwrap())).unwrap();
+        let status = response.status();
+        let body = block_on(::axum::body::to_bytes(response.into_body(), 1024)).unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_layer() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        macaroon.add_first_party_caveat("service = whoami".into());
+        macaroon.add_third_party_caveat("https://login/", &caveat_key, "login".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        discharge.add_declared("username", "alice");
+        macaroon.bind(&mut discharge);
+        let slice = vec![macaroon.clone(), discharge];
+
+        let verifier = Verifier::builder()
+            .satisfy_exact("service = whoami".into())
+            .build_shared();
+        let layer = MacaroonLayer::new(move |_request, macaroon| {
+            if macaroon.identifier() != "id".into() {
+                return Err(MacaroonError::InvalidSignature);
+            }
+            Ok((verifier.clone(), root_key.clone()))
+        });
+        let mut app = Router::new()
+            .route("/whoami", get(whoami))
+            .layer(layer.clone());
+
+        let header = encode_macaroons_header(&slice).unwrap();
+        let ok = (StatusCode::OK, "alice".to_string());
+        assert_eq!(
+            ok,
+            send(
+                &mut app,
+                "/whoami",
+                Some((
+                    "Authorization",
+                    encode_authorization_header(&slice).unwrap()
+                ))
+            )
+        );
+        assert_eq!(
+            ok,
+            send(&mut app, "/whoami", Some(("Macaroons", header.clone())))
+        );
+        let cookies = crate::encode_macaroon_cookies(&slice, 100).unwrap();
+        let cookie = cookies
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<String>>()
+            .join("; ");
+        assert_eq!(ok, send(&mut app, "/whoami", Some(("Cookie", cookie))));
+
+        // missing, undischarged and unknown macaroons
+        let unauthorized = send(&mut app, "/whoami", None);
+        assert_eq!(StatusCode::UNAUTHORIZED, unauthorized.0);
+        let undischarged = encode_macaroons_header(&slice[..1]).unwrap();
+        let unauthorized = send(&mut app, "/whoami", Some(("Macaroons", undischarged)));
+        assert_eq!(StatusCode::UNAUTHORIZED, unauthorized.0);
+        let other = Macaroon::create(None, &caveat_key, "other".into()).unwrap();
+        let other = encode_macaroons_header(&[other]).unwrap();
+        let unauthorized = send(&mut app, "/whoami", Some(("Macaroons", other.clone())));
+        assert_eq!(StatusCode::UNAUTHORIZED, unauthorized.0);
+
+        // other sources, and optional verification
+        let mut app = Router::new()
+            .route("/whoami", get(whoami))
+            .route("/maybe", get(maybe))
+            .layer(
+                layer
+                    .sources(vec![TokenSource::Header("X-Token".to_string())])
+                    .optional(true),
+            );
+        assert_eq!(
+            ok,
+            send(&mut app, "/whoami", Some(("X-Token", header.clone())))
+        );
+        assert_eq!(
+            (StatusCode::OK, "id".to_string()),
+            send(&mut app, "/maybe", Some(("X-Token", header.clone())))
+        );
+        assert_eq!(
+            (StatusCode::OK, "anonymous".to_string()),
+            send(&mut app, "/maybe", Some(("Macaroons", header)))
+        );
+        assert_eq!(
+            (StatusCode::OK, "anonymous".to_string()),
+            send(&mut app, "/maybe", Some(("X-Token", other)))
+        );
+        assert_eq!(StatusCode::UNAUTHORIZED, send(&mut app, "/whoami", None).0);
+    }
+}
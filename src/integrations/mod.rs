@@ -0,0 +1,144 @@
+//! Glue between macaroons and web frameworks, each behind a feature of the same name
+//!
+//! The integrations take the macaroons from each request, as sent by go-macaroon-bakery's
+//! httpbakery or by [encode_authorization_header](crate::encode_authorization_header): by
+//! default from an `Authorization: Macaroon ...` header, a `Macaroons` header or macaroon
+//! cookies, in that order (see [TokenSource]). They verify them with the [Verifier] and root key
+//! returned by the application's factory, using [Verifier::verify_declared], and hand the
+//! [VerifiedMacaroon] to request handlers.
+//!
+//! The gRPC [interceptor](tonic) takes the macaroon from the `macaroon` request metadata instead,
+//! as lnd and other gRPC services expect.
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "tonic")]
+pub mod tonic;
+
+#[cfg(any(feature = "actix", feature = "axum"))]
+use crate::{
+    decode_authorization_header, decode_macaroon_cookies, decode_macaroons_header, MACAROONS_HEADER,
+};
+use crate::{Macaroon, MacaroonError, MacaroonKey, Result, VerifiedAttributes, Verifier};
+use std::fmt;
+use std::sync::Arc;
+
+/// Where in a request to look for macaroons
+#[cfg(any(feature = "actix", feature = "axum"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    /// An `Authorization: Macaroon ...` header (see
+    /// [decode_authorization_header])
+    Authorization,
+    /// A header with this name holding a macaroon slice (see
+    /// [decode_macaroons_header]), eg [MACAROONS_HEADER]
+    Header(String),
+    /// Macaroon cookies (see [decode_macaroon_cookies])
+    Cookies,
+}
+
+#[cfg(any(feature = "actix", feature = "axum"))]
+impl TokenSource {
+    /// `Authorization`, then the `Macaroons` header, then cookies
+    pub fn defaults() -> Vec<TokenSource> {
+        vec![
+            TokenSource::Authorization,
+            TokenSource::Header(MACAROONS_HEADER.to_string()),
+            TokenSource::Cookies,
+        ]
+    }
+
+    /// The macaroon slices (a root macaroon followed by its discharges) found in the headers,
+    /// given the values of the headers called `name`
+    fn slices<'h, H, I>(&self, headers: H) -> Result<Vec<Vec<Macaroon>>>
+    where
+        H: Fn(&str) -> I,
+        I: Iterator<Item = &'h str>,
+    {
+        match self {
+            TokenSource::Authorization => headers("authorization")
+                .map(decode_authorization_header)
+                .collect(),
+            TokenSource::Header(name) => headers(name).map(decode_macaroons_header).collect(),
+            TokenSource::Cookies => Ok(headers("cookie")
+                .flat_map(decode_macaroon_cookies)
+                .collect()),
+        }
+    }
+}
+
+/// A macaroon verified by one of the integrations, with its discharges and the attributes they
+/// declared (see [Verifier::verify_declared], including why they can only be relied on if the
+/// trusted discharger always declares them)
+///
+/// As an extractor, it rejects requests which weren't verified, and an `Option` of it is `None`
+/// for them instead.
+#[derive(Clone, Debug)]
+pub struct VerifiedMacaroon {
+    pub macaroon: Macaroon,
+    pub discharges: Vec<Macaroon>,
+    pub attributes: VerifiedAttributes,
+}
+
+/// The response to a request without a valid macaroon: `401 Unauthorized`, with a
+/// `WWW-Authenticate: Macaroon` header
+///
+/// The response doesn't say what was wrong with the macaroon; the error is logged at debug level.
+#[derive(Debug)]
+pub struct MacaroonRejection(pub MacaroonError);
+
+impl fmt::Display for MacaroonRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Returns the verifier and root key to verify the macaroon of a request of type `R` with
+type VerifierFactory<R> =
+    dyn Fn(&R, &Macaroon) -> Result<(Arc<Verifier>, MacaroonKey)> + Send + Sync;
+
+#[cfg(any(feature = "actix", feature = "axum"))]
+/// Verify the first macaroon slice found in `sources` that verifies, or return the last error
+fn verify_request<'h, R, H, I>(
+    request: &R,
+    headers: H,
+    sources: &[TokenSource],
+    factory: &VerifierFactory<R>,
+) -> Result<VerifiedMacaroon>
+where
+    H: Fn(&str) -> I,
+    I: Iterator<Item = &'h str>,
+{
+    let mut error = MacaroonError::IncompleteMacaroon("no macaroon found in the request");
+    for source in sources.iter() {
+        let slices = match source.slices(&headers) {
+            Ok(slices) => slices,
+            Err(e) => {
+                error = e;
+                continue;
+            }
+        };
+        for mut slice in slices {
+            if slice.is_empty() {
+                continue;
+            }
+            let macaroon = slice.remove(0);
+            let verified = factory(request, &macaroon).and_then(|(verifier, key)| {
+                verifier.verify_declared(&macaroon, &key, slice.clone())
+            });
+            match verified {
+                Ok(attributes) => {
+                    return Ok(VerifiedMacaroon {
+                        macaroon,
+                        discharges: slice,
+                        attributes,
+                    })
+                }
+                Err(e) => error = e,
+            }
+        }
+    }
+    Err(error)
+}
@@ -0,0 +1,236 @@
+//! Verifying request macaroons in [tonic](https://docs.rs/tonic) gRPC services
+//!
+//! Enable the `tonic` feature to use this module. A [MacaroonInterceptor] takes the macaroon
+//! from the `macaroon` request metadata, as sent to lnd and other gRPC services, verifies it and
+//! stores the result in the request extensions, where RPC handlers get it with
+//! `request.extensions().get::<VerifiedMacaroon>()`. RPCs without a valid macaroon are rejected
+//! with `PermissionDenied`.
+//!
+//! The metadata value is either the hex encoding of a binary macaroon, as lnd's clients send it,
+//! or any serialization accepted by [Macaroon::deserialize]. Further values of the same key are
+//! taken as discharges of the first.
+//!
+//! ```rust
+//! use macaroon::integrations::tonic::{MacaroonInterceptor, VerifiedMacaroon};
+//! use macaroon::{Format, Macaroon, MacaroonKey, Verifier};
+//! use tonic::service::Interceptor;
+//!
+//! let root_key = MacaroonKey::generate(b"root key");
+//! let verifier = Verifier::builder()
+//!     .satisfy_exact("service = lightning".into())
+//!     .build_shared();
+//! let key = root_key.clone();
+//! let mut interceptor =
+//!     MacaroonInterceptor::new(move |_metadata, _macaroon| Ok((verifier.clone(), key.clone())));
+//! // eg `LightningServer::with_interceptor(service, interceptor)`
+//!
+//! let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+//! macaroon.add_first_party_caveat("service = lightning".into());
+//! let mut request = tonic::Request::new(());
+//! let token = macaroon.serialize(Format::V2).unwrap();
+//! request.metadata_mut().insert("macaroon", token.parse().unwrap());
+//! let request = interceptor.call(request).unwrap();
+//! assert!(request.extensions().get::<VerifiedMacaroon>().is_some());
+//! ```
+
+pub use super::{MacaroonRejection, VerifiedMacaroon};
+
+use super::VerifierFactory;
+use crate::{decode_hex, Macaroon, MacaroonError, MacaroonKey, Result, Verifier};
+use ::tonic::metadata::MetadataMap;
+use ::tonic::service::Interceptor;
+use ::tonic::{Request, Status};
+use std::fmt;
+use std::sync::Arc;
+
+/// The request metadata key holding the macaroon
+pub const MACAROON_METADATA_KEY: &str = "macaroon";
+
+impl From<MacaroonRejection> for Status {
+    fn from(rejection: MacaroonRejection) -> Status {
+        debug!("rejecting RPC: {}", rejection.0);
+        Status::permission_denied("invalid or missing macaroon")
+    }
+}
+
+/// A tonic interceptor verifying the macaroon of each RPC, see the [module](self) documentation
+#[derive(Clone)]
+pub struct MacaroonInterceptor {
+    factory: Arc<VerifierFactory<MetadataMap>>,
+    key: String,
+    optional: bool,
+}
+
+impl MacaroonInterceptor {
+    /// Verify macaroons with the verifier and root key returned by `factory`, which is given the
+    /// request metadata and the root macaroon, eg to look up the key by identifier in a
+    /// [RootKeyStore](crate::RootKeyStore)
+    pub fn new<F>(factory: F) -> MacaroonInterceptor
+    where
+        F: Fn(&MetadataMap, &Macaroon) -> Result<(Arc<Verifier>, MacaroonKey)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        MacaroonInterceptor {
+            factory: Arc::new(factory),
+            key: MACAROON_METADATA_KEY.to_string(),
+            optional: false,
+        }
+    }
+
+    /// The metadata key to take the macaroon from, [MACAROON_METADATA_KEY] by default
+    pub fn metadata_key(mut self, key: &str) -> Self {
+        self.key = key.to_ascii_lowercase();
+        self
+    }
+
+    /// Pass RPCs without a valid macaroon on to the service, without a [VerifiedMacaroon],
+    /// rather than rejecting them
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    fn verify(&self, metadata: &MetadataMap) -> Result<VerifiedMacaroon> {
+        let mut macaroons = metadata
+            .get_all(self.key.as_str())
+            .iter()
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| MacaroonError::DeserializationError("invalid metadata".into()))
+                    .and_then(decode_metadata)
+            })
+            .collect::<Result<Vec<Macaroon>>>()?;
+        if macaroons.is_empty() {
+            return Err(MacaroonError::IncompleteMacaroon(
+                "no macaroon found in the request",
+            ));
+        }
+        let macaroon = macaroons.remove(0);
+        let (verifier, key) = (self.factory)(metadata, &macaroon)?;
+        let attributes = verifier.verify_declared(&macaroon, &key, macaroons.clone())?;
+        Ok(VerifiedMacaroon {
+            macaroon,
+            discharges: macaroons,
+            attributes,
+        })
+    }
+}
+
+/// Hex-encoded binary, as lnd expects, or any other serialization
+fn decode_metadata(value: &str) -> Result<Macaroon> {
+    let value = value.trim();
+    match decode_hex(value) {
+        Some(binary) => Macaroon::deserialize_binary(&binary),
+        None => Macaroon::deserialize(value),
+    }
+}
+
+impl fmt::Debug for MacaroonInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MacaroonInterceptor")
+            .field("key", &self.key)
+            .field("optional", &self.optional)
+            .finish()
+    }
+}
+
+impl Interceptor for MacaroonInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        match self.verify(request.metadata()) {
+            Ok(verified) => {
+                request.extensions_mut().insert(verified);
+            }
+            Err(e) if self.optional => debug!("no verified macaroon: {}", e),
+            Err(e) => return Err(MacaroonRejection(e).into()),
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MacaroonInterceptor, VerifiedMacaroon};
+    use crate::{encode_hex, Format, Macaroon, MacaroonError, MacaroonKey, Verifier};
+    use ::tonic::service::Interceptor;
+    use ::tonic::{Code, Request};
+
+    /// Send an RPC with `values` of `key` through `interceptor`, returning the verified identifier
+    fn call(
+        interceptor: &mut MacaroonInterceptor,
+        key: &str,
+        values: &[&str],
+    ) -> Result<Option<String>, Code> {
+        let mut request = Request::new(());
+        for value in values {
+            request.metadata_mut().append(
+                key.parse::<::tonic::metadata::AsciiMetadataKey>().unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        let request = interceptor.call(request).map_err(|s| s.code())?;
+        Ok(request
+            .extensions()
+            .get::<VerifiedMacaroon>()
+            .map(|v| String::from_utf8_lossy(v.macaroon.identifier().as_ref()).into_owned()))
+    }
+
+    #[test]
+    fn test_interceptor() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        macaroon.add_first_party_caveat("service = lightning".into());
+        let verifier = Verifier::builder()
+            .satisfy_exact("service = lightning".into())
+            .build_shared();
+        let mut interceptor = MacaroonInterceptor::new(move |_metadata, macaroon| {
+            if macaroon.identifier() != "id".into() {
+                return Err(MacaroonError::InvalidSignature);
+            }
+            Ok((verifier.clone(), root_key.clone()))
+        });
+
+        // lnd's hex, and base64
+        let binary =
+            crate::base64_decode_flexible(macaroon.serialize(Format::V2).unwrap().as_bytes())
+                .unwrap();
+        let id = Ok(Some("id".to_string()));
+        assert_eq!(
+            id,
+            call(&mut interceptor, "macaroon", &[&encode_hex(&binary)])
+        );
+        let base64 = macaroon.serialize(Format::V1).unwrap();
+        assert_eq!(id, call(&mut interceptor, "macaroon", &[&base64]));
+
+        // discharges
+        let mut discharged = macaroon.clone();
+        discharged.add_third_party_caveat("https://auth/", &caveat_key, "login".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        discharged.bind(&mut discharge);
+        let discharged = discharged.serialize(Format::V2).unwrap();
+        let discharge = discharge.serialize(Format::V2).unwrap();
+        let values = [discharged.as_str(), &discharge];
+        assert_eq!(id, call(&mut interceptor, "macaroon", &values));
+        assert_eq!(
+            Err(Code::PermissionDenied),
+            call(&mut interceptor, "macaroon", &values[..1])
+        );
+
+        // missing, garbled and unknown macaroons
+        let denied = Err(Code::PermissionDenied);
+        assert_eq!(denied, call(&mut interceptor, "macaroon", &[]));
+        assert_eq!(denied, call(&mut interceptor, "macaroon", &["abcd"]));
+        let other = Macaroon::create(None, &caveat_key, "other".into()).unwrap();
+        let other = other.serialize(Format::V2).unwrap();
+        assert_eq!(denied, call(&mut interceptor, "macaroon", &[&other]));
+
+        // another key, and optional verification
+        let mut interceptor = interceptor.metadata_key("X-Token").optional(true);
+        assert_eq!(id, call(&mut interceptor, "x-token", &[&base64]));
+        assert_eq!(Ok(None), call(&mut interceptor, "macaroon", &[&base64]));
+        assert_eq!(Ok(None), call(&mut interceptor, "x-token", &[&other]));
+    }
+}
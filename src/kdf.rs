@@ -0,0 +1,206 @@
+use crate::{MacaroonError, MacaroonKey, Result};
+use sodiumoxide::crypto::auth::hmacsha256::State;
+use sodiumoxide::crypto::pwhash::argon2id13;
+
+/// Length in bytes of the salt for [MacaroonKey::derive_argon2]
+pub const ARGON2_SALT_LEN: usize = argon2id13::SALTBYTES;
+
+/// Cost parameters for [MacaroonKey::derive_argon2]
+///
+/// The same parameters (and salt) must be used every time a key is derived from a passphrase, so
+/// they should be stored alongside the salt. The presets are libsodium's: `MODERATE`, the
+/// default, takes around a second and 256 MiB, which suits deriving root keys at startup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Number of passes over the memory
+    pub ops_limit: usize,
+    /// Memory to use, in bytes
+    pub mem_limit: usize,
+}
+
+impl Argon2Params {
+    pub const INTERACTIVE: Argon2Params = Argon2Params {
+        ops_limit: argon2id13::OPSLIMIT_INTERACTIVE.0,
+        mem_limit: argon2id13::MEMLIMIT_INTERACTIVE.0,
+    };
+    pub const MODERATE: Argon2Params = Argon2Params {
+        ops_limit: argon2id13::OPSLIMIT_MODERATE.0,
+        mem_limit: argon2id13::MEMLIMIT_MODERATE.0,
+    };
+    pub const SENSITIVE: Argon2Params = Argon2Params {
+        ops_limit: argon2id13::OPSLIMIT_SENSITIVE.0,
+        mem_limit: argon2id13::MEMLIMIT_SENSITIVE.0,
+    };
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Argon2Params::MODERATE
+    }
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut state = State::init(key);
+    for part in parts {
+        state.update(part);
+    }
+    state.finalize().0
+}
+
+impl MacaroonKey {
+    /// Derive a key from a high-entropy master secret with HKDF-SHA256 (RFC 5869)
+    ///
+    /// Unlike [MacaroonKey::generate], this takes a salt and context `info`, so that several
+    /// independent root keys can be derived from one secret. It isn't suitable for passphrases:
+    /// use [MacaroonKey::derive_argon2] for those.
+    ///
+    /// ```rust
+    /// use macaroon::MacaroonKey;
+    ///
+    /// let master = MacaroonKey::generate_random();
+    /// let billing = MacaroonKey::derive_hkdf(&master[..], b"", b"billing root key");
+    /// let storage = MacaroonKey::derive_hkdf(&master[..], b"", b"storage root key");
+    /// assert_ne!(billing, storage);
+    /// ```
+    pub fn derive_hkdf(ikm: &[u8], salt: &[u8], info: &[u8]) -> MacaroonKey {
+        let salt: &[u8] = if salt.is_empty() { &[0; 32] } else { salt };
+        let prk = hmac_sha256(salt, &[ikm]);
+        MacaroonKey::from(hmac_sha256(&prk, &[info, &[1]]))
+    }
+
+    /// Derive a key from a passphrase with PBKDF2-HMAC-SHA256, for interoperability with
+    /// deployments already using it. Prefer [MacaroonKey::derive_argon2] otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::CryptoError] if `iterations` is zero.
+    pub fn derive_pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> Result<MacaroonKey> {
+        if iterations == 0 {
+            return Err(MacaroonError::CryptoError(
+                "PBKDF2 needs at least one iteration",
+            ));
+        }
+        // One block of output is exactly a key
+        let mut u = hmac_sha256(password, &[salt, &1u32.to_be_bytes()]);
+        let mut key = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &[&u]);
+            for (k, b) in key.iter_mut().zip(u.iter()) {
+                *k ^= b;
+            }
+        }
+        Ok(MacaroonKey::from(key))
+    }
+
+    /// Derive a key from a passphrase with Argon2id, which is deliberately slow and memory-hard
+    /// to resist guessing
+    ///
+    /// ```rust
+    /// use macaroon::{Argon2Params, MacaroonKey, ARGON2_SALT_LEN};
+    ///
+    /// // the salt should be random, and stored along with the parameters
+    /// let salt = [7; ARGON2_SALT_LEN];
+    /// let key = MacaroonKey::derive_argon2(b"correct horse", &salt, &Argon2Params::INTERACTIVE)
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::CryptoError] if the parameters are out of range or the memory
+    /// can't be allocated.
+    pub fn derive_argon2(
+        password: &[u8],
+        salt: &[u8; ARGON2_SALT_LEN],
+        params: &Argon2Params,
+    ) -> Result<MacaroonKey> {
+        let mut key = [0; 32];
+        argon2id13::derive_key(
+            &mut key,
+            password,
+            &argon2id13::Salt(*salt),
+            argon2id13::OpsLimit(params.ops_limit),
+            argon2id13::MemLimit(params.mem_limit),
+        )
+        .map_err(|()| MacaroonError::CryptoError("Argon2 key derivation failed"))?;
+        Ok(MacaroonKey::from(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Argon2Params, ARGON2_SALT_LEN};
+    use crate::{MacaroonError, MacaroonKey};
+
+    #[test]
+    fn test_hkdf() {
+        // RFC 5869 test case 1, truncated to the key length
+        let salt: Vec<u8> = (0..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        assert_eq!(
+            MacaroonKey::from_hex(
+                "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+            )
+            .unwrap(),
+            MacaroonKey::derive_hkdf(&[0x0b; 22], &salt, &info)
+        );
+        // RFC 5869 test case 3, with no salt or info
+        assert_eq!(
+            MacaroonKey::from_hex(
+                "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d"
+            )
+            .unwrap(),
+            MacaroonKey::derive_hkdf(&[0x0b; 22], b"", b"")
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        // RFC 7914 section 11 and the widely published PBKDF2-HMAC-SHA256 vectors
+        assert_eq!(
+            MacaroonKey::from_hex(
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+            )
+            .unwrap(),
+            MacaroonKey::derive_pbkdf2(b"password", b"salt", 1).unwrap()
+        );
+        assert_eq!(
+            MacaroonKey::from_hex(
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+            )
+            .unwrap(),
+            MacaroonKey::derive_pbkdf2(b"password", b"salt", 4096).unwrap()
+        );
+        assert!(matches!(
+            MacaroonKey::derive_pbkdf2(b"password", b"salt", 0),
+            Err(MacaroonError::CryptoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_argon2() {
+        // cheapest allowed parameters, to keep the test fast
+        let params = Argon2Params {
+            ops_limit: 1,
+            mem_limit: 8192,
+        };
+        let salt = [1; ARGON2_SALT_LEN];
+        let key = MacaroonKey::derive_argon2(b"passphrase", &salt, &params).unwrap();
+        assert_eq!(
+            key,
+            MacaroonKey::derive_argon2(b"passphrase", &salt, &params).unwrap()
+        );
+        assert_ne!(
+            key,
+            MacaroonKey::derive_argon2(b"passphrase", &[2; ARGON2_SALT_LEN], &params).unwrap()
+        );
+        assert!(MacaroonKey::derive_argon2(
+            b"passphrase",
+            &salt,
+            &Argon2Params {
+                ops_limit: 0,
+                mem_limit: 8192
+            }
+        )
+        .is_err());
+    }
+}
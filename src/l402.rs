@@ -0,0 +1,209 @@
+use crate::serialization::v2;
+use crate::{
+    decode_hex, encode_hex, ByteString, Caveat, Macaroon, MacaroonError, Result, Verifier,
+};
+use sodiumoxide::crypto::hash::sha256;
+
+/// `Authorization` and `WWW-Authenticate` scheme of L402 tokens
+pub const L402_SCHEME: &str = "L402";
+/// Former name of [L402_SCHEME], still accepted when parsing
+pub const LSAT_SCHEME: &str = "LSAT";
+/// Prefix of payment hash caveats, followed by the hex-encoded SHA-256 hash of the preimage
+pub const PAYMENT_HASH_PREFIX: &str = "payment_hash=";
+
+/// A macaroon (with any discharges) and the preimage proving its Lightning invoice was paid, as
+/// carried by an `Authorization: L402 <macaroons>:<preimage>` header
+///
+/// ```rust
+/// use macaroon::{L402Token, Macaroon, MacaroonKey, Verifier};
+///
+/// let preimage = [7; 32];
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_payment_hash_caveat(&L402Token::hash_preimage(&preimage));
+///
+/// let header = L402Token::new(vec![macaroon], preimage).to_header().unwrap();
+/// let token = L402Token::from_header(&header).unwrap();
+/// let mut verifier = Verifier::default();
+/// verifier.satisfy_preimage(&token.preimage);
+/// verifier
+///     .verify(token.root(), &key, token.discharges().to_vec())
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct L402Token {
+    /// The root macaroon, followed by its discharges
+    pub macaroons: Vec<Macaroon>,
+    pub preimage: [u8; 32],
+}
+
+impl L402Token {
+    /// # Panics
+    ///
+    /// Panics if `macaroons` is empty.
+    pub fn new(macaroons: Vec<Macaroon>, preimage: [u8; 32]) -> L402Token {
+        assert!(!macaroons.is_empty(), "an L402 token needs a macaroon");
+        L402Token {
+            macaroons,
+            preimage,
+        }
+    }
+
+    pub fn root(&self) -> &Macaroon {
+        &self.macaroons[0]
+    }
+
+    pub fn discharges(&self) -> &[Macaroon] {
+        &self.macaroons[1..]
+    }
+
+    /// The payment hash of a preimage: its SHA-256 hash
+    pub fn hash_preimage(preimage: &[u8; 32]) -> [u8; 32] {
+        sha256::hash(preimage).0
+    }
+
+    /// The `Authorization` header value, `L402 <macaroons>:<preimage>`, with each macaroon as
+    /// standard base64 V2 binary, separated by commas, and the preimage in hex
+    pub fn to_header(&self) -> Result<String> {
+        let macaroons = self
+            .macaroons
+            .iter()
+            .map(|m| Ok(base64::encode(v2::serialize_binary(m)?)))
+            .collect::<Result<Vec<String>>>()?;
+        Ok(format!(
+            "{} {}:{}",
+            L402_SCHEME,
+            macaroons.join(","),
+            encode_hex(&self.preimage)
+        ))
+    }
+
+    /// Parse an `Authorization` header value with the `L402` or `LSAT` scheme
+    ///
+    /// The preimage isn't checked here; see [Verifier::satisfy_preimage].
+    pub fn from_header(value: &str) -> Result<L402Token> {
+        let value = value.trim();
+        let (scheme, credentials) = value.split_once(' ').unwrap_or((value, ""));
+        if !scheme.eq_ignore_ascii_case(L402_SCHEME) && !scheme.eq_ignore_ascii_case(LSAT_SCHEME) {
+            return Err(MacaroonError::DeserializationError(
+                "not an L402 authorization header".to_string(),
+            ));
+        }
+        let (macaroons, preimage) = credentials.trim().rsplit_once(':').ok_or_else(|| {
+            MacaroonError::DeserializationError("L402 credentials have no preimage".to_string())
+        })?;
+        let preimage = decode_hex(preimage)
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| {
+                MacaroonError::DeserializationError("malformed L402 preimage".to_string())
+            })?;
+        let macaroons = macaroons
+            .split(',')
+            .map(|m| Macaroon::deserialize(m.trim()))
+            .collect::<Result<Vec<Macaroon>>>()?;
+        Ok(L402Token {
+            macaroons,
+            preimage,
+        })
+    }
+}
+
+/// The payment hash caveat predicate, `payment_hash=<hex>`
+pub fn payment_hash_predicate(payment_hash: &[u8; 32]) -> ByteString {
+    format!("{}{}", PAYMENT_HASH_PREFIX, encode_hex(payment_hash)).into()
+}
+
+/// Payment hash of a payment hash caveat predicate, or `None` if it isn't one
+fn predicate_payment_hash(predicate: &ByteString) -> Option<Vec<u8>> {
+    let predicate = std::str::from_utf8(predicate.as_ref()).ok()?;
+    decode_hex(predicate.strip_prefix(PAYMENT_HASH_PREFIX)?)
+}
+
+impl Macaroon {
+    /// Restrict the macaroon to bearers of the preimage of `payment_hash`, ie to those who paid
+    /// the Lightning invoice with that hash
+    pub fn add_payment_hash_caveat(&mut self, payment_hash: &[u8; 32]) {
+        self.add_first_party_caveat(payment_hash_predicate(payment_hash));
+    }
+
+    /// The payment hash of the macaroon's first payment hash caveat, if it has one
+    ///
+    /// This only inspects the caveats; it doesn't verify the macaroon.
+    pub fn payment_hash(&self) -> Option<[u8; 32]> {
+        self.caveats.iter().find_map(|c| match c {
+            Caveat::FirstParty(fp) => predicate_payment_hash(fp.predicate_ref())?.try_into().ok(),
+            Caveat::ThirdParty(_) => None,
+        })
+    }
+}
+
+impl<C> Verifier<C> {
+    /// Satisfy payment hash caveats whose hash is the SHA-256 hash of `preimage`
+    pub fn satisfy_preimage(&mut self, preimage: &[u8; 32]) {
+        let payment_hash = L402Token::hash_preimage(preimage);
+        self.satisfy_general(move |predicate| {
+            predicate_payment_hash(predicate).as_deref() == Some(&payment_hash[..])
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{payment_hash_predicate, L402Token};
+    use crate::{decode_hex, ByteString, Macaroon, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_payment_hash() {
+        // SHA-256 of 32 zero bytes
+        let hash = L402Token::hash_preimage(&[0; 32]);
+        assert_eq!(
+            ByteString::from(
+                "payment_hash=66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925"
+            ),
+            payment_hash_predicate(&hash)
+        );
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        assert_eq!(None, macaroon.payment_hash());
+        macaroon.add_first_party_caveat("service = api".into());
+        macaroon.add_payment_hash_caveat(&hash);
+        assert_eq!(Some(hash), macaroon.payment_hash());
+
+        let verify = |preimage: &[u8; 32]| {
+            let mut verifier = Verifier::default();
+            verifier.satisfy_exact("service = api".into());
+            verifier.satisfy_preimage(preimage);
+            verifier.verify(&macaroon, &key, vec![]).is_ok()
+        };
+        assert!(verify(&[0; 32]));
+        assert!(!verify(&[1; 32]));
+        assert_eq!(None, decode_hex("abc"));
+        assert_eq!(None, decode_hex("zz"));
+    }
+
+    #[test]
+    fn test_header() {
+        let key = MacaroonKey::generate(b"key");
+        let root = Macaroon::create(None, &key, "root".into()).unwrap();
+        let other = Macaroon::create(None, &key, "other".into()).unwrap();
+        let token = L402Token::new(vec![root.clone(), other], [0xab; 32]);
+        let header = token.to_header().unwrap();
+        assert!(header.starts_with("L402 "));
+        assert!(header.ends_with(&format!(":{}", "ab".repeat(32))));
+        assert_eq!(token, L402Token::from_header(&header).unwrap());
+
+        let lsat = format!(
+            "LSAT {}:{}",
+            root.serialize(crate::Format::V2).unwrap(),
+            "ab".repeat(32)
+        );
+        let parsed = L402Token::from_header(&lsat).unwrap();
+        assert_eq!(&root, parsed.root());
+        assert!(parsed.discharges().is_empty());
+
+        assert!(L402Token::from_header("Bearer abc").is_err());
+        assert!(L402Token::from_header("L402 abc").is_err());
+        let short = format!("L402 {}:abab", root.serialize(crate::Format::V2).unwrap());
+        assert!(L402Token::from_header(&short).is_err());
+    }
+}
@@ -106,20 +106,149 @@ extern crate serde;
 extern crate serde_json;
 extern crate sodiumoxide;
 
+#[cfg(feature = "async")]
+mod async_verifier;
+mod bakery;
+mod builder;
+mod bundle;
 mod caveat;
+mod caveat_id;
+mod channel_binding;
+mod checker;
+mod client_ip;
+#[cfg(feature = "http")]
+mod cookies;
 mod crypto;
+mod discharge;
 mod error;
+#[cfg(feature = "time")]
+pub mod expiry;
+mod extensions;
+mod fingerprint;
+#[cfg(feature = "http")]
+mod http;
+mod identifier;
+#[cfg(any(feature = "actix", feature = "axum", feature = "tonic"))]
+pub mod integrations;
+#[cfg(feature = "kdf")]
+mod kdf;
+mod l402;
+mod lnd;
+mod location;
+mod macaroon_bundle;
+mod macaroon_ref;
+mod macaroon_verifier;
+mod mint_store;
+mod nonce;
+mod oauth;
+mod oven;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod policy;
+mod raw;
+mod redundancy;
+mod report;
+mod revocation;
+mod root_key;
+mod scheme;
 mod serialization;
+mod shared;
+mod std_caveats;
+#[cfg(feature = "tracing")]
+mod telemetry;
+#[cfg(test)]
+pub(crate) mod test_util;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+// the helpers of the `time` feature, also used internally without it
+#[cfg(feature = "time")]
+pub mod timestamp;
+#[cfg(not(feature = "time"))]
+mod timestamp;
 mod verifier;
 
-pub use caveat::Caveat;
-pub use crypto::MacaroonKey;
-pub use error::MacaroonError;
-pub use serialization::Format;
-pub use verifier::{Verifier, VerifyFunc};
+#[cfg(feature = "async")]
+pub use async_verifier::{AsyncVerifier, SatisfierFuture};
+pub use bakery::{Bakery, CaveatTemplate, FirstPartyCaveatChecker};
+pub use builder::MacaroonBuilder;
+#[cfg(feature = "mmap")]
+pub use bundle::MappedBundle;
+pub use bundle::{BundleFormat, BundleReader, BundleRecords};
+pub use caveat::{normalize_predicate, Caveat, FirstParty, ThirdParty};
+pub use caveat_id::{decode_caveat_id, encode_caveat_id, DecodedCaveatId, KeyPair, PublicKey};
+pub use channel_binding::{tls_ekm_predicate, TLS_EKM_LABEL, TLS_EKM_LENGTH, TLS_EKM_PREFIX};
+pub use checker::{Checker, Namespace, NamespacedCaveat, STD_NAMESPACE};
+pub use client_ip::{client_ip_predicate, CLIENT_IP_PREFIX};
+#[cfg(feature = "http")]
+pub use cookies::{
+    decode_macaroon_cookies, encode_macaroon_cookies, MacaroonCookie, DEFAULT_MAX_COOKIE_LEN,
+    MACAROON_COOKIE_PREFIX,
+};
+pub use crypto::{
+    DangerousDebug, Decryptor, Encryptor, HmacWriter, MacaroonKey, NonceSource, SecretBox,
+    XChaCha20Poly1305, NONCE_LEN,
+};
+pub use discharge::{
+    derive_discharge_key, DischargeClient, DischargeError, DischargeFuture, DischargeRequest,
+    DischargeResponse, ThirdPartyCaveatInfo, CODE_INTERACTION_REQUIRED,
+};
+pub use error::{MacaroonError, UnsatisfiedCaveat};
+pub use extensions::{Extensions, EXTENSION_PREFIX};
+pub use fingerprint::Fingerprint;
+#[cfg(feature = "http")]
+pub use http::{
+    decode_authorization_header, decode_macaroons_header, encode_authorization_header,
+    encode_macaroons_header, AUTHORIZATION_SCHEME, MACAROONS_HEADER,
+};
+pub use identifier::{
+    IdentifierBuilder, IdentifierGenerator, IdentifierPayload, RandomBytesGenerator, UlidGenerator,
+    UuidV4Generator, IDENTIFIER_NONCE_LEN, IDENTIFIER_VERSION,
+};
+#[cfg(feature = "kdf")]
+pub use kdf::{Argon2Params, ARGON2_SALT_LEN};
+pub use l402::{payment_hash_predicate, L402Token, L402_SCHEME, LSAT_SCHEME, PAYMENT_HASH_PREFIX};
+pub use lnd::{
+    lnd_custom_predicate, permissions_predicate, Permission, LND_CUSTOM_PREFIX, PERMISSIONS_PREFIX,
+};
+pub use location::LocationMatch;
+pub use macaroon_bundle::MacaroonBundle;
+pub use macaroon_ref::{CaveatRef, MacaroonRef};
+pub use macaroon_verifier::{verify_with_caveat_checker, MacaroonVerifier};
+pub use mint_store::{InMemoryMintStore, MintRecord, MintStore};
+pub use nonce::{InMemoryNonceStore, NonceStore, NONCE_PREFIX};
+pub use oauth::{parse_scope, scope_predicate, SCOPE_PREFIX};
+pub use oven::Oven;
+pub use policy::VerifierPolicy;
+pub use raw::{RawField, RawToken};
+pub use redundancy::{Redundancy, RedundantCaveat};
+pub use report::{CheckedCaveat, SatisfiedBy, VerificationReport};
+pub use revocation::{signature_fingerprint, InMemoryRevocationList, RevocationChecker};
+pub use root_key::{InMemoryRootKeyStore, RootKeyStore};
+pub use scheme::{
+    BindingScheme, Blake2bMac, HmacSha256, HmacSha512_256, KeyedBinding, SignatureScheme,
+    ZeroKeyBinding,
+};
+pub use serialization::compressed::Compression;
+pub use serialization::v1::{V1Packet, V1Packets};
+pub use serialization::v2::CaveatRefIter;
+pub use serialization::{DeserializationLimits, DeserializeOptions, Format, SerializeOptions};
+pub use shared::SharedMacaroon;
+pub use std_caveats::{
+    StdContext, VerifiedAttributes, COND_ALLOW, COND_DECLARED, COND_DENY, COND_ERROR,
+    COND_TIME_BEFORE, STD_PREFIX,
+};
+#[cfg(feature = "time")]
+pub use timestamp::parse_timestamp;
+pub use timestamp::{expiry_predicate, EXPIRY_PREFIX};
+pub use verifier::{
+    Verifier, VerifierBuilder, VerifierOptions, VerifierOptionsBuilder, VerifyFunc,
+    DEFAULT_MAX_DEPTH, DEFAULT_MAX_THIRD_PARTY_CAVEATS,
+};
 
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serialization::macaroon_builder;
+use std::convert::TryFrom;
 use std::fmt;
 
 pub type Result<T> = std::result::Result<T, MacaroonError>;
@@ -143,6 +272,13 @@ impl AsRef<[u8]> for ByteString {
     }
 }
 
+// Lets sets and maps keyed by ByteString be queried with borrowed bytes
+impl std::borrow::Borrow<[u8]> for ByteString {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl From<Vec<u8>> for ByteString {
     fn from(v: Vec<u8>) -> ByteString {
         ByteString(v)
@@ -231,16 +367,52 @@ impl<'de> Deserialize<'de> for ByteString {
 /// Logic is based on pymacaroons helper:
 /// https://github.com/ecordell/pymacaroons/blob/master/pymacaroons/utils.py#L109
 fn base64_decode_flexible(b: &[u8]) -> Result<Vec<u8>> {
+    let mut buf: Vec<u8> = Vec::new();
+    base64_decode_flexible_into(b, &mut buf)?;
+    Ok(buf)
+}
+
+/// Like [base64_decode_flexible], but decodes into `buf` (cleared first) to reuse its allocation
+fn base64_decode_flexible_into(b: &[u8], buf: &mut Vec<u8>) -> Result<()> {
     if b.is_empty() {
         return Err(MacaroonError::DeserializationError(
             "empty token to deserialize".to_string(),
         ));
     }
+    buf.clear();
     if b.contains(&b'_') || b.contains(&b'-') {
-        Ok(base64::decode_config(b, base64::URL_SAFE)?)
+        base64::decode_config_buf(b, base64::URL_SAFE, buf)?;
     } else {
-        Ok(base64::decode_config(b, base64::STANDARD)?)
+        base64::decode_config_buf(b, base64::STANDARD, buf)?;
+    }
+    Ok(())
+}
+
+/// Lower-case hex encoding, for keys, hashes and payment preimages
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hex in either case, or `None` if it isn't valid hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // from_str_radix alone would accept a sign, eg "+f"
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
     }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[test]
+fn test_decode_hex() {
+    assert_eq!(Some(vec![0x0f, 0xab]), decode_hex("0fAb"));
+    assert_eq!(Some(vec![]), decode_hex(""));
+    assert_eq!(None, decode_hex("+f"));
+    assert_eq!(None, decode_hex("-f"));
+    assert_eq!(None, decode_hex("0"));
+    assert_eq!(None, decode_hex("zz"));
 }
 
 // https://github.com/rescrv/libmacaroons/blob/master/doc/format.txt#L87
@@ -256,12 +428,24 @@ fn test_base64_decode_flexible() {
     assert!(base64_decode_flexible(b"").is_err());
 }
 
+/// A first-party caveat predicate present more than once in a macaroon, as returned by
+/// [Macaroon::dedup_report]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateCaveat {
+    /// The repeated predicate
+    pub predicate: ByteString,
+    /// Positions of every occurrence of the predicate among the macaroon's caveats
+    pub indices: Vec<usize>,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Macaroon {
     identifier: ByteString,
     location: Option<String>,
     signature: MacaroonKey,
     caveats: Vec<Caveat>,
+    extensions: Extensions,
+    scheme: scheme::Scheme,
 }
 
 impl Macaroon {
@@ -276,17 +460,108 @@ impl Macaroon {
         location: Option<String>,
         key: &MacaroonKey,
         identifier: ByteString,
+    ) -> Result<Macaroon> {
+        Macaroon::create_with_scheme(location, key, identifier, &HmacSha256)
+    }
+
+    /// A [MacaroonBuilder] for minting a macaroon with its caveats in one expression
+    pub fn builder() -> MacaroonBuilder {
+        MacaroonBuilder::new()
+    }
+
+    /// Like [Macaroon::create], signing with `scheme` instead of HMAC-SHA-256
+    ///
+    /// Caveats added to the macaroon and discharges bound to it are signed with the same scheme,
+    /// and it has to be verified with [Verifier::verify_with_scheme].
+    ///
+    /// ```rust
+    /// use macaroon::{Blake2bMac, Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create_with_scheme(None, &key, "id".into(), &Blake2bMac).unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_exact("account = 3735928559".into());
+    /// assert!(verifier.verify(&macaroon, &key, vec![]).is_err());
+    /// verifier
+    ///     .verify_with_scheme(&macaroon, &key, vec![], &Blake2bMac)
+    ///     .unwrap();
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "macaroon.create",
+            level = "debug",
+            skip_all,
+            fields(identifier = %telemetry::redact(identifier.as_ref()))
+        )
+    )]
+    pub fn create_with_scheme(
+        location: Option<String>,
+        key: &MacaroonKey,
+        identifier: ByteString,
+        scheme: &'static dyn SignatureScheme,
     ) -> Result<Macaroon> {
         let macaroon: Macaroon = Macaroon {
             location,
             identifier: identifier.clone(),
-            signature: crypto::hmac(key, &identifier),
+            signature: scheme.hmac(key, identifier.as_ref()),
             caveats: Vec::new(),
+            extensions: Extensions::default(),
+            scheme: scheme::Scheme(scheme),
         };
-        debug!("Macaroon::create: {:?}", macaroon);
         macaroon.validate()
     }
 
+    /// Sign caveats added from now on, and bound discharges, with `scheme`
+    ///
+    /// Serialized macaroons don't record their signature scheme, so deserialized macaroons use
+    /// HMAC-SHA-256 until told otherwise.
+    pub fn with_scheme(mut self, scheme: &'static dyn SignatureScheme) -> Macaroon {
+        self.scheme = scheme::Scheme(scheme);
+        self
+    }
+
+    pub fn scheme(&self) -> &'static dyn SignatureScheme {
+        self.scheme.0
+    }
+
+    /// Create a macaroon with a `time < ...` expiry caveat `ttl` from now (see
+    /// [expiry_predicate])
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::LimitExceeded] if `ttl` from now isn't representable as a
+    /// [SystemTime](std::time::SystemTime).
+    pub fn create_with_ttl(
+        location: Option<String>,
+        key: &MacaroonKey,
+        identifier: ByteString,
+        ttl: std::time::Duration,
+    ) -> Result<Macaroon> {
+        let expiry = std::time::SystemTime::now()
+            .checked_add(ttl)
+            .ok_or_else(|| MacaroonError::LimitExceeded(format!("ttl of {:?} overflows", ttl)))?;
+        let mut macaroon = Macaroon::create(location, key, identifier)?;
+        macaroon.add_first_party_caveat(expiry_predicate(expiry));
+        Ok(macaroon)
+    }
+
+    /// Extension fields carried by the V2JSON format (see [Extensions])
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Mutable access to the caveat at `index`, for editing its [Extensions]
+    pub fn caveat_extensions_mut(&mut self, index: usize) -> Option<&mut Extensions> {
+        self.caveats.get_mut(index).map(Caveat::extensions_mut)
+    }
+
     /// Returns a clone of the identifier for the macaroon
     pub fn identifier(&self) -> ByteString {
         self.identifier.clone()
@@ -302,7 +577,7 @@ impl Macaroon {
     /// The [MacaroonKey] type is used because it is the same size and format a signature, but the
     /// signature is not and should be used as a cryptographic key.
     pub fn signature(&self) -> MacaroonKey {
-        self.signature
+        self.signature.clone()
     }
 
     pub fn caveats(&self) -> Vec<Caveat> {
@@ -349,11 +624,135 @@ impl Macaroon {
     /// DSL which can be verified either by exact string match,
     /// or by using a function to parse the string and validate it
     /// (see Verifier for more info).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "macaroon.attenuate",
+            level = "debug",
+            skip_all,
+            fields(identifier = %telemetry::redact(self.identifier.as_ref()), caveats = self.caveats.len())
+        )
+    )]
     pub fn add_first_party_caveat(&mut self, predicate: ByteString) {
         let caveat: caveat::Caveat = caveat::new_first_party(predicate);
-        self.signature = caveat.sign(&self.signature);
+        self.signature = caveat.sign_with(self.scheme.0, &self.signature);
         self.caveats.push(caveat);
-        debug!("Macaroon::add_first_party_caveat: {:?}", self);
+    }
+
+    /// Add a first-party caveat to the macaroon, reading the predicate from `reader`
+    ///
+    /// The predicate is signed incrementally as it is read, which avoids holding a second copy
+    /// of very large predicates (eg, embedded documents or manifests) while signing.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from `reader`, in which case the macaroon is left unchanged.
+    pub fn add_first_party_caveat_from_reader<R: std::io::Read>(
+        &mut self,
+        mut reader: R,
+    ) -> std::io::Result<()> {
+        if self.scheme != scheme::Scheme::default() {
+            // Only HMAC-SHA-256 is signed incrementally
+            let mut predicate = Vec::new();
+            reader.read_to_end(&mut predicate)?;
+            self.add_first_party_caveat(predicate.into());
+            return Ok(());
+        }
+        let (caveat, signature) = caveat::read_first_party(&self.signature, reader)?;
+        self.signature = signature;
+        self.caveats.push(caveat);
+        Ok(())
+    }
+
+    /// Add a first-party caveat to the macaroon, unless one with the same predicate is already
+    /// present
+    ///
+    /// Returns `true` if the caveat was added. Attenuating a macaroon with a predicate it already
+    /// carries never narrows its authority, so skipping it keeps repeatedly-attenuated tokens from
+    /// growing without bound.
+    pub fn add_first_party_caveat_unique(&mut self, predicate: ByteString) -> bool {
+        let present = self.caveats.iter().position(|c| match c {
+            Caveat::FirstParty(fp) => fp.predicate_ref() == &predicate,
+            Caveat::ThirdParty(_) => false,
+        });
+        if let Some(index) = present {
+            // the index rather than the predicate, which may carry user data
+            debug!(
+                "Macaroon::add_first_party_caveat_unique: skipping duplicate of caveat {}",
+                index
+            );
+            return false;
+        }
+        self.add_first_party_caveat(predicate);
+        true
+    }
+
+    /// Add a first-party caveat to the macaroon, unless the result wouldn't fit in `limit` bytes
+    /// serialized in `format` (see [Macaroon::ensure_fits])
+    ///
+    /// The macaroon is left unchanged when this fails, so a service can stop attenuating a token
+    /// before it outgrows a cookie or header.
+    ///
+    /// ```rust
+    /// use macaroon::{Format, Macaroon, MacaroonError, MacaroonKey};
+    ///
+    /// let mut macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+    /// macaroon
+    ///     .add_first_party_caveat_within("account = 3735928559".into(), Format::V2, 100)
+    ///     .unwrap();
+    /// let before = macaroon.clone();
+    /// assert!(matches!(
+    ///     macaroon.add_first_party_caveat_within("user = alice".into(), Format::V2, 100),
+    ///     Err(MacaroonError::LimitExceeded(_))
+    /// ));
+    /// assert_eq!(before, macaroon);
+    /// ```
+    pub fn add_first_party_caveat_within(
+        &mut self,
+        predicate: ByteString,
+        format: serialization::Format,
+        limit: usize,
+    ) -> Result<()> {
+        let signature = self.signature.clone();
+        self.add_first_party_caveat(predicate);
+        if let Err(e) = self.ensure_fits(format, limit) {
+            self.caveats.pop();
+            self.signature = signature;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Report first-party caveat predicates which appear more than once in the macaroon
+    ///
+    /// Duplicates are listed in order of their first appearance, each with the positions (in
+    /// [Macaroon::caveats]) of every occurrence.
+    pub fn dedup_report(&self) -> Vec<DuplicateCaveat> {
+        let mut report: Vec<DuplicateCaveat> = Vec::new();
+        for (index, c) in self.caveats.iter().enumerate() {
+            let predicate = match c {
+                Caveat::FirstParty(fp) => fp.predicate(),
+                Caveat::ThirdParty(_) => continue,
+            };
+            match report.iter_mut().find(|d| d.predicate == predicate) {
+                Some(duplicate) => duplicate.indices.push(index),
+                None => report.push(DuplicateCaveat {
+                    predicate,
+                    indices: vec![index],
+                }),
+            }
+        }
+        report.retain(|d| d.indices.len() > 1);
+        report
+    }
+
+    /// Add a first-party caveat to the macaroon, after normalizing the predicate
+    ///
+    /// See [normalize_predicate] for the transformation applied. Because the normalized predicate
+    /// is what gets signed, verifiers matching it exactly must use the normalized form too (or
+    /// enable [Verifier::enable_predicate_normalization]).
+    pub fn add_normalized_first_party_caveat(&mut self, predicate: ByteString) {
+        self.add_first_party_caveat(normalize_predicate(&predicate));
     }
 
     /// Add a third-party caveat to the macaroon
@@ -361,11 +760,112 @@ impl Macaroon {
     /// A third-party caveat is a caveat which must be verified by a third party
     /// using macaroons provided by them (referred to as "discharge macaroons").
     pub fn add_third_party_caveat(&mut self, location: &str, key: &MacaroonKey, id: ByteString) {
-        let vid: Vec<u8> = crypto::encrypt_key(&self.signature, key);
+        self.add_third_party_caveat_with(location, key, id, &crypto::SecretBox);
+    }
+
+    /// Like [Macaroon::add_third_party_caveat], encrypting the caveat key with `encryptor`
+    ///
+    /// [SecretBox] (the default) is what libmacaroons and pymacaroons use, so other encryptors
+    /// are only suitable when every verifier of the macaroon uses this crate, with the matching
+    /// [Decryptor] (see [Verifier::with_decryptor]).
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier, XChaCha20Poly1305};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_third_party_caveat_with(
+    ///     "https://auth.mybank",
+    ///     &caveat_key,
+    ///     "caveat id".into(),
+    ///     &XChaCha20Poly1305,
+    /// );
+    ///
+    /// let mut discharge = Macaroon::create(None, &caveat_key, "caveat id".into()).unwrap();
+    /// macaroon.bind(&mut discharge);
+    /// Verifier::default()
+    ///     .with_decryptor(XChaCha20Poly1305)
+    ///     .verify(&macaroon, &key, vec![discharge])
+    ///     .unwrap();
+    /// ```
+    pub fn add_third_party_caveat_with<E: Encryptor + ?Sized>(
+        &mut self,
+        location: &str,
+        key: &MacaroonKey,
+        id: ByteString,
+        encryptor: &E,
+    ) {
+        let vid: Vec<u8> = encryptor.encrypt_key(&self.signature, key);
+        self.push_third_party_caveat(location, id, vid);
+    }
+
+    /// Like [Macaroon::add_third_party_caveat], taking the nonce which encrypts the caveat key
+    /// from `rng` rather than the system's secure random number generator
+    ///
+    /// This is for tests: with a fixed nonce, the verifier ID and the signature are
+    /// reproducible, eg to compare them with the reference vectors of other implementations.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, NONCE_LEN};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut zero_nonce = |nonce: &mut [u8; NONCE_LEN]| *nonce = [0; NONCE_LEN];
+    ///
+    /// let mut first = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// first.add_third_party_caveat_with_rng("https://auth/", &caveat_key, "cid".into(), &mut zero_nonce);
+    /// let mut second = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// second.add_third_party_caveat_with_rng("https://auth/", &caveat_key, "cid".into(), &mut zero_nonce);
+    /// assert_eq!(first.signature(), second.signature());
+    /// ```
+    pub fn add_third_party_caveat_with_rng<R: NonceSource + ?Sized>(
+        &mut self,
+        location: &str,
+        key: &MacaroonKey,
+        id: ByteString,
+        rng: &mut R,
+    ) {
+        let vid: Vec<u8> = crypto::encrypt_key_with_rng(&self.signature, key, rng);
+        self.push_third_party_caveat(location, id, vid);
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "macaroon.attenuate",
+            level = "debug",
+            skip_all,
+            fields(identifier = %telemetry::redact(self.identifier.as_ref()), caveats = self.caveats.len())
+        )
+    )]
+    fn push_third_party_caveat(&mut self, location: &str, id: ByteString, vid: Vec<u8>) {
         let caveat: caveat::Caveat = caveat::new_third_party(id, ByteString(vid), location);
-        self.signature = caveat.sign(&self.signature);
+        self.signature = caveat.sign_with(self.scheme.0, &self.signature);
         self.caveats.push(caveat);
-        debug!("Macaroon::add_third_party_caveat: {:?}", self);
+    }
+
+    /// Add a third-party caveat which can be discharged at any of `locations`, tried in order
+    ///
+    /// The locations are stored one per line in the caveat's single location field, so other
+    /// implementations still parse the caveat (seeing one combined location), and
+    /// `ThirdParty::locations` splits them again.
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::IncompleteCaveat] if `locations` is empty, or if a location
+    /// contains a line break.
+    pub fn add_third_party_caveat_with_fallbacks(
+        &mut self,
+        locations: &[&str],
+        key: &MacaroonKey,
+        id: ByteString,
+    ) -> Result<()> {
+        if locations.iter().all(|l| l.trim().is_empty()) {
+            return Err(MacaroonError::IncompleteCaveat("no location found"));
+        }
+        self.add_third_party_caveat(&caveat::join_locations(locations)?, key, id);
+        Ok(())
     }
 
     /// Bind a discharge macaroon to the original macaroon
@@ -376,24 +876,363 @@ impl Macaroon {
     /// that the discharge macaroons aren't re-used in some other context, we bind them to the original
     /// macaroon so that they can't be used in a different context.
     pub fn bind(&self, discharge: &mut Macaroon) {
-        let zero_key = MacaroonKey::from([0; 32]);
-        discharge.signature = crypto::hmac2(&zero_key, &self.signature, &discharge.signature);
-        debug!(
-            "Macaroon::bind: original: {:?}, discharge: {:?}",
-            self, discharge
-        );
+        self.bind_with(&ZeroKeyBinding, discharge);
+    }
+
+    /// Like [Macaroon::bind], with a [BindingScheme] other than the standard [ZeroKeyBinding].
+    /// The discharge then has to be verified with the same binding (see
+    /// [Verifier::with_binding]).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "macaroon.bind",
+            level = "debug",
+            skip_all,
+            fields(
+                identifier = %telemetry::redact(self.identifier.as_ref()),
+                discharge = %telemetry::redact(discharge.identifier.as_ref())
+            )
+        )
+    )]
+    pub fn bind_with(&self, binding: &dyn BindingScheme, discharge: &mut Macaroon) {
+        discharge.signature = binding.bind(self.scheme.0, &self.signature, &discharge.signature);
+    }
+
+    /// The running signature of the macaroon minted with `key`: the HMAC of the identifier,
+    /// then the signature after each caveat, in order
+    ///
+    /// The chain has one more entry than there are caveats, and is computed with the macaroon's
+    /// [SignatureScheme]. Its last entry is the macaroon's signature when `key` is the root key,
+    /// unless the macaroon is a discharge which has been bound (see [Macaroon::bind]).
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// let minted = macaroon.signature();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    ///
+    /// let chain = macaroon.signature_chain(&key);
+    /// assert_eq!(vec![minted, macaroon.signature()], chain);
+    /// ```
+    pub fn signature_chain(&self, key: &MacaroonKey) -> Vec<MacaroonKey> {
+        let mut chain = Vec::with_capacity(self.caveats.len() + 1);
+        let mut sig = self.scheme.0.hmac(key, self.identifier.as_ref());
+        for c in self.caveats.iter() {
+            let next = c.sign_with(self.scheme.0, &sig);
+            chain.push(sig);
+            sig = next;
+        }
+        chain.push(sig);
+        chain
+    }
+
+    /// Whether the macaroon's signature is the one minted with `key`, without checking any caveats
+    /// or discharges
+    ///
+    /// This only authenticates the macaroon's contents: a `true` result doesn't authorize
+    /// anything, since caveats aren't checked, so use a [Verifier] for that. Bound discharges
+    /// don't match, since their signature also depends on the macaroon they're bound to.
+    pub fn verify_signature_only(&self, key: &MacaroonKey) -> bool {
+        let chain = self.signature_chain(key);
+        let sig = chain.last().expect("the chain starts with the identifier");
+        crypto::constant_time_eq(&sig[..], &self.signature[..])
+    }
+
+    /// Whether the two macaroons carry the same location, identifier and caveats, ignoring
+    /// signatures and third-party caveats' verifier IDs
+    ///
+    /// Verifier IDs embed a random nonce, so macaroons minted identically with third-party
+    /// caveats are never equal (`==`), even with the same keys. This compares what the
+    /// macaroons say instead: caveats must match in order, by predicate for first-party caveats,
+    /// and by ID and location for third-party ones. Signature schemes and extension fields
+    /// aren't compared either.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mint = || {
+    ///     let mut macaroon = Macaroon::create(None, &key, "keyid".into()).unwrap();
+    ///     macaroon.add_third_party_caveat("https://auth.mybank/", &caveat_key, "caveat id".into());
+    ///     macaroon
+    /// };
+    /// assert_ne!(mint(), mint());
+    /// assert!(mint().structurally_eq(&mint()));
+    /// ```
+    pub fn structurally_eq(&self, other: &Macaroon) -> bool {
+        self.location == other.location
+            && self.identifier == other.identifier
+            && self.caveats.len() == other.caveats.len()
+            && self
+                .caveats
+                .iter()
+                .zip(other.caveats.iter())
+                .all(|pair| match pair {
+                    (Caveat::FirstParty(a), Caveat::FirstParty(b)) => {
+                        a.predicate_ref() == b.predicate_ref()
+                    }
+                    (Caveat::ThirdParty(a), Caveat::ThirdParty(b)) => {
+                        a.id_ref() == b.id_ref() && a.location_ref() == b.location_ref()
+                    }
+                    _ => false,
+                })
+    }
+
+    /// A human-readable dump of the macaroon, in the format of libmacaroons' `macaroon_inspect`
+    ///
+    /// There is a line for the location, the identifier, each caveat's ID (`cid`), and for
+    /// third-party caveats the verifier ID (`vid`) and location (`cl`), followed by the signature
+    /// in hex. Identifiers which aren't printable UTF-8, and verifier IDs, are written as URL-safe
+    /// base64. This is also the [Display](fmt::Display) output.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey};
+    ///
+    /// let key = MacaroonKey::generate(b"this is our super secret key; only we should know it");
+    /// let mut macaroon =
+    ///     Macaroon::create(Some("http://mybank/".into()), &key, "we used our secret key".into())
+    ///         .unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    /// assert_eq!(
+    ///     "location http://mybank/
+    /// identifier we used our secret key
+    /// cid account = 3735928559
+    /// signature 1efe4763f290dbce0c1d08477367e11f4eee456a64933cf662d79772dbb82128",
+    ///     macaroon.inspect()
+    /// );
+    /// ```
+    pub fn inspect(&self) -> String {
+        self.to_string()
     }
 
     /// Serialize the macaroon using the serialization [Format] provided
     ///
-    /// For V1 and V2, the binary format will be encoded as URL-safe base64 with padding
-    /// (`base64::URL_SAFE`). For V2JSON, the output will be JSON.
+    /// For V1 and V2, the binary format will be encoded as URL-safe base64 without padding
+    /// (`base64::URL_SAFE_NO_PAD`), as libmacaroons does. For V2JSON, the output will be JSON.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::SerializationError], naming the offending field, if the
+    /// macaroon doesn't fit the format: V1 packets hold at most 65535 bytes, and no format
+    /// writes a field or caveat count beyond the [DeserializationLimits] defaults, which would
+    /// produce a token [Macaroon::deserialize] rejects.
     pub fn serialize(&self, format: serialization::Format) -> Result<String> {
+        self.serialize_with_options(format, &SerializeOptions::default())
+    }
+
+    /// Serialize the macaroon using the serialization [Format] provided, with
+    /// [SerializeOptions] controlling the encoding
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "macaroon.serialize",
+            level = "debug",
+            skip_all,
+            fields(identifier = %telemetry::redact(self.identifier.as_ref()), ?format, caveats = self.caveats.len())
+        )
+    )]
+    pub fn serialize_with_options(
+        &self,
+        format: serialization::Format,
+        options: &SerializeOptions,
+    ) -> Result<String> {
         match format {
-            serialization::Format::V1 => serialization::v1::serialize(self),
-            serialization::Format::V2 => serialization::v2::serialize(self),
-            serialization::Format::V2JSON => serialization::v2json::serialize(self),
+            serialization::Format::V1 => serialization::v1::serialize(self, options),
+            serialization::Format::V2 => serialization::v2::serialize(self, options),
+            serialization::Format::V2JSON => serialization::v2json::serialize(self, options),
+            serialization::Format::V2Compressed => {
+                serialization::compressed::serialize(self, options)
+            }
+        }
+    }
+
+    /// Serialize the macaroon in the V2 binary format, with no base64 encoding (the counterpart
+    /// of [Macaroon::deserialize_binary])
+    ///
+    /// # Errors
+    ///
+    /// Fails like [Macaroon::serialize], if the macaroon doesn't fit the format.
+    pub fn serialize_binary(&self) -> Result<Vec<u8>> {
+        serialization::v2::serialize_binary(self)
+    }
+
+    /// Serialize the macaroon as [Format::V2Compressed] with a particular [Compression]
+    /// algorithm, rather than [Compression::preferred]
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "zstd")] {
+    /// use macaroon::{Compression, Macaroon, MacaroonKey};
+    ///
+    /// let macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+    /// let token = macaroon.serialize_compressed(Compression::Zstd).unwrap();
+    /// assert_eq!(macaroon, Macaroon::deserialize(&token).unwrap());
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::SerializationError] if the algorithm's feature isn't enabled.
+    pub fn serialize_compressed(&self, compression: Compression) -> Result<String> {
+        let envelope = serialization::compressed::serialize_binary(self, compression)?;
+        Ok(base64::encode_config(
+            &envelope,
+            SerializeOptions::default().base64_config(),
+        ))
+    }
+
+    /// The length of [Macaroon::serialize]'s output in `format`, without serializing the
+    /// macaroon into a buffer, eg to check a token fits a cookie or header before sending it
+    ///
+    /// ```rust
+    /// use macaroon::{Format, Macaroon, MacaroonKey};
+    ///
+    /// let mut macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    /// for format in [Format::V1, Format::V2, Format::V2JSON] {
+    ///     assert_eq!(macaroon.serialize(format).unwrap().len(), macaroon.serialized_len(format));
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics for [Format::V2Compressed] without the `deflate` or `zstd` feature; use
+    /// [Macaroon::try_serialized_len] where that may be the case.
+    pub fn serialized_len(&self, format: serialization::Format) -> usize {
+        self.serialized_len_with_options(format, &SerializeOptions::default())
+    }
+
+    /// Like [Macaroon::serialized_len], for [Macaroon::serialize_with_options]
+    ///
+    /// # Panics
+    ///
+    /// Panics for [Format::V2Compressed] without the `deflate` or `zstd` feature; use
+    /// [Macaroon::try_serialized_len_with_options] where that may be the case.
+    pub fn serialized_len_with_options(
+        &self,
+        format: serialization::Format,
+        options: &SerializeOptions,
+    ) -> usize {
+        self.try_serialized_len_with_options(format, options)
+            .expect("macaroon can't be serialized in this format")
+    }
+
+    /// Like [Macaroon::serialized_len], failing if the macaroon can't be serialized in `format`
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::SerializationError] for [Format::V2Compressed] without the
+    /// `deflate` or `zstd` feature, since the compressed size is only known by compressing.
+    pub fn try_serialized_len(&self, format: serialization::Format) -> Result<usize> {
+        self.try_serialized_len_with_options(format, &SerializeOptions::default())
+    }
+
+    /// Like [Macaroon::try_serialized_len], for [Macaroon::serialize_with_options]
+    pub fn try_serialized_len_with_options(
+        &self,
+        format: serialization::Format,
+        options: &SerializeOptions,
+    ) -> Result<usize> {
+        Ok(match format {
+            serialization::Format::V1 => options.base64_len(serialization::v1::binary_len(self)),
+            serialization::Format::V2 => options.base64_len(serialization::v2::binary_len(self)),
+            serialization::Format::V2JSON => serialization::v2json::serialized_len(self, options),
+            // the compressed size is only known by compressing
+            serialization::Format::V2Compressed => {
+                self.serialize_with_options(format, options)?.len()
+            }
+        })
+    }
+
+    /// Fails with [MacaroonError::LimitExceeded] if the macaroon serialized in `format` would
+    /// be longer than `limit` bytes (see [Macaroon::try_serialized_len]), and with that
+    /// function's error if it can't be serialized in `format`
+    pub fn ensure_fits(&self, format: serialization::Format, limit: usize) -> Result<()> {
+        let len = self.try_serialized_len(format)?;
+        if len > limit {
+            return Err(MacaroonError::LimitExceeded(format!(
+                "{:?} token of {} bytes is longer than {}",
+                format, len, limit
+            )));
         }
+        Ok(())
+    }
+
+    /// Convert `token` from one serialization [Format] to another, keeping its identifiers,
+    /// caveats and signature byte for byte, so it doesn't need to be re-signed
+    ///
+    /// ```rust
+    /// use macaroon::{Format, Macaroon};
+    ///
+    /// let v1 = "MDAxY2xvY2F0aW9uIGh0dHA6Ly9teWJhbmsvCjAwMjZpZGVudGlmaWVyIHdlIHVzZWQgb3VyIHNlY3JldCBrZXkKMDAxNmNpZCB0ZXN0ID0gY2F2ZWF0CjAwMmZzaWduYXR1cmUgGXusegRK8zMyhluSZuJtSTvdZopmDkTYjOGpmMI9vWcK";
+    /// let json = Macaroon::convert(v1, Format::V1, Format::V2JSON).unwrap();
+    /// assert!(json.starts_with('{'));
+    /// assert_eq!(Macaroon::deserialize(v1).unwrap(), Macaroon::deserialize(&json).unwrap());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::DeserializationError] if `token` isn't a valid `from` token,
+    /// and with [MacaroonError::SerializationError] if `to` can't represent it exactly, eg
+    /// because the binary formats don't carry [Extensions] or a field is too large for a V1
+    /// packet.
+    pub fn convert(
+        token: &str,
+        from: serialization::Format,
+        to: serialization::Format,
+    ) -> Result<String> {
+        let options = DeserializeOptions::default();
+        let wrong_format = || {
+            MacaroonError::DeserializationError(format!("token is not in the {:?} format", from))
+        };
+        let macaroon = match from {
+            serialization::Format::V2JSON if token.starts_with('{') => {
+                serialization::v2json::deserialize(token.as_bytes(), &options)?
+            }
+            serialization::Format::V2JSON => return Err(wrong_format()),
+            _ if token.starts_with('{') => return Err(wrong_format()),
+            _ => {
+                let binary = base64_decode_flexible(token.as_bytes())?;
+                match (from, binary.first()) {
+                    (serialization::Format::V2, Some(2)) => {
+                        serialization::v2::deserialize(&binary, &options)?
+                    }
+                    (
+                        serialization::Format::V2Compressed,
+                        Some(&serialization::compressed::ENVELOPE_TAG),
+                    ) => serialization::v2::deserialize(
+                        &serialization::compressed::decompress(&binary, &options)?,
+                        &options,
+                    )?,
+                    (serialization::Format::V1, Some(&b))
+                        if b != 2 && b != serialization::compressed::ENVELOPE_TAG =>
+                    {
+                        serialization::v1::deserialize(&binary, &options)?
+                    }
+                    _ => return Err(wrong_format()),
+                }
+            }
+        }
+        .validate()?;
+
+        let converted = macaroon.serialize(to)?;
+        if Macaroon::deserialize(&converted)? != macaroon {
+            let lost = if to != serialization::Format::V2JSON
+                && (!macaroon.extensions.is_empty()
+                    || macaroon.caveats.iter().any(|c| !c.extensions().is_empty()))
+            {
+                "extensions"
+            } else {
+                "content"
+            };
+            return Err(MacaroonError::SerializationError(format!(
+                "{:?} can't represent the macaroon's {}",
+                to, lost
+            )));
+        }
+        Ok(converted)
     }
 
     /// Deserialize an encoded macaroon token, inferring the [Format].
@@ -421,34 +1260,124 @@ impl Macaroon {
     /// # Ok(()) }
     /// ```
     pub fn deserialize<T: AsRef<[u8]>>(token: T) -> Result<Macaroon> {
+        Macaroon::deserialize_with_options(token, &DeserializeOptions::default())
+    }
+
+    /// Deserialize an encoded macaroon token, inferring the [Format], with [DeserializeOptions]
+    /// controlling how strictly it is parsed
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "macaroon.deserialize",
+            level = "debug",
+            skip_all,
+            fields(len = token.as_ref().len())
+        )
+    )]
+    pub fn deserialize_with_options<T: AsRef<[u8]>>(
+        token: T,
+        options: &DeserializeOptions,
+    ) -> Result<Macaroon> {
         if token.as_ref().is_empty() {
             return Err(MacaroonError::DeserializationError(
                 "empty token provided".to_string(),
             ));
         }
+        options.limits.check_token_len(token.as_ref().len())?;
         let mac: Macaroon = match token.as_ref()[0] as char {
-            '{' => serialization::v2json::deserialize(token.as_ref())?,
+            '{' => serialization::v2json::deserialize(token.as_ref(), options)?,
             _ => {
                 let binary = base64_decode_flexible(token.as_ref())?;
-                Macaroon::deserialize_binary(&binary)?
+                Macaroon::deserialize_binary_with_options(&binary, options)?
             }
         };
         mac.validate()
     }
 
+    /// Deserialize an encoded macaroon token into an existing [Macaroon], reusing its buffers.
+    ///
+    /// This behaves like [Macaroon::deserialize], but clears and refills the identifier and
+    /// caveat list of `macaroon` rather than allocating new ones, so that a loop parsing many
+    /// tokens reaches a steady state with few allocations. Individual caveats (and, for base64
+    /// tokens, the decoded bytes) are still allocated per token; use
+    /// [Macaroon::deserialize_binary_into] on already-decoded tokens to avoid the latter.
+    ///
+    /// If deserialization fails, `macaroon` is left cleared, and must not be used as a token.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey};
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<dyn Error>> {
+    /// let tokens = vec![
+    ///     "MDAxY2xvY2F0aW9uIGh0dHA6Ly9teWJhbmsvCjAwMjZpZGVudGlmaWVyIHdlIHVzZWQgb3VyIHNlY3JldCBrZXkKMDAxNmNpZCB0ZXN0ID0gY2F2ZWF0CjAwMmZzaWduYXR1cmUgGXusegRK8zMyhluSZuJtSTvdZopmDkTYjOGpmMI9vWcK",
+    ///     r#"{"v":2,"l":"http://example.org/","i":"keyid", "c":[{"i":"account = 3735928559"},{"i":"user = alice"}],"s64": "S-lnzR6gxrJrr2pKlO6bBbFYhtoLqF6MQqk8jQ4SXvw"}"#,
+    /// ];
+    /// let mut buffer = Macaroon::create(None, &MacaroonKey::generate_random(), "-".into())?;
+    /// for token in tokens {
+    ///     Macaroon::deserialize_into(token, &mut buffer)?;
+    ///     println!("{:?}", buffer.identifier());
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn deserialize_into<T: AsRef<[u8]>>(token: T, macaroon: &mut Macaroon) -> Result<()> {
+        let mut builder = macaroon_builder::MacaroonBuilder::reuse(macaroon);
+        Macaroon::deserialize_to_builder(
+            token.as_ref(),
+            &DeserializeOptions::default(),
+            &mut builder,
+        )?;
+        builder.build_into(macaroon)
+    }
+
+    fn deserialize_to_builder(
+        token: &[u8],
+        options: &DeserializeOptions,
+        builder: &mut macaroon_builder::MacaroonBuilder,
+    ) -> Result<()> {
+        if token.is_empty() {
+            return Err(MacaroonError::DeserializationError(
+                "empty token provided".to_string(),
+            ));
+        }
+        options.limits.check_token_len(token.len())?;
+        match token[0] as char {
+            '{' => serialization::v2json::deserialize_into(token, options, builder),
+            _ => {
+                let binary = base64_decode_flexible(token)?;
+                Macaroon::deserialize_binary_to_builder(&binary, options, builder)
+            }
+        }
+    }
+
     /// Deserialize a binary macaroon token in binary, inferring the [Format]
     ///
     /// This works with V1 and V2 tokens, with no base64 encoding. It does not make sense to use
     /// this with V2JSON tokens.
     pub fn deserialize_binary(token: &[u8]) -> Result<Macaroon> {
+        Macaroon::deserialize_binary_with_options(token, &DeserializeOptions::default())
+    }
+
+    /// Deserialize a binary macaroon token, inferring the [Format], with [DeserializeOptions]
+    /// controlling how strictly it is parsed
+    pub fn deserialize_binary_with_options(
+        token: &[u8],
+        options: &DeserializeOptions,
+    ) -> Result<Macaroon> {
         if token.is_empty() {
             return Err(MacaroonError::DeserializationError(
                 "empty macaroon token".to_string(),
             ));
         }
+        options.limits.check_token_len(token.len())?;
         let mac: Macaroon = match token[0] as char {
-            '\x02' => serialization::v2::deserialize(token)?,
-            'a'..='f' | 'A'..='Z' | '0'..='9' => serialization::v1::deserialize(token)?,
+            _ if token[0] == serialization::compressed::ENVELOPE_TAG => {
+                let binary = serialization::compressed::decompress(token, options)?;
+                serialization::v2::deserialize(&binary, options)?
+            }
+            '\x02' => serialization::v2::deserialize(token, options)?,
+            'a'..='f' | 'A'..='Z' | '0'..='9' => serialization::v1::deserialize(token, options)?,
+            // other binary version bytes
+            '\x00'..='\x1f' => return Err(MacaroonError::UnsupportedVersion(token[0])),
             _ => {
                 return Err(MacaroonError::DeserializationError(
                     "unknown macaroon serialization format".to_string(),
@@ -457,11 +1386,116 @@ impl Macaroon {
         };
         mac.validate()
     }
+
+    /// Deserialize a binary V1 or V2 token into an existing [Macaroon], reusing its buffers; see
+    /// [Macaroon::deserialize_into]
+    pub fn deserialize_binary_into(token: &[u8], macaroon: &mut Macaroon) -> Result<()> {
+        let mut builder = macaroon_builder::MacaroonBuilder::reuse(macaroon);
+        Macaroon::deserialize_binary_to_builder(
+            token,
+            &DeserializeOptions::default(),
+            &mut builder,
+        )?;
+        builder.build_into(macaroon)
+    }
+
+    fn deserialize_binary_to_builder(
+        token: &[u8],
+        options: &DeserializeOptions,
+        builder: &mut macaroon_builder::MacaroonBuilder,
+    ) -> Result<()> {
+        if token.is_empty() {
+            return Err(MacaroonError::DeserializationError(
+                "empty macaroon token".to_string(),
+            ));
+        }
+        options.limits.check_token_len(token.len())?;
+        match token[0] as char {
+            _ if token[0] == serialization::compressed::ENVELOPE_TAG => {
+                let binary = serialization::compressed::decompress(token, options)?;
+                serialization::v2::deserialize_into(&binary, options, builder)
+            }
+            '\x02' => serialization::v2::deserialize_into(token, options, builder),
+            'a'..='f' | 'A'..='Z' | '0'..='9' => {
+                serialization::v1::deserialize_into(token, options, builder)
+            }
+            '\x00'..='\x1f' => Err(MacaroonError::UnsupportedVersion(token[0])),
+            _ => Err(MacaroonError::DeserializationError(
+                "unknown macaroon serialization format".to_string(),
+            )),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Macaroon {
+    type Error = MacaroonError;
+
+    /// Deserialize a binary (not base64-encoded) V1 or V2 token, as with
+    /// [Macaroon::deserialize_binary]
+    fn try_from(token: &[u8]) -> Result<Self> {
+        Macaroon::deserialize_binary(token)
+    }
+}
+
+impl From<&Macaroon> for Vec<u8> {
+    /// Encode the macaroon in the canonical V2 binary format (no base64 encoding)
+    ///
+    /// Unlike [Macaroon::serialize_binary], this doesn't check the macaroon fits the
+    /// [DeserializationLimits] defaults, so the result may be rejected by
+    /// [Macaroon::deserialize_binary]. Use it only for macaroons known to be within them, eg
+    /// ones just deserialized.
+    fn from(macaroon: &Macaroon) -> Self {
+        serialization::v2::encode(macaroon)
+    }
+}
+
+/// Formats a field for [Macaroon::inspect]: as is if it's printable UTF-8, and URL-safe base64
+/// otherwise
+fn inspect_field(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(s) if !s.chars().any(char::is_control) => s.to_string(),
+        _ => base64::encode_config(value, base64::URL_SAFE_NO_PAD),
+    }
+}
+
+impl fmt::Display for Macaroon {
+    /// The same as [Macaroon::inspect]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            writeln!(f, "location {}", location)?;
+        }
+        writeln!(f, "identifier {}", inspect_field(self.identifier.as_ref()))?;
+        for c in self.caveats.iter() {
+            match c {
+                Caveat::FirstParty(fp) => {
+                    writeln!(f, "cid {}", inspect_field(fp.predicate_ref().as_ref()))?
+                }
+                Caveat::ThirdParty(tp) => {
+                    writeln!(f, "cid {}", inspect_field(tp.id_ref().as_ref()))?;
+                    let vid = tp.verifier_id_ref().as_ref();
+                    writeln!(
+                        f,
+                        "vid {}",
+                        base64::encode_config(vid, base64::URL_SAFE_NO_PAD)
+                    )?;
+                    writeln!(f, "cl {}", tp.location())?;
+                }
+            }
+        }
+        write!(f, "signature ")?;
+        for b in self.signature.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey, Result, Verifier};
+    use crate::{
+        ByteString, Caveat, Format, Macaroon, MacaroonError, MacaroonKey, Result, Verifier,
+    };
+    use std::convert::TryFrom;
 
     #[test]
     fn create_macaroon() {
@@ -497,12 +1531,58 @@ mod tests {
     }
 
     #[test]
-    fn create_macaroon_errors() {
-        let deser_err = Macaroon::deserialize(b"\0");
+    fn deserialization_error_variants() {
+        let key = MacaroonKey::generate(b"key");
+        let macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+
+        let mut v1 = crate::serialization::v1::serialize_binary(&macaroon).unwrap();
+        // the signature packet follows the 18 byte identifier packet
+        v1[18..22].copy_from_slice(b"fff0");
+        let err = Macaroon::deserialize_binary(&v1).unwrap_err();
         assert!(matches!(
-            deser_err,
-            Err(MacaroonError::DeserializationError(_))
+            err,
+            MacaroonError::InvalidPacketHeader { offset: 18 }
+        ));
+        assert_eq!(
+            "Failed to deserialize macaroon: invalid packet header at offset 18",
+            err.to_string()
+        );
+
+        let mut v2: Vec<u8> = (&macaroon).into();
+        v2[0] = 3;
+        assert!(matches!(
+            Macaroon::deserialize_binary(&v2),
+            Err(MacaroonError::UnsupportedVersion(3))
         ));
+        v2[0] = 1;
+        assert!(matches!(
+            Macaroon::deserialize(base64::encode_config(&v2, base64::URL_SAFE)),
+            Err(MacaroonError::UnsupportedVersion(1))
+        ));
+        assert!(matches!(
+            Macaroon::deserialize(r#"{"v":1,"i":"id","c":[],"s64":""}"#),
+            Err(MacaroonError::UnsupportedVersion(1))
+        ));
+
+        // the signature is the last field, after its tag and one byte length
+        let mut v2: Vec<u8> = (&macaroon).into();
+        v2.pop();
+        let len = v2.len();
+        v2[len - 32] = 31;
+        assert!(matches!(
+            Macaroon::deserialize_binary(&v2),
+            Err(MacaroonError::SignatureLengthMismatch { got: 31 })
+        ));
+        assert!(matches!(
+            Macaroon::deserialize(r#"{"v":2,"i":"id","c":[],"s64":"AAAA"}"#),
+            Err(MacaroonError::SignatureLengthMismatch { got: 3 })
+        ));
+    }
+
+    #[test]
+    fn create_macaroon_errors() {
+        let deser_err = Macaroon::deserialize(b"\0");
+        assert!(matches!(deser_err, Err(MacaroonError::InvalidBase64(_))));
         println!("{}", deser_err.unwrap_err());
 
         let key = MacaroonKey::generate(b"this is a super duper secret key");
@@ -563,6 +1643,105 @@ mod tests {
         assert_eq!(&macaroon.caveats[0], &macaroon.first_party_caveats()[0]);
     }
 
+    #[test]
+    fn create_macaroon_with_normalized_caveat() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut normalized = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        normalized.add_normalized_first_party_caveat(" account=3735928559".into());
+        let mut plain = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        plain.add_first_party_caveat("account = 3735928559".into());
+        assert_eq!(plain, normalized);
+    }
+
+    #[test]
+    fn create_macaroon_with_caveat_from_reader() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let payload: Vec<u8> = (0..3_000_000u32).map(|i| (i % 253) as u8).collect();
+        let mut streamed = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        streamed
+            .add_first_party_caveat_from_reader(payload.as_slice())
+            .unwrap();
+        let mut plain = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        plain.add_first_party_caveat(payload.into());
+        assert_eq!(plain, streamed);
+    }
+
+    #[test]
+    fn create_macaroon_with_ttl() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let macaroon = Macaroon::create_with_ttl(
+            None,
+            &key,
+            "identifier".into(),
+            std::time::Duration::from_secs(60),
+        )
+        .unwrap();
+        assert_eq!(1, macaroon.caveats().len());
+        match &macaroon.caveats()[0] {
+            Caveat::FirstParty(fp) => assert!(fp.predicate().0.starts_with(b"time < ")),
+            _ => panic!("expected a first-party caveat"),
+        }
+        assert!(matches!(
+            Macaroon::create_with_ttl(None, &key, "identifier".into(), std::time::Duration::MAX),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn create_macaroon_with_fallback_locations() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let caveat_key = MacaroonKey::generate(b"this is another key");
+        let mut macaroon = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        assert!(macaroon
+            .add_third_party_caveat_with_fallbacks(&[], &caveat_key, "id".into())
+            .is_err());
+        macaroon
+            .add_third_party_caveat_with_fallbacks(
+                &["https://auth.us/", "https://auth.eu/"],
+                &caveat_key,
+                "id".into(),
+            )
+            .unwrap();
+        let token = macaroon.serialize(Format::V2).unwrap();
+        match &Macaroon::deserialize(token).unwrap().caveats()[0] {
+            Caveat::ThirdParty(tp) => assert_eq!(
+                vec!["https://auth.us/", "https://auth.eu/"],
+                tp.locations().collect::<Vec<&str>>()
+            ),
+            _ => panic!("expected a third-party caveat"),
+        }
+    }
+
+    #[test]
+    fn create_macaroon_with_unique_caveats() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        assert!(macaroon.add_first_party_caveat_unique("user = alice".into()));
+        let signature = macaroon.signature();
+        assert!(!macaroon.add_first_party_caveat_unique("user = alice".into()));
+        assert_eq!(1, macaroon.caveats.len());
+        assert_eq!(signature, macaroon.signature);
+        assert!(macaroon.dedup_report().is_empty());
+    }
+
+    #[test]
+    fn macaroon_dedup_report() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "identifier".into()).unwrap();
+        macaroon.add_first_party_caveat("tenant = acme".into());
+        macaroon.add_first_party_caveat("user = alice".into());
+        macaroon.add_third_party_caveat("https://auth.mybank", &key, "tenant = acme".into());
+        macaroon.add_first_party_caveat("tenant = acme".into());
+        macaroon.add_first_party_caveat("tenant = acme".into());
+        assert_eq!(
+            vec![crate::DuplicateCaveat {
+                predicate: "tenant = acme".into(),
+                indices: vec![0, 3, 4],
+            }],
+            macaroon.dedup_report()
+        );
+    }
+
     #[test]
     fn create_macaroon_with_third_party_caveat() {
         // NOTE: using byte string directly, not generating with HMAC
@@ -587,6 +1766,78 @@ mod tests {
         assert_eq!(&macaroon.caveats[0], &macaroon.third_party_caveats()[0]);
     }
 
+    #[test]
+    fn test_binary_conversions() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon =
+            Macaroon::create(Some("http://example.org/".into()), &key, "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_third_party_caveat(
+            "https://auth.mybank.com",
+            &MacaroonKey::generate(b"caveat key"),
+            "caveat".into(),
+        );
+
+        let bytes: Vec<u8> = (&macaroon).into();
+        assert_eq!(2, bytes[0]);
+        assert_eq!(macaroon, Macaroon::try_from(bytes.as_slice()).unwrap());
+        assert_eq!(bytes, macaroon.serialize_binary().unwrap());
+
+        // only serialize_binary checks the deserialization limits
+        let mut huge = macaroon.clone();
+        let limits = crate::DeserializationLimits::default();
+        huge.add_first_party_caveat(vec![b'x'; limits.max_field_len + 1].into());
+        assert!(matches!(
+            huge.serialize_binary(),
+            Err(MacaroonError::SerializationError(_))
+        ));
+        let unchecked: Vec<u8> = (&huge).into();
+        assert!(Macaroon::try_from(unchecked.as_slice()).is_err());
+
+        // V1 binary tokens are accepted too
+        let v1 = base64::decode_config(
+            macaroon.serialize(crate::Format::V1).unwrap(),
+            base64::URL_SAFE,
+        )
+        .unwrap();
+        assert_eq!(macaroon, Macaroon::try_from(v1.as_slice()).unwrap());
+
+        assert!(matches!(
+            Macaroon::try_from(&b""[..]),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_into() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut first = Macaroon::create(None, &key, "first identifier".into()).unwrap();
+        for i in 0..10 {
+            first.add_first_party_caveat(format!("caveat = {}", i).into());
+        }
+        let mut second = Macaroon::create(Some("location".into()), &key, "second".into()).unwrap();
+        second.add_first_party_caveat("user = alice".into());
+
+        let mut buffer = Macaroon::create(None, &key, "buffer".into()).unwrap();
+        Macaroon::deserialize_into(first.serialize(crate::Format::V1).unwrap(), &mut buffer)
+            .unwrap();
+        assert_eq!(first, buffer);
+        let capacity = buffer.caveats.capacity();
+        Macaroon::deserialize_into(
+            second.serialize(crate::Format::V2JSON).unwrap(),
+            &mut buffer,
+        )
+        .unwrap();
+        assert_eq!(second, buffer);
+        assert_eq!(capacity, buffer.caveats.capacity());
+        let binary: Vec<u8> = (&first).into();
+        Macaroon::deserialize_binary_into(&binary, &mut buffer).unwrap();
+        assert_eq!(first, buffer);
+
+        assert!(Macaroon::deserialize_into("", &mut buffer).is_err());
+        assert!(Macaroon::deserialize_binary_into(b"\0", &mut buffer).is_err());
+    }
+
     #[test]
     fn test_deserialize_bad_data() {
         // these are all expected to fail... but not panic!
@@ -596,9 +1847,177 @@ mod tests {
         assert!(Macaroon::deserialize(b"NDhJe_A==").is_err());
 
         // examples that fail from fuzzing for the top-level deserialize function
-        assert!(Macaroon::deserialize(&vec![10]).is_err());
-        assert!(Macaroon::deserialize(&vec![70, 70, 102, 70]).is_err());
-        assert!(Macaroon::deserialize(&vec![2, 2, 212, 212, 212, 212]).is_err());
+        assert!(Macaroon::deserialize(vec![10]).is_err());
+        assert!(Macaroon::deserialize(vec![70, 70, 102, 70]).is_err());
+        assert!(Macaroon::deserialize(vec![2, 2, 212, 212, 212, 212]).is_err());
+    }
+
+    #[test]
+    fn test_inspect_binary_fields() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, vec![0, 255, 1].into()).unwrap();
+        macaroon.add_first_party_caveat("line\nbreak".into());
+        let inspected = macaroon.inspect();
+        let lines: Vec<&str> = inspected.lines().collect();
+        assert_eq!(
+            vec!["identifier AP8B", "cid bGluZQpicmVhaw"],
+            lines[..2].to_vec()
+        );
+        assert!(lines[2].starts_with("signature "));
+        assert_eq!(inspected, format!("{}", macaroon));
+    }
+
+    #[test]
+    fn test_serialized_len() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon =
+            Macaroon::create(Some("https://mybank/".into()), &key, b"\xffid"[..].into()).unwrap();
+        let check = |macaroon: &Macaroon| {
+            for format in [Format::V1, Format::V2, Format::V2JSON] {
+                for padding in [false, true] {
+                    let options = crate::SerializeOptions { padding };
+                    assert_eq!(
+                        macaroon
+                            .serialize_with_options(format, &options)
+                            .unwrap()
+                            .len(),
+                        macaroon.serialized_len_with_options(format, &options),
+                        "{:?} {:?}",
+                        format,
+                        options
+                    );
+                }
+            }
+        };
+        check(&macaroon);
+        macaroon.add_first_party_caveat("quote \" and newline \n and \u{1}".into());
+        macaroon.add_first_party_caveat(vec![b'x'; 300].into());
+        macaroon.add_third_party_caveat("https://auth/", &key, "caveat id".into());
+        macaroon
+            .extensions_mut()
+            .insert("x-note", &vec!["a", "b"])
+            .unwrap();
+        check(&macaroon);
+
+        let len = macaroon.serialized_len(Format::V2);
+        macaroon.ensure_fits(Format::V2, len).unwrap();
+        assert!(matches!(
+            macaroon.ensure_fits(Format::V2, len - 1),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_signature_chain() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        let mut expected = vec![macaroon.signature()];
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        expected.push(macaroon.signature());
+        macaroon.add_third_party_caveat("https://auth/", &key, "caveat id".into());
+        expected.push(macaroon.signature());
+        assert_eq!(expected, macaroon.signature_chain(&key));
+        assert!(macaroon.verify_signature_only(&key));
+        assert!(!macaroon.verify_signature_only(&MacaroonKey::generate(b"wrong key")));
+
+        let blake2 =
+            Macaroon::create_with_scheme(None, &key, "id".into(), &crate::Blake2bMac).unwrap();
+        assert_eq!(vec![blake2.signature()], blake2.signature_chain(&key));
+        assert!(blake2.verify_signature_only(&key));
+
+        let mut discharge = Macaroon::create(None, &key, "caveat id".into()).unwrap();
+        assert!(discharge.verify_signature_only(&key));
+        macaroon.bind(&mut discharge);
+        assert!(!discharge.verify_signature_only(&key));
+    }
+
+    #[test]
+    fn test_structurally_eq() {
+        let key = MacaroonKey::generate(b"key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mint = |location: &str, predicate: &str| {
+            let mut macaroon =
+                Macaroon::create(Some("https://mybank/".into()), &key, "keyid".into()).unwrap();
+            macaroon.add_first_party_caveat(predicate.into());
+            macaroon.add_third_party_caveat(location, &caveat_key, "caveat id".into());
+            macaroon
+        };
+        let macaroon = mint("https://auth/", "user = alice");
+        assert!(macaroon.structurally_eq(&macaroon));
+        assert!(macaroon.structurally_eq(&mint("https://auth/", "user = alice")));
+        let other_key = MacaroonKey::generate(b"other key");
+        let mut rekeyed =
+            Macaroon::create(Some("https://mybank/".into()), &other_key, "keyid".into()).unwrap();
+        rekeyed.add_first_party_caveat("user = alice".into());
+        rekeyed.add_third_party_caveat("https://auth/", &other_key, "caveat id".into());
+        assert!(macaroon.structurally_eq(&rekeyed));
+
+        assert!(!macaroon.structurally_eq(&mint("https://other/", "user = alice")));
+        assert!(!macaroon.structurally_eq(&mint("https://auth/", "user = bob")));
+        let mut longer = macaroon.clone();
+        longer.add_first_party_caveat("op = read".into());
+        assert!(!macaroon.structurally_eq(&longer));
+        assert!(!longer.structurally_eq(&macaroon));
+        let mut first_party =
+            Macaroon::create(Some("https://mybank/".into()), &key, "keyid".into()).unwrap();
+        first_party.add_first_party_caveat("user = alice".into());
+        first_party.add_first_party_caveat("caveat id".into());
+        assert!(!macaroon.structurally_eq(&first_party));
+    }
+
+    #[test]
+    fn test_convert() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(
+            Some("http://example.org/".into()),
+            &key,
+            vec![0, 255, 1].into(),
+        )
+        .unwrap();
+        macaroon.add_first_party_caveat(vec![10, 0, 200].into());
+        macaroon.add_third_party_caveat("http://auth/", &key, "caveat".into());
+
+        let formats = [Format::V1, Format::V2, Format::V2JSON];
+        for from in formats {
+            let token = macaroon.serialize(from).unwrap();
+            for to in formats {
+                let converted = Macaroon::convert(&token, from, to).unwrap();
+                assert_eq!(macaroon.serialize(to).unwrap(), converted);
+            }
+            // the token has to be in the stated format
+            for other in formats.iter().filter(|f| **f != from) {
+                assert!(matches!(
+                    Macaroon::convert(&token, *other, Format::V2),
+                    Err(MacaroonError::DeserializationError(_))
+                ));
+            }
+        }
+
+        // extensions only survive in V2JSON
+        macaroon
+            .caveat_extensions_mut(0)
+            .unwrap()
+            .insert("x-note", "kept in JSON")
+            .unwrap();
+        let json = macaroon.serialize(Format::V2JSON).unwrap();
+        Macaroon::convert(&json, Format::V2JSON, Format::V2JSON).unwrap();
+        for to in [Format::V1, Format::V2] {
+            match Macaroon::convert(&json, Format::V2JSON, to) {
+                Err(MacaroonError::SerializationError(reason)) => {
+                    assert!(reason.contains("extensions"), "{}", reason)
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+
+        // V1 packets are limited to 64 KiB
+        let mut large = Macaroon::create(None, &key, "id".into()).unwrap();
+        large.add_first_party_caveat(vec![b'x'; 0xfff8].into());
+        let token = large.serialize(Format::V2).unwrap();
+        assert!(matches!(
+            Macaroon::convert(&token, Format::V2, Format::V1),
+            Err(MacaroonError::SerializationError(_))
+        ));
     }
 }
 
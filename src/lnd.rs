@@ -0,0 +1,253 @@
+use crate::serialization::v2;
+use crate::{
+    decode_hex, encode_hex, ByteString, Caveat, Macaroon, MacaroonError, Result, Verifier,
+};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Prefix of permission caveats, followed by space-delimited `entity:action` pairs
+pub const PERMISSIONS_PREFIX: &str = "permissions ";
+/// Prefix of lnd's custom caveats, followed by the caveat name and its condition
+pub const LND_CUSTOM_PREFIX: &str = "lnd-custom ";
+
+/// An lnd-style permission: an `action` (eg `read`) on an `entity` (eg `invoices`)
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Permission {
+    pub entity: String,
+    pub action: String,
+}
+
+impl Permission {
+    pub fn new(entity: &str, action: &str) -> Permission {
+        Permission {
+            entity: entity.to_string(),
+            action: action.to_string(),
+        }
+    }
+
+    /// Parse an `entity:action` pair, or `None` if either half is missing
+    pub fn parse(pair: &str) -> Option<Permission> {
+        match pair.split_once(':') {
+            Some((entity, action)) if !entity.is_empty() && !action.is_empty() => {
+                Some(Permission::new(entity, action))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.entity, self.action)
+    }
+}
+
+/// The permission caveat predicate, `permissions <entity:action> ...`. Pairs are de-duplicated
+/// and sorted, so equal sets of permissions give identical predicates.
+pub fn permissions_predicate(permissions: &[Permission]) -> ByteString {
+    let permissions: BTreeSet<&Permission> = permissions.iter().collect();
+    let pairs: Vec<String> = permissions.iter().map(|p| p.to_string()).collect();
+    format!("{}{}", PERMISSIONS_PREFIX, pairs.join(" ")).into()
+}
+
+/// The lnd custom caveat predicate, `lnd-custom <name> <condition>`, as checked by lnd's
+/// custom caveat acceptors
+pub fn lnd_custom_predicate(name: &str, condition: &str) -> ByteString {
+    format!("{}{} {}", LND_CUSTOM_PREFIX, name, condition).into()
+}
+
+/// Permissions of a permission caveat predicate, or `None` if it isn't one or is malformed
+fn predicate_permissions(predicate: &ByteString) -> Option<BTreeSet<Permission>> {
+    let predicate = std::str::from_utf8(predicate.as_ref()).ok()?;
+    predicate
+        .strip_prefix(PERMISSIONS_PREFIX)?
+        .split_whitespace()
+        .map(Permission::parse)
+        .collect()
+}
+
+/// Name and condition of an lnd custom caveat predicate, or `None` if it isn't one
+fn predicate_lnd_custom(predicate: &ByteString) -> Option<(&str, &str)> {
+    let predicate = std::str::from_utf8(predicate.as_ref()).ok()?;
+    let custom = predicate.strip_prefix(LND_CUSTOM_PREFIX)?;
+    Some(custom.split_once(' ').unwrap_or((custom, "")))
+}
+
+impl Macaroon {
+    /// The V2 binary serialization in lower-case hex, as lnd's `--macaroonpath` files are shown
+    /// and sent in the `macaroon` gRPC metadata
+    pub fn serialize_hex(&self) -> Result<String> {
+        Ok(encode_hex(&v2::serialize_binary(self)?))
+    }
+
+    /// Deserialize a hex-encoded binary macaroon, as produced by [Macaroon::serialize_hex] or
+    /// `lncli bakemacaroon`
+    pub fn deserialize_hex(hex: &str) -> Result<Macaroon> {
+        let binary = decode_hex(hex.trim()).ok_or_else(|| {
+            MacaroonError::DeserializationError("invalid hex macaroon".to_string())
+        })?;
+        Macaroon::deserialize_binary(&binary)
+    }
+
+    /// Restrict the macaroon to `permissions`
+    ///
+    /// Each permission caveat can only narrow what the macaroon grants: the effective
+    /// permissions are the intersection of all of them (see [Macaroon::effective_permissions]).
+    pub fn add_permissions_caveat(&mut self, permissions: &[Permission]) {
+        self.add_first_party_caveat(permissions_predicate(permissions));
+    }
+
+    /// Add an lnd custom caveat, `lnd-custom <name> <condition>`
+    pub fn add_lnd_custom_caveat(&mut self, name: &str, condition: &str) {
+        self.add_first_party_caveat(lnd_custom_predicate(name, condition));
+    }
+
+    /// The intersection of all the macaroon's permission caveats, or `None` if it has none (and
+    /// so isn't restricted by permission)
+    ///
+    /// This only inspects the caveats; it doesn't verify the macaroon. A malformed permission
+    /// caveat grants nothing.
+    pub fn effective_permissions(&self) -> Option<BTreeSet<Permission>> {
+        self.caveats
+            .iter()
+            .filter_map(|c| match c {
+                Caveat::FirstParty(fp) => {
+                    let predicate = std::str::from_utf8(fp.predicate_ref().as_ref()).ok()?;
+                    predicate
+                        .starts_with(PERMISSIONS_PREFIX)
+                        .then(|| predicate_permissions(fp.predicate_ref()).unwrap_or_default())
+                }
+                Caveat::ThirdParty(_) => None,
+            })
+            .reduce(|acc, permissions| acc.intersection(&permissions).cloned().collect())
+    }
+}
+
+impl<C> Verifier<C> {
+    /// Satisfy permission caveats which grant every one of the `required` permissions
+    ///
+    /// Since every permission caveat has to be satisfied, a request is only allowed if the
+    /// permissions it requires are within the intersection of all the macaroon's permission
+    /// caveats.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Permission, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_permissions_caveat(&[
+    ///     Permission::new("invoices", "read"),
+    ///     Permission::new("invoices", "write"),
+    /// ]);
+    /// let token = macaroon.serialize_hex().unwrap();
+    ///
+    /// let macaroon = Macaroon::deserialize_hex(&token).unwrap();
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_permissions(&[Permission::new("invoices", "read")]);
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_ok());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_permissions(&[Permission::new("onchain", "write")]);
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_err());
+    /// ```
+    pub fn satisfy_permissions(&mut self, required: &[Permission]) {
+        let required: BTreeSet<Permission> = required.iter().cloned().collect();
+        self.satisfy_general(move |predicate| match predicate_permissions(predicate) {
+            Some(granted) => required.is_subset(&granted),
+            None => false,
+        });
+    }
+
+    /// Satisfy lnd custom caveats called `name` whose condition passes `check`
+    pub fn satisfy_lnd_custom<F>(&mut self, name: &str, check: F)
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        let name = name.to_string();
+        self.satisfy_general(move |predicate| match predicate_lnd_custom(predicate) {
+            Some((n, condition)) => n == name && check(condition),
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lnd_custom_predicate, permissions_predicate, Permission};
+    use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_permissions_predicate() {
+        let read = Permission::new("invoices", "read");
+        let write = Permission::new("offchain", "write");
+        assert_eq!(
+            ByteString::from("permissions invoices:read offchain:write"),
+            permissions_predicate(&[write.clone(), read.clone(), write])
+        );
+        assert_eq!(Some(read), Permission::parse("invoices:read"));
+        assert_eq!(None, Permission::parse("invoices"));
+        assert_eq!(None, Permission::parse(":read"));
+        assert_eq!(
+            ByteString::from("lnd-custom account 1234"),
+            lnd_custom_predicate("account", "1234")
+        );
+    }
+
+    #[test]
+    fn test_hex() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_permissions_caveat(&[Permission::new("info", "read")]);
+        let hex = macaroon.serialize_hex().unwrap();
+        assert!(hex.starts_with("02"));
+        assert_eq!(macaroon, Macaroon::deserialize_hex(&hex).unwrap());
+        assert_eq!(
+            macaroon,
+            Macaroon::deserialize_hex(&hex.to_uppercase()).unwrap()
+        );
+        assert!(matches!(
+            Macaroon::deserialize_hex("not hex"),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_effective_permissions() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        assert_eq!(None, macaroon.effective_permissions());
+        let read = Permission::new("invoices", "read");
+        let write = Permission::new("invoices", "write");
+        macaroon.add_permissions_caveat(&[read.clone(), write.clone()]);
+        macaroon.add_lnd_custom_caveat("account", "1234");
+        macaroon.add_permissions_caveat(&[read.clone(), Permission::new("info", "read")]);
+        assert_eq!(
+            Some([read].into_iter().collect()),
+            macaroon.effective_permissions()
+        );
+        macaroon.add_first_party_caveat("permissions invoices".into());
+        assert_eq!(Some(Default::default()), macaroon.effective_permissions());
+    }
+
+    #[test]
+    fn test_satisfy_permissions() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        let read = Permission::new("invoices", "read");
+        let write = Permission::new("invoices", "write");
+        macaroon.add_permissions_caveat(&[read.clone(), write.clone()]);
+        macaroon.add_permissions_caveat(&[read.clone(), Permission::new("info", "read")]);
+        macaroon.add_lnd_custom_caveat("account", "1234");
+        let check = |required: &[Permission], account: &'static str| {
+            let mut verifier = Verifier::default();
+            verifier.satisfy_permissions(required);
+            verifier.satisfy_lnd_custom("account", move |condition| condition == account);
+            verifier.verify(&macaroon, &key, Default::default()).is_ok()
+        };
+        assert!(check(std::slice::from_ref(&read), "1234"));
+        assert!(check(&[], "1234"));
+        assert!(!check(std::slice::from_ref(&read), "5678"));
+        assert!(!check(std::slice::from_ref(&write), "1234"));
+        assert!(!check(&[read, write], "1234"));
+    }
+}
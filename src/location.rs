@@ -0,0 +1,141 @@
+use crate::{MacaroonError, Result};
+
+/// How [Verifier::expect_location](crate::Verifier::expect_location) compares a macaroon's
+/// location with the expected one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocationMatch {
+    /// The locations are identical
+    Exact,
+    /// The macaroon's location starts with the expected one. End the expected location with a
+    /// `/`, or `https://api.example.com` would also match `https://api.example.com.evil.org`.
+    Prefix,
+    /// The locations are URLs with the same origin: scheme, host and port, ignoring case and
+    /// default ports
+    Origin,
+}
+
+/// The location a verifier accepts macaroons for, to reject macaroons minted for other services
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ExpectedLocation {
+    location: String,
+    matching: LocationMatch,
+}
+
+impl ExpectedLocation {
+    pub(crate) fn new(location: &str, matching: LocationMatch) -> ExpectedLocation {
+        ExpectedLocation {
+            location: location.to_string(),
+            matching,
+        }
+    }
+
+    fn matches(&self, location: &str) -> bool {
+        match self.matching {
+            LocationMatch::Exact => location == self.location,
+            LocationMatch::Prefix => location.starts_with(&self.location),
+            LocationMatch::Origin => match (origin(location), origin(&self.location)) {
+                (Some(origin), Some(expected)) => origin == expected,
+                _ => false,
+            },
+        }
+    }
+
+    /// Fails with [MacaroonError::LocationMismatch] unless `location` is the expected one
+    pub(crate) fn check(&self, location: Option<&str>) -> Result<()> {
+        match location {
+            Some(location) if self.matches(location) => Ok(()),
+            _ => Err(MacaroonError::LocationMismatch(location.map(String::from))),
+        }
+    }
+}
+
+/// The origin of a URL, as `scheme://host[:port]` in lower case without default ports, or
+/// `None` if it has no scheme or host
+fn origin(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    // drop any user info
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+    if scheme.is_empty() || authority.is_empty() {
+        return None;
+    }
+    let scheme = scheme.to_ascii_lowercase();
+    let mut authority = authority.to_ascii_lowercase();
+    let default_port = match scheme.as_str() {
+        "http" => Some(":80"),
+        "https" => Some(":443"),
+        _ => None,
+    };
+    if let Some(port) = default_port {
+        if authority.ends_with(port) {
+            authority.truncate(authority.len() - port.len());
+        }
+    }
+    Some(format!("{}://{}", scheme, authority))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{origin, ExpectedLocation, LocationMatch};
+    use crate::{Macaroon, MacaroonError, MacaroonKey, MacaroonRef, Verifier};
+
+    #[test]
+    fn test_origin() {
+        assert_eq!(
+            Some("https://api.example.com".to_string()),
+            origin("HTTPS://API.example.com:443/v1?x=1")
+        );
+        assert_eq!(
+            Some("http://example.com:8080".to_string()),
+            origin("http://user@example.com:8080#top")
+        );
+        assert_eq!(None, origin("api.example.com/v1"));
+        assert_eq!(None, origin("https:///path"));
+
+        let expected = ExpectedLocation::new("https://api.example.com/", LocationMatch::Prefix);
+        assert!(expected.check(Some("https://api.example.com/v1")).is_ok());
+        assert!(expected
+            .check(Some("https://api.example.com.evil.org/"))
+            .is_err());
+        assert!(expected.check(None).is_err());
+    }
+
+    #[test]
+    fn test_expect_location() {
+        let key = MacaroonKey::generate(b"key");
+        let ours =
+            Macaroon::create(Some("https://api.example.com/v1".into()), &key, "id".into()).unwrap();
+        let theirs = Macaroon::create(
+            Some("https://billing.example.com/".into()),
+            &key,
+            "id".into(),
+        )
+        .unwrap();
+        let anywhere = Macaroon::create(None, &key, "id".into()).unwrap();
+
+        let verifier =
+            Verifier::default().expect_location("https://API.example.com", LocationMatch::Origin);
+        verifier.verify(&ours, &key, vec![]).unwrap();
+        match verifier.verify(&theirs, &key, vec![]) {
+            Err(MacaroonError::LocationMismatch(Some(location))) => {
+                assert_eq!("https://billing.example.com/", location)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(
+            verifier.verify(&anywhere, &key, vec![]),
+            Err(MacaroonError::LocationMismatch(None))
+        ));
+
+        let exact =
+            Verifier::default().expect_location("https://api.example.com/v1", LocationMatch::Exact);
+        exact.verify(&ours, &key, vec![]).unwrap();
+        let token: Vec<u8> = (&theirs).into();
+        assert!(matches!(
+            MacaroonRef::parse(&token)
+                .unwrap()
+                .verify(&exact, &key, &[]),
+            Err(MacaroonError::LocationMismatch(_))
+        ));
+    }
+}
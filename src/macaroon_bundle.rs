@@ -0,0 +1,236 @@
+use crate::serialization::Format;
+use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, MacaroonVerifier, Result};
+
+/// A root macaroon and its discharges, handled as one unit
+///
+/// Discharges are added as the third parties return them, unbound. [MacaroonBundle::verify]
+/// and [MacaroonBundle::serialize] bind copies of them to the root macaroon as they go, so the
+/// root macaroon can still be attenuated until [MacaroonBundle::bind_all] binds them in place.
+/// A deserialized bundle is already bound, as sent.
+///
+/// The bundle tracks whether its discharges are bound, so they are bound exactly once: binding
+/// a discharge twice, or not at all, fails verification (with
+/// [MacaroonError::DischargeBoundTwice] and [MacaroonError::DischargeNotBound]).
+///
+/// ```rust
+/// use macaroon::{Format, Macaroon, MacaroonBundle, MacaroonKey, Verifier};
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let caveat_key = MacaroonKey::generate(b"caveat key");
+/// let mut root = Macaroon::create(None, &key, "id".into()).unwrap();
+/// root.add_third_party_caveat("https://auth.example/", &caveat_key, "login".into());
+/// let discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+///
+/// let mut bundle = MacaroonBundle::new(root, vec![discharge]);
+/// bundle.attenuate("op = read".into()).unwrap();
+/// let token = bundle.serialize(Format::V2JSON).unwrap();
+///
+/// let received = MacaroonBundle::deserialize(&token).unwrap();
+/// let verifier = Verifier::builder()
+///     .satisfy_exact("op = read".into())
+///     .build();
+/// received.verify(&key, &verifier).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacaroonBundle {
+    root: Macaroon,
+    discharges: Vec<Macaroon>,
+    bound: bool,
+}
+
+impl MacaroonBundle {
+    /// A bundle of `root` and its `discharges`, as issued (not yet bound to `root`)
+    pub fn new(root: Macaroon, discharges: Vec<Macaroon>) -> MacaroonBundle {
+        MacaroonBundle {
+            root,
+            discharges,
+            bound: false,
+        }
+    }
+
+    /// A bundle whose `discharges` are already bound to `root`, eg taken from a request
+    pub fn from_bound(root: Macaroon, discharges: Vec<Macaroon>) -> MacaroonBundle {
+        MacaroonBundle {
+            root,
+            discharges,
+            bound: true,
+        }
+    }
+
+    pub fn root(&self) -> &Macaroon {
+        &self.root
+    }
+
+    /// The discharges, bound to the root macaroon if [MacaroonBundle::is_bound]
+    pub fn discharges(&self) -> &[Macaroon] {
+        &self.discharges
+    }
+
+    /// Whether the discharges have been bound to the root macaroon in place
+    pub fn is_bound(&self) -> bool {
+        self.bound
+    }
+
+    /// The root macaroon and its discharges, bound to it
+    pub fn into_parts(self) -> (Macaroon, Vec<Macaroon>) {
+        let discharges = self.bound_discharges();
+        (self.root, discharges)
+    }
+
+    /// Add a discharge as issued, binding it if the others already are
+    pub fn add_discharge(&mut self, mut discharge: Macaroon) {
+        if self.bound {
+            self.root.bind(&mut discharge);
+        }
+        self.discharges.push(discharge);
+    }
+
+    /// Bind every discharge to the root macaroon in place; see [Macaroon::bind]
+    ///
+    /// The root macaroon can't be attenuated afterwards, since the bindings cover its signature.
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::DischargeBoundTwice] if the discharges are already bound, eg
+    /// because the bundle was deserialized, rather than binding them again.
+    pub fn bind_all(&mut self) -> Result<()> {
+        if self.bound {
+            return match self.discharges.first() {
+                Some(discharge) => Err(MacaroonError::DischargeBoundTwice(discharge.identifier())),
+                None => Ok(()),
+            };
+        }
+        for discharge in self.discharges.iter_mut() {
+            self.root.bind(discharge);
+        }
+        self.bound = true;
+        Ok(())
+    }
+
+    /// Add a first-party caveat to the root macaroon
+    ///
+    /// # Errors
+    ///
+    /// Fails with [MacaroonError::IncompleteMacaroon] once the discharges are bound, as the
+    /// caveat would invalidate their bindings.
+    pub fn attenuate(&mut self, predicate: ByteString) -> Result<()> {
+        if self.bound && !self.discharges.is_empty() {
+            return Err(MacaroonError::IncompleteMacaroon(
+                "the discharges are already bound to the root macaroon",
+            ));
+        }
+        self.root.add_first_party_caveat(predicate);
+        Ok(())
+    }
+
+    /// The discharges bound to the root macaroon, binding copies if needed
+    fn bound_discharges(&self) -> Vec<Macaroon> {
+        let mut discharges = self.discharges.clone();
+        if !self.bound {
+            for discharge in discharges.iter_mut() {
+                self.root.bind(discharge);
+            }
+        }
+        discharges
+    }
+
+    /// Verify the root macaroon, minted with `key`, and its discharges with `verifier`
+    pub fn verify<V>(&self, key: &MacaroonKey, verifier: &V) -> Result<()>
+    where
+        V: MacaroonVerifier + ?Sized,
+    {
+        verifier.verify(&self.root, key, self.bound_discharges())
+    }
+
+    /// Serialize the root macaroon followed by its bound discharges, as with
+    /// [Macaroon::serialize_slice]
+    pub fn serialize(&self, format: Format) -> Result<String> {
+        let mut macaroons = Vec::with_capacity(self.discharges.len() + 1);
+        macaroons.push(self.root.clone());
+        macaroons.extend(self.bound_discharges());
+        Macaroon::serialize_slice(&macaroons, format)
+    }
+
+    /// Deserialize a bundle written by [MacaroonBundle::serialize] (or any
+    /// [Macaroon::deserialize_slice] token): the first macaroon is the root
+    pub fn deserialize<T: AsRef<[u8]>>(token: T) -> Result<MacaroonBundle> {
+        let mut macaroons = Macaroon::deserialize_slice(token)?;
+        let root = macaroons.remove(0);
+        Ok(MacaroonBundle::from_bound(root, macaroons))
+    }
+}
+
+impl From<MacaroonBundle> for Vec<Macaroon> {
+    /// The root macaroon followed by its bound discharges, as verifiers and the `http`
+    /// encoders take them
+    fn from(bundle: MacaroonBundle) -> Self {
+        let (root, mut discharges) = bundle.into_parts();
+        discharges.insert(0, root);
+        discharges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MacaroonBundle;
+    use crate::{Format, Macaroon, MacaroonError, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_bundle() {
+        let key = MacaroonKey::generate(b"key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let other_key = MacaroonKey::generate(b"other caveat key");
+        let mut root = Macaroon::create(None, &key, "id".into()).unwrap();
+        root.add_third_party_caveat("https://auth.example/", &caveat_key, "login".into());
+        root.add_third_party_caveat("https://other.example/", &other_key, "other".into());
+        let discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        let other = Macaroon::create(None, &other_key, "other".into()).unwrap();
+
+        let verifier = Verifier::builder()
+            .satisfy_exact("op = read".into())
+            .build();
+        let mut bundle = MacaroonBundle::new(root, vec![discharge]);
+        assert!(bundle.verify(&key, &verifier).is_err());
+        bundle.attenuate("op = read".into()).unwrap();
+        bundle.add_discharge(other.clone());
+        bundle.verify(&key, &verifier).unwrap();
+        assert!(!bundle.is_bound());
+
+        for format in [Format::V1, Format::V2, Format::V2JSON] {
+            let token = bundle.serialize(format).unwrap();
+            let received = MacaroonBundle::deserialize(&token).unwrap();
+            assert!(received.is_bound());
+            assert_eq!(bundle.root(), received.root());
+            received.verify(&key, &verifier).unwrap();
+        }
+
+        // binding in place, once
+        let mut bound = bundle.clone();
+        bound.bind_all().unwrap();
+        assert!(matches!(
+            bound.bind_all(),
+            Err(MacaroonError::DischargeBoundTwice(_))
+        ));
+        bound.verify(&key, &verifier).unwrap();
+        assert_eq!(
+            bundle.serialize(Format::V2).unwrap(),
+            bound.serialize(Format::V2).unwrap()
+        );
+        assert!(matches!(
+            bound.attenuate("op = write".into()),
+            Err(MacaroonError::IncompleteMacaroon(_))
+        ));
+        let macaroons: Vec<Macaroon> = bundle.into();
+        assert_eq!(3, macaroons.len());
+        verifier
+            .verify(&macaroons[0], &key, macaroons[1..].to_vec())
+            .unwrap();
+
+        // discharges added to a bound bundle are bound as they come
+        let mut late =
+            MacaroonBundle::from_bound(bound.root().clone(), bound.discharges()[..1].to_vec());
+        assert!(late.verify(&key, &verifier).is_err());
+        late.add_discharge(other);
+        late.verify(&key, &verifier).unwrap();
+    }
+}
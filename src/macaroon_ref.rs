@@ -0,0 +1,346 @@
+use crate::caveat::{self, Caveat};
+use crate::crypto;
+use crate::serialization::v2::{self, CaveatRefIter, CaveatsRef};
+use crate::serialization::DeserializeOptions;
+use crate::verifier::{binding_error, NO_DISCHARGE};
+use crate::{ByteString, HmacSha256, Macaroon, MacaroonError, MacaroonKey, Result, Verifier};
+
+/// A V2 binary macaroon parsed in place, borrowing its fields from the token bytes.
+///
+/// Parsing validates the whole token up front, like [Macaroon::deserialize_binary], but doesn't
+/// copy the identifier, location or any caveat, which makes it suitable for scanning large
+/// numbers of tokens. Use [MacaroonRef::to_macaroon] to get an owned [Macaroon] when needed.
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, MacaroonRef};
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into())?;
+/// macaroon.add_first_party_caveat("account = 3735928559".into());
+/// let token: Vec<u8> = (&macaroon).into();
+///
+/// let parsed = MacaroonRef::parse(&token)?;
+/// assert_eq!(b"id", parsed.identifier());
+/// assert_eq!(1, parsed.caveats().len());
+/// assert_eq!(macaroon, parsed.to_macaroon());
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MacaroonRef<'a> {
+    location: Option<&'a str>,
+    identifier: &'a [u8],
+    caveats: CaveatsRef<'a>,
+    signature: &'a [u8; 32],
+}
+
+/// A caveat borrowed from a [MacaroonRef]; see [Caveat] for the owned equivalent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaveatRef<'a> {
+    FirstParty {
+        predicate: &'a [u8],
+    },
+    ThirdParty {
+        id: &'a [u8],
+        verifier_id: &'a [u8],
+        location: &'a str,
+    },
+}
+
+impl<'a> MacaroonRef<'a> {
+    pub(crate) fn new(
+        location: Option<&'a str>,
+        identifier: &'a [u8],
+        caveats: CaveatsRef<'a>,
+        signature: &'a [u8; 32],
+    ) -> MacaroonRef<'a> {
+        MacaroonRef {
+            location,
+            identifier,
+            caveats,
+            signature,
+        }
+    }
+
+    /// Parse a V2 binary token (not base64-encoded)
+    pub fn parse(data: &'a [u8]) -> Result<MacaroonRef<'a>> {
+        v2::deserialize_ref(data, &DeserializeOptions::default())
+    }
+
+    /// Parse a V2 binary token (not base64-encoded), with [DeserializeOptions]
+    pub fn parse_with_options(
+        data: &'a [u8],
+        options: &DeserializeOptions,
+    ) -> Result<MacaroonRef<'a>> {
+        v2::deserialize_ref(data, options)
+    }
+
+    pub fn location(&self) -> Option<&'a str> {
+        self.location
+    }
+
+    pub fn identifier(&self) -> &'a [u8] {
+        self.identifier
+    }
+
+    pub fn signature(&self) -> MacaroonKey {
+        self.signature.into()
+    }
+
+    /// Iterate over the caveats, in order. They are decoded lazily, without allocating.
+    pub fn caveats(&self) -> CaveatRefIter<'a> {
+        self.caveats.iter()
+    }
+
+    /// Verify the macaroon and its discharges, like [Verifier::verify], without copying them
+    ///
+    /// Signatures are computed directly over the borrowed fields, and first-party caveats matched
+    /// by an exact satisfier are checked in place, so verifying a macaroon without third-party
+    /// caveats doesn't allocate. Caveats left to general satisfiers, or checked with predicate
+    /// normalization enabled, are copied first, and each third-party caveat allocates its
+    /// decrypted key.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, MacaroonRef, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    /// let token: Vec<u8> = (&macaroon).into();
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_exact("account = 3735928559".into());
+    /// MacaroonRef::parse(&token)
+    ///     .unwrap()
+    ///     .verify(&verifier, &key, &[])
+    ///     .unwrap();
+    /// ```
+    pub fn verify(
+        &self,
+        verifier: &Verifier,
+        key: &MacaroonKey,
+        discharges: &[MacaroonRef<'_>],
+    ) -> Result<()> {
+        if let Some(expected) = verifier.expected_location() {
+            expected.check(self.location)?;
+        }
+        let mut used = vec![false; discharges.len()];
+        self.verify_with_sig(verifier, self.signature, key, discharges, &mut used, true)?;
+        if used.contains(&false) {
+            return Err(MacaroonError::DischargeNotUsed);
+        }
+        Ok(())
+    }
+
+    fn verify_with_sig(
+        &self,
+        verifier: &Verifier,
+        root_sig: &[u8; 32],
+        key: &MacaroonKey,
+        discharges: &[MacaroonRef<'_>],
+        used: &mut [bool],
+        root: bool,
+    ) -> Result<()> {
+        if let Some(revocation) = verifier.revocation_checker() {
+            if revocation.is_revoked(self.identifier, self.signature)? {
+                return Err(MacaroonError::Revoked(self.identifier.into()));
+            }
+        }
+        let mut sig = crypto::hmac(key, self.identifier);
+        let discharge = if root { None } else { Some(self.identifier) };
+        for (i, c) in self.caveats().enumerate() {
+            sig = match c {
+                CaveatRef::FirstParty { predicate } => {
+                    verifier
+                        .check_satisfied_bytes(&(), predicate)
+                        .map_err(|e| e.at_caveat(i, discharge))?;
+                    crypto::hmac(&sig, predicate)
+                }
+                CaveatRef::ThirdParty {
+                    id, verifier_id, ..
+                } => {
+                    let caveat_key = verifier.decryptor().decrypt_key(&sig, verifier_id)?;
+                    let index = (0..discharges.len())
+                        .find(|i| !used[*i] && discharges[*i].identifier == id)
+                        .ok_or_else(|| {
+                            MacaroonError::caveat_not_satisfied(id, NO_DISCHARGE.to_string())
+                                .at_caveat(i, discharge)
+                        })?;
+                    used[index] = true;
+                    discharges[index].verify_with_sig(
+                        verifier,
+                        root_sig,
+                        &caveat_key,
+                        discharges,
+                        used,
+                        false,
+                    )?;
+                    crypto::hmac2(&sig, verifier_id, id)
+                }
+            };
+        }
+        // As in Verifier::verify: the root macaroon's signature is the end of the chain, and
+        // discharges must be bound to it
+        if crypto::constant_time_eq(&root_sig[..], &sig[..]) {
+            return Ok(());
+        }
+        let root_sig = MacaroonKey::from(*root_sig);
+        let bound_sig = verifier.binding().bind(&HmacSha256, &root_sig, &sig);
+        if !crypto::constant_time_eq(&bound_sig[..], &self.signature[..]) {
+            return Err(binding_error(
+                verifier.binding(),
+                &HmacSha256,
+                &root_sig,
+                &sig,
+                &bound_sig,
+                self.signature,
+                self.identifier,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copy every field into an owned [Macaroon]
+    pub fn to_macaroon(&self) -> Macaroon {
+        Macaroon {
+            identifier: ByteString::from(self.identifier),
+            location: self.location.map(String::from),
+            signature: self.signature.into(),
+            caveats: self.caveats().map(|c| c.to_caveat()).collect(),
+            extensions: Default::default(),
+            scheme: Default::default(),
+        }
+    }
+}
+
+impl<'a> CaveatRef<'a> {
+    /// Copy into an owned [Caveat]
+    pub fn to_caveat(&self) -> Caveat {
+        match *self {
+            CaveatRef::FirstParty { predicate } => caveat::new_first_party(predicate.into()),
+            CaveatRef::ThirdParty {
+                id,
+                verifier_id,
+                location,
+            } => caveat::new_third_party(id.into(), verifier_id.into(), location),
+        }
+    }
+}
+
+impl<'a> From<MacaroonRef<'a>> for Macaroon {
+    fn from(macaroon: MacaroonRef<'a>) -> Self {
+        macaroon.to_macaroon()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CaveatRef, MacaroonRef};
+    use crate::{Macaroon, MacaroonError, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_parse_ref() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon =
+            Macaroon::create(Some("http://example.org/".into()), &key, "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_third_party_caveat(
+            "https://auth.mybank.com",
+            &MacaroonKey::generate(b"caveat key"),
+            "caveat".into(),
+        );
+        let token: Vec<u8> = (&macaroon).into();
+
+        let parsed = MacaroonRef::parse(&token).unwrap();
+        assert_eq!(Some("http://example.org/"), parsed.location());
+        assert_eq!(b"keyid", parsed.identifier());
+        assert_eq!(macaroon.signature(), parsed.signature());
+        let caveats: Vec<CaveatRef> = parsed.caveats().collect();
+        assert_eq!(2, caveats.len());
+        assert_eq!(
+            CaveatRef::FirstParty {
+                predicate: b"account = 3735928559"
+            },
+            caveats[0]
+        );
+        match caveats[1] {
+            CaveatRef::ThirdParty { id, location, .. } => {
+                assert_eq!(b"caveat", id);
+                assert_eq!("https://auth.mybank.com", location);
+            }
+            _ => panic!("expected a third-party caveat"),
+        }
+        assert_eq!(macaroon, parsed.to_macaroon());
+    }
+
+    #[test]
+    fn test_parse_ref_errors() {
+        assert!(MacaroonRef::parse(b"").is_err());
+        assert!(MacaroonRef::parse(b"\x01").is_err());
+        let key = MacaroonKey::generate(b"this is the key");
+        let macaroon = Macaroon::create(None, &key, "keyid".into()).unwrap();
+        let token: Vec<u8> = (&macaroon).into();
+        assert!(MacaroonRef::parse(&token[..token.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_verify_ref() {
+        let root_key = MacaroonKey::generate(b"this is the key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_third_party_caveat("https://auth.mybank.com", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        discharge.add_first_party_caveat("user = alice".into());
+        macaroon.bind(&mut discharge);
+        let token: Vec<u8> = (&macaroon).into();
+        let discharge_token: Vec<u8> = (&discharge).into();
+        let parsed = MacaroonRef::parse(&token).unwrap();
+        let discharges = [MacaroonRef::parse(&discharge_token).unwrap()];
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("account = 3735928559".into());
+        verifier.satisfy_general(|predicate| predicate.as_ref() == b"user = alice");
+        parsed.verify(&verifier, &root_key, &discharges).unwrap();
+        verifier
+            .verify(&macaroon, &root_key, vec![discharge.clone()])
+            .unwrap();
+
+        assert!(parsed.verify(&verifier, &caveat_key, &discharges).is_err());
+        let mut tampered = discharge_token.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(
+            parsed.verify(
+                &verifier,
+                &root_key,
+                &[MacaroonRef::parse(&tampered).unwrap()]
+            ),
+            Err(MacaroonError::InvalidSignature)
+        ));
+        assert!(matches!(
+            parsed.verify(&verifier, &root_key, &[]),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+        let twice = [discharges[0], discharges[0]];
+        assert!(matches!(
+            parsed.verify(&verifier, &root_key, &twice),
+            Err(MacaroonError::DischargeNotUsed)
+        ));
+        assert!(matches!(
+            parsed.verify(&Verifier::default(), &root_key, &discharges),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+        let mut unbound = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        unbound.add_first_party_caveat("user = alice".into());
+        let unbound: Vec<u8> = (&unbound).into();
+        assert!(matches!(
+            parsed.verify(
+                &verifier,
+                &root_key,
+                &[MacaroonRef::parse(&unbound).unwrap()]
+            ),
+            Err(MacaroonError::DischargeNotBound(_))
+        ));
+    }
+}
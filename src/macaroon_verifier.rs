@@ -0,0 +1,209 @@
+use crate::crypto::SecretBox;
+use crate::verifier::{self, VerifierOptions, VerifyContext};
+use crate::{
+    FirstPartyCaveatChecker, HmacSha256, Macaroon, MacaroonKey, Result, Verifier, ZeroKeyBinding,
+};
+use std::sync::Arc;
+
+/// A strategy for verifying a macaroon and its discharges
+///
+/// [Verifier] is the standard implementation. Frameworks can accept any `MacaroonVerifier` to
+/// let applications plug in their own, eg one evaluating predicates with a policy engine, which
+/// can still have the signatures and discharge bindings checked by
+/// [verify_with_caveat_checker].
+///
+/// ```rust
+/// use macaroon::{
+///     verify_with_caveat_checker, ByteString, FirstPartyCaveatChecker, Macaroon, MacaroonError,
+///     MacaroonKey, MacaroonVerifier, Result, UnsatisfiedCaveat, VerifierOptions,
+/// };
+///
+/// /// Allows the operations in its list, whatever the other caveats say
+/// struct Operations(Vec<&'static str>);
+///
+/// impl FirstPartyCaveatChecker for Operations {
+///     fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+///         match std::str::from_utf8(predicate.as_ref()) {
+///             Ok(p) if self.0.iter().any(|op| p == format!("op = {}", op)) => Ok(()),
+///             Ok(p) if !p.starts_with("op = ") => Ok(()),
+///             _ => Err(MacaroonError::CaveatNotSatisfied(UnsatisfiedCaveat::new(
+///                 predicate.clone(),
+///                 "operation not allowed".into(),
+///             ))),
+///         }
+///     }
+/// }
+///
+/// impl MacaroonVerifier for Operations {
+///     fn verify_with_options(
+///         &self,
+///         m: &Macaroon,
+///         key: &MacaroonKey,
+///         discharges: Vec<Macaroon>,
+///         options: &VerifierOptions,
+///     ) -> Result<()> {
+///         verify_with_caveat_checker(self, m, key, discharges, options)
+///     }
+/// }
+///
+/// fn authorize(verifier: &dyn MacaroonVerifier, token: &Macaroon, key: &MacaroonKey) -> bool {
+///     verifier.verify(token, key, vec![]).is_ok()
+/// }
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_first_party_caveat("op = read".into());
+/// assert!(authorize(&Operations(vec!["read", "write"]), &macaroon, &key));
+/// assert!(!authorize(&Operations(vec!["write"]), &macaroon, &key));
+/// ```
+pub trait MacaroonVerifier: Send + Sync {
+    /// Verify `m` with `discharges`, within the limits set by `options`
+    fn verify_with_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        options: &VerifierOptions,
+    ) -> Result<()>;
+
+    /// Verify `m` with `discharges`, with the default [VerifierOptions]
+    fn verify(&self, m: &Macaroon, key: &MacaroonKey, discharges: Vec<Macaroon>) -> Result<()> {
+        self.verify_with_options(m, key, discharges, &VerifierOptions::default())
+    }
+}
+
+impl MacaroonVerifier for Verifier {
+    fn verify_with_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        options: &VerifierOptions,
+    ) -> Result<()> {
+        Verifier::verify_with_options(self, m, key, discharges, options)
+    }
+}
+
+impl<V: MacaroonVerifier + ?Sized> MacaroonVerifier for Arc<V> {
+    fn verify_with_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        options: &VerifierOptions,
+    ) -> Result<()> {
+        (**self).verify_with_options(m, key, discharges, options)
+    }
+}
+
+impl<V: MacaroonVerifier + ?Sized> MacaroonVerifier for Box<V> {
+    fn verify_with_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        options: &VerifierOptions,
+    ) -> Result<()> {
+        (**self).verify_with_options(m, key, discharges, options)
+    }
+}
+
+/// Verify `m` and its discharges as [Verifier::verify_with_options] does, deciding whether each
+/// first-party caveat is satisfied with `checker`
+///
+/// Everything else is checked as by a [Verifier] with no extra configuration: the HMAC-SHA-256
+/// signature chains, the [SecretBox] encrypted caveat keys, the [ZeroKeyBinding] of the
+/// discharges, and that every discharge is used. This is the building block for
+/// [MacaroonVerifier]s with their own caveat logic.
+pub fn verify_with_caveat_checker(
+    checker: &dyn FirstPartyCaveatChecker,
+    m: &Macaroon,
+    key: &MacaroonKey,
+    discharges: Vec<Macaroon>,
+    options: &VerifierOptions,
+) -> Result<()> {
+    let ctx = VerifyContext {
+        checker,
+        options,
+        scheme: &HmacSha256,
+        decryptor: &SecretBox,
+        binding: &ZeroKeyBinding,
+        revocation: None,
+        location: None,
+    };
+    verifier::verify_with_checker(&ctx, m, key, discharges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_with_caveat_checker, MacaroonVerifier};
+    use crate::{
+        ByteString, FirstPartyCaveatChecker, Macaroon, MacaroonError, MacaroonKey, Result,
+        Verifier, VerifierOptions,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Satisfies every caveat, counting them
+    #[derive(Default)]
+    struct CountingVerifier(AtomicUsize);
+
+    impl FirstPartyCaveatChecker for CountingVerifier {
+        fn check_first_party_caveat(&self, _predicate: &ByteString) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    impl MacaroonVerifier for CountingVerifier {
+        fn verify_with_options(
+            &self,
+            m: &Macaroon,
+            key: &MacaroonKey,
+            discharges: Vec<Macaroon>,
+            options: &VerifierOptions,
+        ) -> Result<()> {
+            verify_with_caveat_checker(self, m, key, discharges, options)
+        }
+    }
+
+    #[test]
+    fn test_custom_verifier() {
+        let key = MacaroonKey::generate(b"key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        macaroon.add_first_party_caveat("user = alice".into());
+        macaroon.add_third_party_caveat("https://auth/", &caveat_key, "login".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        discharge.add_first_party_caveat("time < 2100-01-01T00:00:00Z".into());
+        macaroon.bind(&mut discharge);
+
+        let counting = Arc::new(CountingVerifier::default());
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact_all(["user = alice", "time < 2100-01-01T00:00:00Z"].map(Into::into));
+        let verifiers: Vec<Box<dyn MacaroonVerifier>> =
+            vec![Box::new(counting.clone()), Box::new(verifier)];
+        for v in verifiers.iter() {
+            v.verify(&macaroon, &key, vec![discharge.clone()]).unwrap();
+        }
+        assert_eq!(2, counting.0.load(Ordering::SeqCst));
+
+        // the signatures and bindings are still checked
+        let unbound = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        let wrong_key = MacaroonKey::generate(b"wrong key");
+        for v in verifiers.iter() {
+            assert!(matches!(
+                v.verify(&macaroon, &wrong_key, vec![discharge.clone()]),
+                Err(MacaroonError::CryptoError(_))
+            ));
+            assert!(matches!(
+                v.verify(&macaroon, &key, vec![unbound.clone()]),
+                Err(MacaroonError::DischargeNotBound(_))
+            ));
+            assert!(matches!(
+                v.verify(&macaroon, &key, vec![]),
+                Err(MacaroonError::CaveatNotSatisfied(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::revocation::signature_fingerprint;
+use crate::{ByteString, Caveat, Macaroon, Result};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// What a [MintStore] keeps about each minted macaroon. The signature itself is never stored,
+/// since together with the identifier it is the bearer token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MintRecord {
+    /// SHA-256 of the signature, to recognise a specific token without being able to use it
+    pub fingerprint: [u8; 32],
+    pub identifier: ByteString,
+    pub location: Option<String>,
+    /// First-party predicates (lossily decoded as UTF-8), and `third-party <location>` for
+    /// third-party caveats
+    pub caveats: Vec<String>,
+    pub minted_at: SystemTime,
+    pub expiry: Option<SystemTime>,
+}
+
+impl MintRecord {
+    pub fn new(macaroon: &Macaroon, expiry: Option<SystemTime>) -> MintRecord {
+        MintRecord {
+            fingerprint: signature_fingerprint(&macaroon.signature),
+            identifier: macaroon.identifier.clone(),
+            location: macaroon.location.clone(),
+            caveats: macaroon
+                .caveats
+                .iter()
+                .map(|c| match c {
+                    Caveat::FirstParty(fp) => {
+                        String::from_utf8_lossy(fp.predicate_ref().as_ref()).into_owned()
+                    }
+                    Caveat::ThirdParty(tp) => format!("third-party {}", tp.location()),
+                })
+                .collect(),
+            minted_at: SystemTime::now(),
+            expiry,
+        }
+    }
+
+    /// Whether the macaroon had a first-party caveat with exactly this predicate at mint time
+    pub fn has_caveat(&self, predicate: &str) -> bool {
+        self.caveats.iter().any(|c| c == predicate)
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        matches!(self.expiry, Some(expiry) if expiry <= now)
+    }
+}
+
+/// Records minted macaroons, so operators can list outstanding tokens and revoke them
+///
+/// A [Bakery](crate::Bakery) configured with
+/// [with_mint_store](crate::Bakery::with_mint_store) records every macaroon it mints.
+/// [InMemoryMintStore] is also a [RevocationChecker](crate::RevocationChecker), so verifiers
+/// can reject the macaroons revoked in it.
+pub trait MintStore: Send + Sync {
+    fn record(&self, record: MintRecord) -> Result<()>;
+
+    /// Mark the macaroon with this identifier as revoked. Returns whether it was known.
+    fn revoke(&self, identifier: &ByteString) -> Result<bool>;
+
+    fn is_revoked(&self, identifier: &ByteString) -> Result<bool>;
+
+    /// Records which are neither revoked nor expired at `now`, and match `filter`
+    fn outstanding(
+        &self,
+        now: SystemTime,
+        filter: &dyn Fn(&MintRecord) -> bool,
+    ) -> Result<Vec<MintRecord>>;
+}
+
+/// A [MintStore] kept in memory, for tests and single-process services
+///
+/// ```rust
+/// use macaroon::{Bakery, CaveatTemplate, InMemoryMintStore, MacaroonKey, MintStore};
+/// use std::sync::Arc;
+/// use std::time::SystemTime;
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let store = Arc::new(InMemoryMintStore::default());
+/// let bakery = Bakery::new(None, MacaroonKey::generate(b"key")).with_mint_store(store.clone());
+/// let token = bakery.mint(&CaveatTemplate::new().first_party("tenant = acme".into()))?;
+///
+/// let acme = store.outstanding(SystemTime::now(), &|r| r.has_caveat("tenant = acme"))?;
+/// assert_eq!(1, acme.len());
+/// store.revoke(&token.identifier())?;
+/// assert!(store.is_revoked(&token.identifier())?);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Default)]
+pub struct InMemoryMintStore {
+    records: Mutex<BTreeMap<ByteString, (MintRecord, bool)>>,
+}
+
+impl InMemoryMintStore {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<ByteString, (MintRecord, bool)>> {
+        // a panic while holding the lock can't leave a map entry half-written
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl MintStore for InMemoryMintStore {
+    fn record(&self, record: MintRecord) -> Result<()> {
+        self.lock()
+            .insert(record.identifier.clone(), (record, false));
+        Ok(())
+    }
+
+    fn revoke(&self, identifier: &ByteString) -> Result<bool> {
+        Ok(match self.lock().get_mut(identifier) {
+            Some((_, revoked)) => {
+                *revoked = true;
+                true
+            }
+            None => false,
+        })
+    }
+
+    fn is_revoked(&self, identifier: &ByteString) -> Result<bool> {
+        Ok(matches!(self.lock().get(identifier), Some((_, true))))
+    }
+
+    fn outstanding(
+        &self,
+        now: SystemTime,
+        filter: &dyn Fn(&MintRecord) -> bool,
+    ) -> Result<Vec<MintRecord>> {
+        Ok(self
+            .lock()
+            .values()
+            .filter(|(record, revoked)| !revoked && !record.is_expired(now) && filter(record))
+            .map(|(record, _)| record.clone())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryMintStore, MintRecord, MintStore};
+    use crate::{Bakery, CaveatTemplate, Macaroon, MacaroonKey};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_in_memory_store() {
+        let store = Arc::new(InMemoryMintStore::default());
+        let key = MacaroonKey::generate(b"this is the key");
+        let bakery = Bakery::new(None, key).with_mint_store(store.clone());
+        let acme = CaveatTemplate::new().first_party("tenant = acme".into());
+        let tokens = bakery.mint_batch(&acme, 2).unwrap();
+        bakery
+            .mint(&CaveatTemplate::new().first_party("tenant = other".into()))
+            .unwrap();
+
+        let now = SystemTime::now();
+        let is_acme = |r: &MintRecord| r.has_caveat("tenant = acme");
+        assert_eq!(2, store.outstanding(now, &is_acme).unwrap().len());
+        assert_eq!(3, store.outstanding(now, &|_| true).unwrap().len());
+
+        assert!(store.revoke(&tokens[0].identifier()).unwrap());
+        assert!(store.is_revoked(&tokens[0].identifier()).unwrap());
+        assert!(!store.is_revoked(&tokens[1].identifier()).unwrap());
+        assert!(!store.revoke(&"unknown".into()).unwrap());
+        let outstanding = store.outstanding(now, &is_acme).unwrap();
+        assert_eq!(1, outstanding.len());
+        assert_eq!(tokens[1].identifier(), outstanding[0].identifier);
+        assert_ne!(*tokens[1].signature(), outstanding[0].fingerprint);
+    }
+
+    #[test]
+    fn test_expired_records() {
+        let store = InMemoryMintStore::default();
+        let key = MacaroonKey::generate(b"this is the key");
+        let macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        let now = SystemTime::now();
+        store
+            .record(MintRecord::new(
+                &macaroon,
+                Some(now + Duration::from_secs(60)),
+            ))
+            .unwrap();
+        assert_eq!(1, store.outstanding(now, &|_| true).unwrap().len());
+        let later = now + Duration::from_secs(61);
+        assert!(store.outstanding(later, &|_| true).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::bakery::FirstPartyCaveatChecker;
+use crate::report::SatisfiedBy;
+use crate::verifier::{self, VerifierOptions, VerifyContext};
+use crate::{
+    ByteString, Caveat, HmacSha256, Macaroon, MacaroonError, MacaroonKey, Result, Verifier,
+};
+use sodiumoxide::randombytes::randombytes;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Prefix of nonce caveats, followed by a random nonce (see [Macaroon::add_nonce_caveat])
+pub const NONCE_PREFIX: &str = "nonce ";
+
+/// Random bytes in generated nonces
+const NONCE_LEN: usize = 16;
+
+/// Remembers the nonces of macaroons already accepted, so single-use macaroons can't be
+/// replayed (see [Verifier::verify_once])
+pub trait NonceStore: Send + Sync {
+    /// Record `nonce` as used, returning `false` if it already was
+    ///
+    /// This has to be atomic: when several requests present the same nonce concurrently, only
+    /// one of them may get `true`.
+    fn insert(&self, nonce: &str) -> Result<bool>;
+}
+
+/// A [NonceStore] kept in memory
+///
+/// With [InMemoryNonceStore::with_ttl], nonces are forgotten after a while so the store doesn't
+/// grow forever. The macaroons should then expire sooner than that (eg with an expiry caveat),
+/// or they become usable again.
+#[derive(Debug, Default)]
+pub struct InMemoryNonceStore {
+    seen: Mutex<HashMap<String, Instant>>,
+    ttl: Option<Duration>,
+}
+
+impl InMemoryNonceStore {
+    pub fn new() -> InMemoryNonceStore {
+        Default::default()
+    }
+
+    /// Forget nonces once they were first seen more than `ttl` ago
+    pub fn with_ttl(mut self, ttl: Duration) -> InMemoryNonceStore {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        // a panic while holding the lock can't leave an entry half-written
+        self.seen.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl NonceStore for InMemoryNonceStore {
+    fn insert(&self, nonce: &str) -> Result<bool> {
+        let mut seen = self.lock();
+        if let Some(ttl) = self.ttl {
+            seen.retain(|_, at| at.elapsed() < ttl);
+        }
+        if seen.contains_key(nonce) {
+            return Ok(false);
+        }
+        seen.insert(nonce.to_string(), Instant::now());
+        Ok(true)
+    }
+}
+
+/// Nonce of a nonce caveat predicate, or `None` if it isn't one
+fn predicate_nonce(predicate: &ByteString) -> Option<&str> {
+    std::str::from_utf8(predicate.as_ref())
+        .ok()?
+        .strip_prefix(NONCE_PREFIX)
+}
+
+/// A [Verifier] which also satisfies nonce caveats, which are checked once the signature is
+struct NonceChecker<'a> {
+    verifier: &'a Verifier,
+}
+
+impl FirstPartyCaveatChecker for NonceChecker<'_> {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        self.find_satisfier(predicate).map(|_| ())
+    }
+
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        match predicate_nonce(predicate) {
+            Some(_) => Ok(SatisfiedBy::Checker),
+            None => self.verifier.find_satisfier(&(), predicate),
+        }
+    }
+}
+
+impl Macaroon {
+    /// Add a caveat with a random nonce, `nonce <nonce>`, so the macaroon can only be used once
+    /// with [Verifier::verify_once]. Returns the nonce.
+    pub fn add_nonce_caveat(&mut self) -> String {
+        let nonce = base64::encode_config(randombytes(NONCE_LEN), base64::URL_SAFE_NO_PAD);
+        self.add_first_party_caveat(format!("{}{}", NONCE_PREFIX, nonce).into());
+        nonce
+    }
+}
+
+impl Verifier {
+    /// Like [Verifier::verify], also rejecting macaroons whose nonce caveats (see
+    /// [Macaroon::add_nonce_caveat]) were already seen by `store`
+    ///
+    /// Nonces, including those in discharges, are only recorded once the macaroon has otherwise
+    /// been verified, so forged or unauthorized tokens can't use them up. Macaroons without a
+    /// nonce caveat are verified as usual, and can be replayed.
+    ///
+    /// ```rust
+    /// use macaroon::{InMemoryNonceStore, Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_nonce_caveat();
+    ///
+    /// let store = InMemoryNonceStore::new();
+    /// let verifier = Verifier::default();
+    /// assert!(verifier.verify_once(&macaroon, &key, vec![], &store).is_ok());
+    /// assert!(verifier.verify_once(&macaroon, &key, vec![], &store).is_err());
+    /// ```
+    pub fn verify_once(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        store: &dyn NonceStore,
+    ) -> Result<()> {
+        let nonces: Vec<ByteString> = std::iter::once(m)
+            .chain(discharges.iter())
+            .flat_map(|m| m.caveats.iter())
+            .filter_map(|c| match c {
+                Caveat::FirstParty(fp) => {
+                    predicate_nonce(fp.predicate_ref()).map(|_| fp.predicate())
+                }
+                Caveat::ThirdParty(_) => None,
+            })
+            .collect();
+        let ctx = VerifyContext {
+            checker: &NonceChecker { verifier: self },
+            options: &VerifierOptions::default(),
+            scheme: &HmacSha256,
+            decryptor: self.decryptor(),
+            binding: self.binding(),
+            revocation: self.revocation_checker(),
+            location: self.expected_location(),
+        };
+        verifier::verify_with_checker(&ctx, m, key, discharges)?;
+        for predicate in nonces.iter() {
+            if !store.insert(predicate_nonce(predicate).unwrap_or_default())? {
+                return Err(MacaroonError::caveat_not_satisfied(
+                    predicate.as_ref(),
+                    format!(
+                        "first party caveat not satisfied: {}: nonce already used",
+                        String::from_utf8_lossy(predicate.as_ref())
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryNonceStore, NonceStore, NONCE_PREFIX};
+    use crate::{Caveat, Macaroon, MacaroonError, MacaroonKey, Verifier};
+    use std::time::Duration;
+
+    #[test]
+    fn test_verify_once() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        let nonce = macaroon.add_nonce_caveat();
+        assert_eq!(22, nonce.len());
+        assert_ne!(nonce, macaroon.clone().add_nonce_caveat());
+        match &macaroon.caveats()[0] {
+            Caveat::FirstParty(fp) => {
+                assert_eq!(
+                    format!("{}{}", NONCE_PREFIX, nonce).as_bytes(),
+                    fp.predicate().as_ref()
+                )
+            }
+            Caveat::ThirdParty(_) => panic!("expected a nonce caveat"),
+        }
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        let discharge_nonce = discharge.add_nonce_caveat();
+        macaroon.bind(&mut discharge);
+
+        let store = InMemoryNonceStore::new();
+        let mut verifier = Verifier::default();
+        // Unsatisfied caveats and bad keys don't use up nonces
+        assert!(verifier
+            .verify_once(&macaroon, &root_key, vec![discharge.clone()], &store)
+            .is_err());
+        assert!(verifier
+            .verify_once(&macaroon, &caveat_key, vec![discharge.clone()], &store)
+            .is_err());
+        assert!(store.is_empty());
+
+        verifier.satisfy_exact("account = 3735928559".into());
+        verifier
+            .verify_once(&macaroon, &root_key, vec![discharge.clone()], &store)
+            .unwrap();
+        assert_eq!(2, store.len());
+        assert!(matches!(
+            verifier.verify_once(&macaroon, &root_key, vec![discharge.clone()], &store),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+        assert!(!store.insert(&discharge_nonce).unwrap());
+        // Plain verification doesn't accept nonce caveats
+        assert!(verifier
+            .verify(&macaroon, &root_key, vec![discharge])
+            .is_err());
+    }
+
+    #[test]
+    fn test_nonce_ttl() {
+        let store = InMemoryNonceStore::new().with_ttl(Duration::from_millis(20));
+        assert!(store.insert("a").unwrap());
+        assert!(!store.insert("a").unwrap());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(store.insert("b").unwrap());
+        assert_eq!(1, store.len());
+        assert!(store.insert("a").unwrap());
+    }
+}
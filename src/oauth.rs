@@ -0,0 +1,123 @@
+use crate::{ByteString, Caveat, Macaroon, Verifier};
+use std::collections::BTreeSet;
+
+/// Prefix of scope caveats, followed by a space-delimited OAuth2 scope (RFC 6749, section 3.3)
+pub const SCOPE_PREFIX: &str = "scope ";
+
+/// Split an OAuth2 scope string into its scope tokens
+pub fn parse_scope(scope: &str) -> BTreeSet<String> {
+    scope.split_whitespace().map(String::from).collect()
+}
+
+/// The scope caveat predicate, `scope <token> <token> ...`, for an OAuth2 scope string. Tokens
+/// are de-duplicated and sorted, so equivalent scopes give identical predicates.
+pub fn scope_predicate(scope: &str) -> ByteString {
+    let tokens: Vec<String> = parse_scope(scope).into_iter().collect();
+    format!("{}{}", SCOPE_PREFIX, tokens.join(" ")).into()
+}
+
+/// Scope tokens of a scope caveat predicate, or `None` if it isn't one
+fn predicate_scope(predicate: &ByteString) -> Option<BTreeSet<String>> {
+    let predicate = std::str::from_utf8(predicate.as_ref()).ok()?;
+    predicate.strip_prefix(SCOPE_PREFIX).map(parse_scope)
+}
+
+impl Macaroon {
+    /// Restrict the macaroon to an OAuth2 scope (eg `"read:repo write:issues"`)
+    ///
+    /// Each scope caveat can only narrow what the macaroon grants: the effective scope is the
+    /// intersection of all of them (see [Macaroon::effective_scope]).
+    pub fn add_scope_caveat(&mut self, scope: &str) {
+        self.add_first_party_caveat(scope_predicate(scope));
+    }
+
+    /// The intersection of all the macaroon's scope caveats, or `None` if it has none (and so
+    /// isn't scope-restricted)
+    ///
+    /// This only inspects the caveats; it doesn't verify the macaroon.
+    pub fn effective_scope(&self) -> Option<BTreeSet<String>> {
+        self.caveats
+            .iter()
+            .filter_map(|c| match c {
+                Caveat::FirstParty(fp) => predicate_scope(fp.predicate_ref()),
+                Caveat::ThirdParty(_) => None,
+            })
+            .reduce(|acc, scope| acc.intersection(&scope).cloned().collect())
+    }
+}
+
+impl<C> Verifier<C> {
+    /// Satisfy scope caveats which grant every token of the `required` OAuth2 scope
+    ///
+    /// Since every scope caveat has to be satisfied, a request is only allowed if the scope it
+    /// requires is within the intersection of all the macaroon's scope caveats.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_scope_caveat("repo:read repo:write issues:read");
+    /// macaroon.add_scope_caveat("repo:read issues:read");
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_scope("repo:read");
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_ok());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_scope("repo:write");
+    /// assert!(verifier.verify(&macaroon, &key, Vec::new()).is_err());
+    /// ```
+    pub fn satisfy_scope(&mut self, required: &str) {
+        let required = parse_scope(required);
+        self.satisfy_general(move |predicate| match predicate_scope(predicate) {
+            Some(granted) => required.is_subset(&granted),
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_scope, scope_predicate};
+    use crate::{ByteString, Macaroon, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_scope_predicate() {
+        assert_eq!(
+            ByteString::from("scope a b c"),
+            scope_predicate("  c a\tb a ")
+        );
+        assert_eq!(3, parse_scope("a b c").len());
+    }
+
+    #[test]
+    fn test_effective_scope() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        assert_eq!(None, macaroon.effective_scope());
+        macaroon.add_scope_caveat("read write admin");
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_scope_caveat("read write delete");
+        assert_eq!(Some(parse_scope("read write")), macaroon.effective_scope());
+        macaroon.add_scope_caveat("admin");
+        assert_eq!(Some(parse_scope("")), macaroon.effective_scope());
+    }
+
+    #[test]
+    fn test_satisfy_scope() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_scope_caveat("read write");
+        macaroon.add_scope_caveat("read");
+        let check = |required: &str| {
+            let mut verifier = Verifier::default();
+            verifier.satisfy_scope(required);
+            verifier.verify(&macaroon, &key, Default::default()).is_ok()
+        };
+        assert!(check("read"));
+        assert!(check(""));
+        assert!(!check("write"));
+        assert!(!check("read write"));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::checker::{Checker, Namespace, NamespacedCaveat};
+use crate::identifier::{IdentifierGenerator, UuidV4Generator};
+use crate::root_key::RootKeyStore;
+use crate::verifier::{self, VerifierOptions, VerifyContext};
+use crate::{HmacSha256, Macaroon, MacaroonError, Result, SecretBox, ZeroKeyBinding};
+use std::fmt;
+use std::sync::Arc;
+
+/// Mints macaroons with namespaced first-party caveats under rotating root keys, and verifies
+/// them with a [Checker], in the style of go-macaroon-bakery
+///
+/// Macaroons are minted with the latest key from a [RootKeyStore], so their identifiers are the
+/// root key ID, `/`, and a freshly generated identifier.
+///
+/// ```rust
+/// use macaroon::{Checker, InMemoryRootKeyStore, Namespace, NamespacedCaveat, Oven};
+/// use std::sync::Arc;
+///
+/// let mut namespace = Namespace::new();
+/// namespace.register("https://example.com/fleet", "fleet").unwrap();
+/// let oven = Oven::new(
+///     Some("https://fleet.example/".into()),
+///     namespace.clone(),
+///     Arc::new(InMemoryRootKeyStore::new()),
+/// );
+/// let macaroon = oven
+///     .mint(&[NamespacedCaveat::new("https://example.com/fleet", "region west")])
+///     .unwrap();
+///
+/// let mut checker = Checker::new(namespace);
+/// checker
+///     .register("https://example.com/fleet", "region", |arg| {
+///         if arg == "west" {
+///             Ok(())
+///         } else {
+///             Err(format!("wrong region {}", arg))
+///         }
+///     })
+///     .unwrap();
+/// oven.verify(&macaroon, vec![], &checker).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Oven {
+    location: Option<String>,
+    namespace: Namespace,
+    root_keys: Arc<dyn RootKeyStore>,
+    identifiers: Arc<dyn IdentifierGenerator>,
+}
+
+impl fmt::Debug for Oven {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Oven")
+            .field("location", &self.location)
+            .field("namespace", &self.namespace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Oven {
+    /// Create an oven, generating identifiers with [UuidV4Generator] by default
+    pub fn new(
+        location: Option<String>,
+        namespace: Namespace,
+        root_keys: Arc<dyn RootKeyStore>,
+    ) -> Oven {
+        Oven {
+            location,
+            namespace,
+            root_keys,
+            identifiers: Arc::new(UuidV4Generator),
+        }
+    }
+
+    /// Use `generator` for the part of minted macaroons' identifiers after the root key ID
+    pub fn with_identifier_generator<G>(mut self, generator: G) -> Oven
+    where
+        G: IdentifierGenerator + 'static,
+    {
+        self.identifiers = Arc::new(generator);
+        self
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// Mint a macaroon with the latest root key and the given caveats
+    ///
+    /// # Errors
+    ///
+    /// Fails if a caveat's namespace isn't registered, or the root key store fails.
+    pub fn mint(&self, caveats: &[NamespacedCaveat]) -> Result<Macaroon> {
+        let conditions = caveats
+            .iter()
+            .map(|c| {
+                self.namespace.resolve(c).ok_or_else(|| {
+                    MacaroonError::DeserializationError(format!(
+                        "caveat namespace {:?} not registered",
+                        c.namespace
+                    ))
+                })
+            })
+            .collect::<Result<Vec<String>>>()?;
+        let mut macaroon = Macaroon::create_with_root_key_store(
+            self.location.clone(),
+            self.root_keys.as_ref(),
+            self.identifiers.generate(),
+        )?;
+        for condition in conditions {
+            macaroon.add_first_party_caveat(condition.into());
+        }
+        Ok(macaroon)
+    }
+
+    /// Verify `macaroon` and its discharges with the root key it was minted with, checking
+    /// first-party caveats with `checker`
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::InvalidSignature] if the root key can't be found, as well as the
+    /// errors from [Verifier::verify](crate::Verifier::verify).
+    pub fn verify(
+        &self,
+        macaroon: &Macaroon,
+        discharges: Vec<Macaroon>,
+        checker: &Checker,
+    ) -> Result<()> {
+        let root_key = self
+            .root_keys
+            .resolve(macaroon)
+            .ok_or(MacaroonError::InvalidSignature)?;
+        let ctx = VerifyContext {
+            checker,
+            options: &VerifierOptions::default(),
+            scheme: &HmacSha256,
+            decryptor: &SecretBox,
+            binding: &ZeroKeyBinding,
+            revocation: None,
+            location: None,
+        };
+        verifier::verify_with_checker(&ctx, macaroon, &root_key, discharges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Oven;
+    use crate::{
+        Checker, InMemoryRootKeyStore, MacaroonError, Namespace, NamespacedCaveat, STD_NAMESPACE,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mint_and_verify() {
+        let store = Arc::new(InMemoryRootKeyStore::new());
+        let oven = Oven::new(None, Namespace::new(), store.clone());
+        let mut checker = Checker::new(Namespace::new());
+        checker
+            .register(STD_NAMESPACE, "allow", |_| Ok(()))
+            .unwrap();
+
+        let old = oven.mint(&[NamespacedCaveat::std("allow")]).unwrap();
+        store.rotate();
+        let new = oven.mint(&[NamespacedCaveat::std("allow")]).unwrap();
+        assert_ne!(old.root_key_id(), new.root_key_id());
+        oven.verify(&old, vec![], &checker).unwrap();
+        oven.verify(&new, vec![], &checker).unwrap();
+
+        let denied = oven.mint(&[NamespacedCaveat::std("deny")]).unwrap();
+        assert!(matches!(
+            oven.verify(&denied, vec![], &checker),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+        assert!(oven
+            .mint(&[NamespacedCaveat::new("https://example.com/", "allow")])
+            .is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Verification of independent discharge subtrees on rayon's thread pool
+//!
+//! Verifying a macaroon with the serial algorithm in [verifier](crate::verifier) walks the
+//! macaroon and its discharges depth-first, taking each discharge from the pool as its
+//! third-party caveat is reached. Here the walk is split in two: a cheap serial pass matches
+//! discharges to caveats in the same order, then the signature chains and first-party caveats of
+//! the resulting tree are checked in parallel. When several things are wrong, the error reported
+//! is the one the serial walk would have hit first, so the result doesn't depend on scheduling.
+
+use crate::verifier::{binding_error, VerifyContext, NO_DISCHARGE};
+use crate::{crypto, Caveat, Macaroon, MacaroonError, MacaroonKey, Result};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+/// A macaroon or discharge, with the discharges matched to its third-party caveats
+struct Node<'m> {
+    macaroon: &'m Macaroon,
+    depth: usize,
+    /// One per third-party caveat, in order
+    slots: Vec<Slot<'m>>,
+}
+
+enum Slot<'m> {
+    Discharge(Node<'m>),
+    /// Verification fails at this caveat, before or after decrypting its caveat key
+    Failed {
+        error: MacaroonError,
+        before_decrypt: bool,
+    },
+}
+
+/// Matches discharges to third-party caveats in the order the serial walk takes them
+struct Planner<'m, 'c> {
+    ctx: &'c VerifyContext<'c>,
+    discharges: &'m [Macaroon],
+    /// Indexes of the discharges not matched yet, by identifier
+    unused: HashMap<&'m [u8], usize>,
+    third_party_caveats: usize,
+}
+
+impl<'m> Planner<'m, '_> {
+    fn plan(&mut self, macaroon: &'m Macaroon, depth: usize) -> Node<'m> {
+        let discharge = (depth > 0).then(|| macaroon.identifier.as_ref());
+        let mut slots = Vec::new();
+        for (i, c) in macaroon.caveats.iter().enumerate() {
+            let tp = match c {
+                Caveat::ThirdParty(tp) => tp,
+                Caveat::FirstParty(_) => continue,
+            };
+            self.third_party_caveats += 1;
+            if let Some(max) = self.ctx.options.max_third_party_caveats() {
+                if self.third_party_caveats > max {
+                    slots.push(Slot::Failed {
+                        error: MacaroonError::LimitExceeded(format!(
+                            "more than {} third-party caveats",
+                            max
+                        )),
+                        before_decrypt: true,
+                    });
+                    continue;
+                }
+            }
+            let index = match self.unused.remove(tp.id_ref().as_ref()) {
+                Some(index) => index,
+                None => {
+                    slots.push(Slot::Failed {
+                        error: MacaroonError::caveat_not_satisfied(
+                            tp.id_ref().as_ref(),
+                            NO_DISCHARGE.to_string(),
+                        )
+                        .at_caveat(i, discharge),
+                        before_decrypt: false,
+                    });
+                    continue;
+                }
+            };
+            if let Some(max_depth) = self.ctx.options.max_depth() {
+                if depth + 1 > max_depth {
+                    slots.push(Slot::Failed {
+                        error: MacaroonError::LimitExceeded(format!(
+                            "discharge macaroons nested deeper than {}",
+                            max_depth
+                        )),
+                        before_decrypt: false,
+                    });
+                    continue;
+                }
+            }
+            slots.push(Slot::Discharge(
+                self.plan(&self.discharges[index], depth + 1),
+            ));
+        }
+        Node {
+            macaroon,
+            depth,
+            slots,
+        }
+    }
+}
+
+/// Verify `m` with `discharges` like [verify_with_report](crate::verifier::verify_with_report)
+/// without a report, checking sibling discharges in parallel
+pub(crate) fn verify(
+    ctx: &VerifyContext,
+    m: &Macaroon,
+    key: &MacaroonKey,
+    discharges: &[Macaroon],
+) -> Result<()> {
+    if let Some(expected) = ctx.location {
+        expected.check(m.location.as_deref())?;
+    }
+    let mut planner = Planner {
+        ctx,
+        discharges,
+        unused: discharges
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (d.identifier.as_ref(), i))
+            .collect(),
+        third_party_caveats: 0,
+    };
+    let root = planner.plan(m, 0);
+    verify_node(ctx, &m.signature, root, key)?;
+    if !planner.unused.is_empty() && !ctx.options.allow_unused_discharges() {
+        return Err(MacaroonError::DischargeNotUsed);
+    }
+    Ok(())
+}
+
+/// Verify `node` and its discharges, returning the first error in depth-first order
+fn verify_node(
+    ctx: &VerifyContext,
+    root_sig: &MacaroonKey,
+    node: Node,
+    key: &MacaroonKey,
+) -> Result<()> {
+    let m = node.macaroon;
+    let discharge = (node.depth > 0).then(|| m.identifier.as_ref());
+    if let Some(revocation) = ctx.revocation {
+        if revocation.is_revoked(m.identifier.as_ref(), &m.signature[..])? {
+            return Err(MacaroonError::Revoked(m.identifier.clone()));
+        }
+    }
+    #[cfg(feature = "time")]
+    if node.depth > 0 {
+        ctx.options.check_discharge_lifetime(m)?;
+    }
+
+    // Walk the caveats, stopping at the first error of this macaroon's own, and collecting the
+    // discharges to verify before it
+    let mut slots = node.slots.into_iter();
+    let mut children = Vec::new();
+    let mut error = None;
+    let mut satisfied = HashSet::new();
+    let mut sig = ctx.scheme.hmac(key, m.identifier.as_ref());
+    for (i, c) in m.caveats.iter().enumerate() {
+        if let Some(deadline) = ctx.options.deadline() {
+            if Instant::now() > deadline {
+                error = Some(MacaroonError::LimitExceeded(
+                    "verification deadline passed".to_string(),
+                ));
+                break;
+            }
+        }
+        match c {
+            Caveat::ThirdParty(tp) => match slots.next().expect("a slot per third-party caveat") {
+                Slot::Failed {
+                    error: e,
+                    before_decrypt: true,
+                } => {
+                    error = Some(e);
+                    break;
+                }
+                slot => {
+                    let caveat_key = match ctx
+                        .decryptor
+                        .decrypt_key(&sig, tp.verifier_id_ref().as_ref())
+                    {
+                        Ok(caveat_key) => caveat_key,
+                        Err(e) => {
+                            error = Some(e);
+                            break;
+                        }
+                    };
+                    match slot {
+                        Slot::Discharge(child) => children.push((child, caveat_key)),
+                        Slot::Failed { error: e, .. } => {
+                            error = Some(e);
+                            break;
+                        }
+                    }
+                }
+            },
+            Caveat::FirstParty(fp) => {
+                let predicate = fp.predicate_ref();
+                if !satisfied.contains(predicate) {
+                    if let Err(e) = ctx.checker.check_first_party_caveat(predicate) {
+                        error = Some(e.at_caveat(i, discharge));
+                        break;
+                    }
+                    satisfied.insert(predicate);
+                }
+            }
+        }
+        sig = c.sign_with(ctx.scheme, &sig);
+    }
+    if error.is_none() && !crypto::constant_time_eq(&root_sig[..], &sig[..]) {
+        let bound_sig = ctx.binding.bind(ctx.scheme, root_sig, &sig);
+        if !crypto::constant_time_eq(&bound_sig[..], &m.signature[..]) {
+            error = Some(binding_error(
+                ctx.binding,
+                ctx.scheme,
+                root_sig,
+                &sig,
+                &bound_sig,
+                &m.signature[..],
+                m.identifier.as_ref(),
+            ));
+        }
+    }
+
+    // The discharges come before any error of this macaroon's own in depth-first order
+    let results: Vec<Result<()>> = children
+        .into_par_iter()
+        .map(|(child, caveat_key)| verify_node(ctx, root_sig, child, &caveat_key))
+        .collect();
+    for result in results {
+        result?;
+    }
+    error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Macaroon, MacaroonKey, Verifier, VerifierOptions, VerifierOptionsBuilder};
+
+    /// A macaroon with `width` third-party caveats, each of whose discharges has a first-party
+    /// caveat and `width` third-party caveats of its own
+    fn tree(width: usize) -> (Macaroon, MacaroonKey, Vec<Macaroon>) {
+        let root_key = MacaroonKey::generate(b"root");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        let mut discharges = Vec::new();
+        for i in 0..width {
+            let id = format!("{}", i);
+            let key = MacaroonKey::generate(id.as_bytes());
+            macaroon.add_third_party_caveat("http://auth/", &key, id.as_str().into());
+            let mut discharge = Macaroon::create(None, &key, id.as_str().into()).unwrap();
+            discharge.add_first_party_caveat(format!("ok {}", id).into());
+            for j in 0..width {
+                let id = format!("{}/{}", i, j);
+                let key = MacaroonKey::generate(id.as_bytes());
+                discharge.add_third_party_caveat("http://auth/", &key, id.as_str().into());
+                let mut nested = Macaroon::create(None, &key, id.as_str().into()).unwrap();
+                nested.add_first_party_caveat(format!("ok {}", id).into());
+                discharges.push(nested);
+            }
+            discharges.push(discharge);
+        }
+        for d in discharges.iter_mut() {
+            macaroon.bind(d);
+        }
+        (macaroon, root_key, discharges)
+    }
+
+    /// Verify serially and in parallel, checking both give the same result
+    fn verify(
+        verifier: &Verifier,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: &[Macaroon],
+        options: VerifierOptionsBuilder,
+    ) -> String {
+        let serial = options.clone().parallel(false).build();
+        let serial = verifier.verify_with_options(m, key, discharges.to_vec(), &serial);
+        let parallel = options.parallel(true).build();
+        let parallel = verifier.verify_with_options(m, key, discharges.to_vec(), &parallel);
+        let (serial, parallel) = (format!("{:?}", serial), format!("{:?}", parallel));
+        assert_eq!(serial, parallel);
+        parallel
+    }
+
+    #[test]
+    fn test_same_as_serial() {
+        let (macaroon, key, discharges) = tree(4);
+        let mut verifier = Verifier::default();
+        verifier.satisfy_general(|p| p.as_ref().starts_with(b"ok ") && p.as_ref() != b"ok 2/1");
+        let options = VerifierOptions::builder();
+
+        // the first error in depth-first order is returned, however the work is scheduled
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            let result =
+                pool.install(|| verify(&verifier, &macaroon, &key, &discharges, options.clone()));
+            assert!(result.contains("ok 2/1"), "{}", result);
+        }
+        let mut verifier = Verifier::default();
+        verifier.satisfy_general(|p| p.as_ref().starts_with(b"ok "));
+        assert_eq!(
+            "Ok(())",
+            verify(&verifier, &macaroon, &key, &discharges, options.clone())
+        );
+
+        // missing, unused and unbound discharges
+        let mut missing = discharges.clone();
+        missing.remove(7);
+        verify(&verifier, &macaroon, &key, &missing, options.clone());
+        let mut unused = discharges.clone();
+        unused.push(Macaroon::create(None, &key, "unused".into()).unwrap());
+        verify(&verifier, &macaroon, &key, &unused, options.clone());
+        let mut unbound = discharges.clone();
+        unbound[3] = Macaroon::create(None, &key, unbound[3].identifier()).unwrap();
+        verify(&verifier, &macaroon, &key, &unbound, options.clone());
+        verify(
+            &verifier,
+            &macaroon,
+            &MacaroonKey::generate(b"wrong"),
+            &discharges,
+            options,
+        );
+
+        // limits
+        for options in [
+            VerifierOptions::builder().max_depth(1),
+            VerifierOptions::builder().max_third_party_caveats(9),
+        ] {
+            let result = verify(&verifier, &macaroon, &key, &discharges, options);
+            assert!(result.contains("LimitExceeded"), "{}", result);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{ByteString, MacaroonError, Result, Verifier};
+use serde::{Deserialize, Serialize};
+
+const POLICY_VERSION: u32 = 1;
+
+/// Declarative snapshot of a [Verifier]'s configuration, for review, versioning and shipping
+/// between services.
+///
+/// Only the declarative parts are captured: the exact predicates and whether predicate
+/// normalization is enabled. General satisfiers are functions, so they can't be exported and must
+/// be registered again after importing. Unknown fields are rejected when importing, so a policy
+/// written by a newer version is never silently weakened by an older one.
+///
+/// **The standard checkers aren't covered either.** [Verifier::satisfy_std],
+/// [Verifier::satisfy_operations] and the other built-in satisfiers are registered as general
+/// satisfiers, and their context is usually per request, so a policy records none of them, and
+/// nor does it record checkers added with [Checker::register_std](crate::Checker::register_std).
+/// Register them again after importing, as below.
+///
+/// ```rust
+/// use macaroon::{StdContext, Verifier, VerifierPolicy};
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let mut verifier = Verifier::default();
+/// verifier.satisfy_exact("account = 3735928559".into());
+/// verifier.satisfy_std(StdContext::new());
+///
+/// let document = verifier.export_policy().to_json()?;
+/// let mut imported = Verifier::from_policy(&VerifierPolicy::from_json(&document)?)?;
+/// assert_eq!(verifier.export_policy(), imported.export_policy());
+/// // not part of the policy
+/// imported.satisfy_std(StdContext::new());
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifierPolicy {
+    version: u32,
+    #[serde(default)]
+    normalize: bool,
+    #[serde(default)]
+    exact: Vec<Predicate>,
+}
+
+/// Predicates are kept readable in the document when they're valid UTF-8
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Predicate {
+    Text(String),
+    Binary { base64: ByteString },
+}
+
+impl From<&ByteString> for Predicate {
+    fn from(b: &ByteString) -> Self {
+        match std::str::from_utf8(b.as_ref()) {
+            Ok(s) => Predicate::Text(s.to_string()),
+            Err(_) => Predicate::Binary { base64: b.clone() },
+        }
+    }
+}
+
+impl From<&Predicate> for ByteString {
+    fn from(p: &Predicate) -> Self {
+        match p {
+            Predicate::Text(s) => s.as_str().into(),
+            Predicate::Binary { base64 } => base64.clone(),
+        }
+    }
+}
+
+impl VerifierPolicy {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(document: &str) -> Result<VerifierPolicy> {
+        Ok(serde_json::from_str(document)?)
+    }
+}
+
+impl Verifier {
+    /// Export the declarative configuration (see [VerifierPolicy])
+    pub fn export_policy(&self) -> VerifierPolicy {
+        VerifierPolicy {
+            version: POLICY_VERSION,
+            normalize: self.normalize,
+            exact: self.exact.iter().map(Predicate::from).collect(),
+        }
+    }
+
+    /// Build a verifier from an exported policy. General satisfiers, including the standard
+    /// checkers, need to be added afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::DeserializationError] if the policy is from an unsupported version.
+    pub fn from_policy(policy: &VerifierPolicy) -> Result<Verifier> {
+        if policy.version != POLICY_VERSION {
+            return Err(MacaroonError::DeserializationError(format!(
+                "unsupported verifier policy version {}",
+                policy.version
+            )));
+        }
+        let mut verifier = Verifier::default();
+        if policy.normalize {
+            verifier.enable_predicate_normalization();
+        }
+        verifier.satisfy_exact_all(policy.exact.iter().map(ByteString::from));
+        Ok(verifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VerifierPolicy;
+    use crate::{Macaroon, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_policy_roundtrip() {
+        let mut verifier = Verifier::default();
+        verifier.enable_predicate_normalization();
+        verifier.satisfy_exact("account=3735928559".into());
+        verifier.satisfy_exact(vec![0xff, 0x00, 0x01].into());
+
+        let document = verifier.export_policy().to_json().unwrap();
+        assert!(document.contains("\"account = 3735928559\""));
+        let imported =
+            Verifier::from_policy(&VerifierPolicy::from_json(&document).unwrap()).unwrap();
+        assert_eq!(verifier.export_policy(), imported.export_policy());
+
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_first_party_caveat("account =3735928559".into());
+        macaroon.add_first_party_caveat(vec![0xff, 0x00, 0x01].into());
+        imported
+            .verify(&macaroon, &key, Default::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_policy_rejects_unknown() {
+        assert!(VerifierPolicy::from_json(r#"{"version": 1, "required": ["x"]}"#).is_err());
+        let policy = VerifierPolicy::from_json(r#"{"version": 2}"#).unwrap();
+        assert!(Verifier::from_policy(&policy).is_err());
+    }
+}
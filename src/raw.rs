@@ -0,0 +1,207 @@
+use crate::serialization::v1::V1Packets;
+use crate::serialization::{v2, Format};
+use crate::{MacaroonError, Result};
+
+/// One field of a binary token, as laid out in its encoding, with its byte offset in the token
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawField<'r> {
+    /// A V1 packet, such as `identifier` or `cid`, and its value
+    Packet {
+        offset: usize,
+        key: &'r str,
+        value: &'r [u8],
+    },
+    /// The version byte at the start of a V2 token
+    Version { offset: usize, version: u8 },
+    /// A V2 field: its tag (eg 2 for an identifier) and value
+    Field {
+        offset: usize,
+        tag: u8,
+        value: &'r [u8],
+    },
+    /// A V2 end of section marker
+    Eos { offset: usize },
+}
+
+impl<'r> RawField<'r> {
+    pub fn offset(&self) -> usize {
+        match *self {
+            RawField::Packet { offset, .. }
+            | RawField::Version { offset, .. }
+            | RawField::Field { offset, .. }
+            | RawField::Eos { offset } => offset,
+        }
+    }
+}
+
+/// The fields of a binary (not base64-encoded) V1 or V2 token, without building a [Macaroon]
+///
+/// Only the framing of the fields is checked, not their order, tags or keys, so this can show
+/// the structure of malformed tokens that [Macaroon::deserialize_binary] rejects, for debugging
+/// tools, fuzzers and compatibility tests. Reading stops at the first framing error, which is
+/// kept alongside the fields read before it.
+///
+/// ```rust
+/// use macaroon::{Format, Macaroon, MacaroonKey, RawField, RawToken};
+///
+/// let macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+/// let token = base64::decode_config(macaroon.serialize(Format::V2).unwrap(), base64::URL_SAFE).unwrap();
+///
+/// let raw = RawToken::parse(&token[..token.len() - 1]).unwrap();
+/// assert_eq!(Format::V2, raw.format());
+/// assert_eq!(
+///     RawField::Field { offset: 1, tag: 2, value: b"keyid" },
+///     raw.fields()[1]
+/// );
+/// assert!(raw.error().is_some());
+/// ```
+///
+/// [Macaroon]: crate::Macaroon
+/// [Macaroon::deserialize_binary]: crate::Macaroon::deserialize_binary
+#[derive(Debug)]
+pub struct RawToken<'r> {
+    format: Format,
+    fields: Vec<RawField<'r>>,
+    error: Option<MacaroonError>,
+}
+
+impl<'r> RawToken<'r> {
+    /// Read the fields of `data`, inferring its [Format] from the first byte
+    ///
+    /// # Errors
+    ///
+    /// Fails only if `data` is empty or isn't a binary V1 or V2 token. Errors reading the fields
+    /// are returned by [RawToken::error].
+    pub fn parse(data: &'r [u8]) -> Result<RawToken<'r>> {
+        let format = match data.first() {
+            Some(2) => Format::V2,
+            Some(b'a'..=b'f') | Some(b'A'..=b'Z') | Some(b'0'..=b'9') => Format::V1,
+            _ => {
+                return Err(MacaroonError::DeserializationError(
+                    "unknown macaroon serialization format".to_string(),
+                ))
+            }
+        };
+        let mut fields = Vec::new();
+        let result = match format {
+            Format::V2 => v2::raw_fields(data, &mut fields),
+            _ => v1_fields(data, &mut fields),
+        };
+        Ok(RawToken {
+            format,
+            fields,
+            error: result.err(),
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The fields read, in order, up to any error
+    pub fn fields(&self) -> &[RawField<'r>] {
+        &self.fields
+    }
+
+    /// Why reading stopped before the end of the token, if it did
+    pub fn error(&self) -> Option<&MacaroonError> {
+        self.error.as_ref()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+fn v1_fields<'r>(data: &'r [u8], fields: &mut Vec<RawField<'r>>) -> Result<()> {
+    let mut packets = V1Packets::new(data);
+    loop {
+        let offset = data.len() - packets.remaining().len();
+        match packets.next() {
+            Some(packet) => {
+                let packet = packet?;
+                fields.push(RawField::Packet {
+                    offset,
+                    key: packet.key,
+                    value: packet.value,
+                });
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RawField, RawToken};
+    use crate::serialization::{v1, v2};
+    use crate::{Format, Macaroon, MacaroonKey};
+
+    fn macaroon() -> Macaroon {
+        let mut macaroon = Macaroon::create(
+            Some("http://example.org/".into()),
+            &MacaroonKey::generate(b"key"),
+            "keyid".into(),
+        )
+        .unwrap();
+        macaroon.add_first_party_caveat("user = alice".into());
+        macaroon
+    }
+
+    #[test]
+    fn test_v2_fields() {
+        let token = v2::serialize_binary(&macaroon()).unwrap();
+        let raw = RawToken::parse(&token).unwrap();
+        assert!(raw.is_complete());
+        let layout = raw
+            .fields()
+            .iter()
+            .map(|f| match f {
+                RawField::Version { version, .. } => format!("v{}", version),
+                RawField::Field { tag, value, .. } => format!("{}:{}", tag, value.len()),
+                RawField::Eos { .. } => "eos".to_string(),
+                RawField::Packet { .. } => unreachable!(),
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            vec!["v2", "1:19", "2:5", "eos", "2:12", "eos", "eos", "6:32"],
+            layout
+        );
+        assert_eq!(token.len() - 34, raw.fields()[7].offset());
+
+        // out-of-order and unknown fields are read, as long as they're framed correctly
+        let odd = [2, 6, 1, b'x', 9, 0, 0];
+        let raw = RawToken::parse(&odd).unwrap();
+        assert!(raw.is_complete());
+        assert_eq!(4, raw.fields().len());
+        assert!(Macaroon::deserialize_binary(&odd).is_err());
+    }
+
+    #[test]
+    fn test_v1_fields() {
+        let token = v1::serialize_binary(&macaroon()).unwrap();
+        let raw = RawToken::parse(&token).unwrap();
+        assert_eq!(Format::V1, raw.format());
+        assert!(raw.is_complete());
+        let keys = raw
+            .fields()
+            .iter()
+            .map(|f| match f {
+                RawField::Packet { key, .. } => *key,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<&str>>();
+        assert_eq!(vec!["location", "identifier", "cid", "signature"], keys);
+        assert_eq!(0, raw.fields()[0].offset());
+
+        let truncated = RawToken::parse(&token[..token.len() - 10]).unwrap();
+        assert_eq!(3, truncated.fields().len());
+        assert!(truncated.error().is_some());
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert!(RawToken::parse(b"").is_err());
+        assert!(RawToken::parse(b"{\"v\":2}").is_err());
+    }
+}
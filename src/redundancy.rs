@@ -0,0 +1,207 @@
+use crate::{Caveat, Macaroon, COND_TIME_BEFORE, EXPIRY_PREFIX, STD_PREFIX};
+
+/// Why a caveat adds no restriction, as reported by [Macaroon::normalize]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Redundancy {
+    /// It repeats an earlier first-party caveat exactly
+    Duplicate,
+    /// It's an expiry caveat (`time < ...` or `time-before ...`) which expires no sooner than
+    /// another one
+    Subsumed,
+}
+
+/// A first-party caveat made redundant by another one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedundantCaveat {
+    /// Position of the redundant caveat
+    pub index: usize,
+    /// Position of the caveat which makes it redundant
+    pub by: usize,
+    pub reason: Redundancy,
+}
+
+/// The expiry time of an expiry caveat, as `YYYY-MM-DDTHH:MM:SSZ` so that times compare as
+/// strings, or `None` if it isn't one in UTC
+fn expiry(predicate: &[u8]) -> Option<String> {
+    let predicate = std::str::from_utf8(predicate).ok()?;
+    let at = match predicate.strip_prefix(EXPIRY_PREFIX) {
+        Some(at) => at,
+        None => predicate
+            .strip_prefix(STD_PREFIX)
+            .unwrap_or(predicate)
+            .strip_prefix(COND_TIME_BEFORE)?
+            .strip_prefix(' ')?,
+    };
+    // the libmacaroons `2030-01-01T00:00` form, or RFC 3339 in UTC without fractions
+    let at = match at.len() {
+        16 => format!("{}:00Z", at),
+        20 if at.ends_with('Z') => at.to_string(),
+        _ => return None,
+    };
+    let well_formed = at.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 => b == b'-',
+        10 => b == b'T',
+        13 | 16 => b == b':',
+        19 => b == b'Z',
+        _ => b.is_ascii_digit(),
+    });
+    if well_formed {
+        Some(at)
+    } else {
+        None
+    }
+}
+
+impl Macaroon {
+    /// The first-party caveats which repeat an earlier one exactly, each paired with its first
+    /// occurrence, in order
+    ///
+    /// This is [Macaroon::dedup_report] by caveat rather than by predicate. Verification checks
+    /// each repeated predicate once. The caveats can't be removed without the root key, so this
+    /// only previews what a freshly minted macaroon could leave out.
+    pub fn dedupe_caveats_preview(&self) -> Vec<RedundantCaveat> {
+        let mut duplicates: Vec<RedundantCaveat> = self
+            .dedup_report()
+            .into_iter()
+            .flat_map(|d| {
+                let by = d.indices[0];
+                d.indices
+                    .into_iter()
+                    .skip(1)
+                    .map(move |index| RedundantCaveat {
+                        index,
+                        by,
+                        reason: Redundancy::Duplicate,
+                    })
+            })
+            .collect();
+        duplicates.sort_by_key(|r| r.index);
+        duplicates
+    }
+
+    /// The first-party caveats which add no restriction, in order: duplicates (see
+    /// [Macaroon::dedupe_caveats_preview]), and expiry caveats which expire no sooner than
+    /// another one
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Redundancy};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_first_party_caveat("time < 2031-01-01T00:00:00Z".into());
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    /// macaroon.add_first_party_caveat("time < 2030-01-01T00:00:00Z".into());
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    ///
+    /// let redundant = macaroon.normalize();
+    /// assert_eq!(2, redundant.len());
+    /// assert_eq!((0, 2, Redundancy::Subsumed), (redundant[0].index, redundant[0].by, redundant[0].reason));
+    /// assert_eq!((3, 1, Redundancy::Duplicate), (redundant[1].index, redundant[1].by, redundant[1].reason));
+    /// ```
+    pub fn normalize(&self) -> Vec<RedundantCaveat> {
+        let mut redundant = self.dedupe_caveats_preview();
+        let expiries: Vec<(usize, String)> = self
+            .caveats
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !redundant.iter().any(|r| r.index == *index))
+            .filter_map(|(index, c)| match c {
+                Caveat::FirstParty(fp) => Some((index, expiry(fp.predicate_ref().as_ref())?)),
+                Caveat::ThirdParty(_) => None,
+            })
+            .collect();
+        if let Some((soonest, _)) = expiries.iter().min_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0))) {
+            redundant.extend(expiries.iter().filter(|(index, _)| index != soonest).map(
+                |(index, _)| RedundantCaveat {
+                    index: *index,
+                    by: *soonest,
+                    reason: Redundancy::Subsumed,
+                },
+            ));
+        }
+        redundant.sort_by_key(|r| r.index);
+        redundant
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expiry, Redundancy, RedundantCaveat};
+    use crate::{Macaroon, MacaroonKey, Verifier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_expiry() {
+        assert_eq!(
+            Some("2030-01-01T00:00:00Z".to_string()),
+            expiry(b"time < 2030-01-01T00:00")
+        );
+        assert_eq!(
+            Some("2030-01-01T00:00:30Z".to_string()),
+            expiry(b"std:time-before 2030-01-01T00:00:30Z")
+        );
+        assert_eq!(None, expiry(b"time < 2030-01-01T00:00:00+01:00"));
+        assert_eq!(None, expiry(b"time < tomorrow"));
+        assert_eq!(None, expiry(b"account = 3735928559"));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        macaroon.add_first_party_caveat("time-before 2030-01-01T00:00:00Z".into());
+        macaroon.add_first_party_caveat("op = read".into());
+        macaroon.add_third_party_caveat("http://auth/", &key, "op = read".into());
+        macaroon.add_first_party_caveat("time < 2030-01-01T00:00".into());
+        macaroon.add_first_party_caveat("op = read".into());
+        macaroon.add_first_party_caveat("time-before 2030-01-01T00:00:00Z".into());
+
+        let duplicate = |index, by| RedundantCaveat {
+            index,
+            by,
+            reason: Redundancy::Duplicate,
+        };
+        assert_eq!(
+            vec![duplicate(4, 1), duplicate(5, 0)],
+            macaroon.dedupe_caveats_preview()
+        );
+        // equal times written differently: the first subsumes the second
+        assert_eq!(
+            vec![
+                RedundantCaveat {
+                    index: 3,
+                    by: 0,
+                    reason: Redundancy::Subsumed
+                },
+                duplicate(4, 1),
+                duplicate(5, 0)
+            ],
+            macaroon.normalize()
+        );
+    }
+
+    #[test]
+    fn test_repeated_caveats_checked_once() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        for _ in 0..3 {
+            macaroon.add_first_party_caveat("op = read".into());
+        }
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut verifier = Verifier::default();
+        verifier.satisfy_general(move |predicate| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            predicate.as_ref() == b"op = read"
+        });
+        verifier.verify(&macaroon, &key, vec![]).unwrap();
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        let report = verifier
+            .verify_with_report(&macaroon, &key, vec![])
+            .unwrap();
+        assert_eq!(3, report.caveats.len());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::verifier::{self, VerifierOptions, VerifyContext};
+use crate::{ByteString, HmacSha256, Macaroon, MacaroonKey, Result, Verifier};
+
+/// How a caveat was satisfied during verification, as listed in a [VerificationReport]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SatisfiedBy {
+    /// An exact satisfier ([Verifier::satisfy_exact])
+    Exact,
+    /// The general satisfier at this position, in the order they were added to the verifier
+    General(usize),
+    /// A [FirstPartyCaveatChecker](crate::FirstPartyCaveatChecker) that doesn't say how it
+    /// satisfied the caveat
+    Checker,
+    /// A third-party caveat, satisfied by the discharge macaroon with this identifier
+    Discharge(ByteString),
+}
+
+/// A caveat checked during verification
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckedCaveat {
+    /// The predicate of a first-party caveat, or the caveat ID of a third-party caveat
+    pub caveat: ByteString,
+    /// The caveat's position in the macaroon it belongs to
+    pub index: usize,
+    /// The identifier of the discharge macaroon the caveat belongs to, or `None` if it belongs to
+    /// the macaroon being verified
+    pub discharge: Option<ByteString>,
+    pub satisfied_by: SatisfiedBy,
+}
+
+/// What a successful verification checked, for audit logs: see [Verifier::verify_with_report]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    /// The identifier of the verified macaroon
+    pub identifier: ByteString,
+    /// Every caveat checked, in order, with a third-party caveat followed by the caveats of its
+    /// discharge
+    pub caveats: Vec<CheckedCaveat>,
+    /// The identifiers of the discharges used, in the order they were consumed
+    pub discharges: Vec<ByteString>,
+}
+
+impl Verifier {
+    /// Like [Verifier::verify], returning what was checked: each caveat, which satisfier or
+    /// discharge satisfied it, and which discharges were used
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, SatisfiedBy, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_exact("account = 3735928559".into());
+    /// let report = verifier.verify_with_report(&macaroon, &key, vec![]).unwrap();
+    /// assert_eq!(SatisfiedBy::Exact, report.caveats[0].satisfied_by);
+    /// ```
+    pub fn verify_with_report(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+    ) -> Result<VerificationReport> {
+        let ctx = VerifyContext {
+            checker: self,
+            options: &VerifierOptions::default(),
+            scheme: &HmacSha256,
+            decryptor: self.decryptor(),
+            binding: self.binding(),
+            revocation: self.revocation_checker(),
+            location: self.expected_location(),
+        };
+        let mut report = VerificationReport {
+            identifier: m.identifier.clone(),
+            ..Default::default()
+        };
+        verifier::verify_with_report(&ctx, m, key, discharges, Some(&mut report))?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheckedCaveat, SatisfiedBy};
+    use crate::{ByteString, Macaroon, MacaroonKey, Verifier};
+
+    #[test]
+    fn test_report() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "login".into());
+        macaroon.add_first_party_caveat("time < 2100-01-01T00:00:00Z".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        discharge.add_first_party_caveat("user = alice".into());
+        macaroon.bind(&mut discharge);
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("account = 3735928559".into());
+        verifier.satisfy_general(|_| false);
+        verifier.satisfy_general(|predicate| predicate.as_ref().starts_with(b"user = "));
+        verifier.satisfy_general(|predicate| predicate.as_ref().starts_with(b"time < "));
+        let report = verifier
+            .verify_with_report(&macaroon, &root_key, vec![discharge.clone()])
+            .unwrap();
+
+        let checked = |caveat: &str, index, discharge: Option<&str>, satisfied_by| CheckedCaveat {
+            caveat: caveat.into(),
+            index,
+            discharge: discharge.map(ByteString::from),
+            satisfied_by,
+        };
+        assert_eq!(ByteString::from("root"), report.identifier);
+        assert_eq!(
+            vec![
+                checked("account = 3735928559", 0, None, SatisfiedBy::Exact),
+                checked("login", 1, None, SatisfiedBy::Discharge("login".into())),
+                checked("user = alice", 0, Some("login"), SatisfiedBy::General(1)),
+                checked(
+                    "time < 2100-01-01T00:00:00Z",
+                    2,
+                    None,
+                    SatisfiedBy::General(2)
+                ),
+            ],
+            report.caveats
+        );
+        assert_eq!(vec![ByteString::from("login")], report.discharges);
+
+        assert!(Verifier::default()
+            .verify_with_report(&macaroon, &root_key, vec![discharge])
+            .is_err());
+    }
+}
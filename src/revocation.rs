@@ -0,0 +1,149 @@
+use crate::mint_store::{InMemoryMintStore, MintStore};
+use crate::{ByteString, Result};
+use sodiumoxide::crypto::hash::sha256;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+/// SHA-256 of a macaroon's signature, identifying that exact token (with its caveats) without
+/// being usable as one. This is [MintRecord::fingerprint](crate::MintRecord::fingerprint).
+pub fn signature_fingerprint(signature: &[u8]) -> [u8; 32] {
+    sha256::hash(signature).0
+}
+
+/// Decides whether a macaroon has been revoked, consulted by a [Verifier](crate::Verifier)
+/// configured with [with_revocation_checker](crate::Verifier::with_revocation_checker) before
+/// checking the caveats of the macaroon and each of its discharges
+pub trait RevocationChecker: Send + Sync {
+    /// Whether the macaroon with this identifier and signature has been revoked
+    fn is_revoked(&self, identifier: &[u8], signature: &[u8]) -> Result<bool>;
+}
+
+impl<R: RevocationChecker + ?Sized> RevocationChecker for Arc<R> {
+    fn is_revoked(&self, identifier: &[u8], signature: &[u8]) -> Result<bool> {
+        (**self).is_revoked(identifier, signature)
+    }
+}
+
+/// Revokes macaroons recorded as revoked in the mint store, by identifier
+impl RevocationChecker for InMemoryMintStore {
+    fn is_revoked(&self, identifier: &[u8], _signature: &[u8]) -> Result<bool> {
+        MintStore::is_revoked(self, &identifier.into())
+    }
+}
+
+/// A revocation list kept in memory
+///
+/// Revoking an identifier revokes every macaroon with it, however attenuated; revoking a
+/// signature fingerprint (see [signature_fingerprint]) only revokes that exact token. Share it
+/// in an `Arc` to keep revoking macaroons after configuring the verifier.
+///
+/// ```rust
+/// use macaroon::{InMemoryRevocationList, Macaroon, MacaroonError, MacaroonKey, Verifier};
+/// use std::sync::Arc;
+///
+/// let revoked = Arc::new(InMemoryRevocationList::new());
+/// let verifier = Verifier::default().with_revocation_checker(revoked.clone());
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let macaroon = Macaroon::create(None, &key, "session-1".into()).unwrap();
+/// assert!(verifier.verify(&macaroon, &key, vec![]).is_ok());
+/// revoked.revoke_identifier("session-1".into());
+/// assert!(matches!(
+///     verifier.verify(&macaroon, &key, vec![]),
+///     Err(MacaroonError::Revoked(_))
+/// ));
+/// ```
+#[derive(Debug, Default)]
+pub struct InMemoryRevocationList {
+    identifiers: RwLock<HashSet<ByteString>>,
+    fingerprints: RwLock<HashSet<[u8; 32]>>,
+}
+
+impl InMemoryRevocationList {
+    pub fn new() -> InMemoryRevocationList {
+        Default::default()
+    }
+
+    /// Revoke every macaroon with this identifier
+    pub fn revoke_identifier(&self, identifier: ByteString) {
+        write(&self.identifiers).insert(identifier);
+    }
+
+    /// Revoke the token whose signature has this fingerprint
+    pub fn revoke_fingerprint(&self, fingerprint: [u8; 32]) {
+        write(&self.fingerprints).insert(fingerprint);
+    }
+}
+
+impl RevocationChecker for InMemoryRevocationList {
+    fn is_revoked(&self, identifier: &[u8], signature: &[u8]) -> Result<bool> {
+        // the sets can't be left half-updated by a panic, so a poisoned lock is still usable
+        let identifiers = self.identifiers.read().unwrap_or_else(|e| e.into_inner());
+        if identifiers.contains(identifier) {
+            return Ok(true);
+        }
+        let fingerprints = self.fingerprints.read().unwrap_or_else(|e| e.into_inner());
+        Ok(fingerprints.contains(&signature_fingerprint(signature)))
+    }
+}
+
+fn write<T>(lock: &RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{signature_fingerprint, InMemoryRevocationList};
+    use crate::{
+        ByteString, InMemoryMintStore, Macaroon, MacaroonError, MacaroonKey, MacaroonRef,
+        MintRecord, MintStore, Verifier,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_revocation() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        let unattenuated = macaroon.clone();
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+        macaroon.bind(&mut discharge);
+
+        let revoked = Arc::new(InMemoryRevocationList::new());
+        let verifier = Verifier::builder()
+            .revocation_checker(revoked.clone())
+            .build();
+        let verify = |m: &Macaroon| verifier.verify(m, &root_key, vec![discharge.clone()]);
+        verify(&macaroon).unwrap();
+
+        // A fingerprint only revokes that token
+        revoked.revoke_fingerprint(signature_fingerprint(&unattenuated.signature()));
+        verify(&macaroon).unwrap();
+        revoked.revoke_fingerprint(signature_fingerprint(&discharge.signature()));
+        match verify(&macaroon) {
+            Err(MacaroonError::Revoked(identifier)) => {
+                assert_eq!(ByteString::from("caveat"), identifier)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let revoked = Arc::new(InMemoryRevocationList::new());
+        let verifier = Verifier::default().with_revocation_checker(revoked.clone());
+        let token = crate::serialization::v2::serialize_binary(&unattenuated).unwrap();
+        let parsed = MacaroonRef::parse(&token).unwrap();
+        parsed.verify(&verifier, &root_key, &[]).unwrap();
+        revoked.revoke_identifier("root".into());
+        assert!(matches!(
+            parsed.verify(&verifier, &root_key, &[]),
+            Err(MacaroonError::Revoked(_))
+        ));
+
+        let store = Arc::new(InMemoryMintStore::default());
+        store.record(MintRecord::new(&unattenuated, None)).unwrap();
+        let verifier = Verifier::default().with_revocation_checker(store.clone());
+        verifier.verify(&unattenuated, &root_key, vec![]).unwrap();
+        store.revoke(&"root".into()).unwrap();
+        assert!(verifier.verify(&unattenuated, &root_key, vec![]).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use crate::identifier::{IdentifierGenerator, UuidV4Generator};
+use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey, Result, Verifier};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Separates the root key ID from the rest of a macaroon's identifier
+const ROOT_KEY_ID_SEPARATOR: u8 = b'/';
+
+/// Root keys looked up by ID, so they can be rotated without invalidating outstanding macaroons
+///
+/// Macaroons minted with a key from the store carry its ID at the start of their identifier,
+/// followed by `/` (see [Macaroon::create_with_root_key_store]), so the key can be found again
+/// when verifying ([Verifier::verify_with_root_key_store]).
+pub trait RootKeyStore: Send + Sync {
+    /// The ID and key to mint new macaroons with
+    fn latest(&self) -> Result<(ByteString, MacaroonKey)>;
+
+    /// The key with the given ID, to verify macaroons minted with it
+    fn get(&self, id: &ByteString) -> Option<MacaroonKey>;
+
+    /// The key `macaroon` was minted with, if it's still in the store
+    fn resolve(&self, macaroon: &Macaroon) -> Option<MacaroonKey> {
+        self.get(&macaroon.root_key_id()?)
+    }
+}
+
+struct StoredKey {
+    id: ByteString,
+    key: MacaroonKey,
+    created: Instant,
+}
+
+/// A [RootKeyStore] kept in memory, whose most recently added key is the latest
+///
+/// If there is no key when one is first needed, a random one is generated. With
+/// [InMemoryRootKeyStore::with_rotation], a new random key is also generated once the latest is
+/// older than the rotation interval, and with [InMemoryRootKeyStore::with_expiry] old keys are
+/// dropped, which invalidates the macaroons minted with them.
+///
+/// ```rust
+/// use macaroon::{InMemoryRootKeyStore, Macaroon, RootKeyStore, Verifier};
+/// use std::time::Duration;
+///
+/// let store = InMemoryRootKeyStore::new()
+///     .with_rotation(Duration::from_secs(24 * 3600))
+///     .with_expiry(Duration::from_secs(7 * 24 * 3600));
+/// let macaroon = Macaroon::create_with_root_key_store(None, &store, "session-1".into()).unwrap();
+/// Verifier::default()
+///     .verify_with_root_key_store(&macaroon, &store, vec![])
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct InMemoryRootKeyStore {
+    keys: Mutex<Vec<StoredKey>>,
+    rotate_after: Option<Duration>,
+    expire_after: Option<Duration>,
+}
+
+impl std::fmt::Debug for InMemoryRootKeyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InMemoryRootKeyStore")
+            .field("keys", &self.lock().len())
+            .field("rotate_after", &self.rotate_after)
+            .field("expire_after", &self.expire_after)
+            .finish()
+    }
+}
+
+impl InMemoryRootKeyStore {
+    pub fn new() -> InMemoryRootKeyStore {
+        Default::default()
+    }
+
+    /// Generate a new latest key once the current one is older than `interval`
+    pub fn with_rotation(mut self, interval: Duration) -> InMemoryRootKeyStore {
+        self.rotate_after = Some(interval);
+        self
+    }
+
+    /// Drop keys once they are older than `ttl`. This should be at least the rotation interval
+    /// plus the lifetime of the macaroons minted with each key.
+    pub fn with_expiry(mut self, ttl: Duration) -> InMemoryRootKeyStore {
+        self.expire_after = Some(ttl);
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<StoredKey>> {
+        // The keys are always left consistent, so a panic elsewhere doesn't invalidate them
+        self.keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a key and make it the latest. Keys added earlier can still verify macaroons.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `id` is empty or contains `/`, which separates it from the rest of the
+    /// identifier of minted macaroons.
+    pub fn insert(&self, id: ByteString, key: MacaroonKey) -> Result<()> {
+        if id.0.is_empty() || id.0.contains(&ROOT_KEY_ID_SEPARATOR) {
+            return Err(MacaroonError::IncompleteMacaroon("invalid root key ID"));
+        }
+        let mut keys = self.lock();
+        keys.retain(|k| k.id != id);
+        keys.push(StoredKey {
+            id,
+            key,
+            created: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Generate a random key, with a UUID as its ID, and make it the latest
+    pub fn rotate(&self) -> ByteString {
+        let mut keys = self.lock();
+        Self::push_random(&mut keys).id.clone()
+    }
+
+    fn push_random(keys: &mut Vec<StoredKey>) -> &StoredKey {
+        keys.push(StoredKey {
+            id: UuidV4Generator.generate(),
+            key: MacaroonKey::generate_random(),
+            created: Instant::now(),
+        });
+        &keys[keys.len() - 1]
+    }
+
+    fn expire(&self, keys: &mut Vec<StoredKey>) {
+        if let Some(ttl) = self.expire_after {
+            keys.retain(|k| k.created.elapsed() < ttl);
+        }
+    }
+}
+
+impl RootKeyStore for InMemoryRootKeyStore {
+    fn latest(&self) -> Result<(ByteString, MacaroonKey)> {
+        let mut keys = self.lock();
+        self.expire(&mut keys);
+        let stale = match (keys.last(), self.rotate_after) {
+            (None, _) => true,
+            (Some(latest), Some(interval)) => latest.created.elapsed() >= interval,
+            (Some(_), None) => false,
+        };
+        let latest = if stale {
+            Self::push_random(&mut keys)
+        } else {
+            &keys[keys.len() - 1]
+        };
+        Ok((latest.id.clone(), latest.key.clone()))
+    }
+
+    fn get(&self, id: &ByteString) -> Option<MacaroonKey> {
+        let mut keys = self.lock();
+        self.expire(&mut keys);
+        keys.iter().find(|k| &k.id == id).map(|k| k.key.clone())
+    }
+}
+
+impl Macaroon {
+    /// Create a macaroon with the latest key from `store`, prefixing `identifier` with the key's
+    /// ID and `/`
+    pub fn create_with_root_key_store(
+        location: Option<String>,
+        store: &dyn RootKeyStore,
+        identifier: ByteString,
+    ) -> Result<Macaroon> {
+        let (key_id, key) = store.latest()?;
+        let mut prefixed = key_id.0;
+        prefixed.push(ROOT_KEY_ID_SEPARATOR);
+        prefixed.extend_from_slice(identifier.as_ref());
+        Macaroon::create(location, &key, prefixed.into())
+    }
+
+    /// The ID of the root key this macaroon was minted with, if its identifier starts with one
+    /// (see [RootKeyStore])
+    pub fn root_key_id(&self) -> Option<ByteString> {
+        let end = self
+            .identifier
+            .0
+            .iter()
+            .position(|b| *b == ROOT_KEY_ID_SEPARATOR)?;
+        Some(self.identifier.0[..end].into())
+    }
+}
+
+impl Verifier {
+    /// Verify `m` with the root key it was minted with, looked up in `store`
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::InvalidSignature] if the key isn't in the store (eg it has
+    /// expired), as well as the errors from [Verifier::verify].
+    pub fn verify_with_root_key_store(
+        &self,
+        m: &Macaroon,
+        store: &dyn RootKeyStore,
+        discharges: Vec<Macaroon>,
+    ) -> Result<()> {
+        let key = store.resolve(m).ok_or(MacaroonError::InvalidSignature)?;
+        self.verify(m, &key, discharges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryRootKeyStore, RootKeyStore};
+    use crate::{Macaroon, MacaroonError, MacaroonKey, Verifier};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_insert_and_rotate() {
+        let store = InMemoryRootKeyStore::new();
+        store
+            .insert("first".into(), MacaroonKey::generate(b"first"))
+            .unwrap();
+        assert!(store
+            .insert("a/b".into(), MacaroonKey::generate(b"a/b"))
+            .is_err());
+        let old = Macaroon::create_with_root_key_store(None, &store, "old".into()).unwrap();
+        assert_eq!(Some("first".into()), old.root_key_id());
+
+        let id = store.rotate();
+        assert_eq!(id, store.latest().unwrap().0);
+        let new = Macaroon::create_with_root_key_store(None, &store, "new".into()).unwrap();
+        assert_eq!(Some(id), new.root_key_id());
+
+        let verifier = Verifier::default();
+        verifier
+            .verify_with_root_key_store(&old, &store, vec![])
+            .unwrap();
+        verifier
+            .verify_with_root_key_store(&new, &store, vec![])
+            .unwrap();
+        let unknown = Macaroon::create(None, &MacaroonKey::generate(b"x"), "x/y".into()).unwrap();
+        assert!(matches!(
+            verifier.verify_with_root_key_store(&unknown, &store, vec![]),
+            Err(MacaroonError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_ttl_rotation() {
+        let store = InMemoryRootKeyStore::new()
+            .with_rotation(Duration::from_millis(20))
+            .with_expiry(Duration::from_millis(60));
+        let (first, _) = store.latest().unwrap();
+        assert_eq!(first, store.latest().unwrap().0);
+        let macaroon = Macaroon::create_with_root_key_store(None, &store, "id".into()).unwrap();
+
+        sleep(Duration::from_millis(30));
+        let (second, _) = store.latest().unwrap();
+        assert_ne!(first, second);
+        assert!(store.resolve(&macaroon).is_some());
+
+        sleep(Duration::from_millis(40));
+        assert!(store.resolve(&macaroon).is_none());
+    }
+}
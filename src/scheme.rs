@@ -0,0 +1,262 @@
+use crate::MacaroonKey;
+use sodiumoxide::crypto::auth::{hmacsha256, hmacsha512256};
+use sodiumoxide::crypto::generichash;
+use std::fmt;
+
+/// The keyed hash used to chain macaroon signatures
+///
+/// [HmacSha256] is the default, and the only scheme interoperable with libmacaroons and most
+/// other implementations. Other schemes are for deployments that control every party that signs
+/// or verifies their macaroons.
+///
+/// The scheme isn't recorded in serialized macaroons, so it has to be agreed out of band: see
+/// [Macaroon::create_with_scheme](crate::Macaroon::create_with_scheme),
+/// [Macaroon::with_scheme](crate::Macaroon::with_scheme) and
+/// [Verifier::verify_with_scheme](crate::Verifier::verify_with_scheme).
+pub trait SignatureScheme: Send + Sync {
+    /// A short, unique name, eg `"hmac-sha256"`
+    fn name(&self) -> &'static str;
+
+    /// Keyed hash of `text`, which must be 32 bytes long
+    fn hmac(&self, key: &MacaroonKey, text: &[u8]) -> MacaroonKey;
+
+    /// Hash of two values, used for third-party caveats and binding discharges
+    fn hmac2(&self, key: &MacaroonKey, text1: &[u8], text2: &[u8]) -> MacaroonKey {
+        let mut tmp = [0; 64];
+        tmp[..32].copy_from_slice(&self.hmac(key, text1)[..]);
+        tmp[32..].copy_from_slice(&self.hmac(key, text2)[..]);
+        self.hmac(key, &tmp)
+    }
+}
+
+/// HMAC-SHA-256, the standard macaroon signature scheme
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HmacSha256;
+
+impl SignatureScheme for HmacSha256 {
+    fn name(&self) -> &'static str {
+        "hmac-sha256"
+    }
+
+    fn hmac(&self, key: &MacaroonKey, text: &[u8]) -> MacaroonKey {
+        let hmacsha256::Tag(tag) = hmacsha256::authenticate(text, &hmacsha256::Key(*key.as_ref()));
+        tag.into()
+    }
+}
+
+/// How a discharge macaroon's signature is bound to the macaroon it discharges
+///
+/// [ZeroKeyBinding] is the default, and the only binding interoperable with libmacaroons and
+/// other implementations. Other bindings can also tie discharges to something else, eg a TLS
+/// channel binding value or a hash of the request, so they are only accepted along with it.
+/// Discharges are bound with [Macaroon::bind_with](crate::Macaroon::bind_with) and verified
+/// with [Verifier::with_binding](crate::Verifier::with_binding).
+pub trait BindingScheme: Send + Sync {
+    /// The signature of a discharge whose unbound signature is `discharge_sig`, bound to the
+    /// macaroon whose signature is `root_sig`
+    fn bind(
+        &self,
+        scheme: &dyn SignatureScheme,
+        root_sig: &MacaroonKey,
+        discharge_sig: &MacaroonKey,
+    ) -> MacaroonKey;
+}
+
+/// The standard binding: the hash of both signatures, keyed with 32 zero bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ZeroKeyBinding;
+
+impl BindingScheme for ZeroKeyBinding {
+    fn bind(
+        &self,
+        scheme: &dyn SignatureScheme,
+        root_sig: &MacaroonKey,
+        discharge_sig: &MacaroonKey,
+    ) -> MacaroonKey {
+        scheme.hmac2(
+            &MacaroonKey::from([0; 32]),
+            &root_sig[..],
+            &discharge_sig[..],
+        )
+    }
+}
+
+/// A binding keyed with a value known to both the bearer and the verifier, such as a TLS
+/// channel binding value or a hash of the request, so bound discharges are only valid with it
+///
+/// ```rust
+/// use macaroon::{KeyedBinding, Macaroon, MacaroonKey, Verifier};
+///
+/// let root_key = MacaroonKey::generate(b"root key");
+/// let caveat_key = MacaroonKey::generate(b"caveat key");
+/// let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+/// macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+/// let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+/// macaroon.bind_with(&KeyedBinding::new(b"tls-exporter value"), &mut discharge);
+///
+/// let verifier = Verifier::default().with_binding(KeyedBinding::new(b"tls-exporter value"));
+/// assert!(verifier.verify(&macaroon, &root_key, vec![discharge.clone()]).is_ok());
+/// let verifier = Verifier::default().with_binding(KeyedBinding::new(b"another connection"));
+/// assert!(verifier.verify(&macaroon, &root_key, vec![discharge.clone()]).is_err());
+/// assert!(Verifier::default().verify(&macaroon, &root_key, vec![discharge]).is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyedBinding {
+    key: MacaroonKey,
+}
+
+impl KeyedBinding {
+    /// A binding keyed with `value`, which can be of any length
+    pub fn new(value: &[u8]) -> KeyedBinding {
+        KeyedBinding {
+            key: MacaroonKey::generate(value),
+        }
+    }
+}
+
+impl BindingScheme for KeyedBinding {
+    fn bind(
+        &self,
+        scheme: &dyn SignatureScheme,
+        root_sig: &MacaroonKey,
+        discharge_sig: &MacaroonKey,
+    ) -> MacaroonKey {
+        scheme.hmac2(&self.key, &root_sig[..], &discharge_sig[..])
+    }
+}
+
+/// HMAC-SHA-512 truncated to 256 bits (as used by macaroon 0.2)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HmacSha512_256;
+
+impl SignatureScheme for HmacSha512_256 {
+    fn name(&self) -> &'static str {
+        "hmac-sha512-256"
+    }
+
+    fn hmac(&self, key: &MacaroonKey, text: &[u8]) -> MacaroonKey {
+        let hmacsha512256::Tag(tag) =
+            hmacsha512256::authenticate(text, &hmacsha512256::Key(*key.as_ref()));
+        tag.into()
+    }
+}
+
+/// Keyed BLAKE2b with a 256-bit output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Blake2bMac;
+
+impl SignatureScheme for Blake2bMac {
+    fn name(&self) -> &'static str {
+        "blake2b-256"
+    }
+
+    fn hmac(&self, key: &MacaroonKey, text: &[u8]) -> MacaroonKey {
+        // Output and key lengths are both within BLAKE2b's limits, so this can't fail
+        let mut state = generichash::State::new(Some(32), Some(&key[..])).unwrap();
+        state.update(text).unwrap();
+        let mut out = [0; 32];
+        out.copy_from_slice(state.finalize().unwrap().as_ref());
+        out.into()
+    }
+}
+
+/// The scheme a [Macaroon](crate::Macaroon) signs new caveats with, compared by name
+#[derive(Clone, Copy)]
+pub(crate) struct Scheme(pub(crate) &'static dyn SignatureScheme);
+
+impl Default for Scheme {
+    fn default() -> Self {
+        Scheme(&HmacSha256)
+    }
+}
+
+impl fmt::Debug for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+impl PartialEq for Scheme {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.name() == other.0.name()
+    }
+}
+
+impl Eq for Scheme {}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BindingScheme, Blake2bMac, HmacSha256, HmacSha512_256, KeyedBinding, SignatureScheme,
+        ZeroKeyBinding,
+    };
+    use crate::crypto;
+    use crate::serialization::v2;
+    use crate::{Macaroon, MacaroonKey, MacaroonRef, SharedMacaroon, Verifier};
+
+    #[test]
+    fn test_schemes() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let text = b"some text";
+        assert_eq!(crypto::hmac(&key, text), HmacSha256.hmac(&key, text));
+        assert_eq!(
+            crypto::hmac2(&key, &text[..], &b"more"[..]),
+            HmacSha256.hmac2(&key, text, b"more")
+        );
+        let schemes: [&dyn SignatureScheme; 3] = [&HmacSha256, &HmacSha512_256, &Blake2bMac];
+        for (i, a) in schemes.iter().enumerate() {
+            for b in schemes[i + 1..].iter() {
+                assert_ne!(a.name(), b.name());
+                assert_ne!(a.hmac(&key, text), b.hmac(&key, text));
+            }
+        }
+    }
+
+    #[test]
+    fn test_bindings() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        macaroon.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+        let unbound = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+
+        let mut standard = unbound.clone();
+        macaroon.bind(&mut standard);
+        let mut zero = unbound.clone();
+        macaroon.bind_with(&ZeroKeyBinding, &mut zero);
+        assert_eq!(standard, zero);
+        let mut shared = unbound.clone();
+        SharedMacaroon::from(macaroon.clone())
+            .bind_with(&KeyedBinding::new(b"request"), &mut shared);
+        let mut keyed = unbound;
+        macaroon.bind_with(&KeyedBinding::new(b"request"), &mut keyed);
+        assert_eq!(shared, keyed);
+        assert_ne!(
+            KeyedBinding::new(b"request").bind(&HmacSha256, &root_key, &caveat_key),
+            ZeroKeyBinding.bind(&HmacSha256, &root_key, &caveat_key)
+        );
+
+        let verifier = Verifier::builder()
+            .binding(KeyedBinding::new(b"request"))
+            .build();
+        verifier
+            .verify(&macaroon, &root_key, vec![keyed.clone()])
+            .unwrap();
+        assert!(verifier
+            .verify(&macaroon, &root_key, vec![standard.clone()])
+            .is_err());
+        assert!(Verifier::default()
+            .verify(&macaroon, &root_key, vec![keyed.clone()])
+            .is_err());
+
+        // Borrowed macaroons too
+        let root_bytes = v2::serialize_binary(&macaroon).unwrap();
+        let keyed_bytes = v2::serialize_binary(&keyed).unwrap();
+        let root_ref = MacaroonRef::parse(&root_bytes).unwrap();
+        let discharges = [MacaroonRef::parse(&keyed_bytes).unwrap()];
+        root_ref.verify(&verifier, &root_key, &discharges).unwrap();
+        assert!(root_ref
+            .verify(&Verifier::default(), &root_key, &discharges)
+            .is_err());
+    }
+}
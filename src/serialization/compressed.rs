@@ -0,0 +1,275 @@
+use crate::serialization::{v2, DeserializeOptions, SerializeOptions};
+use crate::{Macaroon, MacaroonError, Result};
+
+/// First byte of a [Format::V2Compressed](crate::Format::V2Compressed) envelope. It can't start
+/// a V1 (ASCII hex) or V2 (version byte 2) binary token, so the formats are told apart by it.
+pub(crate) const ENVELOPE_TAG: u8 = 0xce;
+
+const DEFLATE: u8 = 1;
+const ZSTD: u8 = 2;
+
+/// Compression algorithm of a [Format::V2Compressed](crate::Format::V2Compressed) token
+///
+/// Each algorithm needs the feature of the same name, both to compress and to decompress; the
+/// algorithm is recorded in the token, so [Macaroon::deserialize] detects it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Raw deflate (RFC 1951), with the `deflate` feature
+    Deflate,
+    /// Zstandard, with the `zstd` feature
+    Zstd,
+}
+
+impl Compression {
+    /// The algorithm [Format::V2Compressed](crate::Format::V2Compressed) is written with:
+    /// deflate, or zstd if only the `zstd` feature is enabled. `None` without either feature.
+    pub fn preferred() -> Option<Compression> {
+        if cfg!(feature = "deflate") {
+            Some(Compression::Deflate)
+        } else if cfg!(feature = "zstd") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Compression::Deflate => DEFLATE,
+            Compression::Zstd => ZSTD,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Compression> {
+        match tag {
+            DEFLATE => Some(Compression::Deflate),
+            ZSTD => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    #[cfg_attr(
+        not(all(feature = "deflate", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => Ok(miniz_oxide::deflate::compress_to_vec(data, 9)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, 19)
+                .map_err(|e| MacaroonError::SerializationError(format!("zstd: {}", e))),
+            #[allow(unreachable_patterns)]
+            _ => Err(MacaroonError::SerializationError(format!(
+                "{:?} compression needs the `{}` feature",
+                self,
+                self.feature()
+            ))),
+        }
+    }
+
+    /// Decompress `data`, failing if it would inflate beyond `limit` bytes
+    #[cfg_attr(
+        not(all(feature = "deflate", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    fn decompress(self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use miniz_oxide::inflate::{decompress_to_vec_with_limit, TINFLStatus};
+                decompress_to_vec_with_limit(data, limit).map_err(|e| match e.status {
+                    TINFLStatus::HasMoreOutput => too_large(limit),
+                    _ => MacaroonError::DeserializationError("corrupt deflate data".to_string()),
+                })
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                // the frame header records the content size, so a bomb is refused up front
+                match zstd::zstd_safe::get_frame_content_size(data) {
+                    Ok(Some(size)) if size > limit as u64 => return Err(too_large(limit)),
+                    _ => {}
+                }
+                zstd::bulk::decompress(data, limit).map_err(|e| {
+                    MacaroonError::DeserializationError(format!("corrupt zstd data: {}", e))
+                })
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(MacaroonError::DeserializationError(format!(
+                "{:?} compressed token needs the `{}` feature",
+                self,
+                self.feature()
+            ))),
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+fn too_large(limit: usize) -> MacaroonError {
+    MacaroonError::LimitExceeded(format!("decompressed token exceeds the limit of {}", limit))
+}
+
+/// The envelope: [ENVELOPE_TAG], the algorithm, then the compressed V2 binary token
+pub fn serialize_binary(macaroon: &Macaroon, compression: Compression) -> Result<Vec<u8>> {
+    let binary = v2::serialize_binary(macaroon)?;
+    let mut envelope = vec![ENVELOPE_TAG, compression.tag()];
+    envelope.extend(compression.compress(&binary)?);
+    Ok(envelope)
+}
+
+pub fn serialize(macaroon: &Macaroon, options: &SerializeOptions) -> Result<String> {
+    let compression = Compression::preferred().ok_or_else(|| {
+        MacaroonError::SerializationError(
+            "V2Compressed needs the `deflate` or `zstd` feature".to_string(),
+        )
+    })?;
+    let envelope = serialize_binary(macaroon, compression)?;
+    Ok(base64::encode_config(&envelope, options.base64_config()))
+}
+
+/// Takes a binary envelope (not base64-encoded), returning the V2 binary token it holds
+///
+/// The token is limited to [max_token_len](crate::DeserializationLimits::max_token_len), like
+/// the envelope.
+pub fn decompress(data: &[u8], options: &DeserializeOptions) -> Result<Vec<u8>> {
+    let (compression, compressed) = match data {
+        [ENVELOPE_TAG, tag, compressed @ ..] => (Compression::from_tag(*tag), compressed),
+        _ => (None, data),
+    };
+    let compression = compression.ok_or_else(|| {
+        MacaroonError::DeserializationError("unknown token compression".to_string())
+    })?;
+    let binary = compression.decompress(compressed, options.limits.max_token_len)?;
+    if binary.first() != Some(&2) {
+        return Err(MacaroonError::DeserializationError(
+            "compressed token doesn't hold a V2 macaroon".to_string(),
+        ));
+    }
+    Ok(binary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, ENVELOPE_TAG};
+    use crate::{Format, Macaroon, MacaroonError, MacaroonKey};
+
+    fn big_macaroon() -> Macaroon {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon =
+            Macaroon::create(Some("https://service.example/".into()), &key, "id".into()).unwrap();
+        for i in 0..40 {
+            macaroon.add_first_party_caveat(format!("account = {}", 3735928559u64 + i).into());
+        }
+        macaroon
+    }
+
+    #[test]
+    fn test_without_compression() {
+        let macaroon = big_macaroon();
+        let envelope = [ENVELOPE_TAG, 9, 1, 2, 3];
+        assert!(matches!(
+            Macaroon::deserialize_binary(&envelope),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+        if Compression::preferred().is_none() {
+            assert!(matches!(
+                macaroon.serialize(Format::V2Compressed),
+                Err(MacaroonError::SerializationError(_))
+            ));
+            assert!(matches!(
+                macaroon.try_serialized_len(Format::V2Compressed),
+                Err(MacaroonError::SerializationError(_))
+            ));
+            let mut attenuated = macaroon.clone();
+            assert!(matches!(
+                attenuated.add_first_party_caveat_within(
+                    "op = read".into(),
+                    Format::V2Compressed,
+                    usize::MAX
+                ),
+                Err(MacaroonError::SerializationError(_))
+            ));
+            assert_eq!(macaroon, attenuated);
+        }
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "deflate", feature = "zstd")))]
+    #[should_panic]
+    fn test_serialized_len_without_compression() {
+        big_macaroon().serialized_len(Format::V2Compressed);
+    }
+
+    #[cfg(any(feature = "deflate", feature = "zstd"))]
+    #[test]
+    fn test_compressed() {
+        use super::serialize_binary;
+        use crate::{DeserializationLimits, DeserializeOptions, SerializeOptions};
+
+        let macaroon = big_macaroon();
+        let token = macaroon.serialize(Format::V2Compressed).unwrap();
+        let v2 = macaroon.serialize(Format::V2).unwrap();
+        assert!(
+            token.len() * 2 < v2.len(),
+            "{} vs {}",
+            token.len(),
+            v2.len()
+        );
+        assert_eq!(macaroon, Macaroon::deserialize(&token).unwrap());
+        assert_eq!(token.len(), macaroon.serialized_len(Format::V2Compressed));
+        assert_eq!(
+            token.len(),
+            macaroon.try_serialized_len(Format::V2Compressed).unwrap()
+        );
+        macaroon
+            .ensure_fits(Format::V2Compressed, token.len())
+            .unwrap();
+        let padded = macaroon
+            .serialize_with_options(Format::V2Compressed, &SerializeOptions { padding: true })
+            .unwrap();
+        assert_eq!(macaroon, Macaroon::deserialize(&padded).unwrap());
+
+        let mut compressions = Vec::new();
+        if cfg!(feature = "deflate") {
+            compressions.push(Compression::Deflate);
+        }
+        if cfg!(feature = "zstd") {
+            compressions.push(Compression::Zstd);
+        }
+        for compression in compressions {
+            let envelope = serialize_binary(&macaroon, compression).unwrap();
+            assert_eq!(macaroon, Macaroon::deserialize_binary(&envelope).unwrap());
+
+            // the decompressed token is held to the token length limit
+            let options = DeserializeOptions {
+                limits: DeserializationLimits {
+                    max_token_len: envelope.len() + 10,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert!(matches!(
+                Macaroon::deserialize_binary_with_options(&envelope, &options),
+                Err(MacaroonError::LimitExceeded(_))
+            ));
+
+            // corrupt data, and an envelope holding something other than a V2 macaroon
+            let mut corrupt = envelope.clone();
+            corrupt.truncate(envelope.len() / 2);
+            assert!(Macaroon::deserialize_binary(&corrupt).is_err());
+            let mut nested = envelope[..2].to_vec();
+            nested.extend(compression.compress(&envelope).unwrap());
+            assert!(matches!(
+                Macaroon::deserialize_binary(&nested),
+                Err(MacaroonError::DeserializationError(_))
+            ));
+        }
+    }
+}
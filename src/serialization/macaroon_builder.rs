@@ -1,5 +1,6 @@
 use crate::caveat::Caveat;
 use crate::error::MacaroonError;
+use crate::extensions::Extensions;
 use crate::{ByteString, Macaroon, MacaroonKey, Result};
 
 pub struct MacaroonBuilder {
@@ -7,6 +8,7 @@ pub struct MacaroonBuilder {
     location: Option<String>,
     signature: MacaroonKey,
     caveats: Vec<Caveat>,
+    extensions: Extensions,
 }
 
 impl MacaroonBuilder {
@@ -16,6 +18,23 @@ impl MacaroonBuilder {
             location: None,
             signature: MacaroonKey::generate_random(),
             caveats: Default::default(),
+            extensions: Default::default(),
+        }
+    }
+
+    /// Start from the buffers of an existing macaroon (cleared), so that building into it with
+    /// [MacaroonBuilder::build_into] reuses their allocations
+    pub fn reuse(macaroon: &mut Macaroon) -> MacaroonBuilder {
+        let mut identifier = std::mem::take(&mut macaroon.identifier);
+        identifier.0.clear();
+        let mut caveats = std::mem::take(&mut macaroon.caveats);
+        caveats.clear();
+        MacaroonBuilder {
+            identifier,
+            location: None,
+            signature: macaroon.signature.clone(),
+            caveats,
+            extensions: Default::default(),
         }
     }
 
@@ -23,35 +42,59 @@ impl MacaroonBuilder {
         self.identifier = identifier;
     }
 
-    pub fn set_location(&mut self, location: &str) {
-        self.location = Some((*location).to_string());
+    /// Copies the identifier into the existing buffer
+    pub fn set_identifier_from_slice(&mut self, identifier: &[u8]) {
+        self.identifier.0.clear();
+        self.identifier.0.extend_from_slice(identifier);
     }
 
-    pub fn has_location(&self) -> bool {
-        self.location.is_some()
+    pub fn set_location(&mut self, location: &str) {
+        self.location = Some((*location).to_string());
     }
 
     pub fn set_signature(&mut self, signature: &[u8]) {
         self.signature.clone_from_slice(signature);
     }
 
+    pub fn set_extensions(&mut self, extensions: Extensions) {
+        self.extensions = extensions;
+    }
+
     pub fn add_caveat(&mut self, caveat: Caveat) {
         self.caveats.push(caveat);
     }
 
-    pub fn build(&self) -> Result<Macaroon> {
+    pub fn build(self) -> Result<Macaroon> {
+        self.validate()?;
+        Ok(Macaroon {
+            identifier: self.identifier,
+            location: self.location,
+            signature: self.signature,
+            caveats: self.caveats,
+            extensions: self.extensions,
+            scheme: Default::default(),
+        })
+    }
+
+    /// Like [MacaroonBuilder::build], but moves the fields into an existing macaroon
+    pub fn build_into(self, macaroon: &mut Macaroon) -> Result<()> {
+        self.validate()?;
+        macaroon.identifier = self.identifier;
+        macaroon.location = self.location;
+        macaroon.signature = self.signature;
+        macaroon.caveats = self.caveats;
+        macaroon.extensions = self.extensions;
+        macaroon.scheme = Default::default();
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
         if self.identifier.0.is_empty() {
             return Err(MacaroonError::IncompleteMacaroon("no identifier found"));
         }
         if self.signature.is_empty() {
             return Err(MacaroonError::IncompleteMacaroon("no signature found"));
         }
-
-        Ok(Macaroon {
-            identifier: self.identifier.clone(),
-            location: self.location.clone(),
-            signature: self.signature,
-            caveats: self.caveats.clone(),
-        })
+        Ok(())
     }
 }
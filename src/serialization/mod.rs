@@ -1,10 +1,240 @@
+use crate::{Caveat, Macaroon, MacaroonError, Result};
+use std::fmt;
+
+pub mod compressed;
 pub mod macaroon_builder;
+mod slice;
 pub mod v1;
 pub mod v2;
 pub mod v2json;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Format {
     V1,
     V2,
     V2JSON,
+    /// The V2 binary format compressed with deflate or zstd, in an envelope recording the
+    /// algorithm, then base64 encoded like V2. Tokens with many caveats typically shrink to half
+    /// their size or less. Writing these needs the `deflate` or `zstd` feature (see
+    /// [Compression](compressed::Compression)), and so does reading them.
+    V2Compressed,
+}
+
+/// Options controlling how tokens are written, used with
+/// [Macaroon::serialize_with_options](crate::Macaroon::serialize_with_options)
+///
+/// The default matches libmacaroons, which writes base64 without padding. Either form is
+/// accepted when deserializing.
+///
+/// ```rust
+/// use macaroon::{Format, Macaroon, MacaroonKey, SerializeOptions};
+///
+/// let macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+/// let padded = macaroon
+///     .serialize_with_options(Format::V1, &SerializeOptions { padding: true })
+///     .unwrap();
+/// assert!(padded.ends_with('='));
+/// assert_eq!(macaroon, Macaroon::deserialize(&padded).unwrap());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SerializeOptions {
+    /// Pad base64 output (the V1 and V2 tokens, and the V2JSON signature) with `=`
+    pub padding: bool,
+}
+
+impl SerializeOptions {
+    pub(crate) fn base64_config(&self) -> base64::Config {
+        if self.padding {
+            base64::URL_SAFE
+        } else {
+            base64::URL_SAFE_NO_PAD
+        }
+    }
+
+    /// Length of `len` bytes encoded as base64 with these options
+    pub(crate) fn base64_len(&self, len: usize) -> usize {
+        if self.padding {
+            (len + 2) / 3 * 4
+        } else {
+            len / 3 * 4 + [0, 2, 3][len % 3]
+        }
+    }
+}
+
+/// Options controlling how tokens are parsed, used with
+/// [Macaroon::deserialize_with_options](crate::Macaroon::deserialize_with_options)
+///
+/// ```rust
+/// use macaroon::DeserializeOptions;
+///
+/// let options = DeserializeOptions {
+///     strict: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeserializeOptions {
+    /// Reject encodings which are otherwise tolerated for compatibility, such as non-minimal
+    /// V2 field size varints
+    pub strict: bool,
+    /// Bounds on the size of the token, checked by every format
+    pub limits: DeserializationLimits,
+    /// Ignore [Format::V2JSON] fields this crate doesn't know, as go-macaroon does, so that
+    /// fields added by later revisions of the version 2 format don't break parsing. Fields
+    /// starting with [EXTENSION_PREFIX](crate::EXTENSION_PREFIX) are always accepted (see
+    /// [Extensions](crate::Extensions)), and tokens of another version are always rejected with
+    /// [MacaroonError::UnsupportedVersion]. On by default.
+    pub allow_unknown_fields: bool,
+}
+
+impl Default for DeserializeOptions {
+    fn default() -> Self {
+        DeserializeOptions {
+            strict: false,
+            limits: DeserializationLimits::default(),
+            allow_unknown_fields: true,
+        }
+    }
+}
+
+/// Upper bounds on what the deserializers accept, so that a hostile token can't make them (or
+/// a verifier) use unbounded memory or time. Exceeding one fails with
+/// [MacaroonError::LimitExceeded].
+///
+/// The defaults are far beyond any legitimate token, but can be lowered for tokens received
+/// from untrusted clients:
+///
+/// ```rust
+/// use macaroon::{DeserializationLimits, DeserializeOptions, Macaroon, MacaroonError};
+///
+/// let options = DeserializeOptions {
+///     limits: DeserializationLimits {
+///         max_caveats: 1,
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// let token = r#"{"v":2,"i":"keyid","c":[{"i":"account = 3735928559"},{"i":"user = alice"}],"s64":"S-lnzR6gxrJrr2pKlO6bBbFYhtoLqF6MQqk8jQ4SXvw"}"#;
+/// assert!(matches!(
+///     Macaroon::deserialize_with_options(token, &options),
+///     Err(MacaroonError::LimitExceeded(_))
+/// ));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeserializationLimits {
+    /// Length of the whole token in bytes, as given to the deserializer (so base64 or JSON
+    /// encoding counts)
+    pub max_token_len: usize,
+    /// Number of caveats in the macaroon
+    pub max_caveats: usize,
+    /// Length in bytes of any single field, such as the identifier or a caveat ID
+    pub max_field_len: usize,
+}
+
+impl Default for DeserializationLimits {
+    fn default() -> Self {
+        DeserializationLimits {
+            max_token_len: 1 << 20,
+            max_caveats: 8192,
+            max_field_len: 65535,
+        }
+    }
+}
+
+impl DeserializationLimits {
+    pub(crate) fn check_token_len(&self, len: usize) -> Result<()> {
+        check_limit("token length", len, self.max_token_len)
+    }
+
+    pub(crate) fn check_caveats(&self, count: usize) -> Result<()> {
+        check_limit("caveat count", count, self.max_caveats)
+    }
+
+    pub(crate) fn check_field_len(&self, len: usize) -> Result<()> {
+        check_limit("field length", len, self.max_field_len)
+    }
+}
+
+fn check_limit(what: &str, value: usize, limit: usize) -> Result<()> {
+    if value > limit {
+        return Err(MacaroonError::LimitExceeded(format!(
+            "{} {} exceeds the limit of {}",
+            what, value, limit
+        )));
+    }
+    Ok(())
+}
+
+/// A variable-length field of a macaroon, for naming it in serialization errors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Field {
+    Location,
+    Identifier,
+    Predicate(usize),
+    CaveatId(usize),
+    VerifierId(usize),
+    CaveatLocation(usize),
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Location => write!(f, "location"),
+            Field::Identifier => write!(f, "identifier"),
+            Field::Predicate(i) => write!(f, "predicate of caveat {}", i),
+            Field::CaveatId(i) => write!(f, "ID of caveat {}", i),
+            Field::VerifierId(i) => write!(f, "verifier ID of caveat {}", i),
+            Field::CaveatLocation(i) => write!(f, "location of caveat {}", i),
+        }
+    }
+}
+
+/// Call `f` with each variable-length field of `macaroon`, in the order V1 serializes them,
+/// stopping at the first error
+pub(crate) fn for_each_field<F>(macaroon: &Macaroon, mut f: F) -> Result<()>
+where
+    F: FnMut(Field, &[u8]) -> Result<()>,
+{
+    if let Some(location) = macaroon.location.as_ref() {
+        f(Field::Location, location.as_bytes())?;
+    }
+    f(Field::Identifier, macaroon.identifier.as_ref())?;
+    for (i, c) in macaroon.caveats.iter().enumerate() {
+        match c {
+            Caveat::FirstParty(fp) => f(Field::Predicate(i), fp.predicate_ref().as_ref())?,
+            Caveat::ThirdParty(tp) => {
+                f(Field::CaveatId(i), tp.id_ref().as_ref())?;
+                f(Field::VerifierId(i), tp.verifier_id_ref().as_ref())?;
+                f(Field::CaveatLocation(i), tp.location().as_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fails with [MacaroonError::SerializationError] if `macaroon` has more caveats or longer
+/// fields than [DeserializationLimits::default] allows, so that `format` would produce a token
+/// which can't be read back with [Macaroon::deserialize]
+pub(crate) fn check_default_limits(macaroon: &Macaroon, format: Format) -> Result<()> {
+    let limits = DeserializationLimits::default();
+    if macaroon.caveats.len() > limits.max_caveats {
+        return Err(MacaroonError::SerializationError(format!(
+            "{} caveats are too many for {:?} (limit is {})",
+            macaroon.caveats.len(),
+            format,
+            limits.max_caveats
+        )));
+    }
+    for_each_field(macaroon, |field, value| {
+        if value.len() > limits.max_field_len {
+            return Err(MacaroonError::SerializationError(format!(
+                "{} of {} bytes is too large for {:?} (limit is {})",
+                field,
+                value.len(),
+                format,
+                limits.max_field_len
+            )));
+        }
+        Ok(())
+    })
 }
@@ -0,0 +1,193 @@
+use crate::serialization::v1::{self, V1Packets};
+use crate::serialization::{v2, v2json, DeserializeOptions, Format, SerializeOptions};
+use crate::{base64_decode_flexible, Macaroon, MacaroonError, Result};
+
+impl Macaroon {
+    /// Serialize several macaroons, typically a root macaroon and its bound discharges, as a
+    /// single token, as go-macaroon's `Slice` does
+    ///
+    /// For V1 and V2, the binary macaroons are concatenated and encoded as URL-safe base64 with
+    /// padding. For V2JSON, the output is a JSON array.
+    ///
+    /// ```rust
+    /// use macaroon::{Format, Macaroon, MacaroonKey};
+    ///
+    /// let root_key = MacaroonKey::generate(b"root key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut root = Macaroon::create(None, &root_key, "root".into()).unwrap();
+    /// root.add_third_party_caveat("https://auth.example/", &caveat_key, "caveat".into());
+    /// let mut discharge = Macaroon::create(None, &caveat_key, "caveat".into()).unwrap();
+    /// root.bind(&mut discharge);
+    ///
+    /// let token = Macaroon::serialize_slice(&[root.clone(), discharge.clone()], Format::V2).unwrap();
+    /// assert_eq!(vec![root, discharge], Macaroon::deserialize_slice(&token).unwrap());
+    /// ```
+    pub fn serialize_slice(macaroons: &[Macaroon], format: Format) -> Result<String> {
+        match format {
+            Format::V1 | Format::V2 => {
+                let binary = Macaroon::serialize_binary_slice(macaroons, format)?;
+                Ok(base64::encode_config(
+                    &binary,
+                    SerializeOptions::default().base64_config(),
+                ))
+            }
+            Format::V2Compressed => Err(MacaroonError::SerializationError(
+                "V2Compressed holds a single macaroon".to_string(),
+            )),
+            Format::V2JSON => {
+                let values = macaroons
+                    .iter()
+                    .map(v2json::to_value)
+                    .collect::<Result<Vec<serde_json::Value>>>()?;
+                Ok(serde_json::to_string(&values)?)
+            }
+        }
+    }
+
+    /// Serialize several macaroons as concatenated V1 or V2 binary macaroons (no base64
+    /// encoding)
+    pub fn serialize_binary_slice(macaroons: &[Macaroon], format: Format) -> Result<Vec<u8>> {
+        let mut binary = Vec::new();
+        for macaroon in macaroons {
+            match format {
+                Format::V1 => binary.extend(v1::serialize_binary(macaroon)?),
+                Format::V2 => binary.extend(v2::encode(macaroon)),
+                Format::V2JSON => {
+                    return Err(MacaroonError::DeserializationError(
+                        "V2JSON is not a binary format".to_string(),
+                    ))
+                }
+                Format::V2Compressed => {
+                    return Err(MacaroonError::SerializationError(
+                        "V2Compressed holds a single macaroon".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(binary)
+    }
+
+    /// Deserialize a token holding several macaroons, as written by
+    /// [Macaroon::serialize_slice]
+    ///
+    /// JSON arrays and base64-encoded V1 or V2 binary macaroons are accepted, as with
+    /// [Macaroon::deserialize]. The macaroons are returned in order.
+    pub fn deserialize_slice<T: AsRef<[u8]>>(token: T) -> Result<Vec<Macaroon>> {
+        let token = token.as_ref();
+        let options = DeserializeOptions::default();
+        options.limits.check_token_len(token.len())?;
+        match token.first() {
+            Some(b'[') => {
+                let values: Vec<serde_json::Value> = serde_json::from_slice(token)?;
+                options.limits.check_caveats(values.len())?;
+                let macaroons = values
+                    .into_iter()
+                    .map(|value| v2json::from_value(value)?.validate())
+                    .collect::<Result<Vec<Macaroon>>>()?;
+                non_empty(macaroons)
+            }
+            _ => Macaroon::deserialize_binary_slice(&base64_decode_flexible(token)?),
+        }
+    }
+
+    /// Deserialize concatenated V1 or V2 binary macaroons (no base64 encoding)
+    pub fn deserialize_binary_slice(data: &[u8]) -> Result<Vec<Macaroon>> {
+        let options = DeserializeOptions::default();
+        options.limits.check_token_len(data.len())?;
+        let mut macaroons = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (macaroon, end) = match rest[0] {
+                2 => {
+                    let (macaroon, end) = v2::deserialize_ref_prefix(rest, &options)?;
+                    (macaroon.to_macaroon(), end)
+                }
+                _ => {
+                    let end = v1_macaroon_len(rest)?;
+                    (v1::deserialize(&rest[..end], &options)?, end)
+                }
+            };
+            macaroons.push(macaroon.validate()?);
+            rest = &rest[end..];
+        }
+        non_empty(macaroons)
+    }
+}
+
+fn non_empty(macaroons: Vec<Macaroon>) -> Result<Vec<Macaroon>> {
+    if macaroons.is_empty() {
+        return Err(MacaroonError::DeserializationError(
+            "empty macaroon slice".to_string(),
+        ));
+    }
+    Ok(macaroons)
+}
+
+/// Length of the V1 macaroon at the start of `data`, which ends with its signature packet
+fn v1_macaroon_len(data: &[u8]) -> Result<usize> {
+    let mut packets = V1Packets::new(data);
+    while let Some(packet) = packets.next() {
+        if packet?.key == "signature" {
+            return Ok(data.len() - packets.remaining().len());
+        }
+    }
+    Err(MacaroonError::IncompleteMacaroon("no signature found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Format, Macaroon, MacaroonKey};
+
+    fn bound_slice() -> Vec<Macaroon> {
+        let root_key = MacaroonKey::generate(b"root key");
+        let mut root = Macaroon::create(
+            Some("https://service.example/".into()),
+            &root_key,
+            "root".into(),
+        )
+        .unwrap();
+        root.add_first_party_caveat("account = 3735928559".into());
+        let mut slice = vec![];
+        for id in ["first", "second"] {
+            let caveat_key = MacaroonKey::generate(id.as_bytes());
+            root.add_third_party_caveat("https://auth.example/", &caveat_key, id.into());
+            let discharge = Macaroon::create(None, &caveat_key, id.into()).unwrap();
+            slice.push(discharge);
+        }
+        for discharge in slice.iter_mut() {
+            root.bind(discharge);
+        }
+        slice.insert(0, root);
+        slice
+    }
+
+    #[test]
+    fn test_slice_roundtrip() {
+        let slice = bound_slice();
+        for format in [Format::V1, Format::V2, Format::V2JSON] {
+            let token = Macaroon::serialize_slice(&slice, format).unwrap();
+            assert_eq!(slice, Macaroon::deserialize_slice(&token).unwrap());
+        }
+        for format in [Format::V1, Format::V2] {
+            let binary = Macaroon::serialize_binary_slice(&slice, format).unwrap();
+            assert_eq!(slice, Macaroon::deserialize_binary_slice(&binary).unwrap());
+        }
+        assert!(Macaroon::serialize_binary_slice(&slice, Format::V2JSON).is_err());
+
+        // a single macaroon is a slice of one
+        let token = slice[0].serialize(Format::V2).unwrap();
+        assert_eq!(slice[..1], Macaroon::deserialize_slice(&token).unwrap()[..]);
+    }
+
+    #[test]
+    fn test_slice_errors() {
+        let slice = bound_slice();
+        let binary = Macaroon::serialize_binary_slice(&slice, Format::V2).unwrap();
+        assert!(Macaroon::deserialize_binary_slice(&binary[..binary.len() - 1]).is_err());
+        let binary = Macaroon::serialize_binary_slice(&slice, Format::V1).unwrap();
+        assert!(Macaroon::deserialize_binary_slice(&binary[..binary.len() - 50]).is_err());
+        assert!(Macaroon::deserialize_binary_slice(b"").is_err());
+        assert!(Macaroon::deserialize_slice("[]").is_err());
+        assert!(Macaroon::deserialize_slice("[1]").is_err());
+    }
+}
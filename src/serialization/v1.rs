@@ -1,7 +1,8 @@
 use crate::caveat::{Caveat, CaveatBuilder};
 use crate::error::MacaroonError;
 use crate::serialization::macaroon_builder::MacaroonBuilder;
-use crate::{ByteString, Macaroon, Result};
+use crate::serialization::{for_each_field, DeserializeOptions, Field, SerializeOptions};
+use crate::{Macaroon, Result};
 use std::str;
 
 // Version 1 fields
@@ -13,10 +14,72 @@ const VID: &str = "vid";
 const CL: &str = "cl";
 
 const HEADER_SIZE: usize = 4;
+// Packet sizes are four hex digits, including the header itself
+const MAX_PACKET_SIZE: usize = 0xffff;
+
+// Upper bound on the number of packets in a single token. Every caveat takes one (first-party)
+// or three (third-party) packets, so this is far beyond any legitimate token.
+const MAX_PACKETS: usize = 8192;
+
+fn field_tag(field: Field) -> &'static str {
+    match field {
+        Field::Location => LOCATION,
+        Field::Identifier => IDENTIFIER,
+        Field::Predicate(_) | Field::CaveatId(_) => CID,
+        Field::VerifierId(_) => VID,
+        Field::CaveatLocation(_) => CL,
+    }
+}
+
+fn packet_size(tag: &str, value: &[u8]) -> usize {
+    HEADER_SIZE + 2 + tag.len() + value.len()
+}
+
+/// Fails with [MacaroonError::SerializationError] if a field of `macaroon` doesn't fit in a
+/// packet, or there are more packets than deserialization accepts
+fn check_packets(macaroon: &Macaroon) -> Result<()> {
+    // the identifier and signature, plus one per other field
+    let mut count = 2;
+    for_each_field(macaroon, |field, value| {
+        let tag = field_tag(field);
+        if field != Field::Identifier {
+            count += 1;
+        }
+        let size = packet_size(tag, value);
+        if size > MAX_PACKET_SIZE {
+            return Err(MacaroonError::SerializationError(format!(
+                "{} of {} bytes is too large for V1: its {} packet would be {} bytes (limit is {})",
+                field,
+                value.len(),
+                tag,
+                size,
+                MAX_PACKET_SIZE
+            )));
+        }
+        Ok(())
+    })?;
+    if count > MAX_PACKETS {
+        return Err(MacaroonError::SerializationError(format!(
+            "{} packets are too many for V1 (limit is {})",
+            count, MAX_PACKETS
+        )));
+    }
+    Ok(())
+}
+
+/// Length of [serialize_binary]'s output, without serializing
+pub(crate) fn binary_len(macaroon: &Macaroon) -> usize {
+    let mut len = packet_size(SIGNATURE, &macaroon.signature);
+    let _ = for_each_field(macaroon, |field, value| {
+        len += packet_size(field_tag(field), value);
+        Ok(())
+    });
+    len
+}
 
 fn serialize_as_packet<'r>(tag: &'r str, value: &'r [u8]) -> Vec<u8> {
     let mut packet: Vec<u8> = Vec::new();
-    let size = HEADER_SIZE + 2 + tag.len() + value.len();
+    let size = packet_size(tag, value);
     packet.extend(packet_header(size));
     packet.extend_from_slice(tag.as_bytes());
     packet.extend_from_slice(b" ");
@@ -41,19 +104,20 @@ fn packet_header(size: usize) -> Vec<u8> {
 }
 
 pub fn serialize_binary(macaroon: &Macaroon) -> Result<Vec<u8>> {
+    check_packets(macaroon)?;
     let mut serialized: Vec<u8> = Vec::new();
     if let Some(ref location) = macaroon.location() {
         serialized.extend(serialize_as_packet(LOCATION, location.as_bytes()));
     };
     serialized.extend(serialize_as_packet(IDENTIFIER, &macaroon.identifier().0));
-    for c in macaroon.caveats() {
+    for c in macaroon.caveats.iter() {
         match c {
             Caveat::FirstParty(fp) => {
-                serialized.extend(serialize_as_packet(CID, &fp.predicate().0));
+                serialized.extend(serialize_as_packet(CID, fp.predicate_ref().as_ref()));
             }
             Caveat::ThirdParty(tp) => {
-                serialized.extend(serialize_as_packet(CID, &tp.id().0));
-                serialized.extend(serialize_as_packet(VID, &tp.verifier_id().0));
+                serialized.extend(serialize_as_packet(CID, tp.id_ref().as_ref()));
+                serialized.extend(serialize_as_packet(VID, tp.verifier_id_ref().as_ref()));
                 serialized.extend(serialize_as_packet(CL, tp.location().as_bytes()))
             }
         }
@@ -62,51 +126,109 @@ pub fn serialize_binary(macaroon: &Macaroon) -> Result<Vec<u8>> {
     Ok(serialized)
 }
 
-pub fn serialize(macaroon: &Macaroon) -> Result<String> {
+pub fn serialize(macaroon: &Macaroon, options: &SerializeOptions) -> Result<String> {
     let buf = serialize_binary(macaroon)?;
-    Ok(base64::encode_config(&buf, base64::URL_SAFE))
+    Ok(base64::encode_config(&buf, options.base64_config()))
 }
 
-struct Packet {
-    key: String,
-    value: Vec<u8>,
+/// A packet of a binary V1 token: a key, such as `identifier` or `cid`, and its value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct V1Packet<'r> {
+    pub key: &'r str,
+    pub value: &'r [u8],
 }
 
-fn deserialize_as_packets(data: &[u8], mut packets: Vec<Packet>) -> Result<Vec<Packet>> {
-    if data.is_empty() {
-        return Ok(packets);
-    }
-    if data.len() < 4 {
-        return Err(MacaroonError::DeserializationError(
-            "packet chunk too small to decode".to_string(),
-        ));
+/// Iterator over the packets of a binary (not base64-encoded) V1 token, parsed one at a time
+///
+/// This is the low-level reader behind V1 deserialization, for tooling that needs to look at
+/// tokens packet by packet, including malformed ones. Each packet is checked for well-formed
+/// framing only, not for its key being known. After an error, the iterator ends.
+///
+/// ```rust
+/// use macaroon::{Format, Macaroon, MacaroonKey, V1Packets};
+///
+/// let mut macaroon = Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+/// macaroon.add_first_party_caveat("account = 3735928559".into());
+/// let token = base64::decode_config(macaroon.serialize(Format::V1).unwrap(), base64::URL_SAFE).unwrap();
+///
+/// let keys = V1Packets::new(&token)
+///     .map(|packet| packet.map(|p| p.key))
+///     .collect::<Result<Vec<&str>, _>>()
+///     .unwrap();
+/// assert_eq!(vec!["identifier", "cid", "signature"], keys);
+/// ```
+#[derive(Clone, Debug)]
+pub struct V1Packets<'r> {
+    data: &'r [u8],
+    /// Of `data` in the token
+    offset: usize,
+    count: usize,
+    failed: bool,
+}
+
+impl<'r> V1Packets<'r> {
+    pub fn new(data: &'r [u8]) -> V1Packets<'r> {
+        V1Packets {
+            data,
+            offset: 0,
+            count: 0,
+            failed: false,
+        }
     }
-    let hex: &str = str::from_utf8(&data[..4])?;
-    let size: usize = usize::from_str_radix(hex, 16)?;
-    if size > data.len() {
-        return Err(MacaroonError::DeserializationError(
-            "packet chunk size larger than token".to_string(),
-        ));
+
+    /// The part of the token after the packets read so far
+    pub(crate) fn remaining(&self) -> &'r [u8] {
+        self.data
     }
-    if size <= 4 {
-        return Err(MacaroonError::DeserializationError(
-            "packet chunk size too small".to_string(),
-        ));
+
+    fn read_packet(&mut self) -> Result<V1Packet<'r>> {
+        let data = self.data;
+        if self.count >= MAX_PACKETS {
+            return Err(MacaroonError::DeserializationError(format!(
+                "too many packets in token (limit is {})",
+                MAX_PACKETS
+            )));
+        }
+        // four hex digits, giving a size which covers them and fits in the token
+        let size = data
+            .get(..HEADER_SIZE)
+            .and_then(|header| str::from_utf8(header).ok())
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .filter(|size| *size > HEADER_SIZE && *size <= data.len())
+            .ok_or(MacaroonError::InvalidPacketHeader {
+                offset: self.offset,
+            })?;
+        let packet_data = &data[4..size];
+        let index = split_index(packet_data)?;
+        let (key_slice, value_slice) = packet_data.split_at(index);
+        if value_slice.len() < 2 {
+            return Err(MacaroonError::DeserializationError(
+                "packet value size too small".to_string(),
+            ));
+        }
+        let packet = V1Packet {
+            key: str::from_utf8(key_slice)?,
+            // skip beginning space and terminating \n
+            value: &value_slice[1..value_slice.len() - 1],
+        };
+        self.data = &data[size..];
+        self.offset += size;
+        self.count += 1;
+        Ok(packet)
     }
-    let packet_data = &data[4..size];
-    let index = split_index(packet_data)?;
-    let (key_slice, value_slice) = packet_data.split_at(index);
-    if value_slice.len() < 2 {
-        return Err(MacaroonError::DeserializationError(
-            "packet value size too small".to_string(),
-        ));
+}
+
+impl<'r> Iterator for V1Packets<'r> {
+    type Item = Result<V1Packet<'r>>;
+
+    fn next(&mut self) -> Option<Result<V1Packet<'r>>> {
+        if self.failed || self.data.is_empty() {
+            return None;
+        }
+        let packet = self.read_packet();
+        self.failed = packet.is_err();
+        Some(packet)
     }
-    packets.push(Packet {
-        key: String::from_utf8(key_slice.to_vec())?,
-        // skip beginning space and terminating \n
-        value: value_slice[1..value_slice.len() - 1].to_vec(),
-    });
-    deserialize_as_packets(&data[size..], packets)
 }
 
 fn split_index(packet: &[u8]) -> Result<usize> {
@@ -119,17 +241,29 @@ fn split_index(packet: &[u8]) -> Result<usize> {
 }
 
 /// Takes a binary token (not base64-encoded)
-pub fn deserialize(data: &[u8]) -> Result<Macaroon> {
-    let data = data.to_vec();
+pub fn deserialize(data: &[u8], options: &DeserializeOptions) -> Result<Macaroon> {
     let mut builder: MacaroonBuilder = MacaroonBuilder::new();
+    deserialize_into(data, options, &mut builder)?;
+    builder.build()
+}
+
+/// Takes a binary token (not base64-encoded), and fills in the provided builder
+pub fn deserialize_into(
+    data: &[u8],
+    options: &DeserializeOptions,
+    builder: &mut MacaroonBuilder,
+) -> Result<()> {
     let mut caveat_builder: CaveatBuilder = CaveatBuilder::new();
-    for packet in deserialize_as_packets(data.as_slice(), Vec::new())? {
-        match packet.key.as_str() {
+    let mut caveat_count: usize = 0;
+    for packet in V1Packets::new(data) {
+        let packet = packet?;
+        options.limits.check_field_len(packet.value.len())?;
+        match packet.key {
             LOCATION => {
-                builder.set_location(&String::from_utf8(packet.value)?);
+                builder.set_location(str::from_utf8(packet.value)?);
             }
             IDENTIFIER => {
-                builder.set_identifier(ByteString(packet.value));
+                builder.set_identifier_from_slice(packet.value);
             }
             SIGNATURE => {
                 if caveat_builder.has_id() {
@@ -141,27 +275,27 @@ pub fn deserialize(data: &[u8]) -> Result<Macaroon> {
                         "deserialize_v1: Deserialization error - signature length is {}",
                         packet.value.len()
                     );
-                    return Err(MacaroonError::DeserializationError(String::from(
-                        "Illegal signature \
-                         length in \
-                         packet",
-                    )));
+                    return Err(MacaroonError::SignatureLengthMismatch {
+                        got: packet.value.len(),
+                    });
                 }
-                builder.set_signature(&packet.value);
+                builder.set_signature(packet.value);
             }
             CID => {
+                caveat_count += 1;
+                options.limits.check_caveats(caveat_count)?;
                 if caveat_builder.has_id() {
                     builder.add_caveat(caveat_builder.build()?);
                     caveat_builder = CaveatBuilder::new();
-                    caveat_builder.add_id(ByteString(packet.value));
+                    caveat_builder.add_id(packet.value.into());
                 } else {
-                    caveat_builder.add_id(ByteString(packet.value));
+                    caveat_builder.add_id(packet.value.into());
                 }
             }
             VID => {
-                caveat_builder.add_verifier_id(ByteString(packet.value));
+                caveat_builder.add_verifier_id(packet.value.into());
             }
-            CL => caveat_builder.add_location(String::from_utf8(packet.value)?),
+            CL => caveat_builder.add_location(str::from_utf8(packet.value)?.to_string()),
             _ => {
                 return Err(MacaroonError::DeserializationError(String::from(
                     "Unknown key",
@@ -169,12 +303,12 @@ pub fn deserialize(data: &[u8]) -> Result<Macaroon> {
             }
         };
     }
-    builder.build()
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ByteString, Caveat, Macaroon, MacaroonKey};
+    use crate::{ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey};
 
     #[test]
     fn test_deserialize() {
@@ -185,7 +319,7 @@ mod tests {
         ]
         .into();
         let data = base64::decode_config(serialized, base64::URL_SAFE).unwrap();
-        let macaroon = super::deserialize(&data).unwrap();
+        let macaroon = super::deserialize(&data, &Default::default()).unwrap();
         let macaroon_lib = Macaroon::deserialize(serialized).unwrap();
         assert_eq!(macaroon, macaroon_lib);
         assert!(macaroon.location().is_some());
@@ -199,7 +333,7 @@ mod tests {
         ]
         .into();
         let data = base64::decode_config(serialized, base64::URL_SAFE).unwrap();
-        let macaroon = super::deserialize(&data).unwrap();
+        let macaroon = super::deserialize(&data, &Default::default()).unwrap();
         assert!(macaroon.location().is_some());
         assert_eq!("http://example.org/", &macaroon.location().unwrap());
         assert_eq!(ByteString::from("keyid"), macaroon.identifier());
@@ -221,7 +355,7 @@ mod tests {
         ]
         .into();
         let data = base64::decode(serialized).unwrap();
-        let macaroon = super::deserialize(&data).unwrap();
+        let macaroon = super::deserialize(&data, &Default::default()).unwrap();
         let macaroon_lib = Macaroon::deserialize(serialized).unwrap();
         assert_eq!(macaroon, macaroon_lib);
         assert!(macaroon.location().is_some());
@@ -264,20 +398,20 @@ mod tests {
     #[test]
     fn test_deserialize_bad_data() {
         // these are all expected to fail... but not panic!
-        assert!(super::deserialize(b"").is_err());
-        assert!(super::deserialize(b"12345").is_err());
-        assert!(super::deserialize(b"\0").is_err());
-        assert!(super::deserialize(b"NDhJe_A==").is_err());
+        assert!(super::deserialize(b"", &Default::default()).is_err());
+        assert!(super::deserialize(b"12345", &Default::default()).is_err());
+        assert!(super::deserialize(b"\0", &Default::default()).is_err());
+        assert!(super::deserialize(b"NDhJe_A==", &Default::default()).is_err());
 
         // these failed fuzz testing for this deserializer (V1)
-        assert!(Macaroon::deserialize(&vec![70, 70, 102, 70]).is_err());
+        assert!(Macaroon::deserialize(vec![70, 70, 102, 70]).is_err());
         let tok = base64::encode_config(
-            &[97, 97, 97, 97, 97, 97, 97, 97, 97, 97, 10],
+            [97, 97, 97, 97, 97, 97, 97, 97, 97, 97, 10],
             base64::URL_SAFE,
         );
-        assert!(Macaroon::deserialize(&tok.as_bytes()).is_err());
+        assert!(Macaroon::deserialize(tok.as_bytes()).is_err());
         let tok = base64::encode_config(
-            &[
+            [
                 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48,
                 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48,
                 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48, 48,
@@ -285,13 +419,115 @@ mod tests {
             ],
             base64::URL_SAFE,
         );
-        assert!(Macaroon::deserialize(&tok.as_bytes()).is_err());
+        assert!(Macaroon::deserialize(tok.as_bytes()).is_err());
         let tok = base64::encode_config(
-            &[
+            [
                 48, 48, 49, 48, 49, 48, 52, 48, 48, 48, 48, 48, 48, 48, 48, 32, 126, 10,
             ],
             base64::URL_SAFE,
         );
-        assert!(Macaroon::deserialize(&tok.as_bytes()).is_err());
+        assert!(Macaroon::deserialize(tok.as_bytes()).is_err());
+    }
+
+    fn synthetic_token(caveats: usize) -> Vec<u8> {
+        let mut token = super::serialize_as_packet(super::IDENTIFIER, b"keyid");
+        for _ in 0..caveats {
+            token.extend(super::serialize_as_packet(super::CID, b"a"));
+        }
+        token.extend(super::serialize_as_packet(super::SIGNATURE, &[0; 32]));
+        token
+    }
+
+    #[test]
+    fn test_deserialize_many_packets() {
+        // just under the packet limit: parsed iteratively, without exhausting the stack
+        let caveats = super::MAX_PACKETS - 2;
+        let macaroon = super::deserialize(&synthetic_token(caveats), &Default::default()).unwrap();
+        assert_eq!(caveats, macaroon.caveats().len());
+
+        // far beyond it: rejected rather than parsed
+        assert!(matches!(
+            super::deserialize(&synthetic_token(50_000), &Default::default()),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_limits() {
+        use crate::{DeserializationLimits, DeserializeOptions, Format};
+
+        let options = |limits| DeserializeOptions {
+            limits,
+            ..Default::default()
+        };
+        let token = synthetic_token(3);
+        assert!(super::deserialize(&token, &options(Default::default())).is_ok());
+        let few_caveats = DeserializationLimits {
+            max_caveats: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            super::deserialize(&token, &options(few_caveats)),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+        let short_fields = DeserializationLimits {
+            max_field_len: 4,
+            ..Default::default()
+        };
+        assert!(matches!(
+            super::deserialize(&token, &options(short_fields)),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+
+        // a field too large for a V1 packet can't be serialized, though V2 can hold it
+        let mut macaroon =
+            Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_first_party_caveat(vec![b'a'; 0xfff8].into());
+        match macaroon.serialize(Format::V1) {
+            Err(MacaroonError::SerializationError(s)) => assert_eq!(
+                "predicate of caveat 1 of 65528 bytes is too large for V1: its cid packet would be 65537 bytes (limit is 65535)",
+                s
+            ),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(macaroon.serialize(Format::V2).is_ok());
+        macaroon.add_first_party_caveat(vec![b'a'; 0x10000].into());
+        for format in [Format::V2, Format::V2JSON] {
+            match macaroon.serialize(format) {
+                Err(MacaroonError::SerializationError(s)) => {
+                    assert!(
+                        s.starts_with("predicate of caveat 2 of 65536 bytes"),
+                        "{}",
+                        s
+                    )
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        use super::{V1Packet, V1Packets};
+
+        let token = synthetic_token(1);
+        let packets: Vec<V1Packet> = V1Packets::new(&token).map(Result::unwrap).collect();
+        assert_eq!(3, packets.len());
+        assert_eq!(
+            V1Packet {
+                key: "cid",
+                value: b"a"
+            },
+            packets[1]
+        );
+
+        // packets before a malformed one are still read, and the iterator stops at the error
+        let mut trailing = token.clone();
+        trailing.extend_from_slice(b"zz");
+        let mut iter = V1Packets::new(&trailing);
+        assert_eq!(3, iter.by_ref().take(3).filter(Result::is_ok).count());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
     }
 }
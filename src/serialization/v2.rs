@@ -1,7 +1,12 @@
-use crate::caveat::{Caveat, CaveatBuilder};
+use crate::caveat::Caveat;
 use crate::error::MacaroonError;
+use crate::macaroon_ref::{CaveatRef, MacaroonRef};
+use crate::raw::RawField;
 use crate::serialization::macaroon_builder::MacaroonBuilder;
-use crate::{ByteString, Macaroon, Result};
+use crate::serialization::{check_default_limits, DeserializeOptions, Format, SerializeOptions};
+use crate::{Macaroon, Result};
+use std::convert::TryFrom;
+use std::str;
 
 // Version 2 fields
 const EOS: u8 = 0;
@@ -11,7 +16,6 @@ const VID: u8 = 4;
 const SIGNATURE: u8 = 6;
 
 const VARINT_PACK_SIZE: usize = 128;
-const MAX_FIELD_SIZE_BYTES: usize = 65535;
 
 fn varint_size(size: usize) -> Vec<u8> {
     let mut buffer: Vec<u8> = Vec::new();
@@ -25,6 +29,17 @@ fn varint_size(size: usize) -> Vec<u8> {
     buffer
 }
 
+/// Length of a field with its tag and size, as written by [serialize_field]
+fn field_len(value: &[u8]) -> usize {
+    let mut varint_len = 1;
+    let mut size = value.len();
+    while size >= VARINT_PACK_SIZE {
+        varint_len += 1;
+        size >>= 7;
+    }
+    1 + varint_len + value.len()
+}
+
 fn serialize_field(tag: u8, value: &[u8], buffer: &mut Vec<u8>) {
     buffer.push(tag);
     buffer.extend(varint_size(value.len()));
@@ -32,55 +47,101 @@ fn serialize_field(tag: u8, value: &[u8], buffer: &mut Vec<u8>) {
 }
 
 pub fn serialize_binary(macaroon: &Macaroon) -> Result<Vec<u8>> {
+    check_default_limits(macaroon, Format::V2)?;
+    Ok(encode(macaroon))
+}
+
+/// Infallible V2 binary encoding, shared by [serialize_binary] and the `From<&Macaroon>`
+/// conversion, which doesn't check the macaroon fits the default deserialization limits
+pub fn encode(macaroon: &Macaroon) -> Vec<u8> {
+    let mut buffer = encode_content(macaroon);
+    serialize_field(SIGNATURE, &macaroon.signature(), &mut buffer);
+    buffer
+}
+
+/// Length of [encode]'s output, without encoding
+pub(crate) fn binary_len(macaroon: &Macaroon) -> usize {
+    // version, and the EOS after the header and the caveats
+    let mut len = 3 + field_len(&macaroon.signature);
+    if let Some(location) = macaroon.location.as_ref() {
+        len += field_len(location.as_bytes());
+    }
+    len += field_len(macaroon.identifier.as_ref());
+    for c in macaroon.caveats.iter() {
+        len += 1;
+        match c {
+            Caveat::FirstParty(fp) => len += field_len(fp.predicate_ref().as_ref()),
+            Caveat::ThirdParty(tp) => {
+                len += field_len(tp.location().as_bytes())
+                    + field_len(tp.id_ref().as_ref())
+                    + field_len(tp.verifier_id_ref().as_ref())
+            }
+        }
+    }
+    len
+}
+
+/// The V2 binary encoding up to the signature, ie what a macaroon says rather than how it's
+/// signed
+pub fn encode_content(macaroon: &Macaroon) -> Vec<u8> {
     let mut buffer: Vec<u8> = vec![2 /* version */];
     if let Some(ref location) = macaroon.location() {
         serialize_field(LOCATION, location.as_bytes(), &mut buffer);
     };
     serialize_field(IDENTIFIER, &macaroon.identifier().0, &mut buffer);
     buffer.push(EOS);
-    for c in macaroon.caveats() {
+    for c in macaroon.caveats.iter() {
         match c {
             Caveat::FirstParty(fp) => {
-                serialize_field(IDENTIFIER, &fp.predicate().0, &mut buffer);
+                serialize_field(IDENTIFIER, fp.predicate_ref().as_ref(), &mut buffer);
                 buffer.push(EOS);
             }
             Caveat::ThirdParty(tp) => {
                 serialize_field(LOCATION, tp.location().as_bytes(), &mut buffer);
-                serialize_field(IDENTIFIER, &tp.id().0, &mut buffer);
-                serialize_field(VID, &tp.verifier_id().0, &mut buffer);
+                serialize_field(IDENTIFIER, tp.id_ref().as_ref(), &mut buffer);
+                serialize_field(VID, tp.verifier_id_ref().as_ref(), &mut buffer);
                 buffer.push(EOS);
             }
         }
     }
     buffer.push(EOS);
-    serialize_field(SIGNATURE, &macaroon.signature(), &mut buffer);
-    Ok(buffer)
+    buffer
 }
 
-pub fn serialize(macaroon: &Macaroon) -> Result<String> {
+pub fn serialize(macaroon: &Macaroon, options: &SerializeOptions) -> Result<String> {
     let buf = serialize_binary(macaroon)?;
-    Ok(base64::encode_config(&buf, base64::URL_SAFE))
+    Ok(base64::encode_config(&buf, options.base64_config()))
 }
 
+#[derive(Clone)]
 struct Deserializer<'r> {
     data: &'r [u8],
     index: usize,
+    strict: bool,
+    max_field_len: usize,
 }
 
 impl<'r> Deserializer<'r> {
-    pub fn new(data: &[u8]) -> Deserializer {
-        Deserializer { data, index: 0 }
+    pub fn new(data: &'r [u8], strict: bool, max_field_len: usize) -> Deserializer<'r> {
+        Deserializer {
+            data,
+            index: 0,
+            strict,
+            max_field_len,
+        }
     }
 
     fn get_byte(&mut self) -> Result<u8> {
-        if self.data.is_empty() || self.index > self.data.len() - 1 {
-            return Err(MacaroonError::DeserializationError(String::from(
-                "Buffer overrun",
-            )));
+        match self.data.get(self.index) {
+            Some(&byte) => {
+                self.index += 1;
+                Ok(byte)
+            }
+            None => Err(MacaroonError::DeserializationError(format!(
+                "Buffer overrun at offset {}",
+                self.index
+            ))),
         }
-        let byte = self.data[self.index];
-        self.index += 1;
-        Ok(byte)
     }
 
     pub fn get_tag(&mut self) -> Result<u8> {
@@ -91,160 +152,275 @@ impl<'r> Deserializer<'r> {
         let eos = self.get_byte()?;
         match eos {
             EOS => Ok(eos),
-            _ => Err(MacaroonError::DeserializationError(String::from(
-                "Expected EOS",
+            _ => Err(MacaroonError::DeserializationError(format!(
+                "Expected EOS at offset {}",
+                self.index - 1
             ))),
         }
     }
 
-    pub fn get_field(&mut self) -> Result<Vec<u8>> {
+    pub fn get_field_slice(&mut self) -> Result<&'r [u8]> {
         let size: usize = self.get_field_size()?;
-        if size + self.index > self.data.len() {
-            return Err(MacaroonError::DeserializationError(String::from(
-                "Unexpected end of \
-                 field",
-            )));
-        }
+        let end = match self.index.checked_add(size) {
+            Some(end) if end <= self.data.len() => end,
+            _ => {
+                return Err(MacaroonError::DeserializationError(format!(
+                    "Unexpected end of field at offset {} ({} bytes declared, {} available)",
+                    self.index,
+                    size,
+                    self.data.len() - self.index
+                )))
+            }
+        };
 
-        let field: Vec<u8> = self.data[self.index..self.index + size].to_vec();
-        self.index += size;
+        let field: &'r [u8] = &self.data[self.index..end];
+        self.index = end;
         Ok(field)
     }
 
+    /// Reads one caveat, starting after its first `tag` and ending after its EOS
+    fn get_caveat(&mut self, mut tag: u8) -> Result<CaveatRef<'r>> {
+        let mut location: Option<&'r str> = None;
+        if tag == LOCATION {
+            location = Some(str::from_utf8(self.get_field_slice()?)?);
+            tag = self.get_tag()?;
+        }
+        let id: &'r [u8] = match tag {
+            IDENTIFIER => self.get_field_slice()?,
+            _ => {
+                return Err(MacaroonError::DeserializationError(String::from(
+                    "Caveat identifier \
+                     not found",
+                )))
+            }
+        };
+        let verifier_id: Option<&'r [u8]> = match self.get_tag()? {
+            VID => {
+                let field: &'r [u8] = self.get_field_slice()?;
+                self.get_eos()?;
+                Some(field)
+            }
+            EOS => None,
+            _ => {
+                return Err(MacaroonError::DeserializationError(
+                    "Unexpected caveat tag found".into(),
+                ))
+            }
+        };
+        match (verifier_id, location) {
+            (None, None) => Ok(CaveatRef::FirstParty { predicate: id }),
+            (Some(verifier_id), Some(location)) => Ok(CaveatRef::ThirdParty {
+                id,
+                verifier_id,
+                location,
+            }),
+            (None, Some(_)) => Err(MacaroonError::IncompleteCaveat("no verifier ID found")),
+            (Some(_), None) => Err(MacaroonError::IncompleteCaveat("no location found")),
+        }
+    }
+
+    /// Reads an unsigned LEB128 varint field size.
+    ///
+    /// All arithmetic is done on `u64` with explicit overflow checks, so the result doesn't
+    /// depend on the width of `usize`. In strict mode, non-minimal encodings (redundant trailing
+    /// zero groups, like `0x80 0x00` for zero) are rejected.
     fn get_field_size(&mut self) -> Result<usize> {
-        let mut size: usize = 0;
-        let mut shift: usize = 0;
-        let mut byte: u8;
-        while shift <= (usize::BITS - 8) as usize {
-            byte = self.get_byte()?;
-            if byte & 128 != 0 {
-                size |= ((byte & 127) as usize) << shift;
-            } else {
-                size |= (byte as usize) << shift;
-                if size > MAX_FIELD_SIZE_BYTES {
+        let start = self.index;
+        let mut size: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte = self.get_byte()?;
+            let group = u64::from(byte & 127);
+            if shift >= u64::BITS || (group << shift) >> shift != group {
+                return Err(MacaroonError::DeserializationError(format!(
+                    "Field size varint at offset {} overflows",
+                    start
+                )));
+            }
+            size |= group << shift;
+            if byte & 128 == 0 {
+                if self.strict && shift > 0 && group == 0 {
                     return Err(MacaroonError::DeserializationError(format!(
-                        "field size too large ({} > {})",
-                        size, MAX_FIELD_SIZE_BYTES
+                        "Non-minimal field size varint at offset {}",
+                        start
                     )));
                 }
-                return Ok(size);
+                return match usize::try_from(size) {
+                    Ok(size) if size <= self.max_field_len => Ok(size),
+                    _ => Err(MacaroonError::LimitExceeded(format!(
+                        "field size too large at offset {} ({} > {})",
+                        start, size, self.max_field_len
+                    ))),
+                };
             }
             shift += 7;
         }
-        Err(MacaroonError::DeserializationError(String::from(
-            "Error in field size",
-        )))
     }
 }
 
 /// Takes a binary token (not base64-encoded)
-pub fn deserialize(data: &[u8]) -> Result<Macaroon> {
+pub fn deserialize(data: &[u8], options: &DeserializeOptions) -> Result<Macaroon> {
     let mut builder: MacaroonBuilder = MacaroonBuilder::new();
-    let mut deserializer: Deserializer = Deserializer::new(data);
-    if deserializer.get_byte()? != 2 {
-        return Err(MacaroonError::DeserializationError(String::from(
-            "Wrong version number",
-        )));
+    deserialize_into(data, options, &mut builder)?;
+    builder.build()
+}
+
+/// Takes a binary token (not base64-encoded), and fills in the provided builder
+pub fn deserialize_into(
+    data: &[u8],
+    options: &DeserializeOptions,
+    builder: &mut MacaroonBuilder,
+) -> Result<()> {
+    let macaroon: MacaroonRef = deserialize_ref(data, options)?;
+    if let Some(location) = macaroon.location() {
+        builder.set_location(location);
     }
+    builder.set_identifier_from_slice(macaroon.identifier());
+    for c in macaroon.caveats() {
+        builder.add_caveat(c.to_caveat());
+    }
+    builder.set_signature(&macaroon.signature());
+    Ok(())
+}
+
+/// Takes a binary token (not base64-encoded), and parses it without copying any fields
+pub fn deserialize_ref<'r>(
+    data: &'r [u8],
+    options: &DeserializeOptions,
+) -> Result<MacaroonRef<'r>> {
+    deserialize_ref_prefix(data, options).map(|(macaroon, _)| macaroon)
+}
+
+/// Like [deserialize_ref], for a macaroon at the start of `data`, also returning where it ends
+pub fn deserialize_ref_prefix<'r>(
+    data: &'r [u8],
+    options: &DeserializeOptions,
+) -> Result<(MacaroonRef<'r>, usize)> {
+    let mut deserializer: Deserializer =
+        Deserializer::new(data, options.strict, options.limits.max_field_len);
+    let version = deserializer.get_byte()?;
+    if version != 2 {
+        return Err(MacaroonError::UnsupportedVersion(version));
+    }
+    let mut location: Option<&'r str> = None;
     let mut tag: u8 = deserializer.get_tag()?;
-    match tag {
-        LOCATION => builder.set_location(&String::from_utf8(deserializer.get_field()?)?),
-        IDENTIFIER => builder.set_identifier(ByteString(deserializer.get_field()?)),
+    if tag == LOCATION {
+        location = Some(str::from_utf8(deserializer.get_field_slice()?)?);
+        tag = deserializer.get_tag()?;
+    }
+    let identifier: &'r [u8] = match tag {
+        IDENTIFIER => deserializer.get_field_slice()?,
         _ => {
             return Err(MacaroonError::DeserializationError(String::from(
                 "Identifier not found",
             )))
         }
-    }
-    if builder.has_location() {
-        tag = deserializer.get_tag()?;
-        match tag {
-            IDENTIFIER => {
-                builder.set_identifier(ByteString(deserializer.get_field()?));
-            }
-            _ => {
-                return Err(MacaroonError::DeserializationError(String::from(
-                    "Identifier not \
-                     found",
-                )))
-            }
-        }
+    };
+    if identifier.is_empty() {
+        return Err(MacaroonError::IncompleteMacaroon("no identifier found"));
     }
     deserializer.get_eos()?;
+    let caveats_start = deserializer.index;
+    let mut caveat_count: usize = 0;
     tag = deserializer.get_tag()?;
     while tag != EOS {
-        let mut caveat_builder: CaveatBuilder = CaveatBuilder::new();
-        match tag {
-            LOCATION => {
-                let field: Vec<u8> = deserializer.get_field()?;
-                caveat_builder.add_location(String::from_utf8(field)?);
-            }
-            IDENTIFIER => caveat_builder.add_id(ByteString(deserializer.get_field()?)),
-            _ => {
-                return Err(MacaroonError::DeserializationError(String::from(
-                    "Caveat identifier \
-                     not found",
-                )))
-            }
-        }
-        if caveat_builder.has_location() {
-            tag = deserializer.get_tag()?;
-            match tag {
-                IDENTIFIER => {
-                    let field: Vec<u8> = deserializer.get_field()?;
-                    caveat_builder.add_id(ByteString(field));
-                }
-                _ => {
-                    return Err(MacaroonError::DeserializationError(String::from(
-                        "Caveat identifier \
-                         not found",
-                    )))
-                }
-            }
-        }
+        options.limits.check_caveats(caveat_count + 1)?;
+        deserializer.get_caveat(tag)?;
+        caveat_count += 1;
         tag = deserializer.get_tag()?;
-        match tag {
-            VID => {
-                let field: Vec<u8> = deserializer.get_field()?;
-                caveat_builder.add_verifier_id(ByteString(field));
-                builder.add_caveat(caveat_builder.build()?);
-                deserializer.get_eos()?;
-                tag = deserializer.get_tag()?;
-            }
-            EOS => {
-                builder.add_caveat(caveat_builder.build()?);
-                tag = deserializer.get_tag()?;
-            }
-            _ => {
-                return Err(MacaroonError::DeserializationError(
-                    "Unexpected caveat tag found".into(),
-                ))
-            }
-        }
     }
+    let caveats: &'r [u8] = &data[caveats_start..deserializer.index];
     tag = deserializer.get_tag()?;
-    if tag == SIGNATURE {
-        let sig: Vec<u8> = deserializer.get_field()?;
-        if sig.len() != 32 {
-            return Err(MacaroonError::DeserializationError(
-                "Bad signature length".into(),
-            ));
-        }
-        builder.set_signature(&sig);
-    } else {
+    if tag != SIGNATURE {
         return Err(MacaroonError::DeserializationError(
             "Unexpected tag found".into(),
         ));
     }
-    builder.build()
+    let sig: &'r [u8] = deserializer.get_field_slice()?;
+    let signature = <&[u8; 32]>::try_from(sig)
+        .map_err(|_| MacaroonError::SignatureLengthMismatch { got: sig.len() })?;
+    let macaroon = MacaroonRef::new(
+        location,
+        identifier,
+        CaveatsRef {
+            data: caveats,
+            strict: options.strict,
+            count: caveat_count,
+        },
+        signature,
+    );
+    Ok((macaroon, deserializer.index))
 }
 
+/// Reads the fields of a binary V2 token into `fields` without checking their order or meaning,
+/// for [RawToken](crate::RawToken). Stops at the first framing error, returning it.
+pub(crate) fn raw_fields<'r>(data: &'r [u8], fields: &mut Vec<RawField<'r>>) -> Result<()> {
+    let mut deserializer: Deserializer = Deserializer::new(data, false, usize::MAX);
+    let version = deserializer.get_byte()?;
+    fields.push(RawField::Version { offset: 0, version });
+    while deserializer.index < data.len() {
+        let offset = deserializer.index;
+        match deserializer.get_tag()? {
+            EOS => fields.push(RawField::Eos { offset }),
+            tag => {
+                let value = deserializer.get_field_slice()?;
+                fields.push(RawField::Field { offset, tag, value });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Caveat section of a V2 token which has already been validated by [deserialize_ref]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaveatsRef<'r> {
+    data: &'r [u8],
+    strict: bool,
+    count: usize,
+}
+
+impl<'r> CaveatsRef<'r> {
+    pub fn iter(&self) -> CaveatRefIter<'r> {
+        CaveatRefIter {
+            // field sizes were checked against the limits by deserialize_ref
+            deserializer: Deserializer::new(self.data, self.strict, usize::MAX),
+            remaining: self.count,
+        }
+    }
+}
+
+/// Iterator over the caveats of a [MacaroonRef](crate::MacaroonRef), in order
+pub struct CaveatRefIter<'r> {
+    deserializer: Deserializer<'r>,
+    remaining: usize,
+}
+
+impl<'r> Iterator for CaveatRefIter<'r> {
+    type Item = CaveatRef<'r>;
+
+    fn next(&mut self) -> Option<CaveatRef<'r>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        // the section was fully parsed by deserialize_ref, so this can't fail
+        let tag = self.deserializer.get_tag().ok()?;
+        self.deserializer.get_caveat(tag).ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'r> ExactSizeIterator for CaveatRefIter<'r> {}
+
 #[cfg(test)]
 mod tests {
     use crate::caveat;
     use crate::caveat::Caveat;
     use crate::serialization::macaroon_builder::MacaroonBuilder;
-    use crate::{ByteString, Macaroon, MacaroonKey};
+    use crate::{ByteString, Macaroon, MacaroonKey, SerializeOptions};
 
     #[test]
     fn test_deserialize() {
@@ -254,7 +430,7 @@ mod tests {
             134, 218, 11, 168, 94, 140, 66, 169, 60, 141, 14, 18, 94, 252,
         ];
         let serialized: Vec<u8> = base64::decode_config(SERIALIZED, base64::URL_SAFE).unwrap();
-        let macaroon = super::deserialize(&serialized).unwrap();
+        let macaroon = super::deserialize(&serialized, &Default::default()).unwrap();
         assert_eq!("http://example.org/", &macaroon.location().unwrap());
         assert_eq!(ByteString::from("keyid"), macaroon.identifier());
         assert_eq!(2, macaroon.caveats().len());
@@ -284,8 +460,11 @@ mod tests {
         builder.set_location("http://example.org/");
         builder.set_identifier("keyid".into());
         builder.set_signature(&SIGNATURE);
-        let serialized = super::serialize(&builder.build().unwrap()).unwrap();
-        assert_eq!(SERIALIZED, serialized);
+        let macaroon = builder.build().unwrap();
+        let padded = super::serialize(&macaroon, &SerializeOptions { padding: true }).unwrap();
+        assert_eq!(SERIALIZED, padded);
+        let unpadded = super::serialize(&macaroon, &Default::default()).unwrap();
+        assert_eq!(SERIALIZED.trim_end_matches('='), unpadded);
     }
 
     #[test]
@@ -304,7 +483,7 @@ mod tests {
             "caveat".into(),
         );
         let serialized = super::serialize_binary(&macaroon).unwrap();
-        macaroon = super::deserialize(&serialized).unwrap();
+        macaroon = super::deserialize(&serialized, &Default::default()).unwrap();
         assert_eq!("http://example.org/", &macaroon.location().unwrap());
         assert_eq!(ByteString::from("keyid"), macaroon.identifier());
         assert_eq!(3, macaroon.caveats().len());
@@ -333,11 +512,107 @@ mod tests {
     #[test]
     fn test_deserialize_bad_data() {
         // these are all expected to fail... but not panic!
-        assert!(super::deserialize(b"").is_err());
-        assert!(super::deserialize(b"12345").is_err());
-        assert!(super::deserialize(b"\0").is_err());
+        assert!(super::deserialize(b"", &Default::default()).is_err());
+        assert!(super::deserialize(b"12345", &Default::default()).is_err());
+        assert!(super::deserialize(b"\0", &Default::default()).is_err());
 
         // these failed fuzz testing for this deserializer (V2)
-        assert!(Macaroon::deserialize(&vec![2, 2, 212, 212, 212, 212]).is_err());
+        assert!(Macaroon::deserialize(vec![2, 2, 212, 212, 212, 212]).is_err());
+    }
+
+    #[test]
+    fn test_field_size_varint() {
+        use super::Deserializer;
+        use crate::MacaroonError;
+
+        let size =
+            |data: &[u8], strict: bool| Deserializer::new(data, strict, 65535).get_field_size();
+        assert_eq!(0, size(&[0], true).unwrap());
+        assert_eq!(300, size(&[0xac, 0x02], true).unwrap());
+        assert_eq!(65535, size(&[0xff, 0xff, 0x03], true).unwrap());
+
+        // non-minimal encodings are only rejected in strict mode
+        assert_eq!(0, size(&[0x80, 0x00], false).unwrap());
+        assert_eq!(5, size(&[0x85, 0x80, 0x00], false).unwrap());
+        assert!(size(&[0x80, 0x00], true).is_err());
+        assert!(size(&[0x85, 0x80, 0x00], true).is_err());
+
+        // too large, overflowing, or truncated sizes are always errors, and report the offset
+        assert!(size(&[0x80, 0x80, 0x04], false).is_err());
+        let overflow = [0xff; 11];
+        assert!(size(&overflow, false).is_err());
+        match size(&[0xff, 0xff], false) {
+            Err(MacaroonError::DeserializationError(s)) => assert!(s.contains("offset 2"), "{}", s),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut deserializer =
+            Deserializer::new(&[0, 0, 0x80, 0x80, 0x80, 0x80, 0x10], false, 65535);
+        deserializer.get_tag().unwrap();
+        deserializer.get_tag().unwrap();
+        match deserializer.get_field_size() {
+            Err(MacaroonError::LimitExceeded(s)) => assert!(s.contains("offset 2"), "{}", s),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_strict() {
+        use crate::serialization::DeserializeOptions;
+
+        // location field with a non-minimal length prefix
+        let mut token = vec![2, 1, 0x93, 0x80, 0x00];
+        token.extend_from_slice(b"http://example.org/");
+        token.extend_from_slice(&[2, 5]);
+        token.extend_from_slice(b"keyid");
+        token.extend_from_slice(&[0, 0, 6, 32]);
+        token.extend_from_slice(&[0; 32]);
+        assert!(super::deserialize(&token, &Default::default()).is_ok());
+        let strict = DeserializeOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(super::deserialize(&token, &strict).is_err());
+        assert!(Macaroon::deserialize_binary_with_options(&token, &strict).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        use crate::serialization::{DeserializationLimits, DeserializeOptions};
+        use crate::{Format, MacaroonError, MacaroonKey};
+
+        let mut macaroon =
+            Macaroon::create(None, &MacaroonKey::generate(b"key"), "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("user = alice".into());
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        let token = macaroon.serialize(Format::V2).unwrap();
+        let limited = |limits| {
+            let options = DeserializeOptions {
+                limits,
+                ..Default::default()
+            };
+            match Macaroon::deserialize_with_options(&token, &options) {
+                Err(MacaroonError::LimitExceeded(_)) => true,
+                Ok(_) => false,
+                Err(e) => panic!("unexpected error {:?}", e),
+            }
+        };
+        assert!(!limited(DeserializationLimits::default()));
+        assert!(limited(DeserializationLimits {
+            max_token_len: token.len() - 1,
+            ..Default::default()
+        }));
+        assert!(limited(DeserializationLimits {
+            max_caveats: 1,
+            ..Default::default()
+        }));
+        assert!(limited(DeserializationLimits {
+            max_field_len: 19,
+            ..Default::default()
+        }));
+        assert!(!limited(DeserializationLimits {
+            max_field_len: 32,
+            max_caveats: 2,
+            max_token_len: token.len(),
+        }));
     }
 }
@@ -1,10 +1,17 @@
 use crate::caveat;
 use crate::caveat::CaveatBuilder;
 use crate::error::MacaroonError;
+use crate::extensions::{Extensions, EXTENSION_PREFIX};
 use crate::serialization::macaroon_builder::MacaroonBuilder;
+use crate::serialization::{check_default_limits, DeserializeOptions, Format, SerializeOptions};
 use crate::{ByteString, Macaroon, Result};
-use serde::{Deserialize, Serialize};
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
 use std::str;
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -15,6 +22,8 @@ struct Caveat {
     l64: Option<String>,
     v: Option<String>,
     v64: Option<ByteString>,
+    #[serde(flatten, deserialize_with = "unique_fields")]
+    extensions: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -24,59 +33,153 @@ struct Serialization {
     i64: Option<ByteString>,
     l: Option<String>,
     l64: Option<String>,
+    #[serde(default, deserialize_with = "caveats")]
     c: Vec<Caveat>,
     s: Option<Vec<u8>>,
     s64: Option<String>,
+    #[serde(flatten, deserialize_with = "unique_fields")]
+    extensions: BTreeMap<String, serde_json::Value>,
+}
+
+/// The caveats, where a missing or `null` array means there are none, as in go-macaroon
+fn caveats<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<Caveat>, D::Error> {
+    Ok(Option::<Vec<Caveat>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// The fields left over from parsing a macaroon or caveat, rejecting repeated keys
+///
+/// serde rejects repeats of the fields it knows, but would keep the last of any others, so a
+/// token could be read differently by implementations keeping the first.
+fn unique_fields<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, serde_json::Value>, D::Error> {
+    struct UniqueFields;
+
+    impl<'de> Visitor<'de> for UniqueFields {
+        type Value = BTreeMap<String, serde_json::Value>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            let mut fields = BTreeMap::new();
+            while let Some((key, value)) = map.next_entry::<String, serde_json::Value>()? {
+                match fields.entry(key) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(value);
+                    }
+                    Entry::Occupied(entry) => {
+                        return Err(serde::de::Error::custom(format_args!(
+                            "duplicate field `{}`",
+                            entry.key()
+                        )))
+                    }
+                }
+            }
+            Ok(fields)
+        }
+    }
+
+    deserializer.deserialize_map(UniqueFields)
+}
+
+/// A field as text (eg `i`) if it's valid UTF-8, as the spec requires, or else base64 (eg `i64`)
+fn text_or_base64(value: ByteString) -> (Option<String>, Option<ByteString>) {
+    match String::from_utf8(value.0) {
+        Ok(text) => (Some(text), None),
+        Err(e) => (None, Some(ByteString(e.into_bytes()))),
+    }
+}
+
+/// Fails unless every one of the `fields` left over from parsing a macaroon or caveat is an
+/// extension
+fn check_known_fields(fields: &BTreeMap<String, serde_json::Value>, what: &str) -> Result<()> {
+    match fields.keys().find(|k| !k.starts_with(EXTENSION_PREFIX)) {
+        Some(field) => Err(MacaroonError::DeserializationError(format!(
+            "unknown {} field {:?}",
+            what, field
+        ))),
+        None => Ok(()),
+    }
 }
 
 impl Serialization {
-    fn from_macaroon(macaroon: Macaroon) -> Result<Serialization> {
+    fn from_macaroon(macaroon: Macaroon, options: &SerializeOptions) -> Serialization {
+        let (i, i64) = text_or_base64(macaroon.identifier());
         let mut serialized: Serialization = Serialization {
             v: 2,
-            i: None,
-            i64: Some(macaroon.identifier()),
+            i,
+            i64,
             l: macaroon.location(),
             l64: None,
             c: Vec::new(),
             s: None,
             s64: Some(base64::encode_config(
-                &macaroon.signature(),
-                base64::URL_SAFE,
+                macaroon.signature(),
+                options.base64_config(),
             )),
+            extensions: macaroon.extensions().to_json_fields(),
         };
         for c in macaroon.caveats() {
             match c {
-                caveat::Caveat::FirstParty(fp) => {
+                caveat::Caveat::FirstParty(ref fp) => {
+                    let (i, i64) = text_or_base64(fp.predicate());
                     let serialized_caveat: Caveat = Caveat {
-                        i: None,
-                        i64: Some(fp.predicate()),
+                        i,
+                        i64,
                         l: None,
                         l64: None,
                         v: None,
                         v64: None,
+                        extensions: c.extensions().to_json_fields(),
                     };
                     serialized.c.push(serialized_caveat);
                 }
-                caveat::Caveat::ThirdParty(tp) => {
+                caveat::Caveat::ThirdParty(ref tp) => {
+                    let (i, i64) = text_or_base64(tp.id());
+                    let (v, v64) = text_or_base64(tp.verifier_id());
                     let serialized_caveat: Caveat = Caveat {
-                        i: None,
-                        i64: Some(tp.id()),
+                        i,
+                        i64,
                         l: Some(tp.location()),
                         l64: None,
-                        v: None,
-                        v64: Some(tp.verifier_id()),
+                        v,
+                        v64,
+                        extensions: c.extensions().to_json_fields(),
                     };
                     serialized.c.push(serialized_caveat);
                 }
             }
         }
 
-        Ok(serialized)
+        serialized
     }
 }
 
-impl Macaroon {
-    fn from_json(ser: Serialization) -> Result<Macaroon> {
+impl Serialization {
+    fn into_builder(
+        self,
+        options: &DeserializeOptions,
+        builder: &mut MacaroonBuilder,
+    ) -> Result<()> {
+        let ser = self;
+        let limits = &options.limits;
+        if ser.v != 2 {
+            return Err(MacaroonError::UnsupportedVersion(ser.v));
+        }
+        if !options.allow_unknown_fields {
+            check_known_fields(&ser.extensions, "macaroon")?;
+            for c in ser.c.iter() {
+                check_known_fields(&c.extensions, "caveat")?;
+            }
+        }
+        limits.check_caveats(ser.c.len())?;
         if ser.i.is_some() && ser.i64.is_some() {
             return Err(MacaroonError::DeserializationError(String::from(
                 "Found i and i64 fields",
@@ -93,8 +196,7 @@ impl Macaroon {
             )));
         }
 
-        let mut builder: MacaroonBuilder = MacaroonBuilder::new();
-        builder.set_identifier(match ser.i {
+        let identifier: ByteString = match ser.i {
             Some(id) => id.into(),
             None => match ser.i64 {
                 Some(id) => id,
@@ -105,19 +207,24 @@ impl Macaroon {
                     )))
                 }
             },
-        });
-
-        match ser.l {
-            Some(loc) => builder.set_location(&loc),
-            None => {
-                if let Some(loc) = ser.l64 {
-                    builder.set_location(&String::from_utf8(base64::decode_config(
-                        &loc,
-                        base64::URL_SAFE,
-                    )?)?)
-                }
-            }
         };
+        limits.check_field_len(identifier.0.len())?;
+        builder.set_identifier(identifier);
+
+        let location = match ser.l {
+            Some(loc) => Some(loc),
+            None => match ser.l64 {
+                Some(loc) => Some(String::from_utf8(base64::decode_config(
+                    &loc,
+                    base64::URL_SAFE,
+                )?)?),
+                None => None,
+            },
+        };
+        if let Some(location) = location {
+            limits.check_field_len(location.len())?;
+            builder.set_location(&location);
+        }
 
         let raw_sig = match ser.s {
             Some(sig) => sig,
@@ -131,16 +238,15 @@ impl Macaroon {
             },
         };
         if raw_sig.len() != 32 {
-            return Err(MacaroonError::DeserializationError(
-                "Illegal signature length".into(),
-            ));
+            return Err(MacaroonError::SignatureLengthMismatch { got: raw_sig.len() });
         }
 
         builder.set_signature(&raw_sig);
+        builder.set_extensions(Extensions::from_json_fields(ser.extensions));
 
         let mut caveat_builder: CaveatBuilder = CaveatBuilder::new();
         for c in ser.c {
-            caveat_builder.add_id(match c.i {
+            let id: ByteString = match c.i {
                 Some(id) => id.into(),
                 None => match c.i64 {
                     Some(id64) => id64,
@@ -151,43 +257,102 @@ impl Macaroon {
                         )))
                     }
                 },
-            });
-            match c.l {
-                Some(loc) => caveat_builder.add_location(loc),
-                None => {
-                    if let Some(loc64) = c.l64 {
-                        caveat_builder.add_location(String::from_utf8(base64::decode_config(
-                            &loc64,
-                            base64::URL_SAFE,
-                        )?)?)
-                    }
-                }
             };
-            match c.v {
-                Some(vid) => caveat_builder.add_verifier_id(vid.into()),
-                None => {
-                    if let Some(vid64) = c.v64 {
-                        caveat_builder.add_verifier_id(vid64)
-                    }
-                }
+            limits.check_field_len(id.0.len())?;
+            caveat_builder.add_id(id);
+            let location = match c.l {
+                Some(loc) => Some(loc),
+                None => match c.l64 {
+                    Some(loc64) => Some(String::from_utf8(base64::decode_config(
+                        &loc64,
+                        base64::URL_SAFE,
+                    )?)?),
+                    None => None,
+                },
+            };
+            if let Some(location) = location {
+                limits.check_field_len(location.len())?;
+                caveat_builder.add_location(location);
+            }
+            let vid: Option<ByteString> = match c.v {
+                Some(vid) => Some(vid.into()),
+                None => c.v64,
             };
+            if let Some(vid) = vid {
+                limits.check_field_len(vid.0.len())?;
+                caveat_builder.add_verifier_id(vid);
+            }
+            caveat_builder.set_extensions(Extensions::from_json_fields(c.extensions));
             builder.add_caveat(caveat_builder.build()?);
             caveat_builder = CaveatBuilder::new();
         }
 
-        builder.build()
+        Ok(())
     }
 }
 
-pub fn serialize(macaroon: &Macaroon) -> Result<String> {
+pub fn serialize(macaroon: &Macaroon, options: &SerializeOptions) -> Result<String> {
+    check_default_limits(macaroon, Format::V2JSON)?;
     let serialized: String =
-        serde_json::to_string(&Serialization::from_macaroon(macaroon.clone())?)?;
+        serde_json::to_string(&Serialization::from_macaroon(macaroon.clone(), options))?;
     Ok(serialized)
 }
 
-pub fn deserialize(data: &[u8]) -> Result<Macaroon> {
+/// Counts the bytes written to it
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Length of [serialize]'s output, counted as it's written rather than buffered
+pub(crate) fn serialized_len(macaroon: &Macaroon, options: &SerializeOptions) -> usize {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(
+        &mut counter,
+        &Serialization::from_macaroon(macaroon.clone(), options),
+    )
+    .expect("string keys and infallible writer");
+    counter.0
+}
+
+/// Serialize to an in-memory JSON value, for embedding in other JSON documents
+pub fn to_value(macaroon: &Macaroon) -> Result<serde_json::Value> {
+    check_default_limits(macaroon, Format::V2JSON)?;
+    Ok(serde_json::to_value(Serialization::from_macaroon(
+        macaroon.clone(),
+        &SerializeOptions::default(),
+    ))?)
+}
+
+/// Deserialize from a JSON value embedded in another JSON document
+pub fn from_value(value: serde_json::Value) -> Result<Macaroon> {
+    let mut builder: MacaroonBuilder = MacaroonBuilder::new();
+    let v2j: Serialization = serde_json::from_value(value)?;
+    v2j.into_builder(&DeserializeOptions::default(), &mut builder)?;
+    builder.build()
+}
+
+pub fn deserialize(data: &[u8], options: &DeserializeOptions) -> Result<Macaroon> {
+    let mut builder: MacaroonBuilder = MacaroonBuilder::new();
+    deserialize_into(data, options, &mut builder)?;
+    builder.build()
+}
+
+pub fn deserialize_into(
+    data: &[u8],
+    options: &DeserializeOptions,
+    builder: &mut MacaroonBuilder,
+) -> Result<()> {
     let v2j: Serialization = serde_json::from_slice(data)?;
-    Macaroon::from_json(v2j)
+    v2j.into_builder(options, builder)
 }
 
 #[cfg(test)]
@@ -207,7 +372,7 @@ mod tests {
     #[test]
     fn test_deserialize() {
         let serialized_json: Vec<u8> = SERIALIZED_JSON.as_bytes().to_vec();
-        let macaroon = super::deserialize(&serialized_json).unwrap();
+        let macaroon = super::deserialize(&serialized_json, &Default::default()).unwrap();
         assert_eq!("http://example.org/", &macaroon.location().unwrap());
         assert_eq!(ByteString::from("keyid"), macaroon.identifier());
         assert_eq!(2, macaroon.caveats().len());
@@ -242,4 +407,128 @@ mod tests {
         let other = Macaroon::deserialize(&serialized).unwrap();
         assert_eq!(macaroon, other);
     }
+
+    #[test]
+    fn test_text_fields_for_utf8() {
+        let mut macaroon = Macaroon::create(None, &SIGNATURE.into(), "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("user = alice".into());
+        macaroon.add_first_party_caveat(vec![0xff, 0x00].into());
+        macaroon.add_third_party_caveat(
+            "https://auth.mybank.com/",
+            &MacaroonKey::generate(b"my key"),
+            "keyid".into(),
+        );
+        let value = super::to_value(&macaroon).unwrap();
+        assert_eq!("keyid", value["i"]);
+        assert!(value["i64"].is_null());
+        assert_eq!("user = alice", value["c"][0]["i"]);
+        assert!(value["c"][0]["i64"].is_null());
+        assert!(value["c"][1]["i"].is_null());
+        assert!(value["c"][1]["i64"].is_string());
+        assert!(value["c"][2]["v"].is_null());
+        assert!(value["c"][2]["v64"].is_string());
+
+        let serialized = macaroon.serialize(Format::V2JSON).unwrap();
+        assert_eq!(macaroon, Macaroon::deserialize(&serialized).unwrap());
+    }
+
+    #[test]
+    fn test_extension_fields() {
+        let json = "{\"v\":2,\"i\":\"keyid\",\"x-ns\":\"billing\",\"other\":1,\
+                    \"c\":[{\"i\":\"user = alice\",\"x-meta\":{\"reason\":\"audit\"}}],\
+                    \"s64\":\"S-lnzR6gxrJrr2pKlO6bBbFYhtoLqF6MQqk8jQ4SXvw\"}";
+        let macaroon = super::deserialize(json.as_bytes(), &Default::default()).unwrap();
+        assert_eq!(
+            Some("billing".to_string()),
+            macaroon.extensions().get("x-ns").unwrap()
+        );
+        assert_eq!(None, macaroon.extensions().get_raw("other"));
+        assert_eq!(
+            Some(&serde_json::json!({"reason": "audit"})),
+            macaroon.caveats()[0].extensions().get_raw("x-meta")
+        );
+
+        let other = Macaroon::deserialize(macaroon.serialize(Format::V2JSON).unwrap()).unwrap();
+        assert_eq!(macaroon, other);
+
+        let binary = Macaroon::deserialize(macaroon.serialize(Format::V2).unwrap()).unwrap();
+        assert!(binary.extensions().is_empty());
+        assert!(binary.caveats()[0].extensions().is_empty());
+        assert_eq!(macaroon.signature(), binary.signature());
+    }
+
+    #[test]
+    fn test_versions_and_unknown_fields() {
+        use crate::{DeserializeOptions, MacaroonError};
+
+        let token = |v: u8, extra: &str| {
+            format!(
+                "{{\"v\":{},\"i\":\"keyid\",{}\"c\":[{{\"i\":\"user = alice\"}}],\
+                 \"s64\":\"S-lnzR6gxrJrr2pKlO6bBbFYhtoLqF6MQqk8jQ4SXvw\"}}",
+                v, extra
+            )
+        };
+        let lenient = DeserializeOptions::default();
+        let strict = DeserializeOptions {
+            allow_unknown_fields: false,
+            ..Default::default()
+        };
+        for options in [&lenient, &strict] {
+            assert!(super::deserialize(token(2, "").as_bytes(), options).is_ok());
+            assert!(super::deserialize(token(2, "\"x-ns\":1,").as_bytes(), options).is_ok());
+            assert!(matches!(
+                super::deserialize(token(7, "").as_bytes(), options),
+                Err(MacaroonError::UnsupportedVersion(7))
+            ));
+        }
+
+        // a field from a later revision of the format
+        let future = token(2, "\"k\":\"kid\",");
+        assert!(super::deserialize(future.as_bytes(), &lenient).is_ok());
+        match super::deserialize(future.as_bytes(), &strict) {
+            Err(MacaroonError::DeserializationError(s)) => {
+                assert_eq!("unknown macaroon field \"k\"", s)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        let future = future.replace("\"user = alice\"", "\"user = alice\",\"k\":1");
+        assert!(matches!(
+            super::deserialize(future.as_bytes(), &strict),
+            Err(MacaroonError::DeserializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_caveat_array_and_duplicate_fields() {
+        use crate::MacaroonError;
+
+        let token = |fields: &str| {
+            format!(
+                "{{\"v\":2,\"i\":\"keyid\",{}\"s64\":\"rLvvlzaRQ5khbYEbiz5rk6rz6oiZcAE2oUsa1JH7AV0\"}}",
+                fields
+            )
+        };
+        // go-macaroon omits an empty caveat array, and reads a missing or null one as empty
+        for fields in ["", "\"c\":null,", "\"c\":[],"] {
+            let macaroon = super::deserialize(token(fields).as_bytes(), &Default::default());
+            assert_eq!(0, macaroon.unwrap().caveats().len(), "{}", fields);
+        }
+        assert!(super::deserialize(token("\"c\":{},").as_bytes(), &Default::default()).is_err());
+
+        // repeated keys are ambiguous, whether known fields or extensions
+        for fields in [
+            "\"i\":\"other\",\"c\":[],",
+            "\"c\":[],\"c\":[],",
+            "\"x-ns\":1,\"x-ns\":2,\"c\":[],",
+            "\"c\":[{\"i\":\"a\",\"i\":\"b\"}],",
+            "\"c\":[{\"i\":\"a\",\"x-ns\":1,\"x-ns\":1}],",
+        ] {
+            match super::deserialize(token(fields).as_bytes(), &Default::default()) {
+                Err(MacaroonError::DeserializationError(s)) => {
+                    assert!(s.starts_with("duplicate field"), "{}", s)
+                }
+                other => panic!("unexpected result {:?} for {}", other, fields),
+            }
+        }
+    }
 }
@@ -0,0 +1,174 @@
+use crate::caveat::{self, Caveat};
+use crate::crypto;
+use crate::serialization::Format;
+use crate::{BindingScheme, ByteString, Macaroon, MacaroonKey, Result, ZeroKeyBinding};
+use std::sync::Arc;
+
+/// Cheap-to-clone, immutable base macaroon with copy-on-attenuate semantics.
+///
+/// Servers commonly keep one base token around and attenuate it with a few request-specific
+/// caveats before handing it out. Cloning a [Macaroon] for that copies its identifier and every
+/// caveat; a `SharedMacaroon` instead keeps the base behind an [Arc] and only stores the caveats
+/// added since, along with the running signature.
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, SharedMacaroon};
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// let key = MacaroonKey::generate(b"key");
+/// let mut base = Macaroon::create(None, &key, "id".into())?;
+/// base.add_first_party_caveat("tenant = acme".into());
+/// let base = SharedMacaroon::from(base);
+///
+/// // per request
+/// let token = base.attenuate("user = alice".into());
+/// assert_eq!(2, token.caveats().count());
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedMacaroon {
+    base: Arc<Macaroon>,
+    caveats: Vec<Caveat>,
+    signature: MacaroonKey,
+}
+
+impl SharedMacaroon {
+    /// Wrap an existing (possibly already shared) base macaroon
+    pub fn new(base: Arc<Macaroon>) -> SharedMacaroon {
+        SharedMacaroon {
+            signature: base.signature.clone(),
+            base,
+            caveats: Vec::new(),
+        }
+    }
+
+    /// The shared base macaroon, without any caveats added through this handle
+    pub fn base(&self) -> &Arc<Macaroon> {
+        &self.base
+    }
+
+    pub fn identifier(&self) -> &ByteString {
+        &self.base.identifier
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.base.location.as_deref()
+    }
+
+    /// Returns the signature covering the base caveats and any added through this handle
+    pub fn signature(&self) -> MacaroonKey {
+        self.signature.clone()
+    }
+
+    /// Iterate over the base caveats followed by those added through this handle
+    pub fn caveats(&self) -> impl Iterator<Item = &Caveat> {
+        self.base.caveats.iter().chain(self.caveats.iter())
+    }
+
+    /// Add a first-party caveat, leaving the shared base untouched
+    pub fn add_first_party_caveat(&mut self, predicate: ByteString) {
+        self.push(caveat::new_first_party(predicate));
+    }
+
+    /// Add a third-party caveat, leaving the shared base untouched
+    pub fn add_third_party_caveat(&mut self, location: &str, key: &MacaroonKey, id: ByteString) {
+        let vid: Vec<u8> = crypto::encrypt_key(&self.signature, key);
+        self.push(caveat::new_third_party(id, ByteString(vid), location));
+    }
+
+    /// Return a new handle with an extra first-party caveat, sharing the same base
+    pub fn attenuate(&self, predicate: ByteString) -> SharedMacaroon {
+        let mut attenuated = self.clone();
+        attenuated.add_first_party_caveat(predicate);
+        attenuated
+    }
+
+    /// Bind a discharge macaroon to this (attenuated) macaroon; see [Macaroon::bind]
+    pub fn bind(&self, discharge: &mut Macaroon) {
+        self.bind_with(&ZeroKeyBinding, discharge);
+    }
+
+    /// Bind a discharge macaroon with a [BindingScheme]; see [Macaroon::bind_with]
+    pub fn bind_with(&self, binding: &dyn BindingScheme, discharge: &mut Macaroon) {
+        discharge.signature =
+            binding.bind(self.base.scheme.0, &self.signature, &discharge.signature);
+    }
+
+    /// Materialize a standalone [Macaroon], e.g. for verification
+    pub fn to_macaroon(&self) -> Macaroon {
+        let mut macaroon = (*self.base).clone();
+        macaroon.caveats.extend(self.caveats.iter().cloned());
+        macaroon.signature = self.signature.clone();
+        macaroon
+    }
+
+    pub fn serialize(&self, format: Format) -> Result<String> {
+        self.to_macaroon().serialize(format)
+    }
+
+    fn push(&mut self, caveat: Caveat) {
+        self.signature = caveat.sign_with(self.base.scheme.0, &self.signature);
+        self.caveats.push(caveat);
+    }
+}
+
+impl From<Macaroon> for SharedMacaroon {
+    fn from(macaroon: Macaroon) -> Self {
+        SharedMacaroon::new(Arc::new(macaroon))
+    }
+}
+
+impl From<Arc<Macaroon>> for SharedMacaroon {
+    fn from(macaroon: Arc<Macaroon>) -> Self {
+        SharedMacaroon::new(macaroon)
+    }
+}
+
+impl From<&SharedMacaroon> for Macaroon {
+    fn from(shared: &SharedMacaroon) -> Self {
+        shared.to_macaroon()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedMacaroon;
+    use crate::{Macaroon, MacaroonKey, Verifier};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_attenuate_matches_macaroon() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut base =
+            Macaroon::create(Some("http://example.org/".into()), &key, "id".into()).unwrap();
+        base.add_first_party_caveat("tenant = acme".into());
+        let shared = SharedMacaroon::from(base.clone());
+
+        let attenuated = shared.attenuate("user = alice".into());
+        assert!(Arc::ptr_eq(shared.base(), attenuated.base()));
+        assert_eq!(1, shared.caveats().count());
+        assert_eq!(base.signature(), shared.signature());
+
+        base.add_first_party_caveat("user = alice".into());
+        assert_eq!(base, attenuated.to_macaroon());
+        assert_eq!(2, attenuated.caveats().count());
+        assert_eq!(base.signature(), attenuated.signature());
+    }
+
+    #[test]
+    fn test_shared_third_party_verifies() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let caveat_key = MacaroonKey::generate(b"this is another key");
+        let shared = SharedMacaroon::from(Macaroon::create(None, &key, "id".into()).unwrap());
+        let mut attenuated = shared.attenuate("user = alice".into());
+        attenuated.add_third_party_caveat("http://auth.mybank/", &caveat_key, "other id".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "other id".into()).unwrap();
+        attenuated.bind(&mut discharge);
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("user = alice".into());
+        verifier
+            .verify(&attenuated.to_macaroon(), &key, vec![discharge])
+            .unwrap();
+    }
+}
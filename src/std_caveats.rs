@@ -0,0 +1,838 @@
+use crate::bakery::FirstPartyCaveatChecker;
+use crate::checker::{Checker, NamespacedCaveat, STD_NAMESPACE};
+use crate::timestamp::format_rfc3339;
+use crate::verifier::{self, VerifierOptions, VerifyContext};
+use crate::{
+    ByteString, Caveat, HmacSha256, Macaroon, MacaroonKey, Result, SignatureScheme, Verifier,
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Prefix for writing standard caveats with their namespace explicit, eg `std:allow read`
+///
+/// go-macaroon-bakery writes standard caveats without a prefix, which
+/// [Verifier::satisfy_std] also accepts.
+pub const STD_PREFIX: &str = "std:";
+
+/// `time-before <RFC 3339 timestamp>`: satisfied until the given time
+pub const COND_TIME_BEFORE: &str = "time-before";
+/// `declared <key> <value>`: satisfied if the request declares `key` with `value`
+pub const COND_DECLARED: &str = "declared";
+/// `allow <op>...`: satisfied if every operation of the request is listed
+pub const COND_ALLOW: &str = "allow";
+/// `deny <op>...`: satisfied if no operation of the request is listed
+pub const COND_DENY: &str = "deny";
+/// `error <message>`: never satisfied, failing with the message
+pub const COND_ERROR: &str = "error";
+
+const CONDITIONS: [&str; 5] = [
+    COND_TIME_BEFORE,
+    COND_DECLARED,
+    COND_ALLOW,
+    COND_DENY,
+    COND_ERROR,
+];
+
+/// What the standard caveats are checked against: the time, the operations the request wants
+/// to perform, and the attributes it declares (eg the authenticated user)
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, StdContext, Verifier};
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_first_party_caveat("allow read write".into());
+/// macaroon.add_first_party_caveat("std:declared user alice".into());
+///
+/// let mut verifier = Verifier::default();
+/// verifier.satisfy_std(
+///     StdContext::new()
+///         .with_operations(&["read"])
+///         .with_declared("user", "alice"),
+/// );
+/// verifier.verify(&macaroon, &key, vec![]).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StdContext {
+    /// The time to check `time-before` caveats against, or `None` to read the clock at each
+    /// check, so a long-lived verifier doesn't keep using the time it was configured at
+    pub now: Option<SystemTime>,
+    pub operations: Vec<String>,
+    pub declared: BTreeMap<String, String>,
+}
+
+impl Default for StdContext {
+    fn default() -> Self {
+        StdContext::new()
+    }
+}
+
+impl StdContext {
+    /// A context at the current time (read at each check), with no operations or declared
+    /// attributes
+    pub fn new() -> StdContext {
+        StdContext {
+            now: None,
+            operations: Vec::new(),
+            declared: BTreeMap::new(),
+        }
+    }
+
+    /// Check `time-before` caveats against `now` rather than the current time
+    pub fn at(mut self, now: SystemTime) -> StdContext {
+        self.now = Some(now);
+        self
+    }
+
+    pub fn with_operations(mut self, operations: &[&str]) -> StdContext {
+        self.operations = operations.iter().map(|op| op.to_string()).collect();
+        self
+    }
+
+    pub fn with_declared(mut self, key: &str, value: &str) -> StdContext {
+        self.declared.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Check the standard condition `name` with argument `arg`, allowing for `clock_skew` in
+    /// `time-before`, or `None` if `name` isn't one
+    fn check(
+        &self,
+        name: &str,
+        arg: &str,
+        clock_skew: Duration,
+    ) -> Option<std::result::Result<(), String>> {
+        let result = match name {
+            COND_TIME_BEFORE => self.check_time_before(arg, clock_skew),
+            COND_DECLARED => match arg.split_once(' ') {
+                Some((key, value)) if self.declared.get(key).map(String::as_str) == Some(value) => {
+                    Ok(())
+                }
+                Some((key, _)) => Err(format!("got {}={:?}", key, self.declared.get(key))),
+                None => Err("declared caveat has no value".to_string()),
+            },
+            COND_ALLOW => self.check_operations(arg, true),
+            COND_DENY => self.check_operations(arg, false),
+            COND_ERROR => Err(arg.to_string()),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    #[cfg(feature = "time")]
+    fn check_time_before(
+        &self,
+        arg: &str,
+        clock_skew: Duration,
+    ) -> std::result::Result<(), String> {
+        let at = crate::parse_timestamp(arg).ok_or("malformed time-before timestamp")?;
+        let now = self.now.unwrap_or_else(SystemTime::now);
+        if time::OffsetDateTime::from(now) < at + clock_skew {
+            Ok(())
+        } else {
+            Err("macaroon has expired".to_string())
+        }
+    }
+
+    #[cfg(not(feature = "time"))]
+    fn check_time_before(
+        &self,
+        _arg: &str,
+        _clock_skew: Duration,
+    ) -> std::result::Result<(), String> {
+        Err("time-before caveats need the `time` feature".to_string())
+    }
+
+    fn check_operations(&self, arg: &str, allow: bool) -> std::result::Result<(), String> {
+        let listed: Vec<&str> = arg.split_whitespace().collect();
+        if allow && self.operations.is_empty() {
+            return Err("no operations requested".to_string());
+        }
+        for op in self.operations.iter() {
+            if listed.contains(&op.as_str()) != allow {
+                return Err(format!("{} not allowed", op));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NamespacedCaveat {
+    /// A standard caveat satisfied until `at` (truncated to the second)
+    pub fn time_before(at: SystemTime) -> NamespacedCaveat {
+        NamespacedCaveat::std(&format!("{} {}", COND_TIME_BEFORE, format_rfc3339(at)))
+    }
+
+    /// A standard caveat satisfied if the request declares `key` with `value`
+    pub fn declared(key: &str, value: &str) -> NamespacedCaveat {
+        NamespacedCaveat::std(&format!("{} {} {}", COND_DECLARED, key, value))
+    }
+
+    /// A standard caveat satisfied if the request only performs the given operations
+    pub fn allow(operations: &[&str]) -> NamespacedCaveat {
+        NamespacedCaveat::std(&format!("{} {}", COND_ALLOW, operations.join(" ")))
+    }
+
+    /// A standard caveat satisfied if the request performs none of the given operations
+    pub fn deny(operations: &[&str]) -> NamespacedCaveat {
+        NamespacedCaveat::std(&format!("{} {}", COND_DENY, operations.join(" ")))
+    }
+}
+
+/// The attributes declared by the `declared` caveats of a verified macaroon and its discharges,
+/// as returned by [Verifier::verify_declared] and [Verifier::verify_declared_from]
+///
+/// Verification only shows the declarations are consistent, not who made them: see
+/// [Verifier::verify_declared] for which ones can be relied on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifiedAttributes {
+    declared: BTreeMap<String, String>,
+}
+
+impl VerifiedAttributes {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.declared.get(key).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.declared.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.declared.is_empty()
+    }
+
+    pub fn into_map(self) -> BTreeMap<String, String> {
+        self.declared
+    }
+}
+
+/// The name and argument of a standard condition, with or without [STD_PREFIX]
+fn parse_condition(predicate: &ByteString) -> Option<(&str, &str)> {
+    let condition = std::str::from_utf8(predicate.as_ref()).ok()?;
+    let condition = condition.strip_prefix(STD_PREFIX).unwrap_or(condition);
+    Some(condition.split_once(' ').unwrap_or((condition, "")))
+}
+
+fn parse_declared(predicate: &ByteString) -> Option<(&str, &str)> {
+    let condition = std::str::from_utf8(predicate.as_ref()).ok()?;
+    let condition = condition.strip_prefix(STD_PREFIX).unwrap_or(condition);
+    condition
+        .strip_prefix(COND_DECLARED)?
+        .strip_prefix(' ')?
+        .split_once(' ')
+}
+
+/// The attributes declared by the first-party caveats of `macaroons`. Attributes declared with
+/// conflicting values are left out, so their caveats can't be satisfied.
+fn infer_declared<'a, I>(macaroons: I) -> BTreeMap<String, String>
+where
+    I: IntoIterator<Item = &'a Macaroon>,
+{
+    let mut declared: BTreeMap<&str, Option<&str>> = BTreeMap::new();
+    for caveat in macaroons.into_iter().flat_map(|m| m.caveats.iter()) {
+        let (key, value) = match caveat {
+            Caveat::FirstParty(fp) => match parse_declared(fp.predicate_ref()) {
+                Some(declaration) => declaration,
+                None => continue,
+            },
+            Caveat::ThirdParty(_) => continue,
+        };
+        let entry = declared.entry(key).or_insert(Some(value));
+        if *entry != Some(value) {
+            *entry = None;
+        }
+    }
+    declared
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?.to_string())))
+        .collect()
+}
+
+/// The discharges of `m`'s third-party caveats (and their own) at `location`
+fn discharges_from<'a>(
+    location: &str,
+    m: &'a Macaroon,
+    discharges: &'a [Macaroon],
+) -> Vec<&'a Macaroon> {
+    let ids: Vec<&ByteString> = std::iter::once(m)
+        .chain(discharges.iter())
+        .flat_map(|m| m.caveats.iter())
+        .filter_map(|caveat| match caveat {
+            Caveat::ThirdParty(tp) if tp.location() == location => Some(tp.id_ref()),
+            _ => None,
+        })
+        .collect();
+    discharges
+        .iter()
+        .filter(|d| ids.contains(&&d.identifier))
+        .collect()
+}
+
+/// A [Verifier] which also satisfies `declared` caveats consistent with the macaroons
+struct DeclaredChecker<'a> {
+    verifier: &'a Verifier,
+    declared: &'a BTreeMap<String, String>,
+    clock_skew: Duration,
+}
+
+impl FirstPartyCaveatChecker for DeclaredChecker<'_> {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        if let Some((key, value)) = parse_declared(predicate) {
+            if self.declared.get(key).map(String::as_str) == Some(value) {
+                return Ok(());
+            }
+        }
+        self.verifier
+            .find_satisfier_with_skew(&(), predicate, self.clock_skew)
+            .map(|_| ())
+    }
+}
+
+impl Macaroon {
+    /// Add a `declared key value` caveat, declaring that the bearer has attribute `key` with
+    /// `value` (eg `username alice`)
+    ///
+    /// The key can't contain spaces; the value can.
+    pub fn add_declared(&mut self, key: &str, value: &str) {
+        self.add_first_party_caveat(format!("{} {} {}", COND_DECLARED, key, value).into());
+    }
+
+    /// Add an `allow op...` caveat, so the macaroon can only be used for the given operations
+    ///
+    /// Operations can't contain spaces. With several `allow` caveats, only the operations they
+    /// all list are allowed.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_allow_caveat(&["read", "write"]);
+    /// macaroon.add_allow_caveat(&["read", "delete"]);
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_operations(&["read"]);
+    /// assert!(verifier.verify(&macaroon, &key, vec![]).is_ok());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_operations(&["write"]);
+    /// assert!(verifier.verify(&macaroon, &key, vec![]).is_err());
+    /// ```
+    pub fn add_allow_caveat(&mut self, operations: &[&str]) {
+        self.add_first_party_caveat(format!("{} {}", COND_ALLOW, operations.join(" ")).into());
+    }
+
+    /// Add a `deny op...` caveat, so the macaroon can't be used for any of the given operations
+    pub fn add_deny_caveat(&mut self, operations: &[&str]) {
+        self.add_first_party_caveat(format!("{} {}", COND_DENY, operations.join(" ")).into());
+    }
+}
+
+impl Verifier {
+    /// Verify `m` and its discharges like [Verifier::verify], and return the attributes they
+    /// declare
+    ///
+    /// As in go-macaroon-bakery, the `declared` caveats of the macaroon and its discharges are
+    /// satisfied as long as they agree with each other: an attribute declared with two
+    /// different values fails verification.
+    ///
+    /// **The attributes aren't authenticated.** They are collected from the root macaroon and
+    /// every discharge, and anyone holding the macaroon can add a `declared` caveat to it, or
+    /// add a third-party caveat of their own and mint its discharge. Only rely on an attribute
+    /// if the discharger you trust always declares it, so a forged declaration conflicts with
+    /// the genuine one and fails verification; prefer [Verifier::verify_declared_from], which
+    /// only takes attributes from the discharges for one location.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let root_key = MacaroonKey::generate(b"root key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+    /// macaroon.add_third_party_caveat("https://login.example/", &caveat_key, "login".into());
+    ///
+    /// // The discharger adds the user it authenticated
+    /// let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+    /// discharge.add_declared("username", "alice");
+    /// macaroon.bind(&mut discharge);
+    ///
+    /// let attributes = Verifier::default()
+    ///     .verify_declared(&macaroon, &root_key, vec![discharge])
+    ///     .unwrap();
+    /// assert_eq!(Some("alice"), attributes.get("username"));
+    /// ```
+    pub fn verify_declared(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+    ) -> Result<VerifiedAttributes> {
+        self.verify_declared_with_scheme_and_options(
+            m,
+            key,
+            discharges,
+            &HmacSha256,
+            &VerifierOptions::default(),
+        )
+    }
+
+    /// Like [Verifier::verify_declared], for macaroons signed with any [SignatureScheme], with
+    /// [VerifierOptions] controlling limits and strictness
+    pub fn verify_declared_with_scheme_and_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        scheme: &dyn SignatureScheme,
+        options: &VerifierOptions,
+    ) -> Result<VerifiedAttributes> {
+        let declared = infer_declared(std::iter::once(m).chain(discharges.iter()));
+        self.verify_declared_in(m, key, discharges, declared, scheme, options)
+    }
+
+    /// Verify `m` and its discharges like [Verifier::verify_declared], taking attributes only
+    /// from the discharges of third-party caveats at `location`, eg the login service
+    ///
+    /// Any other `declared` caveat must agree with those attributes (or be satisfied by this
+    /// verifier), so the bearer can't add attributes by attenuating the macaroon. They can
+    /// still add a third-party caveat for `location` and mint its discharge themselves, so
+    /// only rely on attributes the discharger at `location` always declares: a forged
+    /// declaration then conflicts with the genuine one, and fails verification.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let root_key = MacaroonKey::generate(b"root key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+    /// macaroon.add_third_party_caveat("https://login.example/", &caveat_key, "login".into());
+    ///
+    /// let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+    /// discharge.add_declared("username", "alice");
+    /// macaroon.bind(&mut discharge);
+    ///
+    /// let verifier = Verifier::default();
+    /// let attributes = verifier
+    ///     .verify_declared_from("https://login.example/", &macaroon, &root_key, vec![discharge.clone()])
+    ///     .unwrap();
+    /// assert_eq!(Some("alice"), attributes.get("username"));
+    ///
+    /// // The bearer's own declarations aren't taken
+    /// let mut attenuated = macaroon.clone();
+    /// attenuated.add_declared("role", "admin");
+    /// let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+    /// discharge.add_declared("username", "alice");
+    /// attenuated.bind(&mut discharge);
+    /// assert!(verifier
+    ///     .verify_declared_from("https://login.example/", &attenuated, &root_key, vec![discharge])
+    ///     .is_err());
+    /// ```
+    pub fn verify_declared_from(
+        &self,
+        location: &str,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+    ) -> Result<VerifiedAttributes> {
+        self.verify_declared_from_with_scheme_and_options(
+            location,
+            m,
+            key,
+            discharges,
+            &HmacSha256,
+            &VerifierOptions::default(),
+        )
+    }
+
+    /// Like [Verifier::verify_declared_from], for macaroons signed with any [SignatureScheme],
+    /// with [VerifierOptions] controlling limits and strictness
+    pub fn verify_declared_from_with_scheme_and_options(
+        &self,
+        location: &str,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        scheme: &dyn SignatureScheme,
+        options: &VerifierOptions,
+    ) -> Result<VerifiedAttributes> {
+        let declared = infer_declared(discharges_from(location, m, &discharges));
+        self.verify_declared_in(m, key, discharges, declared, scheme, options)
+    }
+
+    /// Verify `m` and its discharges, satisfying `declared` caveats which agree with `declared`
+    fn verify_declared_in(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        declared: BTreeMap<String, String>,
+        scheme: &dyn SignatureScheme,
+        options: &VerifierOptions,
+    ) -> Result<VerifiedAttributes> {
+        let ctx = VerifyContext {
+            checker: &DeclaredChecker {
+                verifier: self,
+                declared: &declared,
+                clock_skew: options.clock_skew(),
+            },
+            options,
+            scheme,
+            decryptor: self.decryptor(),
+            binding: self.binding(),
+            revocation: self.revocation_checker(),
+            location: self.expected_location(),
+        };
+        verifier::verify_with_checker(&ctx, m, key, discharges)?;
+        Ok(VerifiedAttributes { declared })
+    }
+}
+
+impl<C> Verifier<C> {
+    /// Satisfy `declared` caveats whose key and value are in `declared`, eg the attributes of
+    /// the authenticated request
+    pub fn satisfy_declared(&mut self, declared: BTreeMap<String, String>) {
+        self.satisfy_general(move |predicate| match parse_declared(predicate) {
+            Some((key, value)) => declared.get(key).map(String::as_str) == Some(value),
+            None => false,
+        });
+    }
+
+    /// Satisfy the standard caveats (`time-before`, `declared`, `allow`, `deny` and `error`, as
+    /// in go-macaroon-bakery's checkers package) against `context`
+    ///
+    /// The conditions may be written as is or prefixed with [STD_PREFIX]. Unsatisfied standard
+    /// caveats are reported in the verification error, as with
+    /// [Verifier::satisfy_general_with_err].
+    pub fn satisfy_std(&mut self, context: StdContext) {
+        self.satisfy_expiry(move |predicate, clock_skew| {
+            let (name, arg) = match parse_condition(predicate) {
+                Some(condition) => condition,
+                None => return Ok(false),
+            };
+            match context.check(name, arg, clock_skew) {
+                Some(result) => result.map(|()| true),
+                None => Ok(false),
+            }
+        });
+    }
+
+    /// Satisfy `allow` and `deny` caveats (see [Macaroon::add_allow_caveat]) for a request
+    /// performing all of `operations`
+    ///
+    /// Each `allow` caveat has to list every operation, and no `deny` caveat may list any. As
+    /// with [Verifier::satisfy_std], the conditions may be prefixed with [STD_PREFIX], and
+    /// unsatisfied ones are reported in the verification error.
+    pub fn satisfy_operations(&mut self, operations: &[&str]) {
+        let context = StdContext::new().with_operations(operations);
+        self.satisfy_general_with_err(move |predicate| match parse_condition(predicate) {
+            Some((name, arg)) if name == COND_ALLOW || name == COND_DENY => context
+                .check(name, arg, Duration::ZERO)
+                .unwrap_or(Ok(()))
+                .map(|()| true),
+            _ => Ok(false),
+        });
+    }
+}
+
+impl Checker {
+    /// Check the standard caveats in [STD_NAMESPACE] against `context`, as
+    /// [Verifier::satisfy_std] does
+    pub fn register_std(&mut self, context: StdContext) -> Result<()> {
+        let context = Arc::new(context);
+        for name in CONDITIONS {
+            let context = context.clone();
+            self.register(STD_NAMESPACE, name, move |arg| {
+                context.check(name, arg, Duration::ZERO).unwrap_or(Ok(()))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StdContext, VerifiedAttributes};
+    use crate::{
+        Blake2bMac, Checker, FirstPartyCaveatChecker, HmacSha256, Macaroon, MacaroonError,
+        MacaroonKey, Namespace, NamespacedCaveat, Verifier, VerifierOptions,
+    };
+    #[cfg(feature = "time")]
+    use std::time::Duration;
+
+    fn verify(context: StdContext, predicates: &[&str]) -> Result<(), MacaroonError> {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        for predicate in predicates {
+            macaroon.add_first_party_caveat((*predicate).into());
+        }
+        let mut verifier = Verifier::default();
+        verifier.satisfy_std(context);
+        verifier.verify(&macaroon, &key, vec![])
+    }
+
+    #[test]
+    fn test_operations() {
+        let read = || StdContext::new().with_operations(&["read"]);
+        assert!(verify(read(), &["allow read write", "std:deny delete"]).is_ok());
+        assert!(verify(read(), &["allow write"]).is_err());
+        assert!(verify(read(), &["std:deny read"]).is_err());
+        assert!(verify(StdContext::new(), &["allow read"]).is_err());
+        assert!(verify(StdContext::new(), &["deny read"]).is_ok());
+        match verify(read(), &["error not today"]) {
+            Err(MacaroonError::CaveatNotSatisfied(caveat)) => {
+                assert!(caveat.reason.ends_with(": not today"), "{}", caveat)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        // Not a standard caveat, so left to other satisfiers
+        assert!(verify(read(), &["other:allow read"]).is_err());
+    }
+
+    #[test]
+    fn test_satisfy_operations() {
+        let key = MacaroonKey::generate(b"key");
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        macaroon.add_allow_caveat(&["read", "write", "list"]);
+        macaroon.add_allow_caveat(&["read", "list", "delete"]);
+        macaroon.add_deny_caveat(&["list"]);
+        let verify = |operations: &[&str]| {
+            let mut verifier = Verifier::default();
+            verifier.satisfy_operations(operations);
+            verifier.verify(&macaroon, &key, vec![])
+        };
+        assert!(verify(&["read"]).is_ok());
+        // only operations allowed by every caveat, and not denied
+        assert!(verify(&["write"]).is_err());
+        assert!(verify(&["delete"]).is_err());
+        assert!(verify(&["list"]).is_err());
+        assert!(verify(&["read", "write"]).is_err());
+        assert!(verify(&[]).is_err());
+
+        // other caveats are left to other satisfiers
+        let mut other = macaroon.clone();
+        other.add_first_party_caveat("account = 3735928559".into());
+        let mut verifier = Verifier::default();
+        verifier.satisfy_operations(&["read"]);
+        assert!(verifier.verify(&other, &key, vec![]).is_err());
+        verifier.satisfy_exact("account = 3735928559".into());
+        verifier.verify(&other, &key, vec![]).unwrap();
+    }
+
+    #[test]
+    fn test_declared() {
+        let alice = || StdContext::new().with_declared("user", "alice");
+        assert!(verify(alice(), &["declared user alice"]).is_ok());
+        assert!(verify(alice(), &["declared user bob"]).is_err());
+        assert!(verify(alice(), &["declared group admin"]).is_err());
+        assert!(verify(alice(), &["declared user"]).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time_before() {
+        let now = std::time::SystemTime::now();
+        let caveat = NamespacedCaveat::time_before(now + Duration::from_secs(60));
+        let namespace = Namespace::new();
+        let predicate = namespace.resolve(&caveat).unwrap();
+        assert!(verify(StdContext::new().at(now), &[&predicate]).is_ok());
+        let later = now + Duration::from_secs(120);
+        assert!(verify(StdContext::new().at(later), &[&predicate]).is_err());
+        assert!(verify(StdContext::new(), &["time-before soon"]).is_err());
+
+        // Without a fixed time, the clock is read at each check
+        let mut verifier = Verifier::default();
+        verifier.satisfy_std(StdContext::new());
+        let key = MacaroonKey::generate(b"key");
+        let soon =
+            NamespacedCaveat::time_before(std::time::SystemTime::now() + Duration::from_secs(2));
+        let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+        macaroon.add_first_party_caveat(namespace.resolve(&soon).unwrap().as_str().into());
+        verifier.verify(&macaroon, &key, vec![]).unwrap();
+        std::thread::sleep(Duration::from_secs(3));
+        assert!(verifier.verify(&macaroon, &key, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_checker() {
+        let mut checker = Checker::new(Namespace::new());
+        checker
+            .register_std(
+                StdContext::new()
+                    .with_operations(&["read"])
+                    .with_declared("user", "alice"),
+            )
+            .unwrap();
+        let namespace = Namespace::new();
+        for caveat in [
+            NamespacedCaveat::allow(&["read", "write"]),
+            NamespacedCaveat::deny(&["write"]),
+            NamespacedCaveat::declared("user", "alice"),
+        ]
+        .iter()
+        {
+            let predicate = namespace.resolve(caveat).unwrap();
+            checker
+                .check_first_party_caveat(&predicate.as_str().into())
+                .unwrap();
+        }
+        assert!(checker
+            .check_first_party_caveat(&"deny read".into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_declared_attributes() {
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        macaroon.add_declared("username", "alice");
+        macaroon.add_third_party_caveat("https://login.example/", &caveat_key, "login".into());
+        let discharge = |declared: &[(&str, &str)]| {
+            let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+            for (key, value) in declared {
+                discharge.add_declared(key, value);
+            }
+            macaroon.bind(&mut discharge);
+            discharge
+        };
+
+        let verifier = Verifier::default();
+        let attributes = verifier
+            .verify_declared(
+                &macaroon,
+                &root_key,
+                vec![discharge(&[
+                    ("username", "alice"),
+                    ("name", "Alice Liddell"),
+                ])],
+            )
+            .unwrap();
+        assert_eq!(Some("Alice Liddell"), attributes.get("name"));
+        assert_eq!(
+            vec![("name", "Alice Liddell"), ("username", "alice")],
+            attributes.iter().collect::<Vec<_>>()
+        );
+        assert!(VerifiedAttributes::default().is_empty());
+
+        // Conflicting declarations
+        assert!(matches!(
+            verifier.verify_declared(
+                &macaroon,
+                &root_key,
+                vec![discharge(&[("username", "bob")])]
+            ),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+        // Plain verification doesn't infer declarations
+        assert!(verifier
+            .verify(&macaroon, &root_key, vec![discharge(&[])])
+            .is_err());
+
+        let mut verifier = Verifier::default();
+        verifier.satisfy_declared(
+            [("username".to_string(), "alice".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        verifier
+            .verify(&macaroon, &root_key, vec![discharge(&[])])
+            .unwrap();
+        assert!(verifier
+            .verify(&macaroon, &root_key, vec![discharge(&[("role", "admin")])])
+            .is_err());
+    }
+
+    #[test]
+    fn test_declared_from() {
+        let login = "https://login.example/";
+        let root_key = MacaroonKey::generate(b"root key");
+        let caveat_key = MacaroonKey::generate(b"caveat key");
+        let mut macaroon = Macaroon::create(None, &root_key, "id".into()).unwrap();
+        macaroon.add_third_party_caveat(login, &caveat_key, "login".into());
+        let mut discharge = Macaroon::create(None, &caveat_key, "login".into()).unwrap();
+        discharge.add_declared("username", "alice");
+
+        // A discharge the bearer minted for a caveat of their own
+        let bearer_key = MacaroonKey::generate(b"bearer key");
+        let mut forged = macaroon.clone();
+        forged.add_third_party_caveat("https://bearer.example/", &bearer_key, "mine".into());
+        let mut own = Macaroon::create(None, &bearer_key, "mine".into()).unwrap();
+        own.add_declared("role", "admin");
+        let mut discharges = vec![discharge.clone(), own];
+        for d in discharges.iter_mut() {
+            forged.bind(d);
+        }
+
+        let verifier = Verifier::default();
+        let attributes = verifier
+            .verify_declared(&forged, &root_key, discharges.clone())
+            .unwrap();
+        assert_eq!(Some("admin"), attributes.get("role"));
+        assert!(matches!(
+            verifier.verify_declared_from(login, &forged, &root_key, discharges),
+            Err(MacaroonError::CaveatNotSatisfied(_))
+        ));
+
+        let mut bound = discharge.clone();
+        macaroon.bind(&mut bound);
+        let attributes = verifier
+            .verify_declared_from(login, &macaroon, &root_key, vec![bound.clone()])
+            .unwrap();
+        assert_eq!(
+            vec![("username", "alice")],
+            attributes.iter().collect::<Vec<_>>()
+        );
+        assert!(verifier
+            .verify_declared_from(
+                "https://other.example/",
+                &macaroon,
+                &root_key,
+                vec![bound.clone()]
+            )
+            .is_err());
+
+        let shallow = VerifierOptions::builder().max_depth(0).build();
+        assert!(matches!(
+            verifier.verify_declared_from_with_scheme_and_options(
+                login,
+                &macaroon,
+                &root_key,
+                vec![bound.clone()],
+                &HmacSha256,
+                &shallow
+            ),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            verifier.verify_declared_with_scheme_and_options(
+                &macaroon,
+                &root_key,
+                vec![bound],
+                &HmacSha256,
+                &shallow
+            ),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+
+        let mut blake2b =
+            Macaroon::create_with_scheme(None, &root_key, "id".into(), &Blake2bMac).unwrap();
+        blake2b.add_third_party_caveat(login, &caveat_key, "login".into());
+        let mut discharge =
+            Macaroon::create_with_scheme(None, &caveat_key, "login".into(), &Blake2bMac).unwrap();
+        discharge.add_declared("username", "alice");
+        blake2b.bind(&mut discharge);
+        let attributes = verifier
+            .verify_declared_from_with_scheme_and_options(
+                login,
+                &blake2b,
+                &root_key,
+                vec![discharge],
+                &Blake2bMac,
+                &VerifierOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(Some("alice"), attributes.get("username"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Helpers for the `tracing` spans and events, with the `tracing` feature
+//!
+//! Spans cover creating, attenuating, binding, serializing and verifying macaroons. They record
+//! counts, formats and redacted identifiers only: never keys, signatures or caveat contents.
+
+use crate::{MacaroonError, Result};
+use sodiumoxide::crypto::hash::sha256;
+use std::time::Instant;
+
+/// An identifier as recorded in spans: the start of its SHA-256 hash, like the redacted
+/// [MacaroonKey](crate::MacaroonKey) `Debug` output, so records about one macaroon can be
+/// correlated without logging an identifier that may embed user data
+pub(crate) fn redact(identifier: &[u8]) -> String {
+    let hash = sha256::hash(identifier);
+    hash.0[..4].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Why verification failed, as recorded: the kind of error, without the predicates or
+/// identifiers it carries
+pub(crate) fn reason(error: &MacaroonError) -> &'static str {
+    match error {
+        MacaroonError::InitializationError => "initialization",
+        MacaroonError::CryptoError(_) => "crypto",
+        MacaroonError::IncompleteMacaroon(_) => "incomplete_macaroon",
+        MacaroonError::IncompleteCaveat(_) => "incomplete_caveat",
+        MacaroonError::DeserializationError(_)
+        | MacaroonError::InvalidBase64(_)
+        | MacaroonError::InvalidPacketHeader { .. }
+        | MacaroonError::UnsupportedVersion(_)
+        | MacaroonError::SignatureLengthMismatch { .. } => "deserialization",
+        MacaroonError::CaveatNotSatisfied(_) => "caveat_not_satisfied",
+        MacaroonError::DischargeNotUsed => "discharge_not_used",
+        MacaroonError::InvalidSignature => "invalid_signature",
+        MacaroonError::DischargeNotBound(_) => "discharge_not_bound",
+        MacaroonError::DischargeBoundTwice(_) => "discharge_bound_twice",
+        MacaroonError::LimitExceeded(_) => "limit_exceeded",
+        MacaroonError::Revoked(_) => "revoked",
+        MacaroonError::LocationMismatch(_) => "location_mismatch",
+        MacaroonError::SerializationError(_) => "serialization",
+    }
+}
+
+/// Record the outcome and duration of a verification started at `started`, in the current span
+pub(crate) fn verified(result: &Result<()>, started: Instant) {
+    let elapsed_us = started.elapsed().as_micros() as u64;
+    match result {
+        Ok(()) => tracing::debug!(elapsed_us, "macaroon verified"),
+        Err(e) => tracing::debug!(
+            elapsed_us,
+            reason = reason(e),
+            "macaroon verification failed"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reason, redact};
+    use crate::MacaroonError;
+
+    #[test]
+    fn test_redact() {
+        let redacted = redact(b"user=alice,key=1234");
+        assert_eq!(8, redacted.len());
+        assert_eq!(redacted, redact(b"user=alice,key=1234"));
+        assert_ne!(redacted, redact(b"user=bob,key=1234"));
+        assert_eq!(
+            "revoked",
+            reason(&MacaroonError::Revoked("user=alice".into()))
+        );
+    }
+}
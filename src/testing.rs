@@ -0,0 +1,264 @@
+//! Helpers for testing code which creates or consumes macaroons.
+//!
+//! Enable the `testing` feature to use these from your own tests:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! macaroon = { version = "0.3", features = ["testing"] }
+//! ```
+
+use crate::{ByteString, Format, Macaroon, MacaroonKey, NONCE_LEN};
+use std::convert::TryFrom;
+
+/// Every serialization format supported by this crate, with the enabled features
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+pub const ALL_FORMATS: [Format; 4] = [Format::V1, Format::V2, Format::V2JSON, Format::V2Compressed];
+
+/// Every serialization format supported by this crate, with the enabled features
+/// ([Format::V2Compressed] needs the `deflate` or `zstd` feature)
+#[cfg(not(any(feature = "deflate", feature = "zstd")))]
+pub const ALL_FORMATS: [Format; 3] = [Format::V1, Format::V2, Format::V2JSON];
+
+/// Assert that a macaroon survives a serialization round trip through every supported
+/// [Format], as well as the binary conversions, unchanged.
+///
+/// Panics with a message naming the format at fault if serialization or deserialization
+/// fails, or if the deserialized macaroon (or its signature) differs from the original.
+///
+/// ```rust
+/// use macaroon::{testing::assert_roundtrip, Macaroon, MacaroonKey};
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_first_party_caveat(r#"manifest = {"paths": ["/a", "/b"]}"#.into());
+/// assert_roundtrip(&macaroon);
+/// ```
+#[track_caller]
+pub fn assert_roundtrip(macaroon: &Macaroon) {
+    for format in ALL_FORMATS.iter() {
+        let token = match macaroon.serialize(*format) {
+            Ok(token) => token,
+            Err(error) => panic!("failed to serialize macaroon as {:?}: {}", format, error),
+        };
+        let deserialized = match Macaroon::deserialize(&token) {
+            Ok(deserialized) => deserialized,
+            Err(error) => panic!(
+                "failed to deserialize {:?} token {:?}: {}",
+                format, token, error
+            ),
+        };
+        assert_same(macaroon, &deserialized, &format!("{:?}", format));
+    }
+
+    let binary: Vec<u8> = macaroon.into();
+    match Macaroon::try_from(binary.as_slice()) {
+        Ok(deserialized) => assert_same(macaroon, &deserialized, "V2 binary"),
+        Err(error) => panic!("failed to deserialize V2 binary token: {}", error),
+    }
+}
+
+/// Assert that several macaroons, eg a root macaroon and its discharges, survive a round trip
+/// through [Macaroon::serialize_slice] in every supported [Format] holding several macaroons
+/// (all but [Format::V2Compressed]), unchanged
+#[track_caller]
+pub fn assert_slice_roundtrip(macaroons: &[Macaroon]) {
+    for format in ALL_FORMATS.iter().filter(|f| **f != Format::V2Compressed) {
+        let token = match Macaroon::serialize_slice(macaroons, *format) {
+            Ok(token) => token,
+            Err(error) => panic!("failed to serialize macaroons as {:?}: {}", format, error),
+        };
+        let deserialized = match Macaroon::deserialize_slice(&token) {
+            Ok(deserialized) => deserialized,
+            Err(error) => panic!(
+                "failed to deserialize {:?} token {:?}: {}",
+                format, token, error
+            ),
+        };
+        assert_eq!(
+            macaroons.len(),
+            deserialized.len(),
+            "macaroon count changed after {:?} round trip",
+            format
+        );
+        for (expected, actual) in macaroons.iter().zip(deserialized.iter()) {
+            assert_same(expected, actual, &format!("{:?} slice", format));
+        }
+    }
+}
+
+/// Build a macaroon from arbitrary bytes, as produced by a property-testing crate or a fuzzer
+///
+/// Every byte string gives a valid macaroon, and the same bytes always give the same macaroon,
+/// down to its serialized bytes. The bytes pick the location, a (possibly binary) identifier,
+/// the root key, and a mix of up to 8 first-party caveats (with arbitrary predicates) and
+/// third-party caveats, including the nonces encrypting their caveat keys. Missing bytes are
+/// read as zeros.
+///
+/// It doesn't depend on a particular crate, so it can be mapped from a byte vector strategy
+/// with proptest, or called from a quickcheck property taking a `Vec<u8>`:
+///
+/// ```rust
+/// use macaroon::testing::{arbitrary_macaroon, assert_roundtrip};
+///
+/// fn prop_roundtrip(data: Vec<u8>) -> bool {
+///     assert_roundtrip(&arbitrary_macaroon(&data));
+///     true
+/// }
+/// assert!(prop_roundtrip(b"some fuzzer input".to_vec()));
+/// ```
+pub fn arbitrary_macaroon(data: &[u8]) -> Macaroon {
+    let mut choices = Choices { data };
+    let location = match choices.below(3) {
+        0 => None,
+        1 => Some("https://example.org/".to_string()),
+        _ => Some(choices.text(1, 32)),
+    };
+    let identifier = match choices.below(2) {
+        0 => ByteString(choices.text(1, 64).into_bytes()),
+        _ => ByteString(choices.bytes(1, 64)),
+    };
+    let key = MacaroonKey::generate(&choices.bytes(0, 32));
+    // Neither the identifier nor the location is empty, so this can't fail
+    let mut macaroon = Macaroon::create(location, &key, identifier).unwrap();
+    for _ in 0..choices.below(9) {
+        match choices.below(4) {
+            0 => {
+                let caveat_key = MacaroonKey::generate(&choices.bytes(0, 32));
+                let location = choices.text(1, 32);
+                let id = ByteString(choices.bytes(1, 64));
+                let mut nonce = |nonce: &mut [u8; NONCE_LEN]| {
+                    nonce.iter_mut().for_each(|b| *b = choices.byte());
+                };
+                macaroon.add_third_party_caveat_with_rng(&location, &caveat_key, id, &mut nonce);
+            }
+            1 => macaroon.add_first_party_caveat(ByteString(choices.bytes(1, 64))),
+            _ => macaroon.add_first_party_caveat(ByteString(choices.text(1, 64).into_bytes())),
+        }
+    }
+    macaroon
+}
+
+/// Reads the choices made by [arbitrary_macaroon] from its input, with zeros once it runs out
+struct Choices<'a> {
+    data: &'a [u8],
+}
+
+impl Choices<'_> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((byte, rest)) => {
+                self.data = rest;
+                *byte
+            }
+            None => 0,
+        }
+    }
+
+    /// A number below `n`, which is at most 256
+    fn below(&mut self, n: usize) -> usize {
+        self.byte() as usize % n
+    }
+
+    fn len(&mut self, min: usize, max: usize) -> usize {
+        min + self.below(max - min + 1)
+    }
+
+    fn bytes(&mut self, min: usize, max: usize) -> Vec<u8> {
+        let len = self.len(min, max);
+        (0..len).map(|_| self.byte()).collect()
+    }
+
+    /// Printable ASCII, with the odd space, tab or newline
+    fn text(&mut self, min: usize, max: usize) -> String {
+        let len = self.len(min, max);
+        (0..len)
+            .map(|_| match self.byte() {
+                b @ 0x20..=0x7e => b as char,
+                b'\t' => '\t',
+                b'\n' => '\n',
+                b => (b'a' + b % 26) as char,
+            })
+            .collect()
+    }
+}
+
+#[track_caller]
+fn assert_same(expected: &Macaroon, actual: &Macaroon, format: &str) {
+    assert_eq!(
+        expected.signature(),
+        actual.signature(),
+        "signature changed after {} round trip",
+        format
+    );
+    assert_eq!(
+        expected, actual,
+        "macaroon changed after {} round trip",
+        format
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arbitrary_macaroon, assert_roundtrip, assert_slice_roundtrip};
+    use crate::{ByteString, Format, Macaroon, MacaroonKey};
+
+    #[test]
+    fn test_assert_roundtrip() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(
+            Some("http://example.org/".into()),
+            &key,
+            ByteString(vec![0, 159, 146, 150, 255]),
+        )
+        .unwrap();
+        assert_roundtrip(&macaroon);
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_first_party_caveat(ByteString(vec![10, 0, 32, 255]));
+        macaroon.add_third_party_caveat(
+            "https://auth.mybank.com",
+            &MacaroonKey::generate(b"caveat key"),
+            "caveat".into(),
+        );
+        assert_roundtrip(&macaroon);
+    }
+
+    /// Deterministic pseudo-random input for [arbitrary_macaroon] (xorshift)
+    fn input(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_arbitrary_roundtrips() {
+        assert_eq!(arbitrary_macaroon(b"seed"), arbitrary_macaroon(b"seed"));
+        assert_roundtrip(&arbitrary_macaroon(&[]));
+        for seed in 0..2000 {
+            let macaroon = arbitrary_macaroon(&input(seed, 1024));
+            assert_roundtrip(&macaroon);
+            let discharge = arbitrary_macaroon(&input(seed + 1, 256));
+            assert_slice_roundtrip(&[macaroon, discharge]);
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_deterministic() {
+        let mut third_party = 0;
+        for seed in 0..200 {
+            let data = input(seed, 1024);
+            let macaroon = arbitrary_macaroon(&data);
+            third_party += macaroon.third_party_caveats().len();
+            assert_eq!(
+                macaroon.serialize(Format::V2).unwrap(),
+                arbitrary_macaroon(&data).serialize(Format::V2).unwrap()
+            );
+        }
+        assert!(third_party > 0);
+    }
+}
@@ -0,0 +1,280 @@
+//! Expiry caveats (`time < ...`), with `OffsetDateTime` from the `time` crate
+//!
+//! Enable the `time` feature to use this module. [expiry_predicate] and [EXPIRY_PREFIX] are also
+//! exported at the crate root, without the feature.
+
+use crate::{ByteString, COND_TIME_BEFORE, STD_PREFIX};
+#[cfg(feature = "time")]
+use crate::{Macaroon, Verifier};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "time")]
+use time::{format_description::well_known::Rfc3339, Date, Month, OffsetDateTime, Time};
+
+/// Prefix of the standard expiry caveat, as used by libmacaroons and pymacaroons
+pub const EXPIRY_PREFIX: &str = "time < ";
+
+/// The standard expiry predicate, `time < <RFC 3339 UTC timestamp>`, for a macaroon which
+/// expires at `at` (truncated to the second)
+///
+/// ```rust
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let at = UNIX_EPOCH + Duration::from_secs(1_893_456_000);
+/// assert_eq!(macaroon::ByteString::from("time < 2030-01-01T00:00:00Z"), macaroon::expiry_predicate(at));
+/// ```
+pub fn expiry_predicate(at: SystemTime) -> ByteString {
+    format!("{}{}", EXPIRY_PREFIX, format_rfc3339(at)).into()
+}
+
+/// The (unparsed) timestamp of an expiry caveat predicate: `time < ...` (see
+/// [expiry_predicate]) or bakery's `[std:]time-before ...`
+pub(crate) fn caveat_timestamp(predicate: &[u8]) -> Option<&str> {
+    let predicate = std::str::from_utf8(predicate).ok()?;
+    match predicate.strip_prefix(EXPIRY_PREFIX) {
+        Some(at) => Some(at),
+        None => predicate
+            .strip_prefix(STD_PREFIX)
+            .unwrap_or(predicate)
+            .strip_prefix(COND_TIME_BEFORE)?
+            .strip_prefix(' '),
+    }
+}
+
+/// Parse the timestamp of an expiry caveat, either RFC 3339 (`2030-01-01T00:00:00Z`, as written by
+/// [expiry_predicate]) or the shorter libmacaroons format (`2030-01-01T00:00`, in UTC)
+#[cfg(feature = "time")]
+pub fn parse_timestamp(s: &str) -> Option<OffsetDateTime> {
+    if let Ok(t) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Some(t);
+    }
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?;
+    let month = date.next()?;
+    let day = date.next()?;
+    let (hour, minute) = time.split_once(':')?;
+    let fields = [year, month, day, hour, minute];
+    if fields
+        .iter()
+        .any(|f| f.is_empty() || !f.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let date = Date::from_calendar_date(
+        year.parse().ok()?,
+        Month::try_from(month.parse::<u8>().ok()?).ok()?,
+        day.parse().ok()?,
+    )
+    .ok()?;
+    let time = Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()?;
+    Some(date.with_time(time).assume_utc())
+}
+
+#[cfg(feature = "time")]
+impl Macaroon {
+    /// Add a standard expiry caveat (see [expiry_predicate]), so the macaroon is only valid
+    /// before `at`
+    pub fn add_expiry_caveat(&mut self, at: OffsetDateTime) {
+        self.add_first_party_caveat(expiry_predicate(at.into()));
+    }
+}
+
+#[cfg(feature = "time")]
+impl<C> Verifier<C> {
+    /// Satisfy expiry caveats (`time < ...`, and bakery's `[std:]time-before ...`) whose
+    /// timestamp is after `now`, less the
+    /// [clock skew](crate::VerifierOptionsBuilder::clock_skew) of the verification
+    ///
+    /// Timestamps are parsed with [parse_timestamp]; a malformed one is reported in the
+    /// verification error.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonKey, Verifier};
+    /// use time::{Duration, OffsetDateTime};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let now = OffsetDateTime::now_utc();
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_expiry_caveat(now + Duration::hours(1));
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_before(now);
+    /// assert!(verifier.verify(&macaroon, &key, vec![]).is_ok());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_before(now + Duration::hours(2));
+    /// assert!(verifier.verify(&macaroon, &key, vec![]).is_err());
+    /// ```
+    pub fn satisfy_before(&mut self, now: OffsetDateTime) {
+        self.satisfy_expiry(move |predicate, clock_skew| {
+            let timestamp = match caveat_timestamp(predicate.as_ref()) {
+                Some(timestamp) => timestamp,
+                None => return Ok(false),
+            };
+            let at = parse_timestamp(timestamp).ok_or("malformed expiry timestamp")?;
+            if now < at + clock_skew {
+                Ok(true)
+            } else {
+                Err("macaroon has expired")
+            }
+        });
+    }
+}
+
+/// Formats as `YYYY-MM-DDTHH:MM:SSZ`. Times before the epoch are clamped to it.
+pub(crate) fn format_rfc3339(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Formats as an HTTP date (RFC 7231, section 7.1.1.1), eg for cookie `Expires` attributes:
+/// `Tue, 01 Jan 2030 00:00:00 GMT`. Times before the epoch are clamped to it.
+#[cfg(feature = "http")]
+pub(crate) fn format_http_date(t: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) proleptic Gregorian date, using
+/// Howard Hinnant's `civil_from_days` algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, format_rfc3339};
+    #[cfg(feature = "time")]
+    use super::{expiry_predicate, parse_timestamp};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_format_rfc3339() {
+        let cases = [
+            (0, "1970-01-01T00:00:00Z"),
+            (951_782_400, "2000-02-29T00:00:00Z"),
+            (1_709_251_199, "2024-02-29T23:59:59Z"),
+            (4_102_444_800, "2100-01-01T00:00:00Z"),
+        ];
+        for (secs, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                format_rfc3339(UNIX_EPOCH + Duration::from_secs(*secs))
+            );
+        }
+        assert_eq!(
+            "1970-01-01T00:00:00Z",
+            format_rfc3339(UNIX_EPOCH - Duration::from_secs(5))
+        );
+        assert_eq!((1969, 12, 31), civil_from_days(-1));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_format_http_date() {
+        use super::format_http_date;
+        let cases = [
+            (0, "Thu, 01 Jan 1970 00:00:00 GMT"),
+            (951_782_400, "Tue, 29 Feb 2000 00:00:00 GMT"),
+            (1_709_251_199, "Thu, 29 Feb 2024 23:59:59 GMT"),
+        ];
+        for (secs, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                format_http_date(UNIX_EPOCH + Duration::from_secs(*secs))
+            );
+        }
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_parse_timestamp() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_893_456_000);
+        let predicate = expiry_predicate(at);
+        let formatted = std::str::from_utf8(&predicate.as_ref()[super::EXPIRY_PREFIX.len()..]);
+        let expected = time::OffsetDateTime::from(at);
+        assert_eq!(Some(expected), parse_timestamp(formatted.unwrap()));
+        assert_eq!(Some(expected), parse_timestamp("2030-01-01T00:00"));
+        assert_eq!(Some(expected), parse_timestamp("2030-01-01T01:00:00+01:00"));
+        for bad in ["2030-01-01", "2030-13-01T00:00", "2030-01-01T00:+1", "soon"].iter() {
+            assert_eq!(None, parse_timestamp(bad), "{}", bad);
+        }
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_expiry_clock_skew() {
+        use crate::{Macaroon, MacaroonKey, StdContext, Verifier, VerifierOptions};
+        let key = MacaroonKey::generate(b"key");
+        let now = std::time::SystemTime::now();
+        let expired = now - Duration::from_secs(30);
+        let mut before = Verifier::default();
+        before.satisfy_before(time::OffsetDateTime::from(now));
+        let mut std_verifier = Verifier::default();
+        std_verifier.satisfy_std(StdContext::new().at(now));
+        let skew = VerifierOptions::builder()
+            .clock_skew(Duration::from_secs(60))
+            .build();
+        let short_skew = VerifierOptions::builder()
+            .clock_skew(Duration::from_secs(10))
+            .build();
+
+        let timestamp = super::format_rfc3339(expired);
+        for (verifier, predicate) in [
+            (&before, expiry_predicate(expired)),
+            (&before, format!("time-before {}", timestamp).into()),
+            (&before, format!("std:time-before {}", timestamp).into()),
+            (&std_verifier, format!("time-before {}", timestamp).into()),
+        ] {
+            let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+            macaroon.add_first_party_caveat(predicate);
+            assert!(verifier.verify(&macaroon, &key, vec![]).is_err());
+            assert!(verifier
+                .verify_with_options(&macaroon, &key, vec![], &short_skew)
+                .is_err());
+            verifier
+                .verify_with_options(&macaroon, &key, vec![], &skew)
+                .unwrap();
+        }
+    }
+}
@@ -1,101 +1,1178 @@
-use crate::crypto;
-use crate::{ByteString, Caveat, Macaroon, MacaroonError, MacaroonKey, Result};
+use crate::bakery::FirstPartyCaveatChecker;
+use crate::crypto::{self, Decryptor, SecretBox};
+use crate::location::{ExpectedLocation, LocationMatch};
+use crate::report::{CheckedCaveat, SatisfiedBy, VerificationReport};
+use crate::{
+    normalize_predicate, BindingScheme, ByteString, Caveat, HmacSha256, Macaroon, MacaroonError,
+    MacaroonKey, Result, RevocationChecker, SignatureScheme, ZeroKeyBinding,
+};
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub type VerifyFunc = fn(&ByteString) -> bool;
 
-#[derive(Default)]
-pub struct Verifier {
-    exact: BTreeSet<ByteString>,
-    general: Vec<VerifyFunc>,
+/// Takes the request context, the predicate and the [VerifierOptions::clock_skew], which only
+/// the expiry satisfiers use
+type GeneralSatisfier<C> =
+    Box<dyn Fn(&C, &ByteString, Duration) -> std::result::Result<bool, String> + Send + Sync>;
+
+/// Knobs controlling a single verification, passed to [Verifier::verify_with_options].
+///
+/// The defaults match [Verifier::verify]: unused discharges are an error, discharges may be
+/// nested [DEFAULT_MAX_DEPTH] deep with at most [DEFAULT_MAX_THIRD_PARTY_CAVEATS] third-party
+/// caveats in all, and there is no time limit.
+///
+/// ```rust
+/// use macaroon::VerifierOptions;
+/// use std::time::{Duration, Instant};
+///
+/// let options = VerifierOptions::builder()
+///     .max_depth(4)
+///     .max_third_party_caveats(16)
+///     .deadline(Instant::now() + Duration::from_millis(50))
+///     .build();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifierOptions {
+    allow_unused_discharges: bool,
+    max_depth: Option<usize>,
+    max_third_party_caveats: Option<usize>,
+    deadline: Option<Instant>,
+    clock_skew: Duration,
+    #[cfg(feature = "time")]
+    max_discharge_lifetime: Option<Duration>,
+    #[cfg(feature = "parallel")]
+    parallel: bool,
+}
+
+/// Default for [VerifierOptionsBuilder::max_depth]
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// Default for [VerifierOptionsBuilder::max_third_party_caveats]
+pub const DEFAULT_MAX_THIRD_PARTY_CAVEATS: usize = 1024;
+
+impl Default for VerifierOptions {
+    fn default() -> Self {
+        VerifierOptions {
+            allow_unused_discharges: false,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_third_party_caveats: Some(DEFAULT_MAX_THIRD_PARTY_CAVEATS),
+            deadline: None,
+            clock_skew: Duration::default(),
+            #[cfg(feature = "time")]
+            max_discharge_lifetime: None,
+            #[cfg(feature = "parallel")]
+            parallel: true,
+        }
+    }
+}
+
+impl VerifierOptions {
+    pub fn builder() -> VerifierOptionsBuilder {
+        VerifierOptionsBuilder::default()
+    }
+
+    pub fn allow_unused_discharges(&self) -> bool {
+        self.allow_unused_discharges
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub fn max_third_party_caveats(&self) -> Option<usize> {
+        self.max_third_party_caveats
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn clock_skew(&self) -> Duration {
+        self.clock_skew
+    }
+
+    #[cfg(feature = "time")]
+    pub fn max_discharge_lifetime(&self) -> Option<Duration> {
+        self.max_discharge_lifetime
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn parallel(&self) -> bool {
+        self.parallel
+    }
+
+    /// Check a discharge against [VerifierOptions::max_discharge_lifetime], if set
+    #[cfg(feature = "time")]
+    pub(crate) fn check_discharge_lifetime(&self, discharge: &Macaroon) -> Result<()> {
+        match self.max_discharge_lifetime {
+            Some(lifetime) => crate::expiry::check_discharge_lifetime(
+                discharge,
+                lifetime + self.clock_skew,
+                ::time::OffsetDateTime::now_utc(),
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Builder for [VerifierOptions]
+#[derive(Clone, Debug, Default)]
+pub struct VerifierOptionsBuilder {
+    options: VerifierOptions,
+}
+
+impl VerifierOptionsBuilder {
+    /// Don't fail verification when some of the supplied discharges aren't needed
+    pub fn allow_unused_discharges(mut self, allow: bool) -> Self {
+        self.options.allow_unused_discharges = allow;
+        self
+    }
+
+    /// Maximum nesting of discharge macaroons, where the macaroon being verified is at depth 0
+    /// and the discharges for its third-party caveats are at depth 1
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.options.max_depth = Some(depth);
+        self
+    }
+
+    /// Maximum number of third-party caveats checked in the macaroon and all its discharges,
+    /// which bounds the work done for a token with many (or many nested) discharges
+    pub fn max_third_party_caveats(mut self, count: usize) -> Self {
+        self.options.max_third_party_caveats = Some(count);
+        self
+    }
+
+    /// Remove the depth and third-party caveat limits, for macaroons from trusted sources only
+    pub fn unbounded(mut self) -> Self {
+        self.options.max_depth = None;
+        self.options.max_third_party_caveats = None;
+        self
+    }
+
+    /// Give up on verification (with [MacaroonError::LimitExceeded]) once this instant has passed
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.options.deadline = Some(deadline);
+        self
+    }
+
+    /// Tolerance for clock differences, for time-based caveat checks: expiry caveats
+    /// (see [Verifier::satisfy_before] and [Verifier::satisfy_std]) are still satisfied up to
+    /// `skew` after they expire
+    pub fn clock_skew(mut self, skew: Duration) -> Self {
+        self.options.clock_skew = skew;
+        self
+    }
+
+    /// Require every discharge to expire, with a `time < ...` or bakery's `time-before ...`
+    /// caveat, at most `lifetime` (plus the [clock skew](Self::clock_skew)) from now
+    ///
+    /// This bounds how long a third party's word is trusted: discharges without an expiry
+    /// caveat, or expiring later, fail with [MacaroonError::LimitExceeded] even though they
+    /// would otherwise verify.
+    #[cfg(feature = "time")]
+    pub fn max_discharge_lifetime(mut self, lifetime: Duration) -> Self {
+        self.options.max_discharge_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Verify sibling discharges on rayon's thread pool (the default with the `parallel`
+    /// feature), or one after the other
+    ///
+    /// Either way the result is the same, including which error is returned when several caveats
+    /// fail; only verifications asking for a [VerificationReport] are always serial.
+    #[cfg(feature = "parallel")]
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.options.parallel = parallel;
+        self
+    }
+
+    pub fn build(self) -> VerifierOptions {
+        self.options
+    }
+}
+
+/// Checks a macaroon's signature and its caveats
+///
+/// `C` is the type of the request-scoped context (eg the authenticated user, client address or
+/// requested scopes) passed to satisfiers added with [Verifier::satisfy_general_with_context],
+/// so a single verifier can be built once and shared across requests. Context-free verifiers
+/// use the default, `()`, and [Verifier::verify].
+///
+/// Verifying only borrows the verifier, and satisfiers have to be `Send + Sync`, so a verifier
+/// is `Send + Sync` too and can be configured once (see [VerifierBuilder]) and shared between
+/// threads in an `Arc`.
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, Verifier};
+///
+/// struct Request {
+///     user: String,
+/// }
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_first_party_caveat("user = alice".into());
+///
+/// let mut verifier: Verifier<Request> = Verifier::new();
+/// verifier.satisfy_general_with_context(|request: &Request, predicate| {
+///     predicate.as_ref() == format!("user = {}", request.user).as_bytes()
+/// });
+///
+/// let alice = Request { user: "alice".into() };
+/// assert!(verifier.verify_with_context(&macaroon, &key, vec![], &alice).is_ok());
+/// let bob = Request { user: "bob".into() };
+/// assert!(verifier.verify_with_context(&macaroon, &key, vec![], &bob).is_err());
+/// ```
+pub struct Verifier<C = ()> {
+    /// Shared with [Verifier::exact_satisfiers] snapshots, and copied on write
+    pub(crate) exact: Arc<BTreeSet<ByteString>>,
+    general: Vec<GeneralSatisfier<C>>,
+    pub(crate) normalize: bool,
+    decryptor: Option<Box<dyn Decryptor>>,
+    binding: Option<Box<dyn BindingScheme>>,
+    revocation: Option<Box<dyn RevocationChecker>>,
+    location: Option<ExpectedLocation>,
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Verifier::new()
+    }
+}
+
+// Sharing one verifier between threads is supported, so make losing `Send + Sync` a compile error
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Verifier>();
+};
+
+/// A [Verifier] together with the context of the request being verified, and the clock skew
+/// allowed for it
+struct WithContext<'a, C> {
+    verifier: &'a Verifier<C>,
+    context: &'a C,
+    clock_skew: Duration,
+}
+
+impl<C: Sync> FirstPartyCaveatChecker for WithContext<'_, C> {
+    fn check_first_party_caveat(&self, predicate: &ByteString) -> Result<()> {
+        self.find_satisfier(predicate).map(|_| ())
+    }
+
+    fn find_satisfier(&self, predicate: &ByteString) -> Result<SatisfiedBy> {
+        self.verifier
+            .find_satisfier_with_skew(self.context, predicate, self.clock_skew)
+    }
 }
 
 impl Verifier {
+    pub fn builder() -> VerifierBuilder {
+        VerifierBuilder::default()
+    }
+
     pub fn verify(&self, m: &Macaroon, key: &MacaroonKey, discharges: Vec<Macaroon>) -> Result<()> {
-        let mut discharge_set = discharges
-            .iter()
-            .map(|d| (d.identifier.clone(), d.clone()))
-            .collect::<HashMap<ByteString, Macaroon>>();
-        self.verify_with_sig(&m.signature, m, key, &mut discharge_set)?;
-        // Now check that all discharges were used
-        if !discharge_set.is_empty() {
-            return Err(MacaroonError::DischargeNotUsed);
-        }
-        Ok(())
+        self.verify_with_options(m, key, discharges, &VerifierOptions::default())
     }
 
-    fn verify_with_sig(
+    /// Like [Verifier::verify], with [VerifierOptions] controlling limits and strictness
+    pub fn verify_with_options(
         &self,
-        root_sig: &MacaroonKey,
         m: &Macaroon,
         key: &MacaroonKey,
-        discharge_set: &mut HashMap<ByteString, Macaroon>,
+        discharges: Vec<Macaroon>,
+        options: &VerifierOptions,
     ) -> Result<()> {
-        let mut sig = crypto::hmac(key, &m.identifier());
-        for c in m.caveats() {
-            sig = match &c {
-                Caveat::ThirdParty(tp) => {
-                    let caveat_key = crypto::decrypt_key(&sig, &tp.verifier_id().0)?;
-                    let dm = discharge_set.remove(&tp.id()).ok_or_else(|| MacaroonError::CaveatNotSatisfied("no discharge macaroon found (or discharge has already been used) for third-party caveat".to_string()))?;
-                    self.verify_with_sig(root_sig, &dm, &caveat_key, discharge_set)?;
-                    c.sign(&sig)
-                }
+        self.verify_with_scheme_and_options(m, key, discharges, &HmacSha256, options)
+    }
+
+    /// Like [Verifier::verify], for macaroons signed with a [SignatureScheme] other than the
+    /// default (see [Macaroon::create_with_scheme])
+    pub fn verify_with_scheme(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        scheme: &dyn SignatureScheme,
+    ) -> Result<()> {
+        self.verify_with_scheme_and_options(m, key, discharges, scheme, &VerifierOptions::default())
+    }
+
+    /// Like [Verifier::verify_with_scheme], with [VerifierOptions] controlling limits and
+    /// strictness
+    pub fn verify_with_scheme_and_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        scheme: &dyn SignatureScheme,
+        options: &VerifierOptions,
+    ) -> Result<()> {
+        let ctx = VerifyContext {
+            checker: &WithContext {
+                verifier: self,
+                context: &(),
+                clock_skew: options.clock_skew,
+            },
+            options,
+            scheme,
+            decryptor: self.decryptor(),
+            binding: self.binding(),
+            revocation: self.revocation_checker(),
+            location: self.expected_location(),
+        };
+        verify_with_checker(&ctx, m, key, discharges)
+    }
+
+    /// Check `m`'s signature and first-party caveats, returning its third-party caveats, which
+    /// are left pending, in order
+    ///
+    /// Edge services can use this to reject a macaroon early, or to work out which dischargers
+    /// to contact, before verifying it in full with [Verifier::verify]. The expected location
+    /// and revocation checker apply as usual.
+    ///
+    /// ```rust
+    /// use macaroon::{Caveat, Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let caveat_key = MacaroonKey::generate(b"caveat key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_first_party_caveat("account = 3735928559".into());
+    /// macaroon.add_third_party_caveat("https://auth.mybank/", &caveat_key, "caveat id".into());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_exact("account = 3735928559".into());
+    /// let pending = verifier.verify_first_party_only(&macaroon, &key).unwrap();
+    /// match &pending[..] {
+    ///     [Caveat::ThirdParty(tp)] => assert_eq!("https://auth.mybank/", tp.location()),
+    ///     _ => panic!("expected one pending caveat"),
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [MacaroonError::InvalidSignature] if `m` wasn't minted with `key`, or is a bound
+    /// discharge, before any first-party caveat is checked, and
+    /// [MacaroonError::CaveatNotSatisfied] for the first unsatisfied first-party caveat.
+    pub fn verify_first_party_only(&self, m: &Macaroon, key: &MacaroonKey) -> Result<Vec<Caveat>> {
+        self.verify_first_party_only_with_scheme(m, key, &HmacSha256)
+    }
+
+    /// Like [Verifier::verify_first_party_only], for macaroons signed with a [SignatureScheme]
+    /// other than the default (see [Macaroon::create_with_scheme])
+    pub fn verify_first_party_only_with_scheme(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        scheme: &dyn SignatureScheme,
+    ) -> Result<Vec<Caveat>> {
+        if let Some(expected) = self.expected_location() {
+            expected.check(m.location.as_deref())?;
+        }
+        if let Some(revocation) = self.revocation_checker() {
+            if revocation.is_revoked(m.identifier.as_ref(), &m.signature[..])? {
+                return Err(MacaroonError::Revoked(m.identifier.clone()));
+            }
+        }
+        // Authenticate the macaroon before any satisfier sees its caveats
+        let mut sig = scheme.hmac(key, m.identifier.as_ref());
+        for c in m.caveats.iter() {
+            sig = c.sign_with(scheme, &sig);
+        }
+        if !crypto::constant_time_eq(&sig[..], &m.signature[..]) {
+            return Err(MacaroonError::InvalidSignature);
+        }
+        let mut pending = Vec::new();
+        let mut satisfied = BTreeSet::new();
+        for (i, c) in m.caveats.iter().enumerate() {
+            match c {
+                Caveat::ThirdParty(_) => pending.push(c.clone()),
                 Caveat::FirstParty(fp) => {
-                    // This checks exact caveats first and then general second
-                    // if it fails due to logic short circuiting
-                    if !(self.exact.contains(&fp.predicate())
-                        || self.verify_general(&fp.predicate()))
-                    {
-                        // If both failed, it means we weren't successful at either
-                        return Err(MacaroonError::CaveatNotSatisfied(format!(
-                            "first party caveat not satisfied: {}",
-                            String::from_utf8_lossy(fp.predicate().as_ref())
-                        )));
+                    let predicate = fp.predicate_ref();
+                    if !satisfied.contains(predicate) {
+                        self.check_satisfied(&(), predicate)
+                            .map_err(|e| e.at_caveat(i, None))?;
+                        satisfied.insert(predicate);
                     }
-                    c.sign(&sig)
                 }
-            };
+            }
         }
-        // If the root sig equals the newly generated sig, that means we reached
-        // the end of the line and we are ok to return
-        if root_sig == &sig {
-            return Ok(());
+        Ok(pending)
+    }
+}
+
+impl<C> Verifier<C> {
+    /// A verifier with no satisfiers, for requests with context of type `C`
+    ///
+    /// Use [Verifier::default] for verifiers without context.
+    pub fn new() -> Verifier<C> {
+        Verifier {
+            exact: Arc::new(BTreeSet::new()),
+            general: Vec::new(),
+            normalize: false,
+            decryptor: None,
+            binding: None,
+            revocation: None,
+            location: None,
         }
-        // Check the bound signature equals the signature of the discharge
-        // macaroon
-        let zero_key: MacaroonKey = [0; 32].into();
-        let bound_sig = crypto::hmac2(&zero_key, &ByteString(root_sig.to_vec()), &sig.into());
-        if bound_sig != m.signature {
-            return Err(MacaroonError::InvalidSignature);
+    }
+
+    /// Like [Verifier::verify], passing `context` to the satisfiers added with
+    /// [Verifier::satisfy_general_with_context]
+    pub fn verify_with_context(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        context: &C,
+    ) -> Result<()>
+    where
+        C: Sync,
+    {
+        self.verify_with_context_and_options(
+            m,
+            key,
+            discharges,
+            context,
+            &VerifierOptions::default(),
+        )
+    }
+
+    /// Like [Verifier::verify_with_context], with [VerifierOptions] controlling limits and
+    /// strictness
+    pub fn verify_with_context_and_options(
+        &self,
+        m: &Macaroon,
+        key: &MacaroonKey,
+        discharges: Vec<Macaroon>,
+        context: &C,
+        options: &VerifierOptions,
+    ) -> Result<()>
+    where
+        C: Sync,
+    {
+        let ctx = VerifyContext {
+            checker: &WithContext {
+                verifier: self,
+                context,
+                clock_skew: options.clock_skew,
+            },
+            options,
+            scheme: &HmacSha256,
+            decryptor: self.decryptor(),
+            binding: self.binding(),
+            revocation: self.revocation_checker(),
+            location: self.expected_location(),
+        };
+        verify_with_checker(&ctx, m, key, discharges)
+    }
+
+    /// Decrypt the caveat keys of third-party caveats with `decryptor`, for macaroons whose
+    /// third-party caveats were added with the matching encryptor (see
+    /// [Macaroon::add_third_party_caveat_with]) rather than the default [SecretBox]
+    pub fn with_decryptor<D: Decryptor + 'static>(mut self, decryptor: D) -> Verifier<C> {
+        self.decryptor = Some(Box::new(decryptor));
+        self
+    }
+
+    /// Check that discharges are bound to the macaroon with `binding` rather than the standard
+    /// [ZeroKeyBinding] (see [Macaroon::bind_with])
+    pub fn with_binding<B: BindingScheme + 'static>(mut self, binding: B) -> Verifier<C> {
+        self.binding = Some(Box::new(binding));
+        self
+    }
+
+    /// Reject macaroons revoked according to `checker`, which is consulted for the macaroon and
+    /// each of its discharges before their caveats are checked
+    pub fn with_revocation_checker<R: RevocationChecker + 'static>(
+        mut self,
+        checker: R,
+    ) -> Verifier<C> {
+        self.revocation = Some(Box::new(checker));
+        self
+    }
+
+    pub(crate) fn revocation_checker(&self) -> Option<&dyn RevocationChecker> {
+        self.revocation.as_deref()
+    }
+
+    /// Only accept macaroons whose location matches `location`, so macaroons minted for another
+    /// service (or without a location) are rejected with [MacaroonError::LocationMismatch]
+    ///
+    /// Only the location of the macaroon itself is checked, not those of its discharges.
+    ///
+    /// ```rust
+    /// use macaroon::{LocationMatch, Macaroon, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let verifier =
+    ///     Verifier::default().expect_location("https://api.example.com", LocationMatch::Origin);
+    ///
+    /// let ours = Macaroon::create(Some("https://api.example.com/v1".into()), &key, "id".into());
+    /// assert!(verifier.verify(&ours.unwrap(), &key, vec![]).is_ok());
+    /// let theirs = Macaroon::create(Some("https://other.example.com/".into()), &key, "id".into());
+    /// assert!(verifier.verify(&theirs.unwrap(), &key, vec![]).is_err());
+    /// ```
+    pub fn expect_location(mut self, location: &str, matching: LocationMatch) -> Verifier<C> {
+        self.location = Some(ExpectedLocation::new(location, matching));
+        self
+    }
+
+    pub(crate) fn expected_location(&self) -> Option<&ExpectedLocation> {
+        self.location.as_ref()
+    }
+
+    pub(crate) fn binding(&self) -> &dyn BindingScheme {
+        match &self.binding {
+            Some(binding) => binding.as_ref(),
+            None => &ZeroKeyBinding,
+        }
+    }
+
+    pub(crate) fn decryptor(&self) -> &dyn Decryptor {
+        match &self.decryptor {
+            Some(decryptor) => decryptor.as_ref(),
+            None => &SecretBox,
         }
-        Ok(())
     }
 
     pub fn satisfy_exact(&mut self, b: ByteString) {
-        self.exact.insert(b);
+        self.satisfy_exact_all(std::iter::once(b));
+    }
+
+    /// Satisfy each of `predicates` exactly, like [Verifier::satisfy_exact]
+    ///
+    /// ```rust
+    /// use macaroon::Verifier;
+    ///
+    /// let entitlements = ["read", "write", "admin"];
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_exact_all(entitlements.iter().map(|e| format!("can = {}", e).into()));
+    /// assert_eq!(3, verifier.exact_satisfiers().len());
+    /// ```
+    pub fn satisfy_exact_all<I>(&mut self, predicates: I)
+    where
+        I: IntoIterator<Item = ByteString>,
+    {
+        let normalize = self.normalize;
+        Arc::make_mut(&mut self.exact).extend(predicates.into_iter().map(|b| {
+            if normalize {
+                normalize_predicate(&b)
+            } else {
+                b
+            }
+        }));
+    }
+
+    /// Stop satisfying `predicate` exactly, returning whether it was satisfied before. With
+    /// predicate normalization enabled, `predicate` is normalized first.
+    pub fn remove_exact(&mut self, predicate: &ByteString) -> bool {
+        let normalized;
+        let predicate = if self.normalize {
+            normalized = normalize_predicate(predicate);
+            &normalized
+        } else {
+            predicate
+        };
+        if !self.exact.contains(predicate) {
+            return false;
+        }
+        Arc::make_mut(&mut self.exact).remove(predicate)
+    }
+
+    /// Remove every exact and general satisfier, keeping the rest of the configuration
+    pub fn clear(&mut self) {
+        self.exact = Arc::new(BTreeSet::new());
+        self.general.clear();
+    }
+
+    /// A snapshot of the exact satisfiers, normalized if predicate normalization is enabled
+    ///
+    /// This is a reference count, not a copy: the verifier only copies its set if it's changed
+    /// while a snapshot is alive.
+    pub fn exact_satisfiers(&self) -> Arc<BTreeSet<ByteString>> {
+        self.exact.clone()
+    }
+
+    /// Replace the exact satisfiers with `exact`, without copying it unless predicate
+    /// normalization is enabled
+    ///
+    /// Per-request verifiers can share one set of entitlements built up front:
+    ///
+    /// ```rust
+    /// use macaroon::{ByteString, Verifier};
+    /// use std::collections::BTreeSet;
+    /// use std::sync::Arc;
+    ///
+    /// let entitlements: Arc<BTreeSet<ByteString>> =
+    ///     Arc::new((0..500).map(|i| format!("can = read {}", i).into()).collect());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.set_exact_satisfiers(entitlements.clone());
+    /// verifier.satisfy_exact("user = alice".into());
+    /// assert_eq!(501, verifier.exact_satisfiers().len());
+    /// assert_eq!(500, entitlements.len());
+    /// ```
+    pub fn set_exact_satisfiers(&mut self, exact: Arc<BTreeSet<ByteString>>) {
+        self.exact = exact;
+        if self.normalize {
+            self.normalize_exact();
+        }
+    }
+
+    fn normalize_exact(&mut self) {
+        self.exact = Arc::new(self.exact.iter().map(normalize_predicate).collect());
+    }
+
+    /// Normalize first-party caveat predicates (see [normalize_predicate]) before matching them
+    ///
+    /// Once enabled, both the predicates found in macaroons and the exact satisfiers (including
+    /// those already registered) are normalized, and general satisfiers are passed the normalized
+    /// predicate. Signatures are still checked against the predicates as they were signed.
+    pub fn enable_predicate_normalization(&mut self) {
+        self.normalize = true;
+        self.normalize_exact();
+    }
+
+    /// Satisfy any first-party caveat for which `f` returns true
+    ///
+    /// `f` can be a plain function (a [VerifyFunc]) or a closure capturing request context, such
+    /// as the current time or the scopes being requested.
+    pub fn satisfy_general<F>(&mut self, f: F)
+    where
+        F: Fn(&ByteString) -> bool + Send + Sync + 'static,
+    {
+        self.general
+            .push(Box::new(move |_, predicate, _| Ok(f(predicate))))
+    }
+
+    /// Like [Verifier::satisfy_general], for satisfiers that need the context of the request
+    /// being verified, as passed to [Verifier::verify_with_context]
+    pub fn satisfy_general_with_context<F>(&mut self, f: F)
+    where
+        F: Fn(&C, &ByteString) -> bool + Send + Sync + 'static,
+    {
+        self.general.push(Box::new(move |context, predicate, _| {
+            Ok(f(context, predicate))
+        }))
+    }
+
+    /// Like [Verifier::satisfy_general], for satisfiers that can explain why they rejected a
+    /// caveat
+    ///
+    /// `Ok(true)` satisfies the caveat and `Ok(false)` leaves it to the other satisfiers, as with
+    /// a plain boolean. An `Err` also leaves it to the other satisfiers, but if none of them
+    /// accept the caveat, the error is included in the resulting
+    /// [MacaroonError::CaveatNotSatisfied], eg to tell a malformed caveat apart from a policy
+    /// denial.
+    ///
+    /// ```rust
+    /// use macaroon::{Macaroon, MacaroonError, MacaroonKey, Verifier};
+    ///
+    /// let key = MacaroonKey::generate(b"key");
+    /// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+    /// macaroon.add_first_party_caveat("account = alice".into());
+    ///
+    /// let mut verifier = Verifier::default();
+    /// verifier.satisfy_general_with_err(|predicate| {
+    ///     let account = match predicate.as_ref().strip_prefix(b"account = ") {
+    ///         Some(account) => account,
+    ///         None => return Ok(false),
+    ///     };
+    ///     std::str::from_utf8(account)
+    ///         .map_err(|_| "account is not UTF-8")?
+    ///         .parse::<u64>()
+    ///         .map(|id| id == 3735928559)
+    ///         .map_err(|_| "account is not a number")
+    /// });
+    ///
+    /// match verifier.verify(&macaroon, &key, vec![]) {
+    ///     Err(MacaroonError::CaveatNotSatisfied(caveat)) => {
+    ///         assert!(caveat.reason.ends_with("account is not a number"))
+    ///     }
+    ///     _ => panic!("expected the caveat to be rejected"),
+    /// }
+    /// ```
+    pub fn satisfy_general_with_err<F, E>(&mut self, f: F)
+    where
+        F: Fn(&ByteString) -> std::result::Result<bool, E> + Send + Sync + 'static,
+        E: fmt::Display,
+    {
+        self.general.push(Box::new(move |_, predicate, _| {
+            f(predicate).map_err(|e| e.to_string())
+        }))
+    }
+
+    /// Like [Verifier::satisfy_general_with_err], for expiry satisfiers, which are also given
+    /// the [VerifierOptions::clock_skew] to allow for
+    pub(crate) fn satisfy_expiry<F, E>(&mut self, f: F)
+    where
+        F: Fn(&ByteString, Duration) -> std::result::Result<bool, E> + Send + Sync + 'static,
+        E: fmt::Display,
+    {
+        self.general.push(Box::new(move |_, predicate, clock_skew| {
+            f(predicate, clock_skew).map_err(|e| e.to_string())
+        }))
+    }
+
+    /// Like [Verifier::check_satisfied], but only copies the predicate if it needs normalizing
+    /// or isn't matched exactly
+    pub(crate) fn check_satisfied_bytes(&self, context: &C, predicate: &[u8]) -> Result<()> {
+        if !self.normalize && self.exact.contains(predicate) {
+            return Ok(());
+        }
+        self.check_satisfied(context, &predicate.into())
+    }
+
+    /// Check a single first-party caveat predicate against the exact and general satisfiers
+    pub(crate) fn check_satisfied(&self, context: &C, predicate: &ByteString) -> Result<()> {
+        self.find_satisfier(context, predicate).map(|_| ())
+    }
+
+    /// Like [Verifier::check_satisfied], returning the satisfier that accepted the predicate
+    pub(crate) fn find_satisfier(
+        &self,
+        context: &C,
+        predicate: &ByteString,
+    ) -> Result<SatisfiedBy> {
+        self.find_satisfier_with_skew(context, predicate, Duration::ZERO)
+    }
+
+    /// Like [Verifier::find_satisfier], allowing for `clock_skew` in expiry caveats
+    pub(crate) fn find_satisfier_with_skew(
+        &self,
+        context: &C,
+        predicate: &ByteString,
+        clock_skew: Duration,
+    ) -> Result<SatisfiedBy> {
+        let normalized;
+        let predicate = if self.normalize {
+            normalized = normalize_predicate(predicate);
+            &normalized
+        } else {
+            predicate
+        };
+        // This checks exact caveats first and then general second
+        if self.exact.contains(predicate) {
+            return Ok(SatisfiedBy::Exact);
+        }
+        let mut reasons = Vec::new();
+        for (i, f) in self.general.iter().enumerate() {
+            match f(context, predicate, clock_skew) {
+                Ok(true) => return Ok(SatisfiedBy::General(i)),
+                Ok(false) => {}
+                Err(reason) => reasons.push(reason),
+            }
+        }
+        let mut message = format!(
+            "first party caveat not satisfied: {}",
+            String::from_utf8_lossy(predicate.as_ref())
+        );
+        for reason in reasons {
+            message.push_str(": ");
+            message.push_str(&reason);
+        }
+        Err(MacaroonError::caveat_not_satisfied(
+            predicate.as_ref(),
+            message,
+        ))
+    }
+}
+
+/// Builder for a [Verifier] configured once, eg at startup, and then shared as is
+///
+/// ```rust
+/// use macaroon::{Macaroon, MacaroonKey, Verifier};
+/// use std::thread;
+///
+/// let verifier = Verifier::builder()
+///     .satisfy_exact("account = 3735928559".into())
+///     .satisfy_general(|predicate| predicate.as_ref().starts_with(b"user = "))
+///     .configure(|verifier| verifier.satisfy_operations(&["read"]))
+///     .build_shared();
+///
+/// let key = MacaroonKey::generate(b"key");
+/// let mut macaroon = Macaroon::create(None, &key, "id".into()).unwrap();
+/// macaroon.add_first_party_caveat("user = alice".into());
+/// let worker = {
+///     let verifier = verifier.clone();
+///     thread::spawn(move || verifier.verify(&macaroon, &key, vec![]).is_ok())
+/// };
+/// assert!(worker.join().unwrap());
+/// ```
+pub struct VerifierBuilder<C = ()> {
+    verifier: Verifier<C>,
+}
+
+impl Default for VerifierBuilder {
+    fn default() -> Self {
+        VerifierBuilder::new()
+    }
+}
+
+impl<C> VerifierBuilder<C> {
+    /// A builder for a verifier with request context of type `C` (see [Verifier])
+    pub fn new() -> VerifierBuilder<C> {
+        VerifierBuilder {
+            verifier: Verifier::new(),
+        }
+    }
+
+    /// See [Verifier::satisfy_exact]
+    pub fn satisfy_exact(mut self, b: ByteString) -> Self {
+        self.verifier.satisfy_exact(b);
+        self
+    }
+
+    /// See [Verifier::satisfy_exact_all]
+    pub fn satisfy_exact_all<I>(mut self, predicates: I) -> Self
+    where
+        I: IntoIterator<Item = ByteString>,
+    {
+        self.verifier.satisfy_exact_all(predicates);
+        self
+    }
+
+    /// See [Verifier::set_exact_satisfiers]
+    pub fn exact_satisfiers(mut self, exact: Arc<BTreeSet<ByteString>>) -> Self {
+        self.verifier.set_exact_satisfiers(exact);
+        self
+    }
+
+    /// See [Verifier::satisfy_general]
+    pub fn satisfy_general<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&ByteString) -> bool + Send + Sync + 'static,
+    {
+        self.verifier.satisfy_general(f);
+        self
+    }
+
+    /// See [Verifier::satisfy_general_with_err]
+    pub fn satisfy_general_with_err<F, E>(mut self, f: F) -> Self
+    where
+        F: Fn(&ByteString) -> std::result::Result<bool, E> + Send + Sync + 'static,
+        E: fmt::Display,
+    {
+        self.verifier.satisfy_general_with_err(f);
+        self
+    }
+
+    /// See [Verifier::satisfy_general_with_context]
+    pub fn satisfy_general_with_context<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&C, &ByteString) -> bool + Send + Sync + 'static,
+    {
+        self.verifier.satisfy_general_with_context(f);
+        self
+    }
+
+    /// See [Verifier::enable_predicate_normalization]
+    pub fn normalize_predicates(mut self) -> Self {
+        self.verifier.enable_predicate_normalization();
+        self
+    }
+
+    /// See [Verifier::with_decryptor]
+    pub fn decryptor<D: Decryptor + 'static>(mut self, decryptor: D) -> Self {
+        self.verifier = self.verifier.with_decryptor(decryptor);
+        self
+    }
+
+    /// See [Verifier::with_binding]
+    pub fn binding<B: BindingScheme + 'static>(mut self, binding: B) -> Self {
+        self.verifier = self.verifier.with_binding(binding);
+        self
+    }
+
+    /// See [Verifier::with_revocation_checker]
+    pub fn revocation_checker<R: RevocationChecker + 'static>(mut self, checker: R) -> Self {
+        self.verifier = self.verifier.with_revocation_checker(checker);
+        self
+    }
+
+    /// See [Verifier::expect_location]
+    pub fn expect_location(mut self, location: &str, matching: LocationMatch) -> Self {
+        self.verifier = self.verifier.expect_location(location, matching);
+        self
+    }
+
+    /// Configure the verifier directly, eg with the satisfiers for standard caveats
+    pub fn configure<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut Verifier<C>),
+    {
+        f(&mut self.verifier);
+        self
+    }
+
+    pub fn build(self) -> Verifier<C> {
+        self.verifier
     }
 
-    pub fn satisfy_general(&mut self, f: VerifyFunc) {
-        self.general.push(f)
+    /// Build the verifier, ready to be shared between threads
+    pub fn build_shared(self) -> Arc<Verifier<C>> {
+        Arc::new(self.verifier)
     }
+}
+
+/// Verify `m` and its discharges, checking first-party caveats with `ctx.checker`
+///
+/// This is [Verifier::verify_with_options] for checkers other than a [Verifier], such as a
+/// [Checker](crate::Checker).
+pub(crate) fn verify_with_checker(
+    ctx: &VerifyContext,
+    m: &Macaroon,
+    key: &MacaroonKey,
+    discharges: Vec<Macaroon>,
+) -> Result<()> {
+    verify_with_report(ctx, m, key, discharges, None)
+}
+
+/// Like [verify_with_checker], recording the caveats checked and discharges used in `report`
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "macaroon.verify",
+        level = "debug",
+        skip_all,
+        fields(
+            identifier = %crate::telemetry::redact(m.identifier.as_ref()),
+            caveats = m.caveats.len(),
+            discharges = discharges.len()
+        )
+    )
+)]
+pub(crate) fn verify_with_report(
+    ctx: &VerifyContext,
+    m: &Macaroon,
+    key: &MacaroonKey,
+    discharges: Vec<Macaroon>,
+    report: Option<&mut VerificationReport>,
+) -> Result<()> {
+    #[cfg(feature = "tracing")]
+    let started = Instant::now();
+    let result = verify_tree(ctx, m, key, discharges, report);
+    #[cfg(feature = "tracing")]
+    crate::telemetry::verified(&result, started);
+    result
+}
+
+fn verify_tree(
+    ctx: &VerifyContext,
+    m: &Macaroon,
+    key: &MacaroonKey,
+    discharges: Vec<Macaroon>,
+    report: Option<&mut VerificationReport>,
+) -> Result<()> {
+    #[cfg(feature = "parallel")]
+    if report.is_none() && ctx.options.parallel && !discharges.is_empty() {
+        return crate::parallel::verify(ctx, m, key, &discharges);
+    }
+    let mut state = VerifyState {
+        discharges: discharges
+            .into_iter()
+            .map(|d| (d.identifier.clone(), d))
+            .collect(),
+        third_party_caveats: 0,
+        report,
+    };
+    if let Some(expected) = ctx.location {
+        expected.check(m.location.as_deref())?;
+    }
+    verify_with_sig(ctx, &m.signature, m, key, &mut state, 0)?;
+    // Now check that all discharges were used
+    if !state.discharges.is_empty() && !ctx.options.allow_unused_discharges {
+        return Err(MacaroonError::DischargeNotUsed);
+    }
+    Ok(())
+}
+
+pub(crate) const NO_DISCHARGE: &str =
+    "no discharge macaroon found (or discharge has already been used) for third-party caveat";
 
-    fn verify_general(&self, value: &ByteString) -> bool {
-        for f in self.general.iter() {
-            if f(value) {
-                return true;
+/// What stays the same while verifying a macaroon and all its discharges
+pub(crate) struct VerifyContext<'a> {
+    pub(crate) checker: &'a dyn FirstPartyCaveatChecker,
+    pub(crate) options: &'a VerifierOptions,
+    pub(crate) scheme: &'a dyn SignatureScheme,
+    pub(crate) decryptor: &'a dyn Decryptor,
+    pub(crate) binding: &'a dyn BindingScheme,
+    pub(crate) revocation: Option<&'a dyn RevocationChecker>,
+    pub(crate) location: Option<&'a ExpectedLocation>,
+}
+
+/// What changes while verifying a macaroon and all its discharges
+struct VerifyState<'r> {
+    /// Discharges not used yet
+    discharges: HashMap<ByteString, Macaroon>,
+    third_party_caveats: usize,
+    report: Option<&'r mut VerificationReport>,
+}
+
+fn verify_with_sig(
+    ctx: &VerifyContext,
+    root_sig: &MacaroonKey,
+    m: &Macaroon,
+    key: &MacaroonKey,
+    state: &mut VerifyState,
+    depth: usize,
+) -> Result<()> {
+    let options = ctx.options;
+    if let Some(max_depth) = options.max_depth {
+        if depth > max_depth {
+            return Err(MacaroonError::LimitExceeded(format!(
+                "discharge macaroons nested deeper than {}",
+                max_depth
+            )));
+        }
+    }
+    if let Some(revocation) = ctx.revocation {
+        if revocation.is_revoked(m.identifier.as_ref(), &m.signature[..])? {
+            return Err(MacaroonError::Revoked(m.identifier.clone()));
+        }
+    }
+    #[cfg(feature = "time")]
+    if depth > 0 {
+        options.check_discharge_lifetime(m)?;
+    }
+    let mut sig = ctx.scheme.hmac(key, m.identifier.as_ref());
+    let discharge = if depth == 0 {
+        None
+    } else {
+        Some(m.identifier.as_ref())
+    };
+    // Predicates repeated in this macaroon, eg by middleware attenuating a token twice, are
+    // checked once
+    let mut satisfied: HashMap<&ByteString, SatisfiedBy> = HashMap::new();
+    for (i, c) in m.caveats.iter().enumerate() {
+        if let Some(deadline) = options.deadline {
+            if Instant::now() > deadline {
+                return Err(MacaroonError::LimitExceeded(
+                    "verification deadline passed".to_string(),
+                ));
             }
         }
-        false
+        sig = match c {
+            Caveat::ThirdParty(tp) => {
+                state.third_party_caveats += 1;
+                if let Some(max) = options.max_third_party_caveats {
+                    if state.third_party_caveats > max {
+                        return Err(MacaroonError::LimitExceeded(format!(
+                            "more than {} third-party caveats",
+                            max
+                        )));
+                    }
+                }
+                let caveat_key = ctx
+                    .decryptor
+                    .decrypt_key(&sig, tp.verifier_id_ref().as_ref())?;
+                let dm = state.discharges.remove(tp.id_ref()).ok_or_else(|| {
+                    MacaroonError::caveat_not_satisfied(
+                        tp.id_ref().as_ref(),
+                        NO_DISCHARGE.to_string(),
+                    )
+                    .at_caveat(i, discharge)
+                })?;
+                if let Some(report) = state.report.as_deref_mut() {
+                    report.caveats.push(CheckedCaveat {
+                        caveat: tp.id(),
+                        index: i,
+                        discharge: discharge.map(ByteString::from),
+                        satisfied_by: SatisfiedBy::Discharge(dm.identifier.clone()),
+                    });
+                    report.discharges.push(dm.identifier.clone());
+                }
+                verify_with_sig(ctx, root_sig, &dm, &caveat_key, state, depth + 1)?;
+                c.sign_with(ctx.scheme, &sig)
+            }
+            Caveat::FirstParty(fp) => {
+                let predicate = fp.predicate_ref();
+                let satisfied_by = match satisfied.get(predicate) {
+                    Some(satisfied_by) => satisfied_by.clone(),
+                    None => {
+                        let satisfied_by = match state.report {
+                            Some(_) => ctx.checker.find_satisfier(predicate),
+                            None => ctx
+                                .checker
+                                .check_first_party_caveat(predicate)
+                                .map(|()| SatisfiedBy::Checker),
+                        }
+                        .map_err(|e| e.at_caveat(i, discharge))?;
+                        satisfied.insert(predicate, satisfied_by.clone());
+                        satisfied_by
+                    }
+                };
+                if let Some(report) = state.report.as_deref_mut() {
+                    report.caveats.push(CheckedCaveat {
+                        caveat: fp.predicate(),
+                        index: i,
+                        discharge: discharge.map(ByteString::from),
+                        satisfied_by,
+                    });
+                }
+                c.sign_with(ctx.scheme, &sig)
+            }
+        };
+    }
+    // If the root sig equals the newly generated sig, that means we reached
+    // the end of the line and we are ok to return
+    if crypto::constant_time_eq(&root_sig[..], &sig[..]) {
+        return Ok(());
     }
+    // Check the bound signature equals the signature of the discharge
+    // macaroon
+    let bound_sig = ctx.binding.bind(ctx.scheme, root_sig, &sig);
+    if !crypto::constant_time_eq(&bound_sig[..], &m.signature[..]) {
+        return Err(binding_error(
+            ctx.binding,
+            ctx.scheme,
+            root_sig,
+            &sig,
+            &bound_sig,
+            &m.signature[..],
+            m.identifier.as_ref(),
+        ));
+    }
+    Ok(())
+}
+
+/// The error for a macaroon whose `signature` is neither `root_sig` nor `bound_sig`, the
+/// binding of its computed signature `sig`: a discharge left unbound or bound twice is told
+/// apart from an invalid signature
+pub(crate) fn binding_error(
+    binding: &dyn BindingScheme,
+    scheme: &dyn SignatureScheme,
+    root_sig: &MacaroonKey,
+    sig: &MacaroonKey,
+    bound_sig: &MacaroonKey,
+    signature: &[u8],
+    identifier: &[u8],
+) -> MacaroonError {
+    if crypto::constant_time_eq(&sig[..], signature) {
+        return MacaroonError::DischargeNotBound(identifier.into());
+    }
+    let twice = binding.bind(scheme, root_sig, bound_sig);
+    if crypto::constant_time_eq(&twice[..], signature) {
+        return MacaroonError::DischargeBoundTwice(identifier.into());
+    }
+    MacaroonError::InvalidSignature
 }
 
 #[cfg(test)]
 mod tests {
     extern crate time;
 
-    use super::Verifier;
-    use crate::{ByteString, Macaroon, MacaroonError, MacaroonKey};
+    use super::{Verifier, VerifierBuilder, VerifierOptions};
+    use crate::{
+        Blake2bMac, ByteString, HmacSha256, HmacSha512_256, Macaroon, MacaroonError, MacaroonKey,
+        SignatureScheme,
+    };
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_simple_macaroon() {
@@ -185,6 +1262,66 @@ mod tests {
             .unwrap_err();
     }
 
+    #[test]
+    fn test_macaroon_exact_caveat_normalized() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_first_party_caveat("account=3735928559".into());
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("account = 3735928559".into());
+        verifier
+            .verify(&macaroon, &key, Default::default())
+            .unwrap_err();
+        verifier.enable_predicate_normalization();
+        verifier
+            .verify(&macaroon, &key, Default::default())
+            .unwrap();
+        verifier.satisfy_exact(" user=alice".into());
+        macaroon.add_first_party_caveat("user  =  alice".into());
+        verifier
+            .verify(&macaroon, &key, Default::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_exact_satisfier_set_operations() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact_all(vec!["user = alice".into(), "account = 3735928559".into()]);
+        verifier.verify(&macaroon, &key, vec![]).unwrap();
+
+        // a snapshot is shared until the verifier changes
+        let snapshot = verifier.exact_satisfiers();
+        assert!(Arc::ptr_eq(&snapshot, &verifier.exact_satisfiers()));
+        assert!(verifier.remove_exact(&"account = 3735928559".into()));
+        assert!(!verifier.remove_exact(&"account = 3735928559".into()));
+        verifier.verify(&macaroon, &key, vec![]).unwrap_err();
+        assert_eq!(2, snapshot.len());
+        assert_eq!(1, verifier.exact_satisfiers().len());
+
+        let mut other = Verifier::default();
+        other.set_exact_satisfiers(snapshot.clone());
+        assert!(Arc::ptr_eq(&snapshot, &other.exact_satisfiers()));
+        other.verify(&macaroon, &key, vec![]).unwrap();
+        other.satisfy_general(|_| true);
+        other.clear();
+        assert!(other.exact_satisfiers().is_empty());
+        other.verify(&macaroon, &key, vec![]).unwrap_err();
+
+        // normalized on the way in and out
+        let mut normalizing = Verifier::default();
+        normalizing.enable_predicate_normalization();
+        normalizing.set_exact_satisfiers(Arc::new(
+            vec![ByteString::from("account=3735928559")]
+                .into_iter()
+                .collect(),
+        ));
+        normalizing.verify(&macaroon, &key, vec![]).unwrap();
+        assert!(normalizing.remove_exact(&"account  =3735928559".into()));
+    }
+
     fn after_time_verifier(caveat: &ByteString) -> bool {
         if !caveat.0.starts_with(b"time > ") {
             return false;
@@ -194,7 +1331,7 @@ mod tests {
             Err(_) => return false,
         };
 
-        let format = time::format_description::parse(
+        let format = time::format_description::parse_borrowed::<1>(
             "[year]-[month]-[day]T[hour]:[minute][offset_hour sign:mandatory][offset_minute]",
         )
         .unwrap();
@@ -280,6 +1417,87 @@ mod tests {
             .unwrap()
     }
 
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_max_discharge_lifetime() {
+        use crate::expiry_predicate;
+        use std::time::SystemTime;
+
+        let root_key = MacaroonKey::generate(b"this is the key");
+        let another_key = MacaroonKey::generate(b"this is another key");
+        let mut macaroon = Macaroon::create(None, &root_key, "keyid".into()).unwrap();
+        macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "other keyid".into());
+        let discharge = |ttl: Option<Duration>| {
+            let mut discharge = Macaroon::create(None, &another_key, "other keyid".into()).unwrap();
+            if let Some(ttl) = ttl {
+                discharge.add_first_party_caveat(expiry_predicate(SystemTime::now() + ttl));
+            }
+            macaroon.bind(&mut discharge);
+            vec![discharge]
+        };
+        let mut verifier = Verifier::default();
+        verifier.satisfy_general(|p| p.as_ref().starts_with(b"time < "));
+        let options = VerifierOptions::builder()
+            .max_discharge_lifetime(Duration::from_secs(300))
+            .build();
+
+        let fresh = discharge(Some(Duration::from_secs(120)));
+        verifier
+            .verify_with_options(&macaroon, &root_key, fresh, &options)
+            .unwrap();
+        for discharges in [discharge(Some(Duration::from_secs(3600))), discharge(None)] {
+            assert!(matches!(
+                verifier.verify_with_options(&macaroon, &root_key, discharges.clone(), &options),
+                Err(MacaroonError::LimitExceeded(_))
+            ));
+            verifier.verify(&macaroon, &root_key, discharges).unwrap();
+        }
+
+        // the clock skew is allowed for
+        let options = VerifierOptions::builder()
+            .max_discharge_lifetime(Duration::from_secs(300))
+            .clock_skew(Duration::from_secs(3600))
+            .build();
+        let discharges = discharge(Some(Duration::from_secs(3600)));
+        verifier
+            .verify_with_options(&macaroon, &root_key, discharges, &options)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_discharge_binding() {
+        let root_key = MacaroonKey::generate(b"this is the key");
+        let another_key = MacaroonKey::generate(b"this is another key");
+        let mut macaroon = Macaroon::create(None, &root_key, "keyid".into()).unwrap();
+        macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "other keyid".into());
+        let unbound = Macaroon::create(None, &another_key, "other keyid".into()).unwrap();
+        let verifier = Verifier::default();
+        match verifier.verify(&macaroon, &root_key, vec![unbound.clone()]) {
+            Err(MacaroonError::DischargeNotBound(id)) => {
+                assert_eq!(ByteString::from("other keyid"), id)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let mut twice = unbound.clone();
+        macaroon.bind(&mut twice);
+        macaroon.bind(&mut twice);
+        assert!(matches!(
+            verifier.verify(&macaroon, &root_key, vec![twice]),
+            Err(MacaroonError::DischargeBoundTwice(_))
+        ));
+
+        // bound to another macaroon
+        let mut other = macaroon.clone();
+        other.add_first_party_caveat("account = 3735928559".into());
+        let mut elsewhere = unbound;
+        other.bind(&mut elsewhere);
+        assert!(matches!(
+            verifier.verify(&macaroon, &root_key, vec![elsewhere]),
+            Err(MacaroonError::InvalidSignature)
+        ));
+    }
+
     #[test]
     fn test_macaroon_third_party_caveat_with_cycle() {
         let root_key = MacaroonKey::generate(b"this is the key");
@@ -323,9 +1541,400 @@ mod tests {
 
         // add a third party caveat but no satisfier, should fail
         macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "other keyid".into());
+        match verifier.verify(&macaroon, &root_key, vec![]) {
+            Err(MacaroonError::CaveatNotSatisfied(caveat)) => {
+                assert_eq!(ByteString::from("other keyid"), caveat.caveat);
+                assert_eq!((Some(0), None), (caveat.index, caveat.discharge));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // the discharge's caveat isn't satisfied either
+        let mut discharge = Macaroon::create(None, &another_key, "other keyid".into()).unwrap();
+        discharge.add_first_party_caveat("account = 3735928559".into());
+        discharge.add_first_party_caveat("user = alice".into());
+        macaroon.bind(&mut discharge);
+        let mut verifier = Verifier::default();
+        verifier.satisfy_exact("account = 3735928559".into());
+        match verifier.verify(&macaroon, &root_key, vec![discharge]) {
+            Err(MacaroonError::CaveatNotSatisfied(caveat)) => {
+                assert_eq!(ByteString::from("user = alice"), caveat.caveat);
+                assert_eq!(Some(1), caveat.index);
+                assert_eq!(Some(ByteString::from("other keyid")), caveat.discharge);
+                assert_eq!(
+                    "first party caveat not satisfied: user = alice (caveat 1 of discharge other keyid)",
+                    caveat.to_string()
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_verify_with_options() {
+        let root_key = MacaroonKey::generate(b"this is the key");
+        let another_key = MacaroonKey::generate(b"this is another key");
+        let mut macaroon = Macaroon::create(None, &root_key, "keyid".into()).unwrap();
+        macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "other keyid".into());
+        let mut discharge = Macaroon::create(None, &another_key, "other keyid".into()).unwrap();
+        macaroon.bind(&mut discharge);
+        let mut unused = Macaroon::create(None, &another_key, "unused".into()).unwrap();
+        macaroon.bind(&mut unused);
+        let verifier = Verifier::default();
+
+        let discharges = vec![discharge.clone(), unused.clone()];
+        assert!(matches!(
+            verifier.verify(&macaroon, &root_key, discharges.clone()),
+            Err(MacaroonError::DischargeNotUsed)
+        ));
+        let options = VerifierOptions::builder()
+            .allow_unused_discharges(true)
+            .build();
+        verifier
+            .verify_with_options(&macaroon, &root_key, discharges, &options)
+            .unwrap();
+
+        let options = VerifierOptions::builder().max_depth(1).build();
+        verifier
+            .verify_with_options(&macaroon, &root_key, vec![discharge.clone()], &options)
+            .unwrap();
+        let options = VerifierOptions::builder().max_depth(0).build();
         assert!(matches!(
-            verifier.verify(&macaroon, &root_key, vec![]),
+            verifier.verify_with_options(&macaroon, &root_key, vec![discharge.clone()], &options),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+
+        let options = VerifierOptions::builder()
+            .deadline(Instant::now() - Duration::from_millis(1))
+            .build();
+        assert!(matches!(
+            verifier.verify_with_options(&macaroon, &root_key, vec![discharge], &options),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+    }
+
+    /// A macaroon whose discharges are nested `depth` deep, each with `width` third-party
+    /// caveats (only the first of which needs a further discharge)
+    fn nested_discharges(depth: usize, width: usize) -> (Macaroon, MacaroonKey, Vec<Macaroon>) {
+        let root_key = MacaroonKey::generate(b"root");
+        let mut macaroon = Macaroon::create(None, &root_key, "root".into()).unwrap();
+        let mut discharges: Vec<Macaroon> = Vec::new();
+        for level in 0..depth {
+            for i in 0..width {
+                let id = format!("{}/{}", level, i);
+                let key = MacaroonKey::generate(id.as_bytes());
+                let parent = match level {
+                    0 => &mut macaroon,
+                    _ => &mut discharges[(level - 1) * width],
+                };
+                parent.add_third_party_caveat("http://auth/", &key, id.as_str().into());
+                discharges.push(Macaroon::create(None, &key, id.as_str().into()).unwrap());
+            }
+        }
+        for d in discharges.iter_mut() {
+            macaroon.bind(d);
+        }
+        (macaroon, root_key, discharges)
+    }
+
+    #[test]
+    fn test_discharge_limits() {
+        let verifier = Verifier::default();
+        let verify = |(m, key, discharges): (Macaroon, MacaroonKey, Vec<Macaroon>),
+                      options: VerifierOptions| {
+            let options = VerifierOptions {
+                allow_unused_discharges: true,
+                ..options
+            };
+            verifier.verify_with_options(&m, &key, discharges, &options)
+        };
+        let limited = |result| matches!(result, Err(MacaroonError::LimitExceeded(_)));
+
+        verify(nested_discharges(3, 2), VerifierOptions::default()).unwrap();
+        let depth = |d| VerifierOptions::builder().max_depth(d).build();
+        verify(nested_discharges(3, 1), depth(3)).unwrap();
+        assert!(limited(verify(nested_discharges(3, 1), depth(2))));
+
+        let width = |w| {
+            VerifierOptions::builder()
+                .max_third_party_caveats(w)
+                .build()
+        };
+        verify(nested_discharges(2, 3), width(6)).unwrap();
+        assert!(limited(verify(nested_discharges(2, 3), width(5))));
+
+        let deep = nested_discharges(super::DEFAULT_MAX_DEPTH + 1, 1);
+        assert!(limited(verify(deep.clone(), VerifierOptions::default())));
+        let unbounded = VerifierOptions::builder().unbounded().build();
+        verify(deep, unbounded).unwrap();
+    }
+
+    #[test]
+    fn test_general_satisfier_errors() {
+        let key = MacaroonKey::generate(b"this is the key");
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_first_party_caveat("time < soon".into());
+        let mut verifier = Verifier::default();
+        verifier.satisfy_general_with_err(|predicate| {
+            if predicate.as_ref().starts_with(b"time < ") {
+                Err("invalid timestamp")
+            } else {
+                Ok(false)
+            }
+        });
+        match verifier.verify(&macaroon, &key, vec![]) {
+            Err(MacaroonError::CaveatNotSatisfied(caveat)) => {
+                assert_eq!(
+                    "first party caveat not satisfied: time < soon: invalid timestamp",
+                    caveat.reason
+                );
+                assert_eq!(ByteString::from("time < soon"), caveat.caveat);
+                assert_eq!((Some(0), None), (caveat.index, caveat.discharge));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // Another satisfier accepting the caveat takes precedence over the error
+        verifier.satisfy_general(|predicate| predicate.as_ref() == b"time < soon");
+        verifier.verify(&macaroon, &key, vec![]).unwrap();
+    }
+
+    #[test]
+    fn test_verify_first_party_only() {
+        let root_key = MacaroonKey::generate(b"this is the key");
+        let another_key = MacaroonKey::generate(b"this is another key");
+        let mut macaroon = Macaroon::create(None, &root_key, "keyid".into()).unwrap();
+        macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "first".into());
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "second".into());
+
+        let mut verifier = Verifier::default();
+        let unsatisfied = verifier.verify_first_party_only(&macaroon, &root_key);
+        match unsatisfied {
+            Err(MacaroonError::CaveatNotSatisfied(caveat)) => assert_eq!(Some(1), caveat.index),
+            other => panic!("unexpected result {:?}", other),
+        }
+        verifier.satisfy_exact("account = 3735928559".into());
+        let pending = verifier
+            .verify_first_party_only(&macaroon, &root_key)
+            .unwrap();
+        assert_eq!(macaroon.third_party_caveats(), pending);
+        assert!(matches!(
+            verifier.verify_first_party_only(&macaroon, &another_key),
+            Err(MacaroonError::InvalidSignature)
+        ));
+        // a forged macaroon is rejected before its caveats reach any satisfier
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut watching = Verifier::default();
+        let log = seen.clone();
+        watching.satisfy_general(move |predicate| {
+            log.lock().unwrap().push(predicate.clone());
+            false
+        });
+        let mut forged = Macaroon::create(None, &another_key, "keyid".into()).unwrap();
+        forged.add_first_party_caveat("account = 3735928559".into());
+        assert!(matches!(
+            watching.verify_first_party_only(&forged, &root_key),
+            Err(MacaroonError::InvalidSignature)
+        ));
+        assert!(seen.lock().unwrap().is_empty());
+        // pending caveats still need discharging for full verification
+        assert!(verifier.verify(&macaroon, &root_key, vec![]).is_err());
+
+        let mut blake2b =
+            Macaroon::create_with_scheme(None, &root_key, "keyid".into(), &Blake2bMac).unwrap();
+        blake2b.add_first_party_caveat("account = 3735928559".into());
+        blake2b.add_third_party_caveat("http://auth.mybank/", &another_key, "other".into());
+        let pending = verifier
+            .verify_first_party_only_with_scheme(&blake2b, &root_key, &Blake2bMac)
+            .unwrap();
+        assert_eq!(blake2b.third_party_caveats(), pending);
+        assert!(matches!(
+            verifier.verify_first_party_only(&blake2b, &root_key),
+            Err(MacaroonError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_verify_with_scheme() {
+        let root_key = MacaroonKey::generate(b"this is the key");
+        let another_key = MacaroonKey::generate(b"this is another key");
+        let schemes: [&'static dyn SignatureScheme; 2] = [&HmacSha512_256, &Blake2bMac];
+        for scheme in schemes.iter() {
+            let mut macaroon =
+                Macaroon::create_with_scheme(None, &root_key, "keyid".into(), *scheme).unwrap();
+            macaroon.add_first_party_caveat("account = 3735928559".into());
+            macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "other".into());
+            let mut discharge =
+                Macaroon::create_with_scheme(None, &another_key, "other".into(), *scheme).unwrap();
+            macaroon.bind(&mut discharge);
+
+            let mut verifier = Verifier::default();
+            verifier.satisfy_exact("account = 3735928559".into());
+            verifier
+                .verify_with_scheme(&macaroon, &root_key, vec![discharge.clone()], *scheme)
+                .unwrap();
+            assert!(verifier
+                .verify(&macaroon, &root_key, vec![discharge.clone()])
+                .is_err());
+            assert!(verifier
+                .verify_with_scheme(&macaroon, &root_key, vec![discharge.clone()], &HmacSha256)
+                .is_err());
+
+            // options apply to the other schemes too
+            let shallow = VerifierOptions::builder().max_depth(0).build();
+            assert!(matches!(
+                verifier.verify_with_scheme_and_options(
+                    &macaroon,
+                    &root_key,
+                    vec![discharge.clone()],
+                    *scheme,
+                    &shallow
+                ),
+                Err(MacaroonError::LimitExceeded(_))
+            ));
+            let lenient = VerifierOptions::builder()
+                .allow_unused_discharges(true)
+                .build();
+            let mut unused =
+                Macaroon::create_with_scheme(None, &another_key, "unused".into(), *scheme).unwrap();
+            macaroon.bind(&mut unused);
+            assert!(verifier
+                .verify_with_scheme(
+                    &macaroon,
+                    &root_key,
+                    vec![discharge.clone(), unused.clone()],
+                    *scheme
+                )
+                .is_err());
+            verifier
+                .verify_with_scheme_and_options(
+                    &macaroon,
+                    &root_key,
+                    vec![discharge, unused],
+                    *scheme,
+                    &lenient,
+                )
+                .unwrap();
+
+            let mut plain =
+                Macaroon::create_with_scheme(None, &root_key, "keyid".into(), *scheme).unwrap();
+            plain.add_first_party_caveat("account = 3735928559".into());
+            assert!(matches!(
+                verifier.verify(&plain, &root_key, vec![]),
+                Err(MacaroonError::InvalidSignature)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verify_with_context() {
+        struct Request {
+            account: u64,
+            scopes: Vec<&'static str>,
+        }
+
+        let root_key = MacaroonKey::generate(b"root key");
+        let another_key = MacaroonKey::generate(b"another key");
+        let mut macaroon = Macaroon::create(None, &root_key, "keyid".into()).unwrap();
+        macaroon.add_first_party_caveat("account = 3735928559".into());
+        macaroon.add_first_party_caveat("user = alice".into());
+        macaroon.add_third_party_caveat("http://auth.mybank/", &another_key, "other".into());
+        let mut discharge = Macaroon::create(None, &another_key, "other".into()).unwrap();
+        discharge.add_scope_caveat("read");
+        macaroon.bind(&mut discharge);
+
+        // Built once, shared by requests
+        let mut verifier: Verifier<Request> = Verifier::new();
+        verifier.satisfy_exact("user = alice".into());
+        verifier.satisfy_general_with_context(|request: &Request, predicate| {
+            predicate.as_ref() == format!("account = {}", request.account).as_bytes()
+        });
+        verifier.satisfy_general_with_context(|request: &Request, predicate| {
+            predicate.as_ref() == b"scope read" && request.scopes.contains(&"read")
+        });
+
+        let allowed = Request {
+            account: 3735928559,
+            scopes: vec!["read"],
+        };
+        verifier
+            .verify_with_context(&macaroon, &root_key, vec![discharge.clone()], &allowed)
+            .unwrap();
+        let other_account = Request {
+            account: 1,
+            scopes: vec!["read"],
+        };
+        assert!(matches!(
+            verifier.verify_with_context(
+                &macaroon,
+                &root_key,
+                vec![discharge.clone()],
+                &other_account
+            ),
             Err(MacaroonError::CaveatNotSatisfied(_))
         ));
+        let no_scope = Request {
+            account: 3735928559,
+            scopes: vec![],
+        };
+        assert!(verifier
+            .verify_with_context(&macaroon, &root_key, vec![discharge.clone()], &no_scope)
+            .is_err());
+        let options = VerifierOptions::builder()
+            .max_third_party_caveats(0)
+            .build();
+        assert!(matches!(
+            verifier.verify_with_context_and_options(
+                &macaroon,
+                &root_key,
+                vec![discharge],
+                &allowed,
+                &options
+            ),
+            Err(MacaroonError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_shared_verifier() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Verifier>();
+        assert_send_sync::<Verifier<String>>();
+        assert_send_sync::<VerifierBuilder<String>>();
+
+        let key = MacaroonKey::generate(b"this is the key");
+        let verifier = Verifier::builder()
+            .satisfy_exact("account = 3735928559".into())
+            .satisfy_general(|predicate| predicate.as_ref() == b"user = alice")
+            .normalize_predicates()
+            .build_shared();
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let verifier = verifier.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+                    macaroon.add_first_party_caveat("account=3735928559".into());
+                    if i % 2 == 1 {
+                        macaroon.add_first_party_caveat("user = bob".into());
+                    }
+                    verifier.verify(&macaroon, &key, vec![]).is_ok()
+                })
+            })
+            .collect();
+        let results: Vec<bool> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        assert_eq!(vec![true, false, true, false], results);
+
+        let verifier = VerifierBuilder::<String>::new()
+            .satisfy_general_with_context(|user: &String, predicate| {
+                predicate.as_ref() == format!("user = {}", user).as_bytes()
+            })
+            .build();
+        let mut macaroon = Macaroon::create(None, &key, "testing".into()).unwrap();
+        macaroon.add_first_party_caveat("user = alice".into());
+        assert!(verifier
+            .verify_with_context(&macaroon, &key, vec![], &"alice".to_string())
+            .is_ok());
     }
 }
@@ -1,8 +1,9 @@
 // Examples from libmacaroons reference implementation README:
 // https://github.com/rescrv/libmacaroons
 
-use base64;
-use macaroon::{ByteString, Caveat, Format, Macaroon, MacaroonKey, Verifier};
+use macaroon::{
+    ByteString, Caveat, Format, Macaroon, MacaroonKey, SerializeOptions, Verifier, NONCE_LEN,
+};
 
 fn bytes_to_hex(bytes: &[u8]) -> String {
     bytes
@@ -31,9 +32,16 @@ fn creating_macaroons() {
     );
 
     let b64_standard = "MDAxY2xvY2F0aW9uIGh0dHA6Ly9teWJhbmsvCjAwMjZpZGVudGlmaWVyIHdlIHVzZWQgb3VyIHNlY3JldCBrZXkKMDAyZnNpZ25hdHVyZSDj2eApCFJsTAA5rhURQRXZf91ovyujebNCqvD2F9BVLwo";
-    let b64_url_safe =
-        base64::encode_config(base64::decode(b64_standard).unwrap(), base64::URL_SAFE);
+    let b64_url_safe = base64::encode_config(
+        base64::decode(b64_standard).unwrap(),
+        base64::URL_SAFE_NO_PAD,
+    );
     assert_eq!(mac.serialize(Format::V1).unwrap(), b64_url_safe);
+    assert_eq!(
+        mac.serialize_with_options(Format::V1, &SerializeOptions { padding: true })
+            .unwrap(),
+        b64_url_safe.clone() + "="
+    );
 }
 
 #[test]
@@ -64,7 +72,7 @@ fn adding_caveats() {
     );
 
     // serialize and deserialize using V1
-    let mac2 = Macaroon::deserialize(&mac.serialize(Format::V1).unwrap()).unwrap();
+    let mac2 = Macaroon::deserialize(mac.serialize(Format::V1).unwrap()).unwrap();
     assert_eq!(
         bytes_to_hex(mac2.signature().as_ref()),
         "ddf553e46083e55b8d71ab822be3d8fcf21d6bf19c40d617bb9fb438934474b6"
@@ -90,9 +98,9 @@ fn check_time(caveat: &ByteString) -> bool {
 
 #[test]
 fn test_check_time() {
-    assert_eq!(check_time(&"time < 2020-01-01T00:00".into()), true);
-    assert_eq!(check_time(&"time < 2014-01-01T00:00".into()), false);
-    assert_eq!(check_time(&"account = 3735928559".into()), false);
+    assert!(check_time(&"time < 2020-01-01T00:00".into()));
+    assert!(!check_time(&"time < 2014-01-01T00:00".into()));
+    assert!(!check_time(&"account = 3735928559".into()));
 }
 
 #[test]
@@ -161,33 +169,34 @@ fn third_party_macaroons() {
     );
 
     let caveat_key = MacaroonKey::generate(b"4; guaranteed random by a fair toss of the dice");
-    mac.add_third_party_caveat(
-        "http://auth.mybank/".into(),
+    let mut random_nonce_mac = mac.clone();
+    random_nonce_mac.add_third_party_caveat(
+        "http://auth.mybank/",
+        &caveat_key,
+        "this was how we remind auth of key/pred".into(),
+    );
+    // In the example, libsodium nonce generation is overriden, so the verifier_id is always the
+    // same. Encrypting with the same all-zero nonce reproduces it, and the signature, exactly
+    mac.add_third_party_caveat_with_rng(
+        "http://auth.mybank/",
         &caveat_key,
         "this was how we remind auth of key/pred".into(),
+        &mut |nonce: &mut [u8; NONCE_LEN]| *nonce = [0; NONCE_LEN],
     );
-    // In the example, libsodium none generation is overriden, so the verifier_id is always the
-    // same:
-    // "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA027FAuBYhtHwJ58FX6UlVNFtFsGxQHS7uD_w_dedwv4Jjw7UorCREw5rXbRqIKhr"
-    // We don't do that here, so can't actually verify that the signatures match perfectly.
     match &mac.third_party_caveats()[0] {
-        Caveat::FirstParty(_) => assert!(false),
+        Caveat::FirstParty(_) => panic!("expected a third-party caveat"),
         Caveat::ThirdParty(tp) => {
             assert_eq!(tp.location(), "http://auth.mybank/");
             assert_eq!(tp.id(), "this was how we remind auth of key/pred".into());
-            /*
             assert_eq!(tp.verifier_id(),
                 base64::decode_config("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA027FAuBYhtHwJ58FX6UlVNFtFsGxQHS7uD_w_dedwv4Jjw7UorCREw5rXbRqIKhr", base64::URL_SAFE).unwrap().into(),
             );
-            */
         }
     };
-    /*
     assert_eq!(
         bytes_to_hex(mac.signature().as_ref()),
         "d27db2fd1f22760e4c3dae8137e2d8fc1df6c0741c18aed4b97256bf78d1f55c"
     );
-    */
 
     let mut discharge_mac = Macaroon::create(
         Some("http://auth.mybank/".into()),
@@ -203,16 +212,30 @@ fn third_party_macaroons() {
 
     let mut bound_mac = discharge_mac.clone();
     mac.bind(&mut bound_mac);
-    /*
     assert_eq!(
-        bytes_to_hex(discharge_mac.signature().as_ref()),
+        bytes_to_hex(bound_mac.signature().as_ref()),
         "d115ef1c133b1126978d5ab27f69d99ba9d0468cd6c1b7e47b8c1c59019cb019"
     );
-    */
+    let mut random_nonce_bound_mac = discharge_mac.clone();
+    random_nonce_mac.bind(&mut random_nonce_bound_mac);
+
+    assert_eq!(
+        "location http://mybank/
+identifier we used our other secret key
+cid account = 3735928559
+cid this was how we remind auth of key/pred
+vid AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA027FAuBYhtHwJ58FX6UlVNFtFsGxQHS7uD_w_dedwv4Jjw7UorCREw5rXbRqIKhr
+cl http://auth.mybank/
+signature d27db2fd1f22760e4c3dae8137e2d8fc1df6c0741c18aed4b97256bf78d1f55c",
+        mac.inspect()
+    );
 
     let mut ver = Verifier::default();
     ver.satisfy_exact("account = 3735928559".into());
     ver.satisfy_exact("time < 2020-01-01T00:00".into());
     assert!(ver.verify(&mac, &key, vec![discharge_mac]).is_err());
     assert!(ver.verify(&mac, &key, vec![bound_mac]).is_ok());
+    assert!(ver
+        .verify(&random_nonce_mac, &key, vec![random_nonce_bound_mac])
+        .is_ok());
 }
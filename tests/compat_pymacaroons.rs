@@ -1,4 +1,3 @@
-use base64;
 /// https://github.com/ecordell/pymacaroons/blob/master/tests/functional_tests/functional_tests.py
 use macaroon::{Format, Macaroon, MacaroonError, MacaroonKey};
 
@@ -119,8 +118,10 @@ fn test_serializing_too_long_packet() {
     let root_key = MacaroonKey::generate(b"blah");
     let mut mac = Macaroon::create(Some("test".into()), &root_key, "secret".into()).unwrap();
     mac.add_first_party_caveat(vec![b'x'; 65527].into());
-    // TODO: implement a max size check
-    //assert!(mac.serialize(Format::V2).is_err());
+    assert!(matches!(
+        mac.serialize(Format::V1),
+        Err(MacaroonError::SerializationError(_))
+    ));
 }
 
 #[test]